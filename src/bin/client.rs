@@ -1,7 +1,6 @@
 use clap::{Parser, Subcommand};
 use ecdsa::{SigningKey, VerifyingKey};
 use std::sync::atomic::{AtomicBool, Ordering};
-use ctrlc;
 use std::sync::Arc;
 use std::error;
 use std::fs::File;
@@ -50,7 +49,7 @@ fn run(from_path: Option<String>, save_path: Option<String>) -> Result<(), Box<d
 
     println!("save_path = {:?}", save_path);
     let b = "adamadamadamadamadamadamadamadam".as_bytes(); // arbitrary for testing. 32 long
-    let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(&b).unwrap();
+    let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(b).unwrap();
     let public_key: VerifyingKey<Secp256k1> = private_key.verifying_key();
 
     let running = Arc::new(AtomicBool::new(true)); // this bool tells the process to keep looping
@@ -63,7 +62,9 @@ fn run(from_path: Option<String>, save_path: Option<String>) -> Result<(), Box<d
     while running.load(Ordering::SeqCst) {        
 	let mut block = chain.construct_candidate_block(public_key);
 	block.mine();
-	chain.add_block(block);
+	if let Err(e) = chain.add_block(block) {
+	    println!("could not add our own block: {:?}", e);
+	}
         //chain.print_transactions();
         println!();
     }
@@ -1,11 +1,12 @@
 use serde::{Serialize, Deserialize};
-use sha2::{Sha256, Digest};
 use ethereum_types::U256;
 
 use std::time::{SystemTime};
 
-use crate::{Hash};
-use crate::transaction::{Transaction};
+use crate::{Hash, sha256d, hash_to_bytes, hash_to_hex};
+use crate::transaction::{Transaction, TransactionError, WITNESS_SCALE_FACTOR};
+use crate::script::{Script, StackOp};
+use crate::encode::{Encodable, Decodable, DecodeError, CompactSize, take_bytes};
 
 /// This notation expresses the Proof-of-Work target as a coefficient/exponent format,
 /// with the first two hexadecimal digits for the exponent and the next six hex digits as the coefficient.
@@ -16,71 +17,255 @@ use crate::transaction::{Transaction};
 /// Note that this packed format contains a sign bit in the 24th bit, and for example the negation of the above target would be 0x1b8404cb in packed format.
 /// Since targets are never negative in practice, however, this means the largest legal value for the lower 24 bits is 0x7fffff.
 /// Additionally, 0x008000 is the smallest legal value for the lower 24 bits since targets are always stored with the lowest possible exponent
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct DifficultyBits (pub u32);
 
+/// The reasons that a set of difficulty bits doesn't describe a usable target
+#[derive(Debug, PartialEq)]
+pub enum CompactError {
+    Negative, // the sign bit is set
+    Overflow, // the target doesn't fit in 256 bits
+    Zero, // the target is zero, so no hash could ever meet it
+}
+
+/// the target of difficulty 1, i.e. the pow limit of the main network (0x1d00ffff)
+const DIFFICULTY_ONE_BITS: DifficultyBits = DifficultyBits(0x1d00ffff);
+
 impl DifficultyBits {
-    /// Convert the exponent representation into a vector of bytes that represents the actual number
+    /// Convert the exponent representation into the actual target number, the same way bitcoin core's SetCompact does.
     /// This can then be compared against a candidate block header hash to see if it fits the proof of work
-    pub fn to_u256(&self) -> U256 {
+    pub fn to_target(self) -> Result<U256, CompactError> {
+	let exponent: u32 = self.0 >> 24; // the first byte
+	let mut coefficient: u32 = self.0 & 0x007fffff; // next three bytes, without the sign bit
+	if exponent <= 3 {
+	    // the bytes that would sit below the decimal point get dropped
+	    coefficient >>= 8 * (3 - exponent);
+	}
+	if coefficient == 0 {
+	    return Err(CompactError::Zero);
+	}
+	if self.0 & 0x00800000 != 0 {
+	    return Err(CompactError::Negative);
+	}
+	// the coefficient would get shifted off the top of the 256 bits
+	if exponent > 34 || (coefficient > 0xff && exponent > 33) || (coefficient > 0xffff && exponent > 32) {
+	    return Err(CompactError::Overflow);
+	}
+	if exponent <= 3 {
+	    Ok(U256::from(coefficient))
+	} else {
+	    Ok(U256::from(coefficient) << (8 * (exponent - 3)))
+	}
+    }
+
+    /// The reverse of to_target(): pack a target back into the coefficient/exponent format.
+    /// Only the three most significant bytes of the target are kept, and if the top bit of the coefficient would be set,
+    /// we move over by another byte so that it doesn't get mistaken for the sign bit
+    pub fn from_target(target: U256) -> Self {
+	let mut exponent = (target.bits() as u32).div_ceil(8); // the number of bytes needed to hold the target
+	let mut coefficient = if exponent <= 3 {
+	    target.low_u32() << (8 * (3 - exponent))
+	} else {
+	    (target >> (8 * (exponent - 3))).low_u32()
+	};
+	if coefficient & 0x00800000 != 0 {
+	    coefficient >>= 8;
+	    exponent += 1;
+	}
+	DifficultyBits((exponent << 24) | coefficient)
+    }
 
-	let exponent: u32 = ((self.0 >> 24) & 0xFF ).into(); // get the first byte. TODO: is the mask required?
-	let base: U256 = (self.0 & 0xFFFFFF).into(); // next three bytes represent the base
-	//vec![0, 10, 50, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+    /// how many times harder than difficulty 1 (the main network's pow limit) this target is, as shown by block explorers.
+    /// Like bitcoin core we work straight from the compact form, so this also gives a number for invalid bits
+    pub fn difficulty(self) -> f64 {
+	let mut shift = self.0 >> 24;
+	let one_coefficient = (DIFFICULTY_ONE_BITS.0 & 0x00ffffff) as f64;
+	let one_shift = DIFFICULTY_ONE_BITS.0 >> 24;
+	let mut difficulty = one_coefficient / (self.0 & 0x00ffffff) as f64;
+	while shift < one_shift {
+	    difficulty *= 256.0;
+	    shift += 1;
+	}
+	while shift > one_shift {
+	    difficulty /= 256.0;
+	    shift -= 1;
+	}
+	difficulty
+    }
 
-	let full_exponent: U256 = U256::from(8 * (exponent -  3));
-	//println!("full_exponent = {:?}", full_exponent);
-	assert!(full_exponent < U256::from(255)); // we can't be too big
-	// TODO: the checked_pow() meethod didn't seem to return None when there was overflow hmmm
-	let rhs: U256 = (U256::from(2_u64)).pow(full_exponent).into();
-	//println!("rhs = {:?}", rhs);	
-	let difficulty_target = base * rhs;
-	difficulty_target
-	// U256::from_words(0x01_05_00_00_00_00_00_00_00_00_00_00_00_00_00_00, 0x00_00_00_00_00_00_00_00_00_00_00_00_00_00_00_00)
+    /// the expected number of hashes it takes to find a block at this difficulty, i.e. 2^256 / (target + 1).
+    /// 2^256 doesn't fit in a U256, but since target + 1 <= 2^256 we can instead calculate (2^256 - (target + 1)) / (target + 1) + 1,
+    /// where 2^256 - (target + 1) is simply !target.
+    /// Bits that don't give a valid target are worth no work at all
+    pub fn work(self) -> U256 {
+	let target = match self.to_target() {
+	    Ok(target) => target,
+	    Err(_) => return U256::zero(),
+	};
+	if target == U256::MAX {
+	    return U256::one();
+	}
+	(!target / (target + 1)) + 1
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// the size of a serialized block header: 4 + 32 + 32 + 4 + 4 + 4
+pub const HEADER_SIZE: usize = 80;
+
+/// the most that the transactions (and header) of a block may weigh (BIP141), see Transaction::weight()
+pub const MAX_BLOCK_WEIGHT: usize = 4_000_000;
+
+/// the most sigop cost that the transactions of a block may have between them (BIP141), see Transaction::sigop_cost()
+pub const MAX_BLOCK_SIGOPS_COST: usize = 80_000;
+
+/// the bytes that the witness commitment in the coinbase starts with, so that it can be told apart from any other OpReturn data
+const WITNESS_COMMITMENT_HEADER: [u8; 4] = [0xaa, 0x21, 0xa9, 0xed];
+
+/// The witness commitment (BIP141): the double SHA256 of the witness merkle root and the reserved value, which is the coinbase's witness.
+/// The reserved value is there so that more commitments can be added later on
+pub fn witness_commitment(witness_merkle_root: &Hash, reserved_value: &[u8]) -> Hash {
+    let mut bytes = hash_to_bytes(witness_merkle_root).to_vec();
+    bytes.extend_from_slice(reserved_value);
+    sha256d(&bytes)
+}
+
+/// the coinbase tx_out holding the witness commitment: OpReturn and a push of the header followed by the commitment
+pub fn witness_commitment_script(commitment: &Hash) -> Script {
+    let mut bytes = WITNESS_COMMITMENT_HEADER.to_vec();
+    bytes.extend_from_slice(&hash_to_bytes(commitment));
+    Script {ops: vec![StackOp::OpReturn, StackOp::Bytes(bytes.into_boxed_slice())]}
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct BlockHeader {
     version: u32, // 4 bytes: A version number to track software/protocol upgrades
     previous_block_hash: Hash, // 32 bytes: A reference to the hash of the previous (parent) block in the chain
     merkle_root: Hash, // 32 bytes: A hash of the root of the merkle tree of this block’s transactions
-    time_stamp: u64, // 4  (8 is ok?) bytes: The approximate creation time of this block (in seconds elapsed since Unix Epoch)
+    time_stamp: u32, // 4 bytes: The approximate creation time of this block (in seconds elapsed since Unix Epoch)
     difficulty_bits: DifficultyBits, // 4 bytes: The Proof-of-Work algorithm difficulty target for this block
-    nonce: Option<u32>, // 4 bytes: A counter used for the Proof-of-Work algorithm
+    nonce: u32, // 4 bytes: A counter used for the Proof-of-Work algorithm
 }
 
 
 impl BlockHeader {
     pub fn new(version: u32, previous_block_hash: Hash, merkle_root: Hash, difficulty_bits: DifficultyBits) -> Self {
 	Self {
-	    version: version, 
-	    previous_block_hash: previous_block_hash,
-	    merkle_root: merkle_root, 
-	    time_stamp: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs(), // now is after unix_epoch so we can unrwap
-	    difficulty_bits: difficulty_bits,
-	    nonce: None, // this will get filled by the mining process
+	    version,
+	    previous_block_hash,
+	    merkle_root,
+	    time_stamp: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as u32, // now is after unix_epoch so we can unrwap
+	    difficulty_bits,
+	    nonce: 0, // this will get filled by the mining process
 	}
     }
     
+    pub fn version(&self) -> u32 {
+	self.version
+    }
+
+    pub fn previous_block_hash(&self) -> Hash {
+	self.previous_block_hash
+    }
+
+    pub fn difficulty_bits(&self) -> DifficultyBits {
+	self.difficulty_bits
+    }
+
+    pub fn time_stamp(&self) -> u32 {
+	self.time_stamp
+    }
+
+    /// the time stamp defaults to when the header was created, but a miner is free to choose something else
+    pub fn set_time_stamp(&mut self, time_stamp: u32) {
+	self.time_stamp = time_stamp;
+    }
+
+    pub fn merkle_root(&self) -> Hash {
+	self.merkle_root
+    }
+
+    pub fn nonce(&self) -> u32 {
+	self.nonce
+    }
+
+    /// does the hash of this header satisfy the proof of work given by its difficulty bits?
+    pub fn meets_target(&self) -> bool {
+	match self.difficulty_bits.to_target() {
+	    Ok(target) => self.hash() <= target,
+	    Err(_) => false, // nothing meets a target that doesn't exist
+	}
+    }
+
+    /// The header as it goes over the wire and into the hash: every field is little endian,
+    /// and the two hashes are written out as the raw SHA256 digests they came from
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+	let mut bytes = [0; HEADER_SIZE];
+	bytes[0..4].copy_from_slice(&self.version.to_le_bytes());
+	bytes[4..36].copy_from_slice(&hash_to_bytes(&self.previous_block_hash));
+	bytes[36..68].copy_from_slice(&hash_to_bytes(&self.merkle_root));
+	bytes[68..72].copy_from_slice(&self.time_stamp.to_le_bytes());
+	bytes[72..76].copy_from_slice(&self.difficulty_bits.0.to_le_bytes());
+	bytes[76..80].copy_from_slice(&self.nonce.to_le_bytes());
+	bytes
+    }
+
+    /// the reverse of to_bytes(). Any 80 bytes make a header, whether or not it is a valid one
+    pub fn from_bytes(bytes: &[u8; HEADER_SIZE]) -> Self {
+	let u32_at = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+	Self {
+	    version: u32_at(0),
+	    previous_block_hash: Hash::from_little_endian(&bytes[4..36]),
+	    merkle_root: Hash::from_little_endian(&bytes[36..68]),
+	    time_stamp: u32_at(68),
+	    difficulty_bits: DifficultyBits(u32_at(72)),
+	    nonce: u32_at(76),
+	}
+    }
+
+    /// the block hash is the double SHA256 of the serialized header
     pub fn hash(&self) -> Hash {
-        let mut hasher = Sha256::new();
-        hasher.update(self.version.to_be_bytes());
-        for i in 0..4 {
-            // iterate over the 4 constituent u64
-	    hasher.update(self.previous_block_hash.0[i].to_be_bytes());
-        }
-        for i in 0..4 {
-            // iterate over the 4 constituent u64            
-	    hasher.update(self.merkle_root.0[i].to_be_bytes());
-        }
-        hasher.update(self.time_stamp.to_be_bytes());
-        hasher.update(self.difficulty_bits.0.to_be_bytes());
-	if let Some(nonce) = self.nonce {
-            hasher.update(nonce.to_be_bytes());	    
-	}
-	let hash_vec: Vec<u8> = hasher.finalize().to_vec();
-        Hash::from(&hash_vec[..])
+	sha256d(&self.to_bytes())
+    }
+}
+
+impl Encodable for BlockHeader {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+	bytes.extend_from_slice(&self.to_bytes());
+    }
+}
+
+impl Decodable for BlockHeader {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+	let mut header_bytes = [0; HEADER_SIZE];
+	header_bytes.copy_from_slice(&take_bytes(bytes, HEADER_SIZE)?);
+	Ok(BlockHeader::from_bytes(&header_bytes))
+    }
+}
+
+/// The reasons that a block can be rejected from the chain, one for each consensus rule
+#[derive(Debug, PartialEq)]
+pub enum BlockValidationError {
+    AlreadyKnown, // we have already added this exact block
+    UnknownParent, // the previous block hash does not point to a block we know of (or a second genesis block)
+    BadProofOfWork, // the header hash is above the target given by the difficulty bits
+    BadDifficultyBits, // the difficulty bits are not what the retarget schedule says they should be
+    TimeTooOld, // the time stamp isn't after the median time past of the parent
+    TimeTooNew, // the time stamp is more than MAX_FUTURE_BLOCK_TIME ahead of our clock
+    BadMerkleRoot, // the merkle root in the header doesn't match the transactions
+    BadTransactionCount, // the transaction count doesn't match the number of transactions
+    NoCoinbase, // the first transaction must be a coinbase
+    MultipleCoinbases, // only the first transaction may be a coinbase
+    BadCoinbaseAmount, // the coinbase pays out more than the block reward plus the tips of all other transactions
+    BadBlockWeight, // the block weighs more than MAX_BLOCK_WEIGHT
+    BadBlockSigops, // the transactions check more signatures than MAX_BLOCK_SIGOPS_COST allows
+    BadWitnessCommitment, // the witness commitment in the coinbase doesn't match the witnesses, or the coinbase witness isn't a 32 byte reserved value
+    UnexpectedWitness, // a transaction has a witness, but the coinbase doesn't commit to any
+    InvalidTransaction(TransactionError), // one of the transactions spends an unavailable tx_out, fails its script or overspends
+}
+
+impl From<TransactionError> for BlockValidationError {
+    fn from(error: TransactionError) -> Self {
+	BlockValidationError::InvalidTransaction(error)
     }
 }
 
@@ -109,7 +294,7 @@ impl TransactionList {
     }
 }
 */
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
     pub block_size: u32,
     pub block_header: BlockHeader,
@@ -122,26 +307,65 @@ impl Block {
     /// once we have found a hash that satisfies the difficulty requirment,
     /// we return with self.block_header.nonce set to the appropriate value
     pub fn mine(&mut self)  {
-	let difficulty_target = self.block_header.difficulty_bits.to_u256(); // what we will compare our hashes against
-	let mut nonce: u32 = 0;
+	let difficulty_target = self.block_header.difficulty_bits.to_target().expect("can only mine with valid difficulty bits"); // what we will compare our hashes against
+	self.block_header.nonce = 0;
 	loop {
-	    self.block_header.nonce = Some(nonce);
 	    let struct_hash = self.block_header.hash();
 	    //println!("nonce = {:?}, hash = {:?}, leading_zeros = {:?}", nonce, struct_hash, struct_hash.leading_zeros());
-	    //if struct_hash.is_less_than_or_equal(&difficulty_vector) {
 	    if struct_hash <= difficulty_target {	    
 		// we have found a difficult enough hash value, so we are done
-		println!("Found a valid nonce {:?} for proof of work!", nonce);
-		println!("hash = {:?}", struct_hash);		
+		println!("Found a valid nonce {:?} for proof of work!", self.block_header.nonce);
+		println!("hash = {}", hash_to_hex(&struct_hash));
 		break;
 	    }
-	    nonce += 1;
+	    match self.block_header.nonce.checked_add(1) {
+		Some(nonce) => self.block_header.nonce = nonce,
+		None => {
+		    // we ran out of nonces, so move the time stamp along and start over
+		    self.block_header.time_stamp += 1;
+		    self.block_header.nonce = 0;
+		}
+	    }
 	}
-	//println!("Difficulty target = {:?}", difficulty_target);
-	//println!("Difficulty target bytes = {:?}", difficulty_target.to_fixed_bytes());	
-	
     }
 
+    /// the weight of the whole block: the header and the transaction count weigh 4 per byte like anything else that isn't a witness
+    pub fn weight(&self) -> usize {
+	let mut bytes = self.block_header.to_bytes().to_vec();
+	CompactSize(self.transaction_list.len() as u64).encode(&mut bytes);
+	bytes.len() * WITNESS_SCALE_FACTOR + self.transaction_list.iter().map(|transaction| transaction.weight()).sum::<usize>()
+    }
+
+    /// Where the witness commitment is among the tx_outs of the coinbase, if there is one. If several tx_outs look like one, the last one counts
+    pub fn witness_commitment_index(&self) -> Option<usize> {
+	let coinbase = self.transaction_list.first()?;
+	coinbase.tx_outs.iter().rposition(|tx_out| {
+	    let bytes = tx_out.locking_script.to_bytes();
+	    bytes.len() >= 38 && bytes[0] == 0x6a && bytes[1] == 0x24 && bytes[2..6] == WITNESS_COMMITMENT_HEADER // OP_RETURN <36 bytes>
+	})
+    }
+}
+
+/// a block on the wire is its header followed by the list of transactions
+impl Encodable for Block {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+	self.block_header.encode(bytes);
+	self.transaction_list.encode(bytes);
+    }
+}
+
+impl Decodable for Block {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+	let bytes_before = bytes.len();
+	let block_header = BlockHeader::decode(bytes)?;
+	let transaction_list = Vec::<Transaction>::decode(bytes)?;
+	Ok(Block {
+	    block_size: (bytes_before - bytes.len()) as u32,
+	    block_header,
+	    transaction_count: transaction_list.len() as u32,
+	    transaction_list,
+	})
+    }
 }
 
 
@@ -150,10 +374,13 @@ impl Block {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::convert::TryInto;
+    use crate::encode::{serialize, deserialize};
+    use crate::merkle::get_merkle_root;
     #[test]    
-    fn target_repr_to_u256() {
+    fn target_repr_to_target() {
 	let difficulty_bits = DifficultyBits(0x1903a30c);
-	let difficulty_target = difficulty_bits.to_u256();
+	let difficulty_target = difficulty_bits.to_target().unwrap();
 	let hash_bytes: [u8; 32] = [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xA3, 0x0C, 0x00, 0x00, 0x00, 0x00,
                                  0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
 
@@ -162,4 +389,114 @@ mod tests {
         let answer = Hash::from(&hash_bytes);
 	assert_eq!(difficulty_target, answer);
     }
+
+    /// the compact encoding vectors from bitcoin core's arith_uint256 tests
+    #[test]
+    fn target_repr_core_vectors() {
+	// all of these are zero, whether or not the sign bit is set
+	for bits in [0, 0x00123456, 0x01003456, 0x02000056, 0x03000000, 0x04000000,
+		     0x00923456, 0x01803456, 0x02800056, 0x03800000, 0x04800000] {
+	    assert_eq!(DifficultyBits(bits).to_target(), Err(CompactError::Zero));
+	}
+	assert_eq!(DifficultyBits::from_target(U256::zero()), DifficultyBits(0));
+
+	// (bits, target, canonical bits)
+	let vectors = [
+	    (0x01123456, U256::from(0x12), 0x01120000),
+	    (0x02123456, U256::from(0x1234), 0x02123400),
+	    (0x03123456, U256::from(0x123456), 0x03123456),
+	    (0x04123456, U256::from(0x12345600), 0x04123456),
+	    (0x05009234, U256::from(0x92340000_u64), 0x05009234),
+	    (0x20123456, U256::from(0x123456) << (8 * 29), 0x20123456),
+	];
+	for (bits, target, canonical) in vectors {
+	    assert_eq!(DifficultyBits(bits).to_target(), Ok(target));
+	    assert_eq!(DifficultyBits::from_target(target), DifficultyBits(canonical));
+	}
+
+	assert_eq!(DifficultyBits(0x01fedcba).to_target(), Err(CompactError::Negative));
+	assert_eq!(DifficultyBits(0x04923456).to_target(), Err(CompactError::Negative));
+	assert_eq!(DifficultyBits(0xff123456).to_target(), Err(CompactError::Overflow));
+	// the largest exponent each coefficient size can get away with
+	assert!(DifficultyBits(0x22000001).to_target().is_ok());
+	assert_eq!(DifficultyBits(0x23000001).to_target(), Err(CompactError::Overflow));
+	assert!(DifficultyBits(0x21000100).to_target().is_ok());
+	assert_eq!(DifficultyBits(0x22000100).to_target(), Err(CompactError::Overflow));
+	assert!(DifficultyBits(0x20010000).to_target().is_ok());
+	assert_eq!(DifficultyBits(0x21010000).to_target(), Err(CompactError::Overflow));
+    }
+
+    #[test]
+    fn target_repr_from_target() {
+	for bits in [0x1903a30c, 0x1d00ffff, 0x1f00c3a3, 0x1c05a3f4, 0x207fffff] {
+	    assert_eq!(DifficultyBits::from_target(DifficultyBits(bits).to_target().unwrap()), DifficultyBits(bits));
+	}
+	// 0x80 as the top byte of the coefficient would look like a negative number, so it gets shifted over into the exponent
+	assert_eq!(DifficultyBits::from_target(U256::from(0x80)), DifficultyBits(0x02008000));
+	assert_eq!(DifficultyBits::from_target(U256::MAX), DifficultyBits(0x2100ffff));
+    }
+
+    #[test]
+    fn test_difficulty() {
+	let close = |bits: u32, expected: f64| (DifficultyBits(bits).difficulty() - expected).abs() < 0.00001;
+	// these are the same ones bitcoin core uses for its GetDifficulty tests
+	assert!(close(0x1f111111, 0.000001));
+	assert!(close(0x1ef88f6f, 0.000016));
+	assert!(close(0x1d00ffff, 1.0));
+	// roughly half the target of difficulty 1, and then 256 times smaller again
+	assert!(close(0x1d00800e, 65535.0 / 32782.0));
+	assert!(close(0x1c00800e, 256.0 * 65535.0 / 32782.0));
+	// the genesis block of regtest is about as easy as it gets
+	assert!(DifficultyBits(0x207fffff).difficulty() < 0.000001);
+	// block 100000 of the main network
+	assert!(close(0x1b04864c, 14484.162361225399));
+    }
+
+    /// the genesis block header of the main network, and block 1 which builds on it
+    #[test]
+    fn test_mainnet_headers() {
+	let genesis_bytes = hex::decode("0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c").unwrap();
+	let genesis = BlockHeader::from_bytes(genesis_bytes.as_slice().try_into().unwrap());
+	assert_eq!(genesis.version(), 1);
+	assert!(genesis.previous_block_hash().is_zero());
+	assert_eq!(hash_to_hex(&genesis.merkle_root()), "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b");
+	assert_eq!(genesis.time_stamp(), 1231006505);
+	assert_eq!(genesis.difficulty_bits(), DifficultyBits(0x1d00ffff));
+	assert_eq!(genesis.nonce(), 2083236893);
+	assert_eq!(genesis.to_bytes().to_vec(), genesis_bytes);
+	assert_eq!(hash_to_hex(&genesis.hash()), "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f");
+	assert!(genesis.meets_target());
+
+	let block_1_bytes = hex::decode("010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e857233e0e61bc6649ffff001d01e36299").unwrap();
+	let block_1 = BlockHeader::from_bytes(block_1_bytes.as_slice().try_into().unwrap());
+	assert_eq!(block_1.previous_block_hash(), genesis.hash());
+	assert_eq!(block_1.to_bytes().to_vec(), block_1_bytes);
+	assert_eq!(hash_to_hex(&block_1.hash()), "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048");
+	assert!(block_1.meets_target());
+    }
+
+    /// a mainnet block with two transactions: the merkle root in its header has to match the txids of its transactions
+    #[test]
+    fn test_mainnet_block() {
+	let block_bytes = hex::decode("010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914cd74d6e49ffff001d323b3a7b0201000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0804ffff001d026e04ffffffff0100f2052a0100000043410446ef0102d1ec5240f0d061a4246c1bdef63fc3dbab7733052fbbf0ecd8f41fc26bf049ebb4f9527f374280259e7cfa99c48b0e3f39c51347a19a5819651503a5ac00000000010000000321f75f3139a013f50f315b23b0c9a2b6eac31e2bec98e5891c924664889942260000000049483045022100cb2c6b346a978ab8c61b18b5e9397755cbd17d6eb2fe0083ef32e067fa6c785a02206ce44e613f31d9a6b0517e46f3db1576e9812cc98d159bfdaf759a5014081b5c01ffffffff79cda0945903627c3da1f85fc95d0b8ee3e76ae0cfdc9a65d09744b1f8fc85430000000049483045022047957cdd957cfd0becd642f6b84d82f49b6cb4c51a91f49246908af7c3cfdf4a022100e96b46621f1bffcf5ea5982f88cef651e9354f5791602369bf5a82a6cd61a62501fffffffffe09f5fe3ffbf5ee97a54eb5e5069e9da6b4856ee86fc52938c2f979b0f38e82000000004847304402204165be9a4cbab8049e1af9723b96199bfd3e85f44c6b4c0177e3962686b26073022028f638da23fc003760861ad481ead4099312c60030d4cb57820ce4d33812a5ce01ffffffff01009d966b01000000434104ea1feff861b51fe3f5f8a3b12d0f4712db80e919548a80839fc47c6a21e66d957e9c5d8cd108c7a2d2324bad71f9904ac0ae7336507d785b17a2c115e427a32fac00000000").unwrap();
+	let block: Block = deserialize(&block_bytes).unwrap();
+	assert_eq!(block.transaction_count, 2);
+	assert_eq!(block.block_size as usize, block_bytes.len());
+	assert!(block.transaction_list[0].is_coinbase());
+	assert_eq!(block.transaction_list[1].tx_ins.len(), 3);
+	assert_eq!(get_merkle_root(&block.transaction_list), block.block_header.merkle_root());
+	assert!(block.block_header.meets_target());
+	assert_eq!(serialize(&block), block_bytes);
+    }
+
+    #[test]
+    fn test_work() {
+	// 0x1d00ffff is the minimum difficulty of the main network. It is known to take 2^32 + 2^16 + 1 hashes (0x0100010001) on average
+	assert_eq!(DifficultyBits(0x1d00ffff).work(), U256::from(0x0100010001_u64));
+	// with a target this large, (almost) every hash is a valid one
+	assert_eq!(DifficultyBits(0x2100ffff).work(), U256::one());
+	// invalid bits don't count for anything
+	assert_eq!(DifficultyBits(0x04923456).work(), U256::zero());
+	assert_eq!(DifficultyBits(0).work(), U256::zero());
+    }
 }
@@ -1,41 +1,71 @@
 use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
 use k256::{Secp256k1};
 use ecdsa::{VerifyingKey};
 
-use crate::Hash;
-use crate::script::{Script, StackOp, execute_scripts, hash_160_to_bytes};
-use crate::transaction::{Transaction, TxOut, TxIn, TransactionError};
-use crate::database::{TransactionDataBase};
+use crate::{Hash, hash_to_bytes};
+use crate::script::{Script, StackOp, SCRIPT_VERIFY_CONSENSUS, pay_to_public_key_hash};
+use crate::transaction::{Transaction, TxOut, TxIn, OutPoint, TransactionError};
+use crate::database::{TransactionDataBase, UtxoSet, BlockDataBase, BlockUndo};
 use crate::mempool::{Mempool, TransactionWithTip};
-use crate::block::{Block, DifficultyBits, BlockHeader};
+use crate::block::{Block, DifficultyBits, BlockHeader, BlockValidationError, MAX_BLOCK_WEIGHT, MAX_BLOCK_SIGOPS_COST, witness_commitment, witness_commitment_script};
+use crate::encode::serialize;
+use crate::params::NetworkParams;
+use crate::policy::MempoolPolicy;
 use crate::merkle;
 
 
 const BLOCK_HALVENING: u32 = 210_000; // after this many blocks, the block reward gets cut in half
-const ORIGINAL_COINBASE: u32 = 21_000_000 * 50; // the number of satoshis that get rewarded during the first halvening period (50 Bitcoin))
-const STARTING_DIFFICULTY_BITS: DifficultyBits = DifficultyBits(0x1ec3a30c); // TODO: this is the "real" one --> 0x1d00ffff
+const ORIGINAL_COINBASE: u64 = 21_000_000 * 50; // the number of satoshis that get rewarded during the first halvening period (50 Bitcoin))
+const MEDIAN_TIME_SPAN: usize = 11; // how many blocks the median time past is taken over
+const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60; // how far ahead of our clock the time stamp of a block may be (two hours, like bitcoin core)
+const COINBASE_WEIGHT_RESERVE: usize = 4000; // the weight kept free for the header and the coinbase when filling a block, same as bitcoin core
+const COINBASE_SIGOPS_RESERVE: usize = 400; // and the sigop cost kept free for the coinbase
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BlockChain {
-    pub blocks: Vec<Block>, // TODO: move this to a DB. for now a vec should suffice. (How to handle forks though?)
-    difficulty_bits: DifficultyBits,
-    max_transactions_per_block: usize, // how many transactions can we fit in a block (TODO: actually a function of overall blocksize, not num transactions
+    block_database: BlockDataBase, // every block we know of, including those on side branches. The best tip is the one with the most work
+    params: NetworkParams, // the consensus parameters of the network we are on, e.g. how often the difficulty gets adjusted
+    max_block_weight: usize, // how much weight of transactions we put into a block that we construct, at most MAX_BLOCK_WEIGHT
     mempool: Mempool, // the mempool is a heap of transactions that want to get added to a block (prio given by the tip to the miner)
+    mempool_policy: Option<MempoolPolicy>, // what the mempool takes beyond valid transactions. None takes anything valid
     transaction_database: TransactionDataBase, // keep track of previous transactions in an easier way. helps verify
+    utxo_set: UtxoSet, // which tx_outs are available to be spent by new transactions
+    block_undos: HashMap<Hash, BlockUndo>, // for each block on the active chain, the tx_outs that it spent (so that it can be disconnected)
+}
+
+impl Default for BlockChain {
+    fn default() -> Self {
+	Self::new()
+    }
 }
 
 impl BlockChain {
 
     pub fn new() -> Self {
+	Self::with_params(NetworkParams::default())
+    }
+
+    pub fn with_params(params: NetworkParams) -> Self {
 	Self {
-	    blocks: Vec::new(),
-	    difficulty_bits: STARTING_DIFFICULTY_BITS, //TODO: change over time
-	    max_transactions_per_block: 1000,
+	    block_database: BlockDataBase::new(),
+	    mempool_policy: params.require_standard.then(MempoolPolicy::default),
+	    params,
+	    max_block_weight: MAX_BLOCK_WEIGHT,
 	    mempool: Mempool::new(),
 	    transaction_database: TransactionDataBase::new(),
+	    utxo_set: UtxoSet::new(),
+	    block_undos: HashMap::new(),
 	}
     }
 
+    /// Change what the mempool takes from now on, e.g. to test standardness on regtest, or to switch it off. Transactions that are
+    /// already in the mempool stay there
+    pub fn set_mempool_policy(&mut self, mempool_policy: Option<MempoolPolicy>) {
+	self.mempool_policy = mempool_policy;
+    }
+
     
     /// Is the blockchain empty/there are no blocks yets?
     /// Will mainly be called by the function that spawns the genesis block.
@@ -45,143 +75,513 @@ impl BlockChain {
 	self.len() == 0
     }
 
-    /// return how many block are in the chain, i.e. the height
+    /// return how many block are in the active chain, i.e. the height of the best tip + 1
     fn len(&self) -> u32 {
-	self.blocks.len() as u32
+	match self.block_database.best_tip_entry() {
+	    Some(entry) => entry.height + 1,
+	    None => 0,
+	}
+    }
+
+    /// the hash of the block at the tip of the chain with the most accumulated work
+    pub fn best_tip(&self) -> Option<Hash> {
+	self.block_database.best_tip()
+    }
+
+    /// look up any block we know of, whether it is on the active chain or a side branch
+    pub fn get_block_by_hash(&self, hash: &Hash) -> Option<&Block> {
+	self.block_database.get(hash).map(|entry| &entry.block)
+    }
+
+    /// iterate over the given block and all of its ancestors, going back towards the genesis block
+    pub fn ancestors(&self, hash: Hash) -> impl Iterator<Item = &Block> {
+	self.block_database.ancestors(hash).map(|(_, entry)| &entry.block)
     }
 
     /// if the transaction is valid (the unlocking script unlocks the locking script),
     /// then it is adding to the mempool. else ag
+    /// Every tx_in must refer to an unspent tx_out, which is not already being spent by a transaction waiting in the mempool.
+    /// The tx_out can be in the utxo set, or belong to another transaction that is waiting in the mempool
     pub fn try_add_tx_to_mempool(&mut self, transaction: Transaction) -> Result<(), TransactionError> {
-	let mut tx_in_value_sum = 0; // the total value coming into this transaction from tx_ins
+	transaction.check_not_empty()?;
+	// the policy checks come first, since they are cheap compared to running the scripts
+	if let Some(policy) = &self.mempool_policy {
+	    policy.check_transaction(&transaction).map_err(TransactionError::NonStandard)?;
+	}
+	let mut outpoints_spent: HashSet<OutPoint> = HashSet::new(); // make sure that this transaction doesn't spend the same tx_out twice
+	let mut spent_tx_outs = Vec::new();
 	for tx_in in &transaction.tx_ins {
-	    // each tx_in must be unlocked
-	    if let TxIn::TxPrevious {tx_hash, tx_out_index, unlocking_script, sequence: _  } = tx_in {
-		let transaction_prev_opt = self.transaction_database.get(&tx_hash);
-		if let Some(transaction_prev) = transaction_prev_opt {
-                    // first check if the script actually unlocks it
-		    let tx_out_to_unlock = &transaction_prev.tx_outs[*tx_out_index];                                        
-		    let locking_script = &tx_out_to_unlock.locking_script;
-		    let transaction_prev_hash = transaction_prev.hash_to_bytes();
-		    let is_valid = execute_scripts(&unlocking_script, locking_script, &transaction_prev_hash);
-                    if !is_valid {
-                        return Err(TransactionError::InvalidScript);
-                    }
-                    // we unlocked it, so now and add to the total much we have to spend
-		    tx_in_value_sum += tx_out_to_unlock.value;
-		} else {
-		    return Err(TransactionError::TxInNotFound);
+	    if let Some(outpoint) = tx_in.outpoint() {
+		if !outpoints_spent.insert(outpoint) || self.is_spent_in_mempool(&outpoint) {
+		    return Err(TransactionError::DoubleSpend);
 		}
-		// 
-		
+		let tx_out = match self.utxo_set.check_spendable(&outpoint, self.len(), self.params.coinbase_maturity) {
+		    Ok(tx_out) => tx_out,
+		    Err(error) => self.get_mempool_tx_out(&outpoint).ok_or(error)?,
+		};
+		spent_tx_outs.push(tx_out.clone());
 	    } else {
 		// we can only take as inputs previous outputs. Only a miner may receive a coinbase reward.
 		return Err(TransactionError::CoinbaseSpend);
 	    }
 	}
 
-	// mext check that the tx_out values don't sum to more than the tx_in values
-        let tx_out_value_sum = transaction.tx_outs.iter().fold(0, |sum, tx_out| sum + tx_out.value);
-        if tx_out_value_sum > tx_in_value_sum {
-	    return Err(TransactionError::OverSpend);            
-        }
-	let miner_tip = tx_in_value_sum - tx_out_value_sum; 
-        
-	self.mempool.push(TransactionWithTip::new(transaction, miner_tip));
+	// the transaction has to be allowed in the next block
+	let height = self.len();
+	let median_time_past = self.median_time_past(self.best_tip());
+	if !transaction.is_final(height, median_time_past) {
+	    return Err(TransactionError::NonFinal);
+	}
+	if !self.sequence_locks_satisfied(&transaction, |tx_hash| self.utxo_set.height_of(tx_hash), self.best_tip(), height) {
+	    return Err(TransactionError::SequenceLocked);
+	}
+
+	if let Some(policy) = &self.mempool_policy {
+	    policy.check_tx_ins(&transaction, &spent_tx_outs).map_err(TransactionError::NonStandard)?;
+	}
+
+	// the policy can run the scripts with stricter rules too
+	let flags = self.mempool_policy.as_ref().map_or(SCRIPT_VERIFY_CONSENSUS, |policy| policy.script_flags);
+	let miner_tip = transaction.verify_tx_ins(&spent_tx_outs, flags)?;
+	let sigops_cost = transaction.sigop_cost(&spent_tx_outs);
+	self.mempool.push(TransactionWithTip::new(transaction, miner_tip, sigops_cost));
 	Ok(())
     }
+
+    /// the tx_out referred to by the outpoint, if it belongs to a transaction that is waiting in the mempool
+    fn get_mempool_tx_out(&self, outpoint: &OutPoint) -> Option<&TxOut> {
+	self.mempool.iter()
+	    .find(|tx_with_tip| tx_with_tip.transaction.txid() == outpoint.tx_hash)
+	    .and_then(|tx_with_tip| tx_with_tip.transaction.tx_outs.get(outpoint.tx_out_index as usize))
+    }
+
+    /// is there a transaction waiting in the mempool that already spends this outpoint?
+    fn is_spent_in_mempool(&self, outpoint: &OutPoint) -> bool {
+	self.mempool.iter().any(|tx_with_tip| {
+	    tx_with_tip.transaction.tx_ins.iter().any(|tx_in| tx_in.outpoint().as_ref() == Some(outpoint))
+	})
+    }
+
+    /// The median of the time stamps of the given block and the 10 before it, which is what lock times get measured against
+    /// (a single time stamp could be off by quite a bit). 0 if there is no block
+    fn median_time_past(&self, hash: Option<Hash>) -> u32 {
+	let mut time_stamps: Vec<u32> = match hash {
+	    Some(hash) => self.ancestors(hash).take(MEDIAN_TIME_SPAN).map(|block| block.block_header.time_stamp()).collect(),
+	    None => vec![],
+	};
+	time_stamps.sort_unstable();
+	time_stamps.get(time_stamps.len() / 2).copied().unwrap_or(0)
+    }
+
+    /// Would the relative lock times of the transaction allow it into a block at the given height on top of parent_hash?
+    /// coin_height_of gives the height of the block that a transaction it spends from is in, where None means that it's in the same block
+    fn sequence_locks_satisfied(&self, transaction: &Transaction, coin_height_of: impl Fn(&Hash) -> Option<u32>, parent_hash: Option<Hash>, height: u32) -> bool {
+	let coin_heights: Vec<u32> = transaction.tx_ins.iter()
+	    .filter_map(|tx_in| tx_in.outpoint())
+	    .map(|outpoint| coin_height_of(&outpoint.tx_hash).unwrap_or(height))
+	    .collect();
+	let median_time_past_at = |coin_height: u32| {
+	    // the block at that height on the branch that the transaction is going into
+	    let hash = parent_hash.and_then(|parent_hash| {
+		self.block_database.ancestors(parent_hash).find(|(_, entry)| entry.height == coin_height).map(|(hash, _)| hash)
+	    });
+	    self.median_time_past(hash)
+	};
+	transaction.sequence_locks_satisfied(&coin_heights, height, self.median_time_past(parent_hash), median_time_past_at)
+    }
+
+    /// return the tx_out referred to by the outpoint, if it is currently unspent
+    pub fn get_utxo(&self, outpoint: &OutPoint) -> Option<&TxOut> {
+	self.utxo_set.get_utxo(outpoint)
+    }
+
     
-    fn determine_coinbase_reward(&self) -> u32 {
-	let num_halvenings = self.len() / BLOCK_HALVENING;
-	let coinbase = ORIGINAL_COINBASE / (2 as u32).pow(num_halvenings);
-	coinbase
+    /// the number of new satoshis that the coinbase of the block at the given height may claim
+    fn determine_coinbase_reward(height: u32) -> u64 {
+	let num_halvenings = height / BLOCK_HALVENING;
+	ORIGINAL_COINBASE.checked_shr(num_halvenings).unwrap_or(0) // after 64 halvenings there is nothing left
     }
 
-    fn construct_coinbase_transaction(&self, recipient: VerifyingKey<Secp256k1>, miner_tip: u32) -> Transaction {
+    fn construct_coinbase_transaction(&self, recipient: VerifyingKey<Secp256k1>, miner_tip: u64) -> Transaction {
 	let tx_in = TxIn::Coinbase {
-	    coinbase: self.len(), // the coinbase field is sorta arbitrary, but adding the height here makes sure there won't be duplicate hashes of coinbase transactions,
+	    // the coinbase field is sorta arbitrary, but pushing the height here (like bitcoin does) makes sure there won't be duplicate hashes of coinbase transactions,
+	    coinbase: Script {ops: vec![StackOp::Val(self.len() as i32)]}.to_bytes(),
 	    sequence: 5580,
+	    witness: vec![],
 	};
-	let reward = self.determine_coinbase_reward();
+	let reward = Self::determine_coinbase_reward(self.len());
 
-	// the locking script is the classic pay to public key of recipient.
-        // TODO: make a function that gives us this script from the recipient        
-	let public_key_bytes = recipient.to_encoded_point(true).to_bytes();
-	let pub_hash = hash_160_to_bytes(&public_key_bytes);
-	let locking_script = Script {ops: vec![StackOp::OpDup, StackOp::OpHash160, StackOp::Bytes(pub_hash.into_boxed_slice()), StackOp::OpEqVerify, StackOp::OpCheckSig]};
+	// the locking script is the classic pay to public key hash of recipient, with the 20 byte hash 160 of their compressed public key
+	let locking_script = pay_to_public_key_hash(&recipient.to_encoded_point(true).to_bytes());
         
 	let tx_out = TxOut {
 	    value: reward + miner_tip, // the output is the coinbase reward plus the miner tip from all transactions
-	    locking_script: locking_script,
+	    locking_script,
 	};
 	Transaction {
 	    version: 1,
-	    lock_time: 100,
+	    lock_time: 0, // a coinbase can always go into the block it was made for
 	    tx_ins: vec![tx_in],
 	    tx_outs: vec![tx_out],	    
 	}
     }
         
-    /// given a new block, add it to the blockchain
-    /// TODO: we should validate the block here or no? (yes, since the mined block could/would come from someone else)
-    pub fn add_block(&mut self, block: Block) {
-	self.blocks.push(block);
-	self.transaction_database.read_blocks(&self.blocks);
-        println!("added a block; current len = {:?}", self.len());        
+    /// the difficulty bits that a block on top of the given parent must have (or the genesis block if there is no parent).
+    /// The difficulty stays the same within each retarget interval. At the start of a new interval, it gets adjusted based on
+    /// how long the previous interval took, so that blocks keep coming every target_spacing seconds on average
+    pub fn next_difficulty_bits(&self, parent_hash: Option<Hash>) -> DifficultyBits {
+	let parent = match parent_hash.and_then(|hash| self.block_database.get(&hash)) {
+	    Some(parent) => parent,
+	    None => return self.params.starting_difficulty_bits,
+	};
+	let height = parent.height + 1;
+	let parent_bits = parent.block.block_header.difficulty_bits();
+	if height % self.params.retarget_interval != 0 {
+	    return parent_bits;
+	}
+	// like bitcoin, we measure from the first block of the interval to the last, i.e. over retarget_interval - 1 gaps
+	let first = self.block_database.ancestors(parent_hash.unwrap())
+	    .nth(self.params.retarget_interval as usize - 1)
+	    .map(|(_, entry)| entry)
+	    .expect("the interval is complete, so its first block exists");
+	let last_time_stamp = parent.block.block_header.time_stamp();
+	let first_time_stamp = first.block.block_header.time_stamp();
+	let actual_timespan = u64::from(last_time_stamp.saturating_sub(first_time_stamp));
+	self.params.retarget(parent_bits, actual_timespan)
+    }
+
+    /// check the rules that a block must follow regardless of where it ends up in the tree:
+    /// the difficulty bits, the time stamp, the proof of work, the merkle root, the transaction count,
+    /// that the first and only the first transaction is a coinbase, and that none of them are empty.
+    /// The time stamp has to be after the median time past of the parent, and can't be too far ahead of our own clock.
+    /// The rules that depend on the utxo set (the scripts, tx_out values and the coinbase amount) are checked once the block
+    /// gets connected to the active chain, see validate_transactions()
+    pub fn validate_block(&self, block: &Block) -> Result<(), BlockValidationError> {
+	let previous_block_hash = block.block_header.previous_block_hash();
+	let parent_hash = if previous_block_hash.is_zero() {
+	    None
+	} else if self.block_database.contains(&previous_block_hash) {
+	    Some(previous_block_hash)
+	} else {
+	    return Err(BlockValidationError::UnknownParent);
+	};
+	if block.block_header.difficulty_bits() != self.next_difficulty_bits(parent_hash) {
+	    return Err(BlockValidationError::BadDifficultyBits);
+	}
+	if block.block_header.time_stamp() <= self.median_time_past(parent_hash) {
+	    return Err(BlockValidationError::TimeTooOld);
+	}
+	let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs(); // now is after unix_epoch so we can unwrap
+	if u64::from(block.block_header.time_stamp()) > now + u64::from(MAX_FUTURE_BLOCK_TIME) {
+	    return Err(BlockValidationError::TimeTooNew);
+	}
+	if !block.block_header.meets_target() {
+	    return Err(BlockValidationError::BadProofOfWork);
+	}
+	if block.transaction_count as usize != block.transaction_list.len() {
+	    return Err(BlockValidationError::BadTransactionCount);
+	}
+	match block.transaction_list.first() {
+	    Some(transaction) if transaction.is_coinbase() => (),
+	    _ => return Err(BlockValidationError::NoCoinbase),
+	}
+	let is_coinbase_tx_in = |tx_in: &TxIn| matches!(tx_in, TxIn::Coinbase {..});
+	if block.transaction_list.iter().skip(1).any(|transaction| transaction.tx_ins.iter().any(is_coinbase_tx_in)) {
+	    return Err(BlockValidationError::MultipleCoinbases);
+	}
+	for transaction in &block.transaction_list {
+	    transaction.check_not_empty()?;
+	}
+	if block.block_header.merkle_root() != merkle::get_merkle_root(&block.transaction_list) {
+	    return Err(BlockValidationError::BadMerkleRoot);
+	}
+	if block.weight() > MAX_BLOCK_WEIGHT {
+	    return Err(BlockValidationError::BadBlockWeight);
+	}
+	Self::check_witness_commitment(block)
+    }
+
+    /// The merkle root in the header only covers the txids, so the witnesses are committed to separately (BIP141), in a tx_out of the coinbase.
+    /// If there is a witness commitment, it has to match the witness merkle root, with the coinbase's witness as the reserved value.
+    /// Without one, none of the transactions may have witnesses, since nothing would stop them from being changed.
+    /// The block must already have a coinbase
+    fn check_witness_commitment(block: &Block) -> Result<(), BlockValidationError> {
+	let coinbase = &block.transaction_list[0];
+	let index = match block.witness_commitment_index() {
+	    Some(index) => index,
+	    None => {
+		if block.transaction_list.iter().any(|transaction| transaction.has_witness()) {
+		    return Err(BlockValidationError::UnexpectedWitness);
+		}
+		return Ok(());
+	    },
+	};
+	let reserved_value = match coinbase.tx_ins[0].witness() {
+	    [reserved_value] if reserved_value.len() == 32 => reserved_value,
+	    _ => return Err(BlockValidationError::BadWitnessCommitment),
+	};
+	let commitment = witness_commitment(&merkle::get_witness_merkle_root(&block.transaction_list), reserved_value);
+	// the commitment comes after OP_RETURN, the push and the 4 header bytes
+	if coinbase.tx_outs[index].locking_script.to_bytes()[6..38] != hash_to_bytes(&commitment) {
+	    return Err(BlockValidationError::BadWitnessCommitment);
+	}
+	Ok(())
+    }
+
+    /// check the rules of a block that depend on the utxo set, given the undo data from applying the block,
+    /// which holds exactly the tx_outs that each transaction spends.
+    /// Every transaction must be final, with its relative lock times passed.
+    /// The block can't have too many signatures to check, every unlocking script must unlock what it spends, no transaction may overspend,
+    /// and the coinbase may only pay out the block reward plus the tips from all the other transactions
+    fn validate_transactions(&self, block: &Block, undo: &BlockUndo, parent_hash: Option<Hash>, height: u32) -> Result<(), BlockValidationError> {
+	// lock times are measured against the median time past of the parent, rather than the block's own time stamp
+	let median_time_past = self.median_time_past(parent_hash);
+	if !block.transaction_list.iter().all(|transaction| transaction.is_final(height, median_time_past)) {
+	    return Err(BlockValidationError::InvalidTransaction(TransactionError::NonFinal));
+	}
+	// this is counted before any of the scripts get run, so a block with too many signatures costs next to nothing to turn away
+	let sigops_cost: usize = block.transaction_list.iter().zip(&undo.spent_tx_outs)
+	    .map(|(transaction, spent_tx_outs)| transaction.sigop_cost(spent_tx_outs)).sum();
+	if sigops_cost > MAX_BLOCK_SIGOPS_COST {
+	    return Err(BlockValidationError::BadBlockSigops);
+	}
+	let mut total_tip = 0;
+	for (transaction, spent_tx_outs) in block.transaction_list.iter().zip(&undo.spent_tx_outs).skip(1) {
+	    // the block has been applied already, so the heights of what it spent come from the undo data
+	    if !self.sequence_locks_satisfied(transaction, |tx_hash| undo.height_of(tx_hash), parent_hash, height) {
+		return Err(BlockValidationError::InvalidTransaction(TransactionError::SequenceLocked));
+	    }
+	    total_tip += transaction.verify_tx_ins(spent_tx_outs, SCRIPT_VERIFY_CONSENSUS)?;
+	}
+	let coinbase_value = block.transaction_list[0].tx_out_value_sum()?;
+	if coinbase_value > Self::determine_coinbase_reward(height).saturating_add(total_tip) {
+	    return Err(BlockValidationError::BadCoinbaseAmount);
+	}
+	Ok(())
+    }
+
+    /// given a new block, add it to the block tree below its parent.
+    /// If the block gives its branch more accumulated work than the current best tip, that branch becomes the active chain:
+    /// The utxo set is updated with the block's transactions, and if any of them are invalid, the block is rejected.
+    /// Any transactions in the mempool which conflict with the new block are dropped.
+    /// A block on a side branch with less (or equal) work is simply stored, in case its branch overtakes the active chain later.
+    pub fn add_block(&mut self, block: Block) -> Result<(), BlockValidationError> {
+	self.validate_block(&block)?;
+	let hash = self.block_database.insert(block)?;
+	let entry = self.block_database.get(&hash).unwrap(); // we just inserted it
+	if let Some(best_entry) = self.block_database.best_tip_entry() {
+	    if entry.chain_work <= best_entry.chain_work {
+		return Ok(());
+	    }
+	}
+
+	if entry.parent_hash == self.best_tip() {
+	    // the usual case: the block extends the active chain
+	    if let Err(e) = self.connect_block(hash) {
+		self.block_database.remove(&hash);
+		return Err(e);
+	    }
+	    self.remove_mempool_conflicts();
+	} else {
+	    // a side branch now has the most work
+	    self.reorganize(hash)?;
+	}
+	Ok(())
+    }
+
+    /// apply a block, whose parent must be the current best tip, to the utxo set and make it the new best tip.
+    /// If the block's transactions turn out to be invalid, the utxo set is left untouched.
+    /// The undo data is kept around in case we need to disconnect the block later
+    fn connect_block(&mut self, hash: Hash) -> Result<(), BlockValidationError> {
+	let entry = self.block_database.get(&hash).expect("can only connect a block we know of");
+	assert_eq!(entry.parent_hash, self.best_tip(), "a block must be connected on top of the best tip");
+	let undo = self.utxo_set.apply_block(&entry.block, entry.height, self.params.coinbase_maturity)?;
+	if let Err(e) = self.validate_transactions(&entry.block, &undo, entry.parent_hash, entry.height) {
+	    self.utxo_set.undo_block(&entry.block, &undo);
+	    return Err(e);
+	}
+	self.transaction_database.read_block(&entry.block);
+	self.block_undos.insert(hash, undo);
+	self.block_database.set_best_tip(Some(hash));
+	Ok(())
+    }
+
+    /// roll the best tip back out of the utxo set, making its parent the new best tip.
+    /// The block itself stays in the block tree (on what is now a side branch).
+    /// Returns the transactions of the disconnected block, minus the coinbase
+    fn disconnect_block(&mut self) -> Vec<Transaction> {
+	let hash = self.best_tip().expect("there must be a block to disconnect");
+	let entry = self.block_database.get(&hash).unwrap(); // the best tip is always in the database
+	let undo = self.block_undos.remove(&hash).expect("every connected block has undo data");
+	self.utxo_set.undo_block(&entry.block, &undo);
+	self.transaction_database.unread_block(&entry.block);
+	let parent_hash = entry.parent_hash;
+	let transactions = entry.block.transaction_list.iter().skip(1).cloned().collect();
+	self.block_database.set_best_tip(parent_hash);
+	transactions
+    }
+
+    /// switch the active chain over to the branch ending at new_tip, which has more work than the current best tip.
+    /// We disconnect blocks from the current tip back to the fork point, and then connect the blocks of the new branch.
+    /// If a block on the new branch turns out to be invalid, we go back to the old branch and forget the invalid block,
+    /// along with everything built on top of it (not just the rest of the new branch, but any other branches off of it too).
+    /// Transactions from the disconnected blocks go back into the mempool if they are still valid on the new branch.
+    /// They go back in chain order, so one that spends from another disconnected transaction finds it in the mempool
+    fn reorganize(&mut self, new_tip: Hash) -> Result<(), BlockValidationError> {
+	let old_branch: HashSet<Hash> = match self.best_tip() {
+	    Some(old_tip) => self.block_database.ancestors(old_tip).map(|(hash, _)| hash).collect(),
+	    None => HashSet::new(),
+	};
+	let mut new_branch: Vec<Hash> = self.block_database.ancestors(new_tip)
+	    .map(|(hash, _)| hash)
+	    .take_while(|hash| !old_branch.contains(hash))
+	    .collect();
+	new_branch.reverse(); // connect from the fork point upwards
+	let fork_point = self.block_database.get(&new_branch[0]).unwrap().parent_hash;
+
+	let mut disconnected_hashes = Vec::new();
+	let mut disconnected_transactions = Vec::new();
+	while self.best_tip() != fork_point {
+	    disconnected_hashes.push(self.best_tip().unwrap());
+	    // keep the transactions in chain order, i.e. those of lower blocks first
+	    let mut transactions = self.disconnect_block();
+	    transactions.append(&mut disconnected_transactions);
+	    disconnected_transactions = transactions;
+	}
+
+	for (num_connected, hash) in new_branch.iter().enumerate() {
+	    if let Err(e) = self.connect_block(*hash) {
+		for _ in 0..num_connected {
+		    self.disconnect_block();
+		}
+		for hash in disconnected_hashes.iter().rev() {
+		    self.connect_block(*hash).expect("the old branch was valid before");
+		}
+		self.block_database.remove_with_descendants(hash);
+		return Err(e);
+	    }
+	}
+
+	for transaction in disconnected_transactions {
+	    // transactions that made it into the new branch, or that conflict with it, will be rejected here and just dropped
+	    let _ = self.try_add_tx_to_mempool(transaction);
+	}
+	// only now, since a transaction that was already in the mempool may spend from one that just came back
+	self.remove_mempool_conflicts();
+	Ok(())
+    }
+
+    /// remove any transactions from the mempool that spend a tx_out that is no longer unspent,
+    /// e.g. because a block from another miner already included a conflicting transaction,
+    /// or a coinbase that isn't mature any more, because a reorg moved it up.
+    /// That takes any transactions that spend from the removed ones with them, and then any that spend from those, and so on
+    fn remove_mempool_conflicts(&mut self) {
+	let height = self.len();
+	loop {
+	    let mempool_len = self.mempool.len();
+	    let mempool_hashes: HashSet<Hash> = self.mempool.iter().map(|tx_with_tip| tx_with_tip.transaction.txid()).collect();
+	    let utxo_set = &self.utxo_set;
+	    let coinbase_maturity = self.params.coinbase_maturity;
+	    self.mempool.retain(|tx_with_tip| {
+		tx_with_tip.transaction.tx_ins.iter().filter_map(|tx_in| tx_in.outpoint()).all(|outpoint| {
+		    utxo_set.check_spendable(&outpoint, height, coinbase_maturity).is_ok() || mempool_hashes.contains(&outpoint.tx_hash)
+		})
+	    });
+	    if self.mempool.len() == mempool_len {
+		break;
+	    }
+	}
     }
 
     /// given the recipient of the coinbase transaction, we construct and return a list of transactions to include in the
     /// next candidate block.
     /// The coinbase transaction is always the first in the list.
+    /// Transactions go in by the tip they pay per weight, for as long as they fit in max_block_weight and MAX_BLOCK_SIGOPS_COST.
+    /// One that doesn't fit goes back into the mempool for a later block, but a smaller one after it might still fit.
+    /// A transaction that spends from another one in the mempool has to wait until that one is in the block.
+    /// If any of the transactions have witnesses, the coinbase commits to them
     fn construct_transaction_list(&mut self, recipient: VerifyingKey<Secp256k1>) -> Vec<Transaction> {
 	let mut transaction_list = vec![];
         let mut total_tip = 0;
 	if !self.is_empty() {
 	    // if is_empty()< 1 (i.e. this is the genesis block), then do not go to the mempool
-	    while self.mempool.len() > 0 && transaction_list.len() < self.max_transactions_per_block{
-		let TransactionWithTip{miner_tip, transaction} = self.mempool.pop().unwrap(); // we already checked for len > 0, so can unwrap
+	    let mut block_weight = COINBASE_WEIGHT_RESERVE;
+	    let mut block_sigops_cost = COINBASE_SIGOPS_RESERVE;
+	    let mut too_heavy = vec![];
+	    let mut waiting_for_parent = vec![];
+	    let mut included: HashSet<Hash> = HashSet::new();
+	    while let Some(tx_with_tip) = self.mempool.pop() {
+		// whatever isn't in the utxo set comes from the mempool
+		let utxo_set = &self.utxo_set;
+		let parents_included = tx_with_tip.transaction.tx_ins.iter().filter_map(|tx_in| tx_in.outpoint())
+		    .all(|outpoint| utxo_set.height_of(&outpoint.tx_hash).is_some() || included.contains(&outpoint.tx_hash));
+		if !parents_included {
+		    waiting_for_parent.push(tx_with_tip);
+		    continue;
+		}
+		if block_weight + tx_with_tip.weight > self.max_block_weight || block_sigops_cost + tx_with_tip.sigops_cost > MAX_BLOCK_SIGOPS_COST {
+		    too_heavy.push(tx_with_tip);
+		    continue;
+		}
+		let TransactionWithTip{miner_tip, weight, sigops_cost, transaction} = tx_with_tip;
+		block_weight += weight;
+		block_sigops_cost += sigops_cost;
                 total_tip += miner_tip;
+		included.insert(transaction.txid());
 		transaction_list.push(transaction);
+		// this might have been the parent that they were waiting for
+		self.mempool.extend(waiting_for_parent.drain(..));
 	    }
+	    self.mempool.extend(too_heavy);
+	    self.mempool.extend(waiting_for_parent);
 	}
 	let coinbase_transaction = self.construct_coinbase_transaction(recipient, total_tip);
 
         // I guess it would be more efficient to start with the coinbase transaction in the vec, and then add the tip to modify the transaction in place,
         // but that seems less readable, so for now I am choosing to simply do one expensive insert at 0
         transaction_list.insert(0, coinbase_transaction);
+	if transaction_list.iter().any(|transaction| transaction.has_witness()) {
+	    // the coinbase's own wtxid doesn't go into the witness merkle root, so it can be changed after the fact
+	    let reserved_value = vec![0; 32];
+	    let commitment = witness_commitment(&merkle::get_witness_merkle_root(&transaction_list), &reserved_value);
+	    let coinbase = &mut transaction_list[0];
+	    *coinbase.tx_ins[0].witness_mut() = vec![reserved_value];
+	    coinbase.tx_outs.push(TxOut {value: 0, locking_script: witness_commitment_script(&commitment)});
+	}
 	transaction_list
     }
 
     /// get the hash of the block header of the previous block in the chain
     /// if the blockchain is empty, i.e. we are spawning the genesis block, then the previous hash is simply 0
     fn get_previous_block_hash(&self) -> Hash {
-	if self.is_empty() {
-	    Hash::zero()
-	} else {
-	    let previous_block = self.blocks.last().unwrap();
-	    previous_block.block_header.hash()
-	}
+	self.best_tip().unwrap_or_else(Hash::zero)
     }
 
     /// given the recipient of the coinbase reward, this method constructs a list of transactions from the mempool and returns a Block
-    /// with the nonce value of the header initialized to None and pointing at the most recent block in the chain.
+    /// with the nonce value of the header initialized to 0 and pointing at the most recent block in the chain.
     /// The block can now be mined but adjusting the nonce and hashing
     pub fn construct_candidate_block(&mut self, recipient: VerifyingKey<Secp256k1>) -> Block {
 	let transaction_list = self.construct_transaction_list(recipient);
 	let previous_block_hash = self.get_previous_block_hash();
-	let block_header = BlockHeader::new(
+	let mut block_header = BlockHeader::new(
 	    1,
 	    previous_block_hash,
 	    merkle::get_merkle_root(&transaction_list),
-	    self.difficulty_bits
+	    self.next_difficulty_bits(self.best_tip())
 	);
+	// even if our clock is behind (or blocks come in faster than a second), the time stamp has to be after the median time past
+	let time_stamp = block_header.time_stamp().max(self.median_time_past(self.best_tip()) + 1);
+	block_header.set_time_stamp(time_stamp);
 	
-	Block {
-	    block_size: 100, // TODO: how is this measured?
-	    block_header: block_header,
+	let mut block = Block {
+	    block_size: 0,
+	    block_header,
 	    transaction_count: transaction_list.len() as u32,
-	    transaction_list: transaction_list,
-	}
+	    transaction_list,
+	};
+	// the size of the block as it goes over the wire, witnesses and all
+	block.block_size = serialize(&block).len() as u32;
+	block
     }
 
     pub fn print_transactions(&self) {
@@ -194,34 +594,55 @@ impl BlockChain {
 mod tests {
     use super::*;
     use ecdsa::{SigningKey};
-    use ecdsa::signature::{Signer, Signature}; // trait in scope for signing a message
+    use ecdsa::Signature;
+    use crate::sighash::{sign_tx_in, sign_segwit_tx_in, sign_taproot_tx_in, signature_from_der, signature_to_der, SIGHASH_ALL, SIGHASH_DEFAULT};
+    use crate::taproot::{x_only_public_key, tweak_private_key};
+    use crate::block::HEADER_SIZE;
+    use crate::script::{ScriptError, MAX_OPS_PER_SCRIPT, hash_160_to_bytes, pay_to_multisig, pay_to_script_hash, pay_to_witness_public_key_hash, pay_to_witness_script_hash, pay_to_taproot};
+    use crate::transaction::{SEQUENCE_FINAL, MAX_MONEY};
+    use crate::policy::PolicyError;
     
     #[test]
     fn run_basic_blocks() {
 	// a couple blocks here with only the coinbase transaction
-	let mut chain = BlockChain::new();
+	let mut chain = BlockChain::with_params(test_params());
 	let num_blocks = 2;
 	let b = "adamadamadamadamadamadamadamadam".as_bytes(); // arbitrary for testing. 32 long
-	let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(&b).unwrap();
+	let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(b).unwrap();
 	let public_key: VerifyingKey<Secp256k1> = private_key.verifying_key();    	
 	for _ in 0..num_blocks {
 	    let mut block = chain.construct_candidate_block(public_key);
 	    block.mine();
 	    println!("about to add block: {:?}", block);
-	    chain.add_block(block);
+	    chain.add_block(block).unwrap();
 	}
 	
         assert_eq!(chain.len(), num_blocks);	
     }
 
+    #[test]
+    fn coinbase_pays_to_public_key_hash() {
+	let chain = BlockChain::with_params(test_params());
+	let b = "adamadamadamadamadamadamadamadam".as_bytes(); // arbitrary for testing. 32 long
+	let public_key = SigningKey::<Secp256k1>::from_bytes(b).unwrap().verifying_key();
+	let coinbase = chain.construct_coinbase_transaction(public_key, 0);
+	// the output is locked to the 20 byte hash 160 of the compressed public key, like a real P2PKH output
+	let pub_hash = hash_160_to_bytes(&public_key.to_encoded_point(true).to_bytes());
+	assert_eq!(pub_hash.len(), 20);
+	let asm = format!("OP_DUP OP_HASH160 {} OP_EQUALVERIFY OP_CHECKSIG", hex::encode(&pub_hash));
+	assert_eq!(coinbase.tx_outs[0].locking_script.to_string(), asm);
+	assert_eq!(coinbase.tx_outs[0].locking_script.to_bytes().len(), 25);
+    }
+
     /// we attempt to add a transaction to the mempool that include a coinbase as a tx_in;
     /// this is invalid, since only the miner gets to construct a coinbase transaction
     #[test]    
     fn add_to_mempool_invalid_coinbase() {
-	let mut chain = BlockChain::new();
+	let mut chain = BlockChain::with_params(test_params());
 	let tx_in = TxIn::Coinbase {
-	    coinbase: 33,
+	    coinbase: vec![33],
 	    sequence: 5580,
+	    witness: vec![],
 	};
 	let tx_out = TxOut {
 	    value: 22,
@@ -229,7 +650,7 @@ mod tests {
 	};
 	let transaction = Transaction {
 	    version: 1,
-	    lock_time: 5,
+	    lock_time: 0,
 	    tx_ins: vec![tx_in],
 	    tx_outs: vec![tx_out],		
 	};
@@ -242,7 +663,7 @@ mod tests {
     /// we attempt to add a transaction to the mempool that include a reference to a tx that does not exist    
     #[test]
     fn add_to_mempool_invalid_missing_tx() {
-	let mut chain = BlockChain::new();
+	let mut chain = BlockChain::with_params(test_params());
 
 	let transaction_hash = Hash::zero(); // this tx will not exist in the blockchain db
 	
@@ -251,6 +672,7 @@ mod tests {
 	    tx_out_index: 0,
 	    unlocking_script: Script{ops: vec![StackOp::OpDup]}, // arbitrary for this test
 	    sequence: 1234,
+	    witness: vec![],
 	};
 	// the tx_out is arbitrary
 	let tx_out = TxOut {
@@ -259,7 +681,7 @@ mod tests {
 	};
 	let transaction = Transaction {
 	    version: 1,
-	    lock_time: 5,
+	    lock_time: 0,
 	    tx_ins: vec![tx_in],
 	    tx_outs: vec![tx_out],		
 	};
@@ -272,53 +694,50 @@ mod tests {
     fn add_to_mempool_invalid_overpsend() {
 	// we attempt to add a transaction to the mempool that wants to spend as tx outputs more than the tx ins
 	// first we must mine an empty block to have a tx_out available to theoretically spend
-	let mut chain = BlockChain::new();
+	let mut chain = BlockChain::with_params(test_params());
 	let b = "adamadamadamadamadamadamadamadam".as_bytes(); // arbitrary for testing. 32 long
-	let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(&b).unwrap();
+	let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(b).unwrap();
 	let public_key: VerifyingKey<Secp256k1> = private_key.verifying_key();
-	let public_key_bytes = public_key.to_encoded_point(true).to_bytes();	
 	let mut block = chain.construct_candidate_block(public_key);
 	block.mine();
-	chain.add_block(block);
+	chain.add_block(block).unwrap();
 	println!("{:?}", chain.transaction_database);
 
 	// Note: this particular coinbase transaction has this hash.
 	// A wallet would need to look it up by recipient public key or something like that
-	// decimal: 38321321692519566122529587483305535719886798403229990577862410369545149044829
-	// hex: 54B919753E5FC47F98A0574A2F5D5679726EAB8349DD943622C2DE14A497585D
-	//let hi: u128 = 0x54_B9_19_75_3E_5F_C4_7F_98_A0_57_4A_2F_5D_56_79;
-	//let low: u128 = 0x72_6E_AB_83_49_DD_94_36_22_C2_DE_14_A4_97_58_5D;
-        let hash_bytes: [u8; 32] = [0x54, 0xB9, 0x19, 0x75, 0x3E, 0x5F, 0xC4, 0x7F, 0x98, 0xA0, 0x57, 0x4A, 0x2F, 0x5D, 0x56, 0x79,
-                                    0x72, 0x6E, 0xAB, 0x83, 0x49, 0xDD, 0x94, 0x36, 0x22, 0xC2, 0xDE, 0x14, 0xA4, 0x97, 0x58, 0x5D];
+	// decimal: 55059100040282840353057595965762844118032809861884871990182642367721085270203
+	// hex: 79BA58A535125FD5017464FCED48D92CA7A3A6869B245CCE218FAC390D4EF8BB
+	let hash_bytes: [u8; 32] = [0x79, 0xBA, 0x58, 0xA5, 0x35, 0x12, 0x5F, 0xD5, 0x01, 0x74, 0x64, 0xFC, 0xED, 0x48, 0xD9, 0x2C,
+				    0xA7, 0xA3, 0xA6, 0x86, 0x9B, 0x24, 0x5C, 0xCE, 0x21, 0x8F, 0xAC, 0x39, 0x0D, 0x4E, 0xF8, 0xBB];
 	let transaction_hash = Hash::from(&hash_bytes);
-	//let tx_hash_bytes = transaction_hash.to_be_bytes();
-	
-	let sig = private_key.try_sign(&hash_bytes).expect("should be able to sign the transaction hash here");
-	let sig_as_bytes = sig.as_bytes().to_vec(); //TODO: is there a way to go right from &[u8] --> Box instead of through vec?
-	let unlocking_script = Script {ops: vec![StackOp::Bytes(sig_as_bytes.into_boxed_slice()), StackOp::Bytes(public_key_bytes)]};
 
-	
 	let tx_in = TxIn::TxPrevious {
 	    tx_hash: transaction_hash, // Hash of the transaction that we are getting this input from
 	    tx_out_index: 0,// The index of the tx_out within the transaction (only one for the first block just the rewward to the miner)
-	    unlocking_script: unlocking_script, 
+	    unlocking_script: Script {ops: vec![]}, // filled in once the rest of the transaction is there to sign
 	    sequence: 1234,
+	    witness: vec![],
 	};
 
 	let tx_out = TxOut {
 	    value: 1050000000 + 1, // 1 more than allowed
 	    locking_script: Script {ops: vec![StackOp::OpDup]},	
 	};
-	let transaction = Transaction {
+	let mut transaction = Transaction {
 	    version: 1,
-	    lock_time: 5,
+	    lock_time: 0,
 	    tx_ins: vec![tx_in],
 	    tx_outs: vec![tx_out],		
 	};
+	sign_p2pkh_tx_in(&private_key, &mut transaction, 0);
 
-	
-	let result = chain.try_add_tx_to_mempool(transaction);
+	let result = chain.try_add_tx_to_mempool(transaction.clone());
         assert_eq!(result, Err(TransactionError::OverSpend));                
+
+	// tx_outs that add up to more than a u64 can hold are turned away too, rather than wrapping around to something small
+	transaction.tx_outs = vec![TxOut {value: u64::MAX, locking_script: Script {ops: vec![StackOp::OpDup]}}; 2];
+	sign_p2pkh_tx_in(&private_key, &mut transaction, 0);
+	assert_eq!(chain.try_add_tx_to_mempool(transaction), Err(TransactionError::TxOutValueTooLarge));
     }
 
     /// first we must mine an empty block to have a tx_out available to spend spend
@@ -326,49 +745,43 @@ mod tests {
     /// We also check that the next candidate block will take the transaction from the mempool
     #[test]
     fn add_to_mempool_valid_spend() {
-	let mut chain = BlockChain::new();
+	let mut chain = BlockChain::with_params(test_params());
 	let b = "adamadamadamadamadamadamadamadam".as_bytes(); // arbitrary for testing. 32 long
-	let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(&b).unwrap();
+	let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(b).unwrap();
 	let public_key: VerifyingKey<Secp256k1> = private_key.verifying_key();
-	let public_key_bytes = public_key.to_encoded_point(true).to_bytes();	
 	let mut block = chain.construct_candidate_block(public_key);
 	block.mine();
-	chain.add_block(block);
+	chain.add_block(block).unwrap();
 	println!("{:?}", chain.transaction_database);
 
 	// Note: this particular coinbase transaction has this hash.
 	// A wallet would need to look it up by recipient public key or something like that
-	// decimal: 38321321692519566122529587483305535719886798403229990577862410369545149044829
-	// hex: 54B919753E5FC47F98A0574A2F5D5679726EAB8349DD943622C2DE14A497585D
-        let hash_bytes: [u8; 32] = [0x54, 0xB9, 0x19, 0x75, 0x3E, 0x5F, 0xC4, 0x7F, 0x98, 0xA0, 0x57, 0x4A, 0x2F, 0x5D, 0x56, 0x79,
-                                    0x72, 0x6E, 0xAB, 0x83, 0x49, 0xDD, 0x94, 0x36, 0x22, 0xC2, 0xDE, 0x14, 0xA4, 0x97, 0x58, 0x5D];
+	// decimal: 55059100040282840353057595965762844118032809861884871990182642367721085270203
+	// hex: 79BA58A535125FD5017464FCED48D92CA7A3A6869B245CCE218FAC390D4EF8BB
+	let hash_bytes: [u8; 32] = [0x79, 0xBA, 0x58, 0xA5, 0x35, 0x12, 0x5F, 0xD5, 0x01, 0x74, 0x64, 0xFC, 0xED, 0x48, 0xD9, 0x2C,
+				    0xA7, 0xA3, 0xA6, 0x86, 0x9B, 0x24, 0x5C, 0xCE, 0x21, 0x8F, 0xAC, 0x39, 0x0D, 0x4E, 0xF8, 0xBB];
 	let transaction_hash = Hash::from(&hash_bytes);
-	//let tx_hash_bytes = transaction_hash.to_be_bytes();
-	
-	let sig = private_key.try_sign(&hash_bytes).expect("should be able to sign the transaction hash here");
-	let sig_as_bytes = sig.as_bytes().to_vec(); //TODO: is there a way to go right from &[u8] --> Box instead of through vec?
-	let unlocking_script = Script {ops: vec![StackOp::Bytes(sig_as_bytes.into_boxed_slice()), StackOp::Bytes(public_key_bytes)]};
 
-	
 	let tx_in = TxIn::TxPrevious {
 	    tx_hash: transaction_hash, // Hash of the transaction that we are getting this input from
 	    tx_out_index: 0,// The index of the tx_out within the transaction (only one for the first block just the rewward to the miner)
-	    unlocking_script: unlocking_script, 
+	    unlocking_script: Script {ops: vec![]}, // filled in once the rest of the transaction is there to sign
 	    sequence: 1234,
+	    witness: vec![],
 	};
 
 	let tx_out = TxOut {
 	    value: 1050000000 - 1, // 1 less than allowed (so tipping 1 Eve to the miner)
 	    locking_script: Script {ops: vec![StackOp::OpDup]},	
 	};
-	let transaction = Transaction {
+	let mut transaction = Transaction {
 	    version: 1,
-	    lock_time: 5,
+	    lock_time: 0,
 	    tx_ins: vec![tx_in],
 	    tx_outs: vec![tx_out],		
 	};
+	sign_p2pkh_tx_in(&private_key, &mut transaction, 0);
 
-	
 	let result = chain.try_add_tx_to_mempool(transaction);
 	let expected = Ok(());
         assert_eq!(result, expected);
@@ -388,4 +801,1026 @@ mod tests {
 	
     }
     
+
+    /// sign the given tx_in of the transaction, which spends a tx_out locked to the public key of the given private key,
+    /// and put the signature and public key into its unlocking script
+    fn sign_p2pkh_tx_in(private_key: &SigningKey<Secp256k1>, transaction: &mut Transaction, input_index: usize) {
+	let public_key_bytes = private_key.verifying_key().to_encoded_point(true).to_bytes();
+	let locking_script = pay_to_public_key_hash(&public_key_bytes);
+	let sig_as_bytes = sign_tx_in(private_key, transaction, input_index, &locking_script, SIGHASH_ALL);
+	if let TxIn::TxPrevious {unlocking_script, ..} = &mut transaction.tx_ins[input_index] {
+	    *unlocking_script = Script {ops: vec![StackOp::Bytes(sig_as_bytes.into_boxed_slice()), StackOp::Bytes(public_key_bytes)]};
+	}
+    }
+
+    /// build a transaction that spends the given tx_out, which must be locked to the public key of the given private key,
+    /// and sends value to an arbitrary locking script
+    fn spend_transaction(private_key: &SigningKey<Secp256k1>, tx_hash: Hash, tx_out_index: u32, value: u64) -> Transaction {
+	let tx_in = TxIn::TxPrevious {
+	    tx_hash,
+	    tx_out_index,
+	    unlocking_script: Script {ops: vec![]},
+	    sequence: 1234,
+	    witness: vec![],
+	};
+	let tx_out = TxOut {
+	    value,
+	    locking_script: Script {ops: vec![StackOp::OpDup]},
+	};
+	let mut transaction = Transaction {
+	    version: 1,
+	    lock_time: 0,
+	    tx_ins: vec![tx_in],
+	    tx_outs: vec![tx_out],
+	};
+	sign_p2pkh_tx_in(private_key, &mut transaction, 0);
+	transaction
+    }
+
+    /// set up a chain with a single mined block, and return the hash of its coinbase transaction (which pays out to private_key)
+    fn chain_with_one_block(private_key: &SigningKey<Secp256k1>) -> (BlockChain, Hash) {
+	let mut chain = BlockChain::with_params(test_params());
+	let mut block = chain.construct_candidate_block(private_key.verifying_key());
+	block.mine();
+	let coinbase_hash = block.transaction_list[0].txid();
+	chain.add_block(block).unwrap();
+	(chain, coinbase_hash)
+    }
+
+    /// the signature commits to the whole spending transaction, so it can't be lifted onto a copy that pays more or elsewhere
+    #[test]
+    fn add_to_mempool_signature_from_other_transaction() {
+	let b = "adamadamadamadamadamadamadamadam".as_bytes(); // arbitrary for testing. 32 long
+	let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(b).unwrap();
+	let (mut chain, coinbase_hash) = chain_with_one_block(&private_key);
+
+	let signed = spend_transaction(&private_key, coinbase_hash, 0, 1000);
+	let mut more_value = signed.clone();
+	more_value.tx_outs[0].value = 2000;
+	assert_eq!(chain.try_add_tx_to_mempool(more_value), Err(TransactionError::InvalidScript {input_index: 0, error: ScriptError::EvalFalse}));
+	let mut elsewhere = signed.clone();
+	elsewhere.tx_outs[0].locking_script = Script {ops: vec![StackOp::OpAdd]};
+	assert_eq!(chain.try_add_tx_to_mempool(elsewhere), Err(TransactionError::InvalidScript {input_index: 0, error: ScriptError::EvalFalse}));
+	assert_eq!(chain.try_add_tx_to_mempool(signed), Ok(()));
+    }
+
+    /// where standard transactions are required, the mempool turns away transactions that break its policy, however valid they are
+    #[test]
+    fn add_to_mempool_policy() {
+	let private_key = key_from_seed("adamadamadamadamadamadamadamadam");
+	let mut chain = BlockChain::with_params(NetworkParams {require_standard: true, ..test_params()});
+	let mut block = chain.construct_candidate_block(private_key.verifying_key());
+	block.mine();
+	let coinbase_hash = block.transaction_list[0].txid();
+	let coinbase_value = block.transaction_list[0].tx_outs[0].value;
+	chain.add_block(block).unwrap();
+	let signed = |tx_outs: Vec<TxOut>| {
+	    let mut transaction = spend_transaction(&private_key, coinbase_hash, 0, 0);
+	    transaction.tx_outs = tx_outs;
+	    sign_p2pkh_tx_in(&private_key, &mut transaction, 0);
+	    transaction
+	};
+	let pay_to = |value: u64| TxOut {value, locking_script: pay_to_public_key_hash(&[2; 33])};
+	let nonstandard = signed(vec![pay_to(5000), TxOut {value: 1000, locking_script: Script {ops: vec![StackOp::OpDup]}}]);
+	assert_eq!(chain.try_add_tx_to_mempool(nonstandard.clone()), Err(TransactionError::NonStandard(PolicyError::NonStandardOutput {output_index: 1})));
+	assert_eq!(chain.try_add_tx_to_mempool(signed(vec![pay_to(500)])), Err(TransactionError::NonStandard(PolicyError::Dust {output_index: 0})));
+	let no_fee = signed(vec![pay_to(coinbase_value)]);
+	assert_eq!(chain.try_add_tx_to_mempool(no_fee.clone()), Err(TransactionError::NonStandard(PolicyError::FeeTooLow {fee: 0, min_fee: no_fee.vsize() as u64})));
+	// the policy is checked before the scripts, but spending too much is still invalid
+	let mut not_signed = signed(vec![pay_to(5000), TxOut {value: 1000, locking_script: Script {ops: vec![StackOp::OpDup]}}]);
+	not_signed.tx_outs[0].value = 6000;
+	assert_eq!(chain.try_add_tx_to_mempool(not_signed), Err(TransactionError::NonStandard(PolicyError::NonStandardOutput {output_index: 1})));
+	assert_eq!(chain.try_add_tx_to_mempool(signed(vec![pay_to(coinbase_value + 1)])), Err(TransactionError::OverSpend));
+	// and so is paying out more bitcoin than there will ever be
+	assert_eq!(chain.try_add_tx_to_mempool(signed(vec![pay_to(MAX_MONEY + 1)])), Err(TransactionError::TxOutValueTooLarge));
+	assert_eq!(chain.try_add_tx_to_mempool(signed(vec![pay_to(MAX_MONEY), pay_to(MAX_MONEY)])), Err(TransactionError::TxOutTotalTooLarge));
+
+	// the scripts run with the standard flags, so a signature with the high s value (which anyone could have swapped in) is turned away
+	let mut high_s = signed(vec![pay_to(5000)]);
+	if let TxIn::TxPrevious {unlocking_script, ..} = &mut high_s.tx_ins[0] {
+	    let (hash_type, der) = match &unlocking_script.ops[0] {
+		StackOp::Bytes(signature_bytes) => signature_bytes.split_last().unwrap(),
+		_ => panic!("expected a signature"),
+	    };
+	    let signature = signature_from_der(der).unwrap();
+	    let mut high_s_bytes = signature_to_der(&Signature::from_scalars(signature.r().to_bytes(), (-*signature.s()).to_bytes()).unwrap());
+	    high_s_bytes.push(*hash_type);
+	    unlocking_script.ops[0] = StackOp::Bytes(high_s_bytes.into_boxed_slice());
+	}
+	assert_eq!(chain.try_add_tx_to_mempool(high_s.clone()), Err(TransactionError::InvalidScript {input_index: 0, error: ScriptError::SigHighS}));
+	// and so is a public key pushed with OP_PUSHDATA1, which the signature doesn't cover either
+	let mut non_minimal_push = signed(vec![pay_to(5000)]);
+	if let TxIn::TxPrevious {unlocking_script, ..} = &mut non_minimal_push.tx_ins[0] {
+	    if let StackOp::Bytes(public_key_bytes) = &unlocking_script.ops[1] {
+		unlocking_script.ops[1] = StackOp::NonMinimalPush(0x4c, public_key_bytes.clone()); // OP_PUSHDATA1
+	    }
+	}
+	assert_eq!(chain.try_add_tx_to_mempool(non_minimal_push), Err(TransactionError::InvalidScript {input_index: 0, error: ScriptError::MinimalData}));
+
+	// some data is fine though
+	let standard = signed(vec![pay_to(5000), TxOut {value: 0, locking_script: Script::parse_asm("OP_RETURN 68656c6c6f").unwrap()}]);
+	assert_eq!(chain.try_add_tx_to_mempool(standard), Ok(()));
+
+	// and without a policy, only validity counts
+	let mut chain = BlockChain::with_params(NetworkParams {require_standard: true, ..test_params()});
+	let mut block = chain.construct_candidate_block(private_key.verifying_key());
+	block.mine();
+	chain.add_block(block).unwrap();
+	chain.set_mempool_policy(None);
+	assert_eq!(chain.try_add_tx_to_mempool(no_fee), Ok(()));
+	// which is all that a block has to be
+	let block = mine_block_on(&chain, chain.best_tip().unwrap(), private_key.verifying_key(), vec![high_s]);
+	assert_eq!(chain.add_block(block), Ok(()));
+    }
+
+    /// a second transaction in the mempool may not spend the same tx_out as one that is already there
+    #[test]
+    fn add_to_mempool_double_spend_in_mempool() {
+	let b = "adamadamadamadamadamadamadamadam".as_bytes(); // arbitrary for testing. 32 long
+	let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(b).unwrap();
+	let (mut chain, coinbase_hash) = chain_with_one_block(&private_key);
+
+	let first = spend_transaction(&private_key, coinbase_hash, 0, 1000);
+	assert_eq!(chain.try_add_tx_to_mempool(first), Ok(()));
+	let second = spend_transaction(&private_key, coinbase_hash, 0, 2000);
+	assert_eq!(chain.try_add_tx_to_mempool(second), Err(TransactionError::DoubleSpend));
+	assert_eq!(chain.mempool.len(), 1);
+    }
+
+    /// a single transaction may not list the same tx_out twice as a tx_in
+    #[test]
+    fn add_to_mempool_double_spend_in_transaction() {
+	let b = "adamadamadamadamadamadamadamadam".as_bytes(); // arbitrary for testing. 32 long
+	let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(b).unwrap();
+	let (mut chain, coinbase_hash) = chain_with_one_block(&private_key);
+
+	let mut transaction = spend_transaction(&private_key, coinbase_hash, 0, 1000);
+	let tx_in = transaction.tx_ins[0].clone();
+	transaction.tx_ins.push(tx_in);
+	assert_eq!(chain.try_add_tx_to_mempool(transaction), Err(TransactionError::DoubleSpend));
+    }
+
+    /// once a block has spent a tx_out, neither the mempool nor a later block may spend it again
+    #[test]
+    fn double_spend_across_blocks() {
+	let b = "adamadamadamadamadamadamadamadam".as_bytes(); // arbitrary for testing. 32 long
+	let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(b).unwrap();
+	let (mut chain, coinbase_hash) = chain_with_one_block(&private_key);
+	let outpoint = OutPoint::new(coinbase_hash, 0);
+	assert!(chain.get_utxo(&outpoint).is_some());
+
+	let first = spend_transaction(&private_key, coinbase_hash, 0, 1000);
+	let first_hash = first.txid();
+	chain.try_add_tx_to_mempool(first).unwrap();
+	let mut second_block = chain.construct_candidate_block(private_key.verifying_key());
+	second_block.mine();
+	chain.add_block(second_block).unwrap();
+	assert!(chain.get_utxo(&outpoint).is_none());
+	assert!(chain.get_utxo(&OutPoint::new(first_hash, 0)).is_some());
+
+	// the mempool rejects another spend. The tx_out is gone from the utxo set, as if it never existed
+	let second = spend_transaction(&private_key, coinbase_hash, 0, 2000);
+	assert_eq!(chain.try_add_tx_to_mempool(second.clone()), Err(TransactionError::TxInNotFound));
+
+	// and so does the chain if a block tries to sneak it in
+	let third_block = mine_block_on(&chain, chain.best_tip().unwrap(), private_key.verifying_key(), vec![second]);
+	assert_eq!(chain.add_block(third_block), Err(BlockValidationError::InvalidTransaction(TransactionError::TxInNotFound)));
+	assert_eq!(chain.len(), 2);
+    }
+
+    /// two transactions within the same block may not spend the same tx_out
+    #[test]
+    fn double_spend_within_block() {
+	let b = "adamadamadamadamadamadamadamadam".as_bytes(); // arbitrary for testing. 32 long
+	let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(b).unwrap();
+	let (mut chain, coinbase_hash) = chain_with_one_block(&private_key);
+
+	let double_spends = vec![
+	    spend_transaction(&private_key, coinbase_hash, 0, 1000),
+	    spend_transaction(&private_key, coinbase_hash, 0, 2000),
+	];
+	let block = mine_block_on(&chain, chain.best_tip().unwrap(), private_key.verifying_key(), double_spends);
+	assert_eq!(chain.add_block(block), Err(BlockValidationError::InvalidTransaction(TransactionError::DoubleSpend)));
+
+	// the rejected block must not have touched the utxo set
+	assert_eq!(chain.len(), 1);
+	assert!(chain.get_utxo(&OutPoint::new(coinbase_hash, 0)).is_some());
+    }
+
+    /// nothing makes a miner push the height into the coinbase, so a block can repeat an old coinbase exactly.
+    /// That is only allowed once the old one has been spent, or else its tx_outs would get overwritten (BIP30)
+    #[test]
+    fn duplicate_coinbase() {
+	let private_key = key_from_seed("adamadamadamadamadamadamadamadam");
+	let (mut chain, coinbase_hash) = chain_with_one_block(&private_key);
+	let coinbase = chain.block_database.get(&chain.best_tip().unwrap()).unwrap().block.transaction_list[0].clone();
+	let block = mine_block_with(&chain, chain.best_tip().unwrap(), vec![coinbase.clone()]);
+	assert_eq!(chain.add_block(block), Err(BlockValidationError::InvalidTransaction(TransactionError::DuplicateTransaction)));
+	assert_eq!(chain.len(), 1);
+	assert!(chain.get_utxo(&OutPoint::new(coinbase_hash, 0)).is_some());
+
+	let spend = spend_transaction(&private_key, coinbase_hash, 0, 1000);
+	let block = mine_block_on(&chain, chain.best_tip().unwrap(), private_key.verifying_key(), vec![spend]);
+	chain.add_block(block).unwrap();
+	let block = mine_block_with(&chain, chain.best_tip().unwrap(), vec![coinbase]);
+	assert_eq!(chain.add_block(block), Ok(()));
+	assert!(chain.get_utxo(&OutPoint::new(coinbase_hash, 0)).is_some());
+    }
+
+    /// when a block spends a tx_out that a transaction in our mempool also spends, that transaction gets dropped
+    #[test]
+    fn block_evicts_conflicting_mempool_transaction() {
+	let b = "adamadamadamadamadamadamadamadam".as_bytes(); // arbitrary for testing. 32 long
+	let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(b).unwrap();
+	let (mut chain, coinbase_hash) = chain_with_one_block(&private_key);
+
+	// e.g. another miner's block, which was built without the transaction that is about to enter our mempool
+	let conflicting = spend_transaction(&private_key, coinbase_hash, 0, 2000);
+	let block = mine_block_on(&chain, chain.best_tip().unwrap(), private_key.verifying_key(), vec![conflicting]);
+
+	chain.try_add_tx_to_mempool(spend_transaction(&private_key, coinbase_hash, 0, 1000)).unwrap();
+	assert_eq!(chain.mempool.len(), 1);
+	chain.add_block(block).unwrap();
+	assert_eq!(chain.mempool.len(), 0);
+    }
+
+    /// build and mine a block with a coinbase paying out to recipient followed by the given transactions,
+    /// on top of the given parent (rather than the best tip)
+    fn mine_block_on(chain: &BlockChain, parent: Hash, recipient: VerifyingKey<Secp256k1>, transactions: Vec<Transaction>) -> Block {
+	let mut transaction_list = vec![chain.construct_coinbase_transaction(recipient, 0)];
+	transaction_list.extend(transactions);
+	mine_block_with(chain, parent, transaction_list)
+    }
+
+    /// build and mine a block with exactly the given transactions on top of the given parent
+    fn mine_block_with(chain: &BlockChain, parent: Hash, transaction_list: Vec<Transaction>) -> Block {
+	let parent_hash = if parent.is_zero() { None } else { Some(parent) };
+	let difficulty_bits = chain.next_difficulty_bits(parent_hash);
+	let mut block_header = BlockHeader::new(1, parent, merkle::get_merkle_root(&transaction_list), difficulty_bits);
+	let time_stamp = block_header.time_stamp().max(chain.median_time_past(parent_hash) + 1);
+	block_header.set_time_stamp(time_stamp);
+	let mut block = Block {
+	    block_size: 100,
+	    block_header,
+	    transaction_count: transaction_list.len() as u32,
+	    transaction_list,
+	};
+	block.mine();
+	block
+    }
+
+    /// our own network, except that a coinbase can be spent from the very next block on, so that the tests don't have to mine 100 blocks first
+    fn test_params() -> NetworkParams {
+	NetworkParams {coinbase_maturity: 1, ..NetworkParams::default()}
+    }
+
+    fn key_from_seed(seed: &str) -> SigningKey<Secp256k1> {
+	SigningKey::<Secp256k1>::from_bytes(seed.as_bytes()).unwrap()
+    }
+
+    /// a competing block at the same height is kept on a side branch, until its branch gets more work than the active chain
+    #[test]
+    fn fork_switches_to_most_work() {
+	let key_a = key_from_seed("adamadamadamadamadamadamadamadam");
+	let key_b = key_from_seed("bobbobbobbobbobbobbobbobbobbobbo");
+	let key_c = key_from_seed("carlcarlcarlcarlcarlcarlcarlcarl");
+	let mut chain = BlockChain::with_params(test_params());
+	let genesis = mine_block_on(&chain, Hash::zero(), key_a.verifying_key(), vec![]);
+	let genesis_hash = genesis.block_header.hash();
+	chain.add_block(genesis).unwrap();
+
+	let block_1a = mine_block_on(&chain, genesis_hash, key_a.verifying_key(), vec![]);
+	let block_1a_hash = block_1a.block_header.hash();
+	let coinbase_1a = OutPoint::new(block_1a.transaction_list[0].txid(), 0);
+	chain.add_block(block_1a).unwrap();
+	assert_eq!(chain.best_tip(), Some(block_1a_hash));
+
+	// the competing block has the same amount of work, so we stick with the block we saw first
+	let block_1b = mine_block_on(&chain, genesis_hash, key_b.verifying_key(), vec![]);
+	let block_1b_hash = block_1b.block_header.hash();
+	let coinbase_1b = OutPoint::new(block_1b.transaction_list[0].txid(), 0);
+	chain.add_block(block_1b).unwrap();
+	assert_eq!(chain.best_tip(), Some(block_1a_hash));
+	assert_eq!(chain.len(), 2);
+	assert!(chain.get_block_by_hash(&block_1b_hash).is_some());
+	assert!(chain.get_utxo(&coinbase_1a).is_some());
+	assert!(chain.get_utxo(&coinbase_1b).is_none());
+
+	// extending the side branch gives it the most work, so it becomes the active chain
+	let block_2b = mine_block_on(&chain, block_1b_hash, key_c.verifying_key(), vec![]);
+	let block_2b_hash = block_2b.block_header.hash();
+	chain.add_block(block_2b).unwrap();
+	assert_eq!(chain.best_tip(), Some(block_2b_hash));
+	assert_eq!(chain.len(), 3);
+	assert!(chain.get_utxo(&coinbase_1a).is_none());
+	assert!(chain.get_utxo(&coinbase_1b).is_some());
+
+	let ancestor_hashes: Vec<Hash> = chain.ancestors(block_2b_hash).map(|block| block.block_header.hash()).collect();
+	assert_eq!(ancestor_hashes, vec![block_2b_hash, block_1b_hash, genesis_hash]);
+	// the old tip is still around
+	assert!(chain.get_block_by_hash(&block_1a_hash).is_some());
+    }
+
+    /// a block must build on a block that we know of, and can't be added twice
+    #[test]
+    fn add_block_unknown_parent_and_duplicate() {
+	let key_a = key_from_seed("adamadamadamadamadamadamadamadam");
+	let mut chain = BlockChain::with_params(test_params());
+	let genesis = mine_block_on(&chain, Hash::zero(), key_a.verifying_key(), vec![]);
+	chain.add_block(genesis.clone()).unwrap();
+	assert_eq!(chain.add_block(genesis), Err(BlockValidationError::AlreadyKnown));
+
+	let orphan = mine_block_on(&chain, Hash::from(12345), key_a.verifying_key(), vec![]);
+	assert_eq!(chain.add_block(orphan), Err(BlockValidationError::UnknownParent));
+	let second_genesis = mine_block_on(&chain, Hash::zero(), key_a.verifying_key(), vec![]);
+	assert_eq!(chain.add_block(second_genesis), Err(BlockValidationError::UnknownParent));
+	assert_eq!(chain.len(), 1);
+    }
+
+    /// a disconnected block with a transaction and another one that spends it: both go back into the mempool,
+    /// and the next block we build puts the parent before the child
+    #[test]
+    fn reorg_returns_chained_transactions_to_mempool() {
+	let key_a = key_from_seed("adamadamadamadamadamadamadamadam");
+	let key_b = key_from_seed("bobbobbobbobbobbobbobbobbobbobbo");
+	let key_c = key_from_seed("carlcarlcarlcarlcarlcarlcarlcarl");
+	let (mut chain, coinbase_hash) = chain_with_one_block(&key_a);
+	let fork_point = chain.best_tip().unwrap();
+	let mut parent = spend_transaction(&key_a, coinbase_hash, 0, ORIGINAL_COINBASE - 1000);
+	parent.tx_outs[0].locking_script = pay_to_public_key_hash(&key_a.verifying_key().to_encoded_point(true).to_bytes());
+	sign_p2pkh_tx_in(&key_a, &mut parent, 0);
+	// the child pays the bigger tip, so it comes out of the mempool first
+	let child = spend_transaction(&key_a, parent.txid(), 0, ORIGINAL_COINBASE - 5000);
+	assert_eq!(chain.try_add_tx_to_mempool(child.clone()), Err(TransactionError::TxInNotFound));
+	chain.try_add_tx_to_mempool(parent.clone()).unwrap();
+	chain.try_add_tx_to_mempool(child.clone()).unwrap();
+	let mut block_1a = chain.construct_candidate_block(key_a.verifying_key());
+	assert_eq!(block_1a.transaction_list[1..], [parent.clone(), child.clone()]);
+	block_1a.mine();
+	chain.add_block(block_1a).unwrap();
+	assert_eq!(chain.mempool.len(), 0);
+
+	let block_1b = mine_block_on(&chain, fork_point, key_b.verifying_key(), vec![]);
+	let block_1b_hash = block_1b.block_header.hash();
+	chain.add_block(block_1b).unwrap();
+	let block_2b = mine_block_on(&chain, block_1b_hash, key_c.verifying_key(), vec![]);
+	let block_2b_hash = block_2b.block_header.hash();
+	chain.add_block(block_2b).unwrap();
+	assert_eq!(chain.best_tip(), Some(block_2b_hash));
+	assert_eq!(mempool_hashes(&chain), vec![parent.txid(), child.txid()].into_iter().collect());
+	let mut block_3b = chain.construct_candidate_block(key_b.verifying_key());
+	assert_eq!(block_3b.transaction_list[1..], [parent.clone(), child]);
+	block_3b.mine();
+
+	// a block that spends the coinbase some other way takes the parent out of the mempool, and the child goes with it
+	let conflict = spend_transaction(&key_a, coinbase_hash, 0, ORIGINAL_COINBASE);
+	let block_3c = mine_block_on(&chain, block_2b_hash, key_b.verifying_key(), vec![conflict]);
+	chain.add_block(block_3c).unwrap();
+	assert_eq!(chain.mempool.len(), 0);
+	// the block with both of them on the same parent is still fine, it just doesn't have more work
+	assert_eq!(chain.add_block(block_3b), Ok(()));
+    }
+
+    fn mempool_hashes(chain: &BlockChain) -> HashSet<Hash> {
+	chain.mempool.iter().map(|tx_with_tip| tx_with_tip.transaction.txid()).collect()
+    }
+
+    /// two competing branches, where the second one overtakes the first. Afterwards the utxo set and the mempool
+    /// must look exactly like they would if we had only ever seen the winning branch
+    #[test]
+    fn reorg_matches_replay_of_winning_branch() {
+	let key_a = key_from_seed("adamadamadamadamadamadamadamadam");
+	let key_b = key_from_seed("bobbobbobbobbobbobbobbobbobbobbo");
+	let key_c = key_from_seed("carlcarlcarlcarlcarlcarlcarlcarl");
+	let mut chain = BlockChain::with_params(test_params());
+	let genesis = mine_block_on(&chain, Hash::zero(), key_a.verifying_key(), vec![]);
+	let genesis_hash = genesis.block_header.hash();
+	let genesis_coinbase = genesis.transaction_list[0].txid();
+	chain.add_block(genesis).unwrap();
+	let block_1 = mine_block_on(&chain, genesis_hash, key_a.verifying_key(), vec![]);
+	let block_1_hash = block_1.block_header.hash();
+	let block_1_coinbase = block_1.transaction_list[0].txid();
+	chain.add_block(block_1).unwrap();
+
+	// both transactions spend the full amount, so there is no tip for the miner
+	let transaction_1 = spend_transaction(&key_a, genesis_coinbase, 0, ORIGINAL_COINBASE);
+	let transaction_2 = spend_transaction(&key_a, block_1_coinbase, 0, ORIGINAL_COINBASE);
+	chain.try_add_tx_to_mempool(transaction_1.clone()).unwrap();
+	chain.try_add_tx_to_mempool(transaction_2.clone()).unwrap();
+
+	// branch a takes both transactions out of the mempool
+	let mut block_2a = chain.construct_candidate_block(key_a.verifying_key());
+	block_2a.mine();
+	let block_2a_hash = block_2a.block_header.hash();
+	let block_2a_coinbase = block_2a.transaction_list[0].txid();
+	assert_eq!(block_2a.transaction_count, 3);
+	chain.add_block(block_2a).unwrap();
+	assert_eq!(chain.mempool.len(), 0);
+
+	// a transaction that only makes sense on branch a
+	let transaction_3 = spend_transaction(&key_a, block_2a_coinbase, 0, ORIGINAL_COINBASE);
+	chain.try_add_tx_to_mempool(transaction_3.clone()).unwrap();
+
+	// branch b only includes the second transaction, and then gets ahead
+	let block_2b = mine_block_on(&chain, block_1_hash, key_b.verifying_key(), vec![transaction_2.clone()]);
+	let block_2b_hash = block_2b.block_header.hash();
+	chain.add_block(block_2b).unwrap();
+	assert_eq!(chain.best_tip(), Some(block_2a_hash));
+	let block_3b = mine_block_on(&chain, block_2b_hash, key_c.verifying_key(), vec![]);
+	let block_3b_hash = block_3b.block_header.hash();
+	chain.add_block(block_3b).unwrap();
+	assert_eq!(chain.best_tip(), Some(block_3b_hash));
+	assert!(!chain.block_undos.contains_key(&block_2a_hash));
+
+	// the first transaction is back in the mempool, the second is in branch b, and the third spends a coinbase that no longer exists
+	assert_eq!(mempool_hashes(&chain), vec![transaction_1.txid()].into_iter().collect());
+
+	let mut replay = BlockChain::with_params(test_params());
+	for hash in [genesis_hash, block_1_hash, block_2b_hash, block_3b_hash] {
+	    replay.add_block(chain.get_block_by_hash(&hash).unwrap().clone()).unwrap();
+	}
+	for transaction in [transaction_1, transaction_2, transaction_3] {
+	    let _ = replay.try_add_tx_to_mempool(transaction);
+	}
+	assert_eq!(chain.utxo_set, replay.utxo_set);
+	assert_eq!(mempool_hashes(&chain), mempool_hashes(&replay));
+    }
+
+    /// if a block on the heavier branch turns out to be invalid, we stay on (or go back to) the old branch
+    #[test]
+    fn reorg_to_invalid_branch_is_rolled_back() {
+	let key_a = key_from_seed("adamadamadamadamadamadamadamadam");
+	let key_b = key_from_seed("bobbobbobbobbobbobbobbobbobbobbo");
+	let key_c = key_from_seed("carlcarlcarlcarlcarlcarlcarlcarl");
+	let mut chain = BlockChain::with_params(test_params());
+	let genesis = mine_block_on(&chain, Hash::zero(), key_a.verifying_key(), vec![]);
+	let genesis_hash = genesis.block_header.hash();
+	let genesis_coinbase = genesis.transaction_list[0].txid();
+	chain.add_block(genesis).unwrap();
+	let block_1a = mine_block_on(&chain, genesis_hash, key_a.verifying_key(), vec![]);
+	let block_1a_hash = block_1a.block_header.hash();
+	chain.add_block(block_1a).unwrap();
+	let utxo_set_before = chain.utxo_set.clone();
+
+	let block_1b = mine_block_on(&chain, genesis_hash, key_b.verifying_key(), vec![]);
+	let block_1b_hash = block_1b.block_header.hash();
+	chain.add_block(block_1b).unwrap();
+	let double_spends = vec![
+	    spend_transaction(&key_a, genesis_coinbase, 0, 1000),
+	    spend_transaction(&key_a, genesis_coinbase, 0, 2000),
+	];
+	let block_2b = mine_block_on(&chain, block_1b_hash, key_c.verifying_key(), double_spends);
+	let block_2b_hash = block_2b.block_header.hash();
+	assert_eq!(chain.add_block(block_2b), Err(BlockValidationError::InvalidTransaction(TransactionError::DoubleSpend)));
+
+	assert_eq!(chain.best_tip(), Some(block_1a_hash));
+	assert_eq!(chain.utxo_set, utxo_set_before);
+	assert!(chain.get_block_by_hash(&block_2b_hash).is_none());
+	// the valid part of the branch is still around
+	assert!(chain.get_block_by_hash(&block_1b_hash).is_some());
+    }
+
+    /// when a reorg fails, the other blocks built on top of the invalid one go too, so nothing can be built on them later
+    #[test]
+    fn reorg_to_invalid_branch_forgets_descendants() {
+	let key_a = key_from_seed("adamadamadamadamadamadamadamadam");
+	let key_b = key_from_seed("bobbobbobbobbobbobbobbobbobbobbo");
+	let key_c = key_from_seed("carlcarlcarlcarlcarlcarlcarlcarl");
+	let mut chain = BlockChain::with_params(test_params());
+	let genesis = mine_block_on(&chain, Hash::zero(), key_a.verifying_key(), vec![]);
+	let genesis_hash = genesis.block_header.hash();
+	let genesis_coinbase = genesis.transaction_list[0].txid();
+	chain.add_block(genesis).unwrap();
+	for _ in 0..3 {
+	    let block = mine_block_on(&chain, chain.best_tip().unwrap(), key_a.verifying_key(), vec![]);
+	    chain.add_block(block).unwrap();
+	}
+	let tip = chain.best_tip();
+	let utxo_set_before = chain.utxo_set.clone();
+
+	// 1b is invalid, but that only shows once it gets connected
+	let double_spends = vec![
+	    spend_transaction(&key_a, genesis_coinbase, 0, 1000),
+	    spend_transaction(&key_a, genesis_coinbase, 0, 2000),
+	];
+	let block_1b = mine_block_on(&chain, genesis_hash, key_b.verifying_key(), double_spends);
+	let mut parent = block_1b.block_header.hash();
+	chain.add_block(block_1b).unwrap();
+	let block_2c = mine_block_on(&chain, parent, key_c.verifying_key(), vec![]);
+	let block_2c_hash = block_2c.block_header.hash();
+	chain.add_block(block_2c).unwrap();
+	for _ in 0..2 {
+	    let block = mine_block_on(&chain, parent, key_b.verifying_key(), vec![]);
+	    parent = block.block_header.hash();
+	    chain.add_block(block).unwrap();
+	}
+	let block_4b = mine_block_on(&chain, parent, key_b.verifying_key(), vec![]);
+	assert_eq!(chain.add_block(block_4b), Err(BlockValidationError::InvalidTransaction(TransactionError::DoubleSpend)));
+	assert_eq!(chain.best_tip(), tip);
+	assert_eq!(chain.utxo_set, utxo_set_before);
+	assert!(chain.get_block_by_hash(&block_2c_hash).is_none());
+
+	// so the branch off of 1b can't be extended
+	let block_3c = mine_block_on(&chain, block_2c_hash, key_c.verifying_key(), vec![]);
+	assert_eq!(chain.add_block(block_3c), Err(BlockValidationError::UnknownParent));
+	assert_eq!(chain.best_tip(), tip);
+    }
+
+    /// each of the consensus rules checked by validate_block() and validate_transactions()
+    #[test]
+    fn add_block_consensus_rules() {
+	let key_a = key_from_seed("adamadamadamadamadamadamadamadam");
+	let key_b = key_from_seed("bobbobbobbobbobbobbobbobbobbobbo");
+	let (mut chain, coinbase_hash) = chain_with_one_block(&key_a);
+	let tip = chain.best_tip().unwrap();
+	let reward = BlockChain::determine_coinbase_reward(1);
+
+	// a block that would need a hash of at most 1, which is not what the chain asks for
+	let transaction_list = vec![chain.construct_coinbase_transaction(key_a.verifying_key(), 0)];
+	let block_header = BlockHeader::new(1, tip, merkle::get_merkle_root(&transaction_list), DifficultyBits(0x03000001));
+	let too_hard = Block {block_size: 100, block_header, transaction_count: 1, transaction_list};
+	assert_eq!(chain.add_block(too_hard), Err(BlockValidationError::BadDifficultyBits));
+
+	// an unmined block (making sure we didn't get lucky with the hash)
+	let mut unmined = chain.construct_candidate_block(key_a.verifying_key());
+	while unmined.block_header.meets_target() {
+	    let time_stamp = unmined.block_header.time_stamp();
+	    unmined.block_header.set_time_stamp(time_stamp + 1);
+	}
+	assert_eq!(chain.add_block(unmined), Err(BlockValidationError::BadProofOfWork));
+
+	// the transaction count and list aren't covered by the proof of work, so we can tamper with them after mining
+	let mut block = mine_block_on(&chain, tip, key_a.verifying_key(), vec![]);
+	block.transaction_count = 2;
+	assert_eq!(chain.add_block(block), Err(BlockValidationError::BadTransactionCount));
+
+	let mut block = mine_block_on(&chain, tip, key_a.verifying_key(), vec![]);
+	block.transaction_list[0].tx_outs[0].value -= 1;
+	assert_eq!(chain.add_block(block), Err(BlockValidationError::BadMerkleRoot));
+
+	let spend = spend_transaction(&key_a, coinbase_hash, 0, 1000);
+	let block = mine_block_with(&chain, tip, vec![spend.clone()]);
+	assert_eq!(chain.add_block(block), Err(BlockValidationError::NoCoinbase));
+
+	let second_coinbase = chain.construct_coinbase_transaction(key_b.verifying_key(), 0);
+	let block = mine_block_on(&chain, tip, key_a.verifying_key(), vec![second_coinbase]);
+	assert_eq!(chain.add_block(block), Err(BlockValidationError::MultipleCoinbases));
+
+	// signed by the wrong key
+	let stolen = spend_transaction(&key_b, coinbase_hash, 0, 1000);
+	let block = mine_block_on(&chain, tip, key_b.verifying_key(), vec![stolen]);
+	assert_eq!(chain.add_block(block), Err(BlockValidationError::InvalidTransaction(TransactionError::InvalidScript {input_index: 0, error: ScriptError::EqualVerifyFailed})));
+
+	let overspend = spend_transaction(&key_a, coinbase_hash, 0, ORIGINAL_COINBASE + 1);
+	let block = mine_block_on(&chain, tip, key_a.verifying_key(), vec![overspend]);
+	assert_eq!(chain.add_block(block), Err(BlockValidationError::InvalidTransaction(TransactionError::OverSpend)));
+
+	// the spend leaves a tip of ORIGINAL_COINBASE - 1000, so the coinbase can claim exactly that much on top of the reward
+	let greedy_coinbase = chain.construct_coinbase_transaction(key_a.verifying_key(), ORIGINAL_COINBASE - 1000 + 1);
+	let block = mine_block_with(&chain, tip, vec![greedy_coinbase, spend.clone()]);
+	assert_eq!(chain.add_block(block), Err(BlockValidationError::BadCoinbaseAmount));
+	let coinbase = chain.construct_coinbase_transaction(key_a.verifying_key(), ORIGINAL_COINBASE - 1000);
+	assert_eq!(coinbase.tx_outs[0].value, reward + ORIGINAL_COINBASE - 1000);
+	let block = mine_block_with(&chain, tip, vec![coinbase, spend]);
+	assert_eq!(chain.add_block(block), Ok(()));
+
+	// none of the rejected blocks made it into the chain or touched the utxo set
+	assert_eq!(chain.len(), 2);
+	assert!(chain.get_utxo(&OutPoint::new(coinbase_hash, 0)).is_none());
+    }
+
+    /// the time stamp of a block has to be after the median time past of its parent, and at most two hours ahead of our clock
+    #[test]
+    fn block_time_stamp_rules() {
+	let key_a = key_from_seed("adamadamadamadamadamadamadamadam");
+	let (mut chain, _) = chain_with_one_block(&key_a);
+	let tip = chain.best_tip().unwrap();
+	let median_time_past = chain.median_time_past(Some(tip));
+	let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as u32;
+	let with_time_stamp = |chain: &BlockChain, time_stamp: u32| {
+	    let mut block = mine_block_on(chain, tip, key_a.verifying_key(), vec![]);
+	    block.block_header.set_time_stamp(time_stamp);
+	    block.mine();
+	    block
+	};
+	let block = with_time_stamp(&chain, median_time_past);
+	assert_eq!(chain.add_block(block), Err(BlockValidationError::TimeTooOld));
+	let block = with_time_stamp(&chain, now + MAX_FUTURE_BLOCK_TIME + 60);
+	assert_eq!(chain.add_block(block), Err(BlockValidationError::TimeTooNew));
+	// somewhat ahead is fine, since clocks don't agree exactly
+	let block = with_time_stamp(&chain, now + MAX_FUTURE_BLOCK_TIME - 60);
+	assert_eq!(chain.add_block(block), Ok(()));
+    }
+
+    /// transactions without tx_ins or tx_outs are turned away by the mempool and by blocks
+    #[test]
+    fn empty_transactions_rejected() {
+	let private_key = key_from_seed("adamadamadamadamadamadamadamadam");
+	let (mut chain, coinbase_hash) = chain_with_one_block(&private_key);
+	let tip = chain.best_tip().unwrap();
+	let mut no_tx_outs = spend_transaction(&private_key, coinbase_hash, 0, 1000);
+	no_tx_outs.tx_outs.clear();
+	sign_p2pkh_tx_in(&private_key, &mut no_tx_outs, 0);
+	let no_tx_ins = Transaction {version: 1, lock_time: 0, tx_ins: vec![], tx_outs: vec![TxOut {value: 0, locking_script: Script {ops: vec![StackOp::OpDup]}}]};
+	assert_eq!(chain.try_add_tx_to_mempool(no_tx_outs.clone()), Err(TransactionError::NoTxOuts));
+	let block = mine_block_on(&chain, tip, private_key.verifying_key(), vec![no_tx_outs]);
+	assert_eq!(chain.add_block(block), Err(BlockValidationError::InvalidTransaction(TransactionError::NoTxOuts)));
+	assert_eq!(chain.try_add_tx_to_mempool(no_tx_ins.clone()), Err(TransactionError::NoTxIns));
+	let block = mine_block_on(&chain, tip, private_key.verifying_key(), vec![no_tx_ins]);
+	assert_eq!(chain.add_block(block), Err(BlockValidationError::InvalidTransaction(TransactionError::NoTxIns)));
+	// that goes for the coinbase too
+	let mut coinbase = chain.construct_coinbase_transaction(private_key.verifying_key(), 0);
+	coinbase.tx_outs.clear();
+	let block = mine_block_with(&chain, tip, vec![coinbase]);
+	assert_eq!(chain.add_block(block), Err(BlockValidationError::InvalidTransaction(TransactionError::NoTxOuts)));
+    }
+
+    /// with a retarget interval of 4 blocks, the fifth block must carry the adjusted difficulty
+    #[test]
+    fn difficulty_retargets_at_interval() {
+	let key_a = key_from_seed("adamadamadamadamadamadamadamadam");
+	let mut params = NetworkParams::regtest();
+	params.retarget_interval = 4;
+	let starting_bits = params.starting_difficulty_bits;
+	let mut chain = BlockChain::with_params(params.clone());
+	let start_time = 1_600_000_000;
+	for height in 0..4 {
+	    let mut block = chain.construct_candidate_block(key_a.verifying_key());
+	    assert_eq!(block.block_header.difficulty_bits(), starting_bits);
+	    // a block every 2 minutes rather than every 10
+	    block.block_header.set_time_stamp(start_time + height * 120);
+	    block.mine();
+	    chain.add_block(block).unwrap();
+	}
+
+	// blocks came 5 times too quickly, but the adjustment is clamped to 4x harder
+	let expected_bits = params.retarget(starting_bits, 3 * 120);
+	assert_eq!(expected_bits, DifficultyBits::from_target(starting_bits.to_target().unwrap() / 4));
+	assert_eq!(chain.next_difficulty_bits(chain.best_tip()), expected_bits);
+
+	// a block that ignores the retarget gets rejected
+	let tip = chain.best_tip().unwrap();
+	let transaction_list = vec![chain.construct_coinbase_transaction(key_a.verifying_key(), 0)];
+	let block_header = BlockHeader::new(1, tip, merkle::get_merkle_root(&transaction_list), starting_bits);
+	let mut stale_bits = Block {block_size: 100, block_header, transaction_count: 1, transaction_list};
+	stale_bits.mine();
+	assert_eq!(chain.add_block(stale_bits), Err(BlockValidationError::BadDifficultyBits));
+
+	let mut block = chain.construct_candidate_block(key_a.verifying_key());
+	assert_eq!(block.block_header.difficulty_bits(), expected_bits);
+	block.mine();
+	chain.add_block(block).unwrap();
+	// and within the interval the difficulty stays put
+	assert_eq!(chain.next_difficulty_bits(chain.best_tip()), expected_bits);
+    }
+
+    /// change the version, lock_time and sequence of a transaction from spend_transaction(), and sign it again
+    fn with_lock_times(private_key: &SigningKey<Secp256k1>, mut transaction: Transaction, version: u32, lock_time: u32, sequence: u32) -> Transaction {
+	transaction.version = version;
+	transaction.lock_time = lock_time;
+	if let TxIn::TxPrevious {sequence: tx_sequence, ..} = &mut transaction.tx_ins[0] {
+	    *tx_sequence = sequence;
+	}
+	sign_p2pkh_tx_in(private_key, &mut transaction, 0);
+	transaction
+    }
+
+    /// mine an empty block on top of the best tip
+    fn mine_empty_block(chain: &mut BlockChain, private_key: &SigningKey<Secp256k1>) {
+	let block = mine_block_on(chain, chain.best_tip().unwrap(), private_key.verifying_key(), vec![]);
+	chain.add_block(block).unwrap();
+    }
+
+    /// the coinbase of the first block can be spent by the block at height 100, but not any sooner, by the mempool or by a block
+    #[test]
+    fn coinbase_maturity_enforced() {
+	let private_key = key_from_seed("adamadamadamadamadamadamadamadam");
+	let mut chain = BlockChain::with_params(NetworkParams::regtest());
+	assert_eq!(chain.params.coinbase_maturity, 100);
+	let mut block = chain.construct_candidate_block(private_key.verifying_key());
+	block.mine();
+	let coinbase_hash = block.transaction_list[0].txid();
+	chain.add_block(block).unwrap();
+	while chain.len() < 99 {
+	    mine_empty_block(&mut chain, &private_key);
+	}
+
+	let spend = spend_transaction(&private_key, coinbase_hash, 0, 1000);
+	assert_eq!(chain.try_add_tx_to_mempool(spend.clone()), Err(TransactionError::ImmatureCoinbaseSpend));
+	let block = mine_block_on(&chain, chain.best_tip().unwrap(), private_key.verifying_key(), vec![spend.clone()]);
+	assert_eq!(chain.add_block(block), Err(BlockValidationError::InvalidTransaction(TransactionError::ImmatureCoinbaseSpend)));
+
+	mine_empty_block(&mut chain, &private_key);
+	assert_eq!(chain.try_add_tx_to_mempool(spend.clone()), Ok(()));
+	let block = mine_block_on(&chain, chain.best_tip().unwrap(), private_key.verifying_key(), vec![spend]);
+	assert_eq!(chain.add_block(block), Ok(()));
+    }
+
+    /// a transaction with a lock_time at some height can't get into the mempool (or a block) until the chain gets past it
+    #[test]
+    fn lock_time_enforced() {
+	let private_key = key_from_seed("adamadamadamadamadamadamadamadam");
+	let (mut chain, coinbase_hash) = chain_with_one_block(&private_key);
+	let spend = spend_transaction(&private_key, coinbase_hash, 0, 1000);
+	// the next block is at height 1, so a lock_time of 2 needs two more blocks
+	let locked = with_lock_times(&private_key, spend.clone(), 1, 2, 0);
+	assert_eq!(chain.try_add_tx_to_mempool(locked.clone()), Err(TransactionError::NonFinal));
+	let block = mine_block_on(&chain, chain.best_tip().unwrap(), private_key.verifying_key(), vec![locked.clone()]);
+	assert_eq!(chain.add_block(block), Err(BlockValidationError::InvalidTransaction(TransactionError::NonFinal)));
+	// unless its tx_in opts out
+	let opted_out = with_lock_times(&private_key, spend, 1, 2, SEQUENCE_FINAL);
+	let mut other_chain = chain_with_one_block(&private_key).0;
+	assert_eq!(other_chain.try_add_tx_to_mempool(opted_out), Ok(()));
+
+	mine_empty_block(&mut chain, &private_key);
+	assert_eq!(chain.try_add_tx_to_mempool(locked.clone()), Err(TransactionError::NonFinal));
+	mine_empty_block(&mut chain, &private_key);
+	assert_eq!(chain.try_add_tx_to_mempool(locked), Ok(()));
+
+	// a lock_time in the future never passes
+	let (mut chain, coinbase_hash) = chain_with_one_block(&private_key);
+	let spend = spend_transaction(&private_key, coinbase_hash, 0, 1000);
+	let far_future = with_lock_times(&private_key, spend, 1, u32::MAX, 0);
+	assert_eq!(chain.try_add_tx_to_mempool(far_future), Err(TransactionError::NonFinal));
+    }
+
+    /// with version 2, the sequence of a tx_in is the number of blocks that have to go by after the tx_out it spends was mined
+    #[test]
+    fn relative_lock_time_enforced() {
+	let private_key = key_from_seed("adamadamadamadamadamadamadamadam");
+	let (mut chain, coinbase_hash) = chain_with_one_block(&private_key);
+	let spend = spend_transaction(&private_key, coinbase_hash, 0, 1000);
+	// the coinbase is at height 0, so it can be spent with a sequence of 2 from height 2 on
+	let locked = with_lock_times(&private_key, spend.clone(), 2, 0, 2);
+	assert_eq!(chain.try_add_tx_to_mempool(locked.clone()), Err(TransactionError::SequenceLocked));
+	let block = mine_block_on(&chain, chain.best_tip().unwrap(), private_key.verifying_key(), vec![locked.clone()]);
+	assert_eq!(chain.add_block(block), Err(BlockValidationError::InvalidTransaction(TransactionError::SequenceLocked)));
+	// the same sequence means nothing in version 1
+	let version_1 = with_lock_times(&private_key, spend, 1, 0, 2);
+	let mut other_chain = chain_with_one_block(&private_key).0;
+	assert_eq!(other_chain.try_add_tx_to_mempool(version_1), Ok(()));
+
+	mine_empty_block(&mut chain, &private_key);
+	let block = mine_block_on(&chain, chain.best_tip().unwrap(), private_key.verifying_key(), vec![locked]);
+	assert_eq!(chain.add_block(block), Ok(()));
+    }
+
+    /// the coinbase of the first block can only be claimed through a script path that checks a relative lock time
+    #[test]
+    fn check_sequence_verify_in_chain() {
+	let private_key = key_from_seed("adamadamadamadamadamadamadamadam");
+	let (mut chain, coinbase_hash) = chain_with_one_block(&private_key);
+	// first move the coinbase to a tx_out that can be spent 2 blocks after it is mined
+	let public_key_bytes = private_key.verifying_key().to_encoded_point(true).to_bytes();
+	let mut vault_script = pay_to_public_key_hash(&public_key_bytes);
+	vault_script.ops.insert(0, StackOp::OpCheckSequenceVerify);
+	vault_script.ops.insert(0, StackOp::Val(2));
+	vault_script.ops.insert(2, StackOp::OpEqual); // OP_CHECKSEQUENCEVERIFY leaves the 2 behind, so compare it with one from the unlocking script
+	vault_script.ops.insert(3, StackOp::OpVerify);
+	let mut into_vault = spend_transaction(&private_key, coinbase_hash, 0, 1000);
+	into_vault.tx_outs[0].locking_script = vault_script.clone();
+	sign_p2pkh_tx_in(&private_key, &mut into_vault, 0);
+	let vault_hash = into_vault.txid();
+	let block = mine_block_on(&chain, chain.best_tip().unwrap(), private_key.verifying_key(), vec![into_vault]);
+	chain.add_block(block).unwrap();
+
+	let mut out_of_vault = Transaction {
+	    version: 2,
+	    lock_time: 0,
+	    tx_ins: vec![TxIn::TxPrevious {tx_hash: vault_hash, tx_out_index: 0, unlocking_script: Script {ops: vec![]}, sequence: 1, witness: vec![]}],
+	    tx_outs: vec![TxOut {value: 900, locking_script: Script {ops: vec![StackOp::OpDup]}}],
+	};
+	let sign = |transaction: &mut Transaction| {
+	    let signature = sign_tx_in(&private_key, transaction, 0, &vault_script, SIGHASH_ALL);
+	    if let TxIn::TxPrevious {unlocking_script, ..} = &mut transaction.tx_ins[0] {
+		*unlocking_script = Script {ops: vec![
+		    StackOp::Bytes(signature.into_boxed_slice()),
+		    StackOp::Bytes(public_key_bytes.clone()),
+		    StackOp::Val(2),
+		]};
+	    }
+	};
+	// a sequence of 1 is too short for the script
+	sign(&mut out_of_vault);
+	let too_soon = TransactionError::InvalidScript {input_index: 0, error: ScriptError::UnsatisfiedLockTime};
+	assert_eq!(chain.try_add_tx_to_mempool(out_of_vault.clone()), Err(too_soon));
+	// a sequence of 2 satisfies the script, but then the relative lock time itself has to pass
+	if let TxIn::TxPrevious {sequence, ..} = &mut out_of_vault.tx_ins[0] {
+	    *sequence = 2;
+	}
+	sign(&mut out_of_vault);
+	assert_eq!(chain.try_add_tx_to_mempool(out_of_vault.clone()), Err(TransactionError::SequenceLocked));
+	mine_empty_block(&mut chain, &private_key);
+	assert_eq!(chain.try_add_tx_to_mempool(out_of_vault), Ok(()));
+    }
+
+    /// the coinbase of the first block gets moved to a 2 of 3 multisig wrapped in pay to script hash, which is then spent with two of the keys
+    #[test]
+    fn pay_to_script_hash_in_chain() {
+	let private_key = key_from_seed("adamadamadamadamadamadamadamadam");
+	let (mut chain, coinbase_hash) = chain_with_one_block(&private_key);
+	let multisig_keys = [key_from_seed("bobbobbobbobbobbobbobbobbobbobbo"), key_from_seed("evevevevevevevevevevevevevevevev"), key_from_seed("caincaincaincaincaincaincaincain")];
+	let public_keys: Vec<VerifyingKey<Secp256k1>> = multisig_keys.iter().map(|key| key.verifying_key()).collect();
+	let redeem_script = pay_to_multisig(2, &public_keys);
+	let mut into_multisig = spend_transaction(&private_key, coinbase_hash, 0, 1000);
+	into_multisig.tx_outs[0].locking_script = pay_to_script_hash(&redeem_script);
+	sign_p2pkh_tx_in(&private_key, &mut into_multisig, 0);
+	let multisig_hash = into_multisig.txid();
+	let block = mine_block_on(&chain, chain.best_tip().unwrap(), private_key.verifying_key(), vec![into_multisig]);
+	chain.add_block(block).unwrap();
+
+	let mut out_of_multisig = Transaction {
+	    version: 1,
+	    lock_time: 0,
+	    tx_ins: vec![TxIn::TxPrevious {tx_hash: multisig_hash, tx_out_index: 0, unlocking_script: Script {ops: vec![]}, sequence: SEQUENCE_FINAL, witness: vec![]}],
+	    tx_outs: vec![TxOut {value: 900, locking_script: Script {ops: vec![StackOp::OpDup]}}],
+	};
+	let sign = |transaction: &mut Transaction, signers: &[usize]| {
+	    let mut ops = vec![StackOp::Val(0)];
+	    for signer in signers {
+		let signature = sign_tx_in(&multisig_keys[*signer], transaction, 0, &redeem_script, SIGHASH_ALL);
+		ops.push(StackOp::Bytes(signature.into_boxed_slice()));
+	    }
+	    ops.push(StackOp::Bytes(redeem_script.to_bytes().into_boxed_slice()));
+	    if let TxIn::TxPrevious {unlocking_script, ..} = &mut transaction.tx_ins[0] {
+		*unlocking_script = Script {ops};
+	    }
+	};
+	// the signatures have to be in the order of the keys in the redeem script
+	sign(&mut out_of_multisig, &[2, 1]);
+	let out_of_order = TransactionError::InvalidScript {input_index: 0, error: ScriptError::EvalFalse};
+	assert_eq!(chain.try_add_tx_to_mempool(out_of_multisig.clone()), Err(out_of_order));
+	sign(&mut out_of_multisig, &[1, 2]);
+	// the signatures don't cover the dummy item that OpCheckMultiSig pops, so anyone could change it, but a block can't (BIP147)
+	let mut non_null_dummy = out_of_multisig.clone();
+	if let TxIn::TxPrevious {unlocking_script, ..} = &mut non_null_dummy.tx_ins[0] {
+	    unlocking_script.ops[0] = StackOp::Val(1);
+	}
+	let block = mine_block_on(&chain, chain.best_tip().unwrap(), private_key.verifying_key(), vec![non_null_dummy]);
+	let dummy_error = TransactionError::InvalidScript {input_index: 0, error: ScriptError::SigNullDummy};
+	assert_eq!(chain.add_block(block), Err(BlockValidationError::InvalidTransaction(dummy_error)));
+	assert_eq!(chain.try_add_tx_to_mempool(out_of_multisig.clone()), Ok(()));
+	let block = mine_block_on(&chain, chain.best_tip().unwrap(), private_key.verifying_key(), vec![out_of_multisig]);
+	assert_eq!(chain.add_block(block), Ok(()));
+    }
+    /// spend into a pay to witness public key hash and a pay to witness script hash, and then out of both of them again.
+    /// The block with the second transaction has to commit to its witnesses
+    #[test]
+    fn segwit_in_chain() {
+	let private_key = key_from_seed("adamadamadamadamadamadamadamadam");
+	let (mut chain, coinbase_hash) = chain_with_one_block(&private_key);
+	let witness_key = key_from_seed("bobbobbobbobbobbobbobbobbobbobbo");
+	let witness_public_key_bytes = witness_key.verifying_key().to_encoded_point(true).to_bytes().to_vec();
+	let multisig_keys = [key_from_seed("evevevevevevevevevevevevevevevev"), key_from_seed("caincaincaincaincaincaincaincain")];
+	let public_keys: Vec<VerifyingKey<Secp256k1>> = multisig_keys.iter().map(|key| key.verifying_key()).collect();
+	let witness_script = pay_to_multisig(2, &public_keys);
+
+	let mut into_segwit = spend_transaction(&private_key, coinbase_hash, 0, 1000);
+	into_segwit.tx_outs[0].locking_script = pay_to_witness_public_key_hash(&witness_public_key_bytes);
+	into_segwit.tx_outs.push(TxOut {value: 2000, locking_script: pay_to_witness_script_hash(&witness_script)});
+	sign_p2pkh_tx_in(&private_key, &mut into_segwit, 0);
+	let segwit_hash = into_segwit.txid();
+	let block = mine_block_on(&chain, chain.best_tip().unwrap(), private_key.verifying_key(), vec![into_segwit]);
+	chain.add_block(block).unwrap();
+
+	let tx_in = |tx_out_index| TxIn::TxPrevious {tx_hash: segwit_hash, tx_out_index, unlocking_script: Script {ops: vec![]}, sequence: SEQUENCE_FINAL, witness: vec![]};
+	let mut out_of_segwit = Transaction {
+	    version: 1,
+	    lock_time: 0,
+	    tx_ins: vec![tx_in(0), tx_in(1)],
+	    tx_outs: vec![TxOut {value: 2500, locking_script: Script {ops: vec![StackOp::OpDup]}}],
+	};
+	// the signatures commit to the amounts being spent
+	let script_code = pay_to_public_key_hash(&witness_public_key_bytes);
+	let signature = sign_segwit_tx_in(&witness_key, &out_of_segwit, 0, &script_code, 1000, SIGHASH_ALL);
+	*out_of_segwit.tx_ins[0].witness_mut() = vec![signature, witness_public_key_bytes];
+	let mut witness = vec![vec![]];
+	for key in &multisig_keys {
+	    witness.push(sign_segwit_tx_in(key, &out_of_segwit, 1, &witness_script, 2000, SIGHASH_ALL));
+	}
+	witness.push(witness_script.to_bytes());
+	*out_of_segwit.tx_ins[1].witness_mut() = witness;
+	// the witness is a quarter of the weight
+	assert!(out_of_segwit.weight() < 4 * out_of_segwit.total_size());
+	assert_eq!(chain.try_add_tx_to_mempool(out_of_segwit.clone()), Ok(()));
+
+	let mut block = chain.construct_candidate_block(private_key.verifying_key());
+	block.mine();
+	assert_eq!(block.transaction_list[1], out_of_segwit);
+	assert!(block.witness_commitment_index().is_some());
+	assert_eq!(block.block_size as usize, serialize(&block).len());
+	// the coinbase's witness is part of the commitment
+	let mut bad_commitment = block.clone();
+	*bad_commitment.transaction_list[0].tx_ins[0].witness_mut() = vec![vec![1; 32]];
+	assert_eq!(chain.add_block(bad_commitment), Err(BlockValidationError::BadWitnessCommitment));
+	// and so are the other witnesses, even though they aren't in the merkle root
+	let mut malleated = block.clone();
+	malleated.transaction_list[1].tx_ins[1].witness_mut()[0] = vec![0];
+	assert_eq!(chain.add_block(malleated), Err(BlockValidationError::BadWitnessCommitment));
+	// without a commitment, there can't be any witnesses
+	let no_commitment = mine_block_on(&chain, chain.best_tip().unwrap(), private_key.verifying_key(), vec![out_of_segwit]);
+	assert_eq!(chain.add_block(no_commitment), Err(BlockValidationError::UnexpectedWitness));
+	assert_eq!(chain.add_block(block), Ok(()));
+    }
+
+    /// taproot signatures commit to everything being spent, so verifying needs the tx_outs of all the tx_ins
+    #[test]
+    fn taproot_in_chain() {
+	let private_key = key_from_seed("adamadamadamadamadamadamadamadam");
+	let (mut chain, coinbase_hash) = chain_with_one_block(&private_key);
+	let taproot_keys = [key_from_seed("evevevevevevevevevevevevevevevev"), key_from_seed("caincaincaincaincaincaincaincain")];
+	let locking_scripts: Vec<Script> = taproot_keys.iter().map(|key| pay_to_taproot(&x_only_public_key(key), None)).collect();
+
+	let mut into_taproot = spend_transaction(&private_key, coinbase_hash, 0, 1000);
+	into_taproot.tx_outs[0].locking_script = locking_scripts[0].clone();
+	into_taproot.tx_outs.push(TxOut {value: 2000, locking_script: locking_scripts[1].clone()});
+	sign_p2pkh_tx_in(&private_key, &mut into_taproot, 0);
+	let taproot_hash = into_taproot.txid();
+	let block = mine_block_on(&chain, chain.best_tip().unwrap(), private_key.verifying_key(), vec![into_taproot.clone()]);
+	chain.add_block(block).unwrap();
+
+	let tx_in = |tx_out_index| TxIn::TxPrevious {tx_hash: taproot_hash, tx_out_index, unlocking_script: Script {ops: vec![]}, sequence: SEQUENCE_FINAL, witness: vec![]};
+	let mut out_of_taproot = Transaction {
+	    version: 2,
+	    lock_time: 0,
+	    tx_ins: vec![tx_in(0), tx_in(1)],
+	    tx_outs: vec![TxOut {value: 2500, locking_script: Script {ops: vec![StackOp::OpDup]}}],
+	};
+	let sign = |transaction: &Transaction, spent_tx_outs: &[TxOut]| -> Vec<Vec<Vec<u8>>> {
+	    taproot_keys.iter().enumerate().map(|(input_index, key)| {
+		let tweaked_key = tweak_private_key(key, None).unwrap();
+		vec![sign_taproot_tx_in(&tweaked_key, transaction, input_index, spent_tx_outs, SIGHASH_DEFAULT, None)]
+	    }).collect()
+	};
+	// signing for the wrong amounts doesn't do
+	let mut wrong_amounts = into_taproot.tx_outs.clone();
+	wrong_amounts[1].value = 2001;
+	for (input_index, witness) in sign(&out_of_taproot, &wrong_amounts).into_iter().enumerate() {
+	    *out_of_taproot.tx_ins[input_index].witness_mut() = witness;
+	}
+	assert_eq!(chain.try_add_tx_to_mempool(out_of_taproot.clone()), Err(TransactionError::InvalidScript {input_index: 0, error: ScriptError::SchnorrSig}));
+	for (input_index, witness) in sign(&out_of_taproot, &into_taproot.tx_outs).into_iter().enumerate() {
+	    *out_of_taproot.tx_ins[input_index].witness_mut() = witness;
+	}
+	assert_eq!(chain.try_add_tx_to_mempool(out_of_taproot.clone()), Ok(()));
+
+	let mut block = chain.construct_candidate_block(private_key.verifying_key());
+	block.mine();
+	assert_eq!(block.transaction_list[1], out_of_taproot);
+	assert_eq!(chain.add_block(block), Ok(()));
+    }
+
+    /// transactions go into a block by tip per weight, and the ones that don't fit wait for the next block
+    #[test]
+    fn candidate_block_by_weight() {
+	let private_key = key_from_seed("adamadamadamadamadamadamadamadam");
+	let (mut chain, first_coinbase_hash) = chain_with_one_block(&private_key);
+	let block = mine_block_on(&chain, chain.best_tip().unwrap(), private_key.verifying_key(), vec![]);
+	let second_coinbase_hash = block.transaction_list[0].txid();
+	chain.add_block(block).unwrap();
+	let reward = BlockChain::determine_coinbase_reward(0);
+	let low_tip = spend_transaction(&private_key, first_coinbase_hash, 0, reward - 10);
+	let high_tip = spend_transaction(&private_key, second_coinbase_hash, 0, reward - 1000);
+	assert_eq!(chain.try_add_tx_to_mempool(low_tip.clone()), Ok(()));
+	assert_eq!(chain.try_add_tx_to_mempool(high_tip.clone()), Ok(()));
+
+	chain.max_block_weight = COINBASE_WEIGHT_RESERVE + high_tip.weight();
+	let block = chain.construct_candidate_block(private_key.verifying_key());
+	assert_eq!(block.transaction_list[1..], [high_tip]);
+	assert_eq!(block.transaction_list[0].tx_outs[0].value, reward + 1000);
+	assert_eq!(chain.mempool.len(), 1);
+	chain.max_block_weight = MAX_BLOCK_WEIGHT;
+	let block = chain.construct_candidate_block(private_key.verifying_key());
+	assert_eq!(block.transaction_list[1..], [low_tip]);
+	assert!(block.weight() > 4 * HEADER_SIZE);
+    }
+
+    /// a block can only check so many signatures, however many the mempool has taken in
+    #[test]
+    fn block_sigops_limit() {
+	let private_key = key_from_seed("adamadamadamadamadamadamadamadam");
+	let (mut chain, coinbase_hash) = chain_with_one_block(&private_key);
+	let tip = chain.best_tip().unwrap();
+	// each of these counts as 20 signatures for every OpCheckMultiSig, at 4 apiece, even though it never gets run
+	let check_multisigs = TxOut {value: 1, locking_script: Script {ops: vec![StackOp::OpCheckMultiSig; MAX_OPS_PER_SCRIPT]}};
+	let signed = |count: usize| {
+	    let mut transaction = spend_transaction(&private_key, coinbase_hash, 0, 0);
+	    transaction.tx_outs = vec![check_multisigs.clone(); count];
+	    sign_p2pkh_tx_in(&private_key, &mut transaction, 0);
+	    transaction
+	};
+	let too_many = signed(5);
+	assert_eq!(too_many.sigop_cost(&[]), 5 * MAX_OPS_PER_SCRIPT * 20 * 4);
+	let block = mine_block_on(&chain, tip, private_key.verifying_key(), vec![too_many.clone()]);
+	assert_eq!(chain.add_block(block), Err(BlockValidationError::BadBlockSigops));
+
+	// the mempool takes it, but leaves it out of the candidate block
+	assert_eq!(chain.try_add_tx_to_mempool(too_many), Ok(()));
+	let block = chain.construct_candidate_block(private_key.verifying_key());
+	assert_eq!(block.transaction_list.len(), 1);
+	assert_eq!(chain.mempool.len(), 1);
+
+	let block = mine_block_on(&chain, tip, private_key.verifying_key(), vec![signed(4)]);
+	assert_eq!(chain.add_block(block), Ok(()));
+    }
 }
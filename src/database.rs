@@ -1,8 +1,9 @@
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
-use crate::transaction::{Transaction};
+use std::collections::{HashMap, HashSet};
+use crate::transaction::{Transaction, TxOut, OutPoint, TransactionError};
+use ethereum_types::U256;
 use crate::{Hash};
-use crate::block::{Block};
+use crate::block::{Block, BlockValidationError};
 
 /// This struct holds a mapping from transaction hash to the transaction for all exisitng blocks
 /// It also keeps a record of how many blocks it has seen so far
@@ -20,32 +21,288 @@ impl TransactionDataBase {
 	}
     }
 
-    pub fn get(&self, entry: &Hash) -> Option<&Transaction> {
-	self.transactions_by_hash.get(entry)
+    /// given a block that has just been connected to the chain, include its transactions into our storage
+    pub fn read_block(&mut self, block: &Block) {
+	for transaction in &block.transaction_list {
+	    let transaction_hash = transaction.txid();
+	    self.transactions_by_hash.insert(transaction_hash, transaction.clone());
+	}
+	self.num_blocks_analyzed += 1;
+    }
+
+    /// the reverse of read_block(), for a block that has just been disconnected from the chain
+    pub fn unread_block(&mut self, block: &Block) {
+	for transaction in &block.transaction_list {
+	    self.transactions_by_hash.remove(&transaction.txid());
+	}
+	self.num_blocks_analyzed -= 1;
+    }
+}
+
+
+/// The tx_outs of one transaction in the utxo set, along with the height of the block that the transaction is in
+/// (which relative lock times count from) and whether it is a coinbase (which can't be spent until it is deep enough)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct TransactionOutputs {
+    height: u32,
+    is_coinbase: bool,
+    tx_outs: Vec<Option<TxOut>>,
+}
+
+/// The set of unspent transaction outputs (UTXOs) of the chain.
+/// For every transaction that has been added, we hold one slot per tx_out, which is Some while the tx_out
+/// is unspent and None once a tx_in has consumed it.
+/// Once all of its tx_outs are spent, the transaction is dropped, so the set only grows with what is still unspent.
+/// That means that (like in bitcoin core) a tx_out that was spent by a block looks just the same as one that never existed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UtxoSet {
+    tx_outs_by_hash: HashMap<Hash, TransactionOutputs>,
+}
+
+impl UtxoSet {
+    pub fn new() -> Self {
+	Self {
+	    tx_outs_by_hash: HashMap::new(),
+	}
+    }
+
+    /// return the tx_out referred to by the outpoint, if it exists and is still unspent
+    pub fn get_utxo(&self, outpoint: &OutPoint) -> Option<&TxOut> {
+	self.tx_outs_by_hash.get(&outpoint.tx_hash)?.tx_outs.get(outpoint.tx_out_index as usize)?.as_ref()
+    }
+
+    /// the height of the block that the transaction with the given hash is in, as long as it has unspent tx_outs
+    pub fn height_of(&self, tx_hash: &Hash) -> Option<u32> {
+	self.tx_outs_by_hash.get(tx_hash).map(|outputs| outputs.height)
+    }
+
+    /// look up an outpoint that a tx_in wants to spend, with an error if it isn't there (any more)
+    pub fn check_unspent(&self, outpoint: &OutPoint) -> Result<&TxOut, TransactionError> {
+	self.get_utxo(outpoint).ok_or(TransactionError::TxInNotFound)
+    }
+
+    /// like check_unspent(), for a tx_in of a transaction that goes into a block at the given height.
+    /// The tx_outs of a coinbase can only be spent once it is coinbase_maturity blocks deep
+    pub fn check_spendable(&self, outpoint: &OutPoint, height: u32, coinbase_maturity: u32) -> Result<&TxOut, TransactionError> {
+	let tx_out = self.check_unspent(outpoint)?;
+	let outputs = &self.tx_outs_by_hash[&outpoint.tx_hash];
+	if outputs.is_coinbase && height.saturating_sub(outputs.height) < coinbase_maturity {
+	    return Err(TransactionError::ImmatureCoinbaseSpend);
+	}
+	Ok(tx_out)
     }
-	
-    /// given a blockchain, we read blocks that we have not already read yet, and include the
-    /// TxOuts from the newly read blocks into our storage
-    pub fn read_blocks(&mut self, blocks: &Vec<Block>) {
-	// TODO: impl iterator for blockchain struct itself?
-	for block in blocks.iter().skip(self.num_blocks_analyzed as usize) {
-	    for transaction in &block.transaction_list {
-		println!("transaction = {:?}", transaction);
-		let transaction_hash = transaction.hash();
-		println!("transaction_hash = {:?}", transaction_hash);
-		self.transactions_by_hash.insert(transaction_hash, transaction.clone());
+
+    /// go through the transactions of a new block, removing every tx_out that gets spent and adding all new tx_outs.
+    /// We first check that every tx_in refers to an unspent tx_out (either from a previous block or from earlier in this block),
+    /// that no tx_out gets spent twice, and that no coinbase gets spent before it is coinbase_maturity blocks deep. Only then is the set modified, so an invalid block leaves the set untouched.
+    /// The tx_outs that the block spent are returned, so that the block can be undone later on, along with how the transactions
+    /// that they came from looked before the block (which might have been fully spent and dropped since)
+    pub fn apply_block(&mut self, block: &Block, height: u32, coinbase_maturity: u32) -> Result<BlockUndo, TransactionError> {
+	let coinbase_hash = block.transaction_list.first().filter(|transaction| transaction.is_coinbase()).map(Transaction::txid);
+	let mut created_in_block: HashSet<OutPoint> = HashSet::new();
+	let mut spent_in_block: HashSet<OutPoint> = HashSet::new();
+	let mut previous_outputs = HashMap::new();
+	for transaction in &block.transaction_list {
+	    for outpoint in transaction.tx_ins.iter().filter_map(|tx_in| tx_in.outpoint()) {
+		if !spent_in_block.insert(outpoint) {
+		    return Err(TransactionError::DoubleSpend);
+		}
+		if created_in_block.contains(&outpoint) {
+		    // the coinbase of this very block is as immature as it gets
+		    if Some(outpoint.tx_hash) == coinbase_hash && coinbase_maturity > 0 {
+			return Err(TransactionError::ImmatureCoinbaseSpend);
+		    }
+		} else {
+		    self.check_spendable(&outpoint, height, coinbase_maturity)?;
+		    previous_outputs.entry(outpoint.tx_hash).or_insert_with(|| self.tx_outs_by_hash[&outpoint.tx_hash].clone());
+		}
+	    }
+	    let tx_hash = transaction.txid();
+	    // BIP30: nothing may overwrite a transaction with the same txid that still has unspent tx_outs, e.g. a copy of an old coinbase,
+	    // since undoing the block would then lose the old tx_outs too. Fully spent ones are gone already, so those can come back
+	    if self.tx_outs_by_hash.contains_key(&tx_hash) {
+		return Err(TransactionError::DuplicateTransaction);
+	    }
+	    for tx_out_index in 0..transaction.tx_outs.len() as u32 {
+		created_in_block.insert(OutPoint::new(tx_hash, tx_out_index));
+	    }
+	}
+
+	// everything checks out, so now we can update the set
+	let mut spent_tx_outs = Vec::new();
+	for transaction in &block.transaction_list {
+	    let mut spent_by_transaction = Vec::new();
+	    for outpoint in transaction.tx_ins.iter().filter_map(|tx_in| tx_in.outpoint()) {
+		let tx_out = self.spend(&outpoint).expect("we already checked that the outpoint is unspent");
+		spent_by_transaction.push(tx_out);
 	    }
-	    self.num_blocks_analyzed += 1;
+	    spent_tx_outs.push(spent_by_transaction);
+	    self.add_transaction(transaction, height);
+	}
+	Ok(BlockUndo {spent_tx_outs, previous_outputs})
+    }
+
+    /// the reverse of apply_block(): remove the tx_outs that the block created, and put back the ones that it spent.
+    /// This must be called with the block that was most recently applied, along with the undo data that applying it returned
+    pub fn undo_block(&mut self, block: &Block, undo: &BlockUndo) {
+	for transaction in &block.transaction_list {
+	    self.tx_outs_by_hash.remove(&transaction.txid());
+	}
+	// the transactions that the block spent from go back to how they were, whether or not the block dropped them
+	for (tx_hash, outputs) in &undo.previous_outputs {
+	    self.tx_outs_by_hash.insert(*tx_hash, outputs.clone());
+	}
+    }
+
+    /// add all of the tx_outs of the transaction as unspent
+    fn add_transaction(&mut self, transaction: &Transaction, height: u32) {
+	let tx_outs = transaction.tx_outs.iter().cloned().map(Some).collect();
+	self.tx_outs_by_hash.insert(transaction.txid(), TransactionOutputs {height, is_coinbase: transaction.is_coinbase(), tx_outs});
+    }
+
+    /// mark the tx_out referred to by the outpoint as spent, returning it if it was unspent.
+    /// If that was the last unspent tx_out of its transaction, the transaction is dropped
+    fn spend(&mut self, outpoint: &OutPoint) -> Option<TxOut> {
+	let outputs = self.tx_outs_by_hash.get_mut(&outpoint.tx_hash)?;
+	let tx_out = outputs.tx_outs.get_mut(outpoint.tx_out_index as usize)?.take();
+	if outputs.tx_outs.iter().all(Option::is_none) {
+	    self.tx_outs_by_hash.remove(&outpoint.tx_hash);
 	}
+	tx_out
     }
 }
 
+/// The undo data for a block: for each of its transactions, the tx_outs (in the order of the tx_ins) that it spent,
+/// and the earlier transactions that it spent from, as they were in the utxo set before the block.
+/// This is everything we need to roll the utxo set back to how it was before the block, e.g. during a reorg
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockUndo {
+    pub spent_tx_outs: Vec<Vec<TxOut>>,
+    previous_outputs: HashMap<Hash, TransactionOutputs>,
+}
 
+impl BlockUndo {
+    /// the height of the block that an earlier transaction which this block spends from is in
+    pub fn height_of(&self, tx_hash: &Hash) -> Option<u32> {
+	self.previous_outputs.get(tx_hash).map(|outputs| outputs.height)
+    }
+}
 
-/// eventually i think the blocks will be stored differently than a Vec<Block>
-/// TODO
-//struct BlockDataBase {}
-//struct BlockHeaderDataBase {}
+/// A block that we know of, along with where it sits in the tree of blocks
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockEntry {
+    pub block: Block,
+    pub parent_hash: Option<Hash>, // the hash of the previous block, or None if this is the genesis block
+    pub height: u32, // how many blocks come before this one
+    pub chain_work: U256, // the total work of this block plus all of its ancestors
+}
+
+/// This struct holds every block that we know of, keyed by the hash of its header.
+/// Each block points back at its parent, so together the blocks form a tree rooted at the genesis block,
+/// where each leaf is the tip of a (potentially competing) branch.
+/// The block database itself does not decide which branch is the active chain; it simply keeps track of the tip
+/// it is told is the best.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockDataBase {
+    blocks_by_hash: HashMap<Hash, BlockEntry>,
+    best_tip: Option<Hash>,
+}
+
+impl BlockDataBase {
+    pub fn new() -> Self {
+	Self {
+	    blocks_by_hash: HashMap::new(),
+	    best_tip: None,
+	}
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<&BlockEntry> {
+	self.blocks_by_hash.get(hash)
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+	self.blocks_by_hash.contains_key(hash)
+    }
+
+    pub fn best_tip(&self) -> Option<Hash> {
+	self.best_tip
+    }
+
+    pub fn best_tip_entry(&self) -> Option<&BlockEntry> {
+	self.best_tip.and_then(|hash| self.get(&hash))
+    }
+
+    pub fn set_best_tip(&mut self, hash: Option<Hash>) {
+	self.best_tip = hash;
+    }
+
+    /// given a new block, put it into the tree below its parent and return its hash.
+    /// The genesis block is the only one with a previous hash of 0, and there may only be one.
+    /// Any other block must point at a block that we already know of.
+    pub fn insert(&mut self, block: Block) -> Result<Hash, BlockValidationError> {
+	let hash = block.block_header.hash();
+	if self.contains(&hash) {
+	    return Err(BlockValidationError::AlreadyKnown);
+	}
+	let previous_block_hash = block.block_header.previous_block_hash();
+	let block_work = block.block_header.difficulty_bits().work();
+	let entry = if previous_block_hash.is_zero() {
+	    if self.blocks_by_hash.values().any(|entry| entry.parent_hash.is_none()) {
+		return Err(BlockValidationError::UnknownParent);
+	    }
+	    BlockEntry {block, parent_hash: None, height: 0, chain_work: block_work}
+	} else {
+	    let parent = self.get(&previous_block_hash).ok_or(BlockValidationError::UnknownParent)?;
+	    let height = parent.height + 1;
+	    let chain_work = parent.chain_work + block_work;
+	    BlockEntry {block, parent_hash: Some(previous_block_hash), height, chain_work}
+	};
+	self.blocks_by_hash.insert(hash, entry);
+	Ok(hash)
+    }
+
+    /// forget about a block, e.g. when it turns out to be invalid. Only leaves (blocks without children) should be removed
+    pub fn remove(&mut self, hash: &Hash) -> Option<BlockEntry> {
+	self.blocks_by_hash.remove(hash)
+    }
+
+    /// forget about a block along with every block built on top of it, since they can never be valid either.
+    /// Returns how many blocks were removed
+    pub fn remove_with_descendants(&mut self, hash: &Hash) -> usize {
+	let mut to_remove = vec![*hash];
+	let mut num_removed = 0;
+	while let Some(hash) = to_remove.pop() {
+	    to_remove.extend(self.blocks_by_hash.iter().filter(|(_, entry)| entry.parent_hash == Some(hash)).map(|(child_hash, _)| *child_hash));
+	    num_removed += self.remove(&hash).is_some() as usize;
+	}
+	num_removed
+    }
+
+    /// iterate from the given block back through its parents to the genesis block
+    pub fn ancestors(&self, hash: Hash) -> Ancestors<'_> {
+	Ancestors {
+	    block_database: self,
+	    next_hash: Some(hash),
+	}
+    }
+}
+
+/// Iterator that walks from a block back to the genesis block, see BlockDataBase::ancestors()
+pub struct Ancestors<'a> {
+    block_database: &'a BlockDataBase,
+    next_hash: Option<Hash>,
+}
+
+impl<'a> Iterator for Ancestors<'a> {
+    type Item = (Hash, &'a BlockEntry);
+
+    fn next(&mut self) -> Option<Self::Item> {
+	let hash = self.next_hash?;
+	let entry = self.block_database.get(&hash)?;
+	self.next_hash = entry.parent_hash;
+	Some((hash, entry))
+    }
+}
 
 
 #[cfg(test)]
@@ -54,26 +311,115 @@ mod tests {
     use ecdsa::{SigningKey, VerifyingKey};
     use k256::{Secp256k1};
     use crate::blockchain::BlockChain;
+    use crate::transaction::TxIn;
+    use crate::script::Script;
     
     #[test]
     fn test_read_blocks() {
 	let mut chain = BlockChain::new();	
 	let num_blocks = 3;
 	let b = "adamadamadamadamadamadamadamadam".as_bytes(); // arbitrary for testing. 32 long
-	let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(&b).unwrap();
+	let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(b).unwrap();
 	let public_key: VerifyingKey<Secp256k1> = private_key.verifying_key();
-	// add some aribtrary blocks to the chain (don't bother mining them)
-	//let hashes_vec = Vec::new();
+	// add some aribtrary blocks to the chain
 	for _ in 0..num_blocks {
-	    let block = chain.construct_candidate_block(public_key);
+	    let mut block = chain.construct_candidate_block(public_key);
+	    block.mine();
 	    println!("about to add block: {:?}", block);
-	    chain.add_block(block);
+	    chain.add_block(block).unwrap();
 	}
 
 	let mut database = TransactionDataBase::new();
-	database.read_blocks(&chain.blocks);
+	for block in chain.ancestors(chain.best_tip().unwrap()) {
+	    database.read_block(block);
+	}
 	assert_eq!(database.num_blocks_analyzed, num_blocks);
 	// each block only has the coinbase transaction
 	assert_eq!(database.transactions_by_hash.len(), num_blocks as usize);	
+
+	// taking the tip off again forgets its coinbase
+	let tip = chain.ancestors(chain.best_tip().unwrap()).next().unwrap();
+	database.unread_block(tip);
+	assert_eq!(database.num_blocks_analyzed, num_blocks - 1);
+	assert_eq!(database.transactions_by_hash.len(), num_blocks as usize - 1);
+	assert!(!database.transactions_by_hash.contains_key(&tip.transaction_list[0].txid()));
+    }
+
+    /// a transaction may spend a tx_out created earlier in the same block
+    #[test]
+    fn test_apply_block_spend_within_block() {
+	let mut chain = BlockChain::new();
+	let b = "adamadamadamadamadamadamadamadam".as_bytes(); // arbitrary for testing. 32 long
+	let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(b).unwrap();
+	let mut block = chain.construct_candidate_block(private_key.verifying_key());
+	let coinbase = block.transaction_list[0].clone();
+	let mut spend = coinbase.clone();
+	spend.tx_ins = vec![TxIn::TxPrevious {
+	    tx_hash: coinbase.txid(),
+	    tx_out_index: 0,
+	    unlocking_script: Script {ops: vec![]}, // scripts are not the concern of the utxo set
+	    sequence: 0,
+	    witness: vec![],
+	}];
+	block.transaction_list.push(spend.clone());
+
+	let mut utxo_set = UtxoSet::new();
+	// a coinbase has to be a block deep at least
+	assert_eq!(utxo_set.apply_block(&block, 7, 1), Err(TransactionError::ImmatureCoinbaseSpend));
+	let undo = utxo_set.apply_block(&block, 7, 0).unwrap();
+	// the coinbase spends nothing, and the second transaction spends the coinbase's tx_out
+	assert_eq!(undo.spent_tx_outs, vec![vec![], vec![coinbase.tx_outs[0].clone()]]);
+	let coinbase_outpoint = OutPoint::new(coinbase.txid(), 0);
+	assert!(utxo_set.get_utxo(&coinbase_outpoint).is_none());
+	assert!(utxo_set.get_utxo(&OutPoint::new(spend.txid(), 0)).is_some());
+	assert_eq!(utxo_set.check_unspent(&coinbase_outpoint).err(), Some(TransactionError::TxInNotFound));
+	assert_eq!(utxo_set.check_unspent(&OutPoint::new(Hash::zero(), 0)).err(), Some(TransactionError::TxInNotFound));
+	// the coinbase is fully spent, so it's gone
+	assert_eq!(utxo_set.height_of(&coinbase.txid()), None);
+	assert_eq!(utxo_set.height_of(&spend.txid()), Some(7));
+	assert_eq!(utxo_set.tx_outs_by_hash.len(), 1);
+
+	// undoing the block takes us right back to where we started
+	utxo_set.undo_block(&block, &undo);
+	assert_eq!(utxo_set, UtxoSet::new());
+    }
+
+    /// a fully spent transaction is dropped, so a later block may bring back the same txid (BIP30 allows that).
+    /// Undoing both blocks has to give back the tx_outs that the earlier one spent
+    #[test]
+    fn test_undo_block_after_txid_comes_back() {
+	let mut chain = BlockChain::new();
+	let b = "adamadamadamadamadamadamadamadam".as_bytes(); // arbitrary for testing. 32 long
+	let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(b).unwrap();
+	let template = chain.construct_candidate_block(private_key.verifying_key());
+	let coinbase = template.transaction_list[0].clone();
+	let block_with = |transaction_list: Vec<Transaction>| Block {transaction_list, ..template.clone()};
+	let mut spend = coinbase.clone();
+	spend.tx_ins = vec![TxIn::TxPrevious {
+	    tx_hash: coinbase.txid(),
+	    tx_out_index: 0,
+	    unlocking_script: Script {ops: vec![]}, // scripts are not the concern of the utxo set
+	    sequence: 0,
+	    witness: vec![],
+	}];
+	let first_block = block_with(vec![coinbase.clone()]);
+	let spending_block = block_with(vec![spend]);
+	let duplicate_block = block_with(vec![coinbase.clone()]);
+
+	let mut utxo_set = UtxoSet::new();
+	utxo_set.apply_block(&first_block, 0, 1).unwrap();
+	let after_first_block = utxo_set.clone();
+	let spending_undo = utxo_set.apply_block(&spending_block, 1, 1).unwrap();
+	assert_eq!(utxo_set.height_of(&coinbase.txid()), None);
+	// the same txid again, while the first one still has unspent tx_outs, is not allowed
+	assert_eq!(after_first_block.clone().apply_block(&duplicate_block, 1, 1), Err(TransactionError::DuplicateTransaction));
+	let duplicate_undo = utxo_set.apply_block(&duplicate_block, 2, 1).unwrap();
+	assert_eq!(utxo_set.height_of(&coinbase.txid()), Some(2));
+
+	utxo_set.undo_block(&duplicate_block, &duplicate_undo);
+	assert_eq!(utxo_set.height_of(&coinbase.txid()), None);
+	utxo_set.undo_block(&spending_block, &spending_undo);
+	assert_eq!(utxo_set, after_first_block);
+	assert_eq!(utxo_set.get_utxo(&OutPoint::new(coinbase.txid(), 0)), Some(&coinbase.tx_outs[0]));
     }
 }
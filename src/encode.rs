@@ -0,0 +1,217 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::{Hash, hash_to_bytes};
+use crate::script::ScriptDecodeError;
+
+/// The reasons that some bytes can't be decoded into what we asked for
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    UnexpectedEnd, // we ran out of bytes part way through
+    NonMinimalCompactSize, // a CompactSize used more bytes than it needed to, so the same data would have two encodings
+    InvalidScript(ScriptDecodeError), // a push in a script runs off the end of it
+    TrailingBytes, // there were bytes left over after the thing we were decoding
+    SuperfluousWitness, // a transaction in the segwit serialization, but without any witnesses
+    UnknownTransactionFlag(u8), // the flag after the segwit marker has bits set that we don't know the meaning of
+}
+
+impl From<std::io::Error> for DecodeError {
+    /// we only ever read from byte slices, so the only thing that can go wrong is running out of them
+    fn from(_error: std::io::Error) -> Self {
+	DecodeError::UnexpectedEnd
+    }
+}
+
+impl From<ScriptDecodeError> for DecodeError {
+    fn from(error: ScriptDecodeError) -> Self {
+	DecodeError::InvalidScript(error)
+    }
+}
+
+/// Bitcoin's consensus serialization, i.e. how things look on the wire and what gets hashed into txids and block hashes.
+/// Everything is little endian
+pub trait Encodable {
+    fn encode(&self, bytes: &mut Vec<u8>);
+}
+
+/// the reverse of Encodable. The bytes get advanced past whatever was decoded, so that decodes can be chained one after the other
+pub trait Decodable: Sized {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, DecodeError>;
+}
+
+/// encode something into a fresh vec of bytes
+pub fn serialize<T: Encodable>(data: &T) -> Vec<u8> {
+    let mut bytes = vec![];
+    data.encode(&mut bytes);
+    bytes
+}
+
+/// decode something that takes up exactly all of the given bytes
+pub fn deserialize<T: Decodable>(mut bytes: &[u8]) -> Result<T, DecodeError> {
+    let data = T::decode(&mut bytes)?;
+    if !bytes.is_empty() {
+	return Err(DecodeError::TrailingBytes);
+    }
+    Ok(data)
+}
+
+/// The variable length integer that bitcoin uses to prefix lists and byte strings with their length.
+/// Small numbers take a single byte, and larger ones get a marker byte followed by 2, 4 or 8 bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompactSize(pub u64);
+
+impl Encodable for CompactSize {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+	match self.0 {
+	    0..=0xfc => bytes.push(self.0 as u8),
+	    0xfd..=0xffff => {
+		bytes.push(0xfd);
+		bytes.extend_from_slice(&(self.0 as u16).to_le_bytes());
+	    },
+	    0x10000..=0xffffffff => {
+		bytes.push(0xfe);
+		bytes.extend_from_slice(&(self.0 as u32).to_le_bytes());
+	    },
+	    _ => {
+		bytes.push(0xff);
+		bytes.extend_from_slice(&self.0.to_le_bytes());
+	    },
+	}
+    }
+}
+
+impl Decodable for CompactSize {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+	let (value, minimum) = match bytes.read_u8()? {
+	    0xfd => (bytes.read_u16::<LittleEndian>()? as u64, 0xfd),
+	    0xfe => (bytes.read_u32::<LittleEndian>()? as u64, 0x10000),
+	    0xff => (bytes.read_u64::<LittleEndian>()?, 0x100000000),
+	    small => (small as u64, 0),
+	};
+	if value < minimum {
+	    return Err(DecodeError::NonMinimalCompactSize);
+	}
+	Ok(CompactSize(value))
+    }
+}
+
+impl Encodable for u32 {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+	bytes.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Decodable for u32 {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+	Ok(bytes.read_u32::<LittleEndian>()?)
+    }
+}
+
+impl Encodable for u64 {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+	bytes.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Decodable for u64 {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+	Ok(bytes.read_u64::<LittleEndian>()?)
+    }
+}
+
+/// hashes go out as the raw SHA256 digest, i.e. the little endian bytes of the number
+impl Encodable for Hash {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+	bytes.extend_from_slice(&hash_to_bytes(self));
+    }
+}
+
+impl Decodable for Hash {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+	Ok(Hash::from_little_endian(&take_bytes(bytes, 32)?))
+    }
+}
+
+/// a list is its length followed by each of the elements
+impl<T: Encodable> Encodable for Vec<T> {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+	CompactSize(self.len() as u64).encode(bytes);
+	for element in self {
+	    element.encode(bytes);
+	}
+    }
+}
+
+impl<T: Decodable> Decodable for Vec<T> {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+	let CompactSize(len) = CompactSize::decode(bytes)?;
+	// we don't trust the length enough to allocate it up front, a bad one will run out of bytes soon enough
+	let mut elements = vec![];
+	for _ in 0..len {
+	    elements.push(T::decode(bytes)?);
+	}
+	Ok(elements)
+    }
+}
+
+/// a byte string (like a script) is its length followed by the bytes themselves
+pub fn encode_bytes(data: &[u8], bytes: &mut Vec<u8>) {
+    CompactSize(data.len() as u64).encode(bytes);
+    bytes.extend_from_slice(data);
+}
+
+/// the reverse of encode_bytes()
+pub fn decode_bytes(bytes: &mut &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let CompactSize(len) = CompactSize::decode(bytes)?;
+    if len > bytes.len() as u64 {
+	return Err(DecodeError::UnexpectedEnd);
+    }
+    take_bytes(bytes, len as usize)
+}
+
+/// split the next len bytes off the front
+pub fn take_bytes(bytes: &mut &[u8], len: usize) -> Result<Vec<u8>, DecodeError> {
+    if bytes.len() < len {
+	return Err(DecodeError::UnexpectedEnd);
+    }
+    let (taken, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(taken.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact_size() {
+	let vectors: [(u64, &str); 7] = [
+	    (0, "00"),
+	    (0xfc, "fc"),
+	    (0xfd, "fdfd00"),
+	    (0xffff, "fdffff"),
+	    (0x10000, "fe00000100"),
+	    (0xffffffff, "feffffffff"),
+	    (0x100000000, "ff0000000001000000"),
+	];
+	for (value, hex_str) in vectors {
+	    let bytes = hex::decode(hex_str).unwrap();
+	    assert_eq!(serialize(&CompactSize(value)), bytes);
+	    assert_eq!(deserialize(&bytes), Ok(CompactSize(value)));
+	}
+    }
+
+    #[test]
+    fn test_decode_errors() {
+	// each of these could have been written in fewer bytes
+	for hex_str in ["fdfc00", "feffff0000", "ffffffffff00000000"] {
+	    let bytes = hex::decode(hex_str).unwrap();
+	    assert_eq!(deserialize::<CompactSize>(&bytes), Err(DecodeError::NonMinimalCompactSize));
+	}
+	assert_eq!(deserialize::<CompactSize>(&hex::decode("fe").unwrap()), Err(DecodeError::UnexpectedEnd));
+	assert_eq!(deserialize::<u32>(&[1, 2, 3]), Err(DecodeError::UnexpectedEnd));
+	assert_eq!(deserialize::<u32>(&[1, 2, 3, 4, 5]), Err(DecodeError::TrailingBytes));
+	// a byte string that claims to be longer than what's left
+	assert_eq!(decode_bytes(&mut &[3, 1, 2][..]), Err(DecodeError::UnexpectedEnd));
+	assert_eq!(decode_bytes(&mut &[2, 1, 2][..]), Ok(vec![1, 2]));
+    }
+}
@@ -1,12 +1,18 @@
 use ethereum_types::U256;
+use sha2::{Sha256, Digest};
 
 mod transaction;
-mod script;
+pub mod script;
 mod block;
 pub mod blockchain;
 mod database;
 mod merkle;
 mod mempool;
+pub mod policy;
+pub mod encode;
+pub mod sighash;
+pub mod taproot;
+pub mod params;
 pub type Hash = U256;
 
 /// This trait defines a function that returns a hash created by
@@ -15,3 +21,33 @@ pub type Hash = U256;
 pub trait DoubleSHA {
     fn sha256d(&self) -> Hash;
 }
+
+/// run some bytes through SHA256 twice. Bitcoin reads the resulting digest as a little endian number,
+/// which is what gets compared against the proof of work target
+pub fn sha256d(bytes: &[u8]) -> Hash {
+    let first_hash = Sha256::digest(bytes);
+    let second_hash = Sha256::digest(first_hash);
+    Hash::from_little_endian(&second_hash)
+}
+
+/// the bytes of a hash the way it gets serialized, i.e. the digest as it came out of SHA256
+pub fn hash_to_bytes(hash: &Hash) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    hash.to_little_endian(&mut bytes);
+    bytes
+}
+
+/// Hashes are displayed with their bytes reversed, which happens to be simply the number in big endian hex.
+/// This is how block explorers show block and transaction hashes
+pub fn hash_to_hex(hash: &Hash) -> String {
+    format!("{:064x}", hash)
+}
+
+/// parse a hash from the (byte reversed) hex that block explorers show
+pub fn hash_from_hex(hex_str: &str) -> Option<Hash> {
+    let bytes = hex::decode(hex_str).ok()?;
+    if bytes.len() != 32 {
+	return None;
+    }
+    Some(Hash::from_big_endian(&bytes))
+}
@@ -6,24 +6,29 @@ use crate::transaction::Transaction;
 pub type Mempool = BinaryHeap<TransactionWithTip>;
 
 /// this struct holds the tip for the miner (the difference between the inputs and the outputs),
-/// so that we can easily store this in the mem pool in a sorted order
+/// so that we can easily store this in the mem pool in a sorted order.
+/// The weight is kept alongside, since block space is measured in weight, so that is what the tip pays for.
+/// So is the sigop cost, which needs the tx_outs that the transaction spends, and a block can only have so much of
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransactionWithTip {
-    pub miner_tip: u32,
+    pub miner_tip: u64,
+    pub weight: usize,
+    pub sigops_cost: usize,
     pub transaction: Transaction,
 }
 
 impl TransactionWithTip {
-    pub fn new(transaction: Transaction, miner_tip: u32) -> Self {
-        Self {transaction, miner_tip}
+    pub fn new(transaction: Transaction, miner_tip: u64, sigops_cost: usize) -> Self {
+	Self {weight: transaction.weight(), sigops_cost, transaction, miner_tip}
     }
 }
 
 // The priority queue depends on `Ord`.
 impl Ord for TransactionWithTip {
-    /// we compare based strictly on the miner's tip
+    /// We compare the tips per weight unit, since that's what the miner gets for the space in the block.
+    /// We cross multiply rather than divide, so that nothing gets rounded
     fn cmp(&self, other: &Self) -> Ordering {
-        self.miner_tip.cmp(&other.miner_tip)
+	(self.miner_tip as u128 * other.weight as u128).cmp(&(other.miner_tip as u128 * self.weight as u128))
     }
 }
 
@@ -35,7 +40,7 @@ impl PartialOrd for TransactionWithTip {
 
 impl PartialEq for TransactionWithTip {
     fn eq(&self, other: &Self) -> bool {
-        self.miner_tip == other.miner_tip
+	self.cmp(other) == Ordering::Equal
     }
 }
 
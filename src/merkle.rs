@@ -1,15 +1,29 @@
-use crate::Hash;
+use crate::{Hash, sha256d, hash_to_bytes};
 use crate::DoubleSHA;
+use crate::transaction::Transaction;
 
 use itertools::Itertools;
-use sha2::{Sha256, Digest};
 
 
 /// given a vec of data, construct a merkle root by repeatedly concatting pairs of hashes
 /// to reduce the final result into a single hash
 /// todo: need a good unit test
-pub fn get_merkle_root<T: DoubleSHA>(data: &Vec<T>) -> Hash {
-    let mut hashes: Vec<Hash> = data.iter().map(|d| d.sha256d()).collect(); // first hash each datum
+pub fn get_merkle_root<T: DoubleSHA>(data: &[T]) -> Hash {
+    let hashes: Vec<Hash> = data.iter().map(|d| d.sha256d()).collect(); // first hash each datum
+    merkle_root_of_hashes(hashes)
+}
+
+/// The witness merkle root (BIP141) is the merkle root of the wtxids of a block's transactions, which commits to their witnesses as well.
+/// The coinbase counts as zero, since it is where the commitment to the witness merkle root goes
+pub fn get_witness_merkle_root(transactions: &[Transaction]) -> Hash {
+    let hashes: Vec<Hash> = transactions.iter().enumerate()
+	.map(|(i, transaction)| if i == 0 {Hash::zero()} else {transaction.wtxid()})
+	.collect();
+    merkle_root_of_hashes(hashes)
+}
+
+/// the merkle root of hashes that we already have
+fn merkle_root_of_hashes(mut hashes: Vec<Hash>) -> Hash {
     while hashes.len() > 1 {
         println!("len of hashes = {:?}", hashes.len());
         if hashes.len() % 2 == 1 {
@@ -27,22 +41,10 @@ pub fn get_merkle_root<T: DoubleSHA>(data: &Vec<T>) -> Hash {
 }
 
 
-/// Given two input hashes, we sha256 the concat of them twice
-/// TODO: is there a better way to refactor all of this sha256 on top of U256
+/// Given two input hashes, we sha256 the concat of them twice.
+/// Like everywhere else, the hashes get concatenated as their raw (little endian) digests
 fn sha256d_two_hashes(a: &Hash, b: &Hash) -> Hash {
-    let mut hasher_1 = Sha256::new();    
-    for i in 0..4 {
-        // iterate over the 4 constituent u64            
-	hasher_1.update(a.0[i].to_be_bytes());
-    }
-    for i in 0..4 {
-        // iterate over the 4 constituent u64            
-	hasher_1.update(b.0[i].to_be_bytes());
-    }
-
-    let first_hash_vec: Vec<u8> = hasher_1.finalize().to_vec();    
-    let mut hasher_2 = Sha256::new();
-    hasher_2.update(first_hash_vec);
-    let second_hash_vec = hasher_2.finalize().to_vec();
-    Hash::from(&second_hash_vec[..])
+    let mut bytes = hash_to_bytes(a).to_vec();
+    bytes.extend_from_slice(&hash_to_bytes(b));
+    sha256d(&bytes)
 }
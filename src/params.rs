@@ -0,0 +1,104 @@
+use serde::{Serialize, Deserialize};
+use ethereum_types::{U256, U512};
+use std::convert::TryFrom;
+
+use crate::block::{DifficultyBits};
+
+// TODO: this is the "real" one --> 0x1d00ffff
+// Note: this used to be 0x1ec3a30c, but the top bit of that coefficient is the sign bit, so this is (almost) the same target in canonical form
+const STARTING_DIFFICULTY_BITS: DifficultyBits = DifficultyBits(0x1f00c3a3);
+const RETARGET_INTERVAL: u32 = 2016; // the difficulty is adjusted every 2016 blocks, i.e. every two weeks if blocks come every 10 minutes
+const TARGET_SPACING: u64 = 10 * 60; // we want a block every 10 minutes
+const COINBASE_MATURITY: u32 = 100; // a coinbase can only be spent by a block 100 blocks after it, in case it gets reorged away
+
+/// The consensus parameters that can differ from one network to another, along with whether its mempools are picky
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkParams {
+    pub starting_difficulty_bits: DifficultyBits, // the difficulty of the genesis block
+    pub pow_limit: U256, // the largest (i.e. easiest) target that a block may ever have
+    pub retarget_interval: u32, // after this many blocks, the difficulty gets adjusted
+    pub target_spacing: u64, // the number of seconds we would like between blocks
+    pub coinbase_maturity: u32, // how many blocks have to come after a coinbase before its tx_outs can be spent
+    pub require_standard: bool, // whether the mempool starts out with the default MempoolPolicy, rather than taking any valid transaction
+}
+
+impl Default for NetworkParams {
+    /// our own little network, which starts out much easier than the real thing so that blocks can be mined on a laptop.
+    /// Like bitcoin core's test networks, it takes any valid transaction into the mempool
+    fn default() -> Self {
+	Self {
+	    starting_difficulty_bits: STARTING_DIFFICULTY_BITS,
+	    pow_limit: STARTING_DIFFICULTY_BITS.to_target().unwrap(),
+	    retarget_interval: RETARGET_INTERVAL,
+	    target_spacing: TARGET_SPACING,
+	    coinbase_maturity: COINBASE_MATURITY,
+	    require_standard: false,
+	}
+    }
+}
+
+impl NetworkParams {
+    /// the parameters of the real bitcoin network
+    pub fn mainnet() -> Self {
+	Self {
+	    starting_difficulty_bits: DifficultyBits(0x1d00ffff),
+	    pow_limit: DifficultyBits(0x1d00ffff).to_target().unwrap(),
+	    retarget_interval: RETARGET_INTERVAL,
+	    target_spacing: TARGET_SPACING,
+	    coinbase_maturity: COINBASE_MATURITY,
+	    require_standard: true,
+	}
+    }
+
+    /// a network for local testing, where the difficulty is so low that every other hash or so is a valid block
+    pub fn regtest() -> Self {
+	Self {
+	    starting_difficulty_bits: DifficultyBits(0x207fffff),
+	    pow_limit: DifficultyBits(0x207fffff).to_target().unwrap(),
+	    retarget_interval: RETARGET_INTERVAL,
+	    target_spacing: TARGET_SPACING,
+	    coinbase_maturity: COINBASE_MATURITY,
+	    require_standard: false,
+	}
+    }
+
+    /// how long (in seconds) we would like a whole retarget interval to take
+    pub fn target_timespan(&self) -> u64 {
+	self.retarget_interval as u64 * self.target_spacing
+    }
+
+    /// Bitcoin's difficulty adjustment: given the difficulty of the last interval and how long that interval actually took,
+    /// scale the target by actual_timespan / target_timespan. The timespan is clamped to within a factor of 4 of the target timespan,
+    /// so the difficulty can't change too drastically in one go, and the new target can never be easier than the pow limit
+    pub fn retarget(&self, difficulty_bits: DifficultyBits, actual_timespan: u64) -> DifficultyBits {
+	let target_timespan = self.target_timespan();
+	let actual_timespan = actual_timespan.clamp(target_timespan / 4, target_timespan * 4);
+
+	// the multiplication can overflow a U256 for very easy targets, so do it in 512 bits
+	let new_target = difficulty_bits.to_target().unwrap_or(self.pow_limit).full_mul(U256::from(actual_timespan)) / U512::from(target_timespan);
+	let new_target = match U256::try_from(new_target) {
+	    Ok(new_target) if new_target <= self.pow_limit => new_target,
+	    _ => self.pow_limit,
+	};
+	DifficultyBits::from_target(new_target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// these are real retargets from the main network (taken from bitcoin core's pow tests)
+    #[test]
+    fn test_retarget_mainnet() {
+	let params = NetworkParams::mainnet();
+	// block 32255 -> 32256
+	assert_eq!(params.retarget(DifficultyBits(0x1d00ffff), 1262152739 - 1261130161).0, 0x1d00d86a);
+	// blocks came too slowly, but we can't get any easier than the pow limit
+	assert_eq!(params.retarget(DifficultyBits(0x1d00ffff), 1233061996 - 1231006505).0, 0x1d00ffff);
+	// blocks came much too quickly, so the adjustment gets clamped to 4x harder
+	assert_eq!(params.retarget(DifficultyBits(0x1c05a3f4), 1279297671 - 1279008237).0, 0x1c0168fd);
+	// blocks came much too slowly, so the adjustment gets clamped to 4x easier
+	assert_eq!(params.retarget(DifficultyBits(0x1c387f6f), 1269211443 - 1263163443).0, 0x1d00e1fd);
+    }
+}
@@ -0,0 +1,229 @@
+use serde::{Serialize, Deserialize};
+
+use crate::transaction::{Transaction, TxIn, TxOut};
+use crate::script::{ScriptType, SCRIPT_VERIFY_CONSENSUS, SCRIPT_VERIFY_STRICTENC, SCRIPT_VERIFY_LOW_S, SCRIPT_VERIFY_NULLDUMMY, SCRIPT_VERIFY_MINIMALDATA,
+		    SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_NOPS, SCRIPT_VERIFY_CLEANSTACK, SCRIPT_VERIFY_NULLFAIL};
+use crate::encode::serialize;
+
+// bitcoin core's defaults
+pub const DEFAULT_MIN_RELAY_FEE_RATE: u64 = 1000; // satoshis per 1000 vbytes
+pub const DUST_RELAY_FEE_RATE: u64 = 3000; // a tx_out is dust if spending it would cost more than it is worth at this rate
+pub const MAX_STANDARD_TX_WEIGHT: usize = 400_000;
+pub const MAX_STANDARD_TX_SIGOPS_COST: usize = 16_000;
+pub const MAX_OP_RETURN_RELAY: usize = 83; // the whole locking script, so 80 bytes of data
+pub const MAX_STANDARD_UNLOCKING_SCRIPT_SIZE: usize = 1650; // enough for a 15 of 15 multisig redeem script with its signatures
+const MAX_STANDARD_BARE_MULTISIG_KEYS: usize = 3;
+/// the consensus rules, plus the ones that stop anyone but the signer from changing the unlocking script (and so the txid),
+/// and the one that keeps the upgradable NOPs free for soft forks. Like bitcoin core's standard flags, as far as we implement them
+pub const STANDARD_SCRIPT_VERIFY_FLAGS: u32 = SCRIPT_VERIFY_CONSENSUS | SCRIPT_VERIFY_STRICTENC | SCRIPT_VERIFY_LOW_S | SCRIPT_VERIFY_NULLDUMMY
+    | SCRIPT_VERIFY_MINIMALDATA | SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_NOPS | SCRIPT_VERIFY_CLEANSTACK | SCRIPT_VERIFY_NULLFAIL;
+
+/// What the mempool takes on top of the consensus rules, i.e. what we are willing to relay and mine. None of this makes a transaction
+/// invalid, it keeps out transactions that are expensive to deal with for what they pay, or that could be used to mess with future upgrades.
+/// Everything here is checked before the (much more expensive) scripts get run, and then those run with script_flags
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MempoolPolicy {
+    pub min_relay_fee_rate: u64, // the tip per 1000 vbytes that a transaction has to pay at least
+    pub dust_relay_fee_rate: u64, // tx_outs worth less than spending them costs at this fee rate are dust, see dust_threshold()
+    pub max_tx_weight: usize, // bigger transactions could make it hard to fill blocks well
+    pub max_tx_sigops_cost: usize, // see Transaction::sigop_cost()
+    pub standard_scripts_only: bool, // every tx_out has to follow one of the templates of Script::classify()
+    pub max_op_return_size: usize, // the largest null data locking script (OP_RETURN and the data pushes)
+    pub push_only_unlocking_scripts: bool, // unlocking scripts can only push data, and only up to MAX_STANDARD_UNLOCKING_SCRIPT_SIZE bytes
+    pub script_flags: u32, // the SCRIPT_VERIFY_ rules for running the scripts, which should include SCRIPT_VERIFY_CONSENSUS
+}
+
+impl Default for MempoolPolicy {
+    fn default() -> Self {
+	Self {
+	    min_relay_fee_rate: DEFAULT_MIN_RELAY_FEE_RATE,
+	    dust_relay_fee_rate: DUST_RELAY_FEE_RATE,
+	    max_tx_weight: MAX_STANDARD_TX_WEIGHT,
+	    max_tx_sigops_cost: MAX_STANDARD_TX_SIGOPS_COST,
+	    standard_scripts_only: true,
+	    max_op_return_size: MAX_OP_RETURN_RELAY,
+	    push_only_unlocking_scripts: true,
+	    script_flags: STANDARD_SCRIPT_VERIFY_FLAGS,
+	}
+    }
+}
+
+/// The reasons that the mempool turns away a transaction that is valid, but not standard
+#[derive(Debug, PartialEq)]
+pub enum PolicyError {
+    TxWeight(usize), // the transaction weighs more than max_tx_weight
+    NonStandardOutput {output_index: usize}, // this tx_out doesn't follow a standard template, or is a bare multisig with too many keys
+    OpReturnSize {output_index: usize}, // this null data tx_out is bigger than max_op_return_size
+    Dust {output_index: usize}, // this tx_out is worth less than its dust threshold
+    UnlockingScriptNotPushOnly {input_index: usize}, // the unlocking script of this tx_in does something other than push
+    UnlockingScriptSize {input_index: usize}, // the unlocking script of this tx_in is bigger than MAX_STANDARD_UNLOCKING_SCRIPT_SIZE
+    SigopsCost(usize), // the transaction has a sigop cost of more than max_tx_sigops_cost
+    FeeTooLow {fee: u64, min_fee: u64}, // the transaction doesn't pay the minimum relay fee for its size
+}
+
+impl MempoolPolicy {
+    /// the fee that a transaction of this virtual size has to pay at least, rounded up
+    pub fn min_fee(&self, vsize: usize) -> u64 {
+	(vsize as u64 * self.min_relay_fee_rate).div_ceil(1000)
+    }
+
+    /// The value below which a tx_out is dust: what it costs to pay the dust relay fee rate for the tx_out and for the tx_in that spends it,
+    /// which is assumed to be a signature and a compressed public key (in the witness for segwit, so a lot cheaper). Null data is never dust
+    pub fn dust_threshold(&self, tx_out: &TxOut) -> u64 {
+	if tx_out.locking_script.is_unspendable() {
+	    return 0;
+	}
+	// the outpoint, the sequence and the unlocking script (with its length), plus a quarter of the 107 bytes of witness for segwit
+	let spend_size = match tx_out.locking_script.witness_program() {
+	    Some(_) => 32 + 4 + 1 + 107 / 4 + 4,
+	    None => 32 + 4 + 1 + 107 + 4,
+	};
+	(serialize(tx_out).len() + spend_size) as u64 * self.dust_relay_fee_rate / 1000
+    }
+
+    /// the checks that only need the transaction itself, which happen before the tx_outs that it spends get looked up
+    pub fn check_transaction(&self, transaction: &Transaction) -> Result<(), PolicyError> {
+	let weight = transaction.weight();
+	if weight > self.max_tx_weight {
+	    return Err(PolicyError::TxWeight(weight));
+	}
+	if self.push_only_unlocking_scripts {
+	    for (input_index, tx_in) in transaction.tx_ins.iter().enumerate() {
+		if let TxIn::TxPrevious {unlocking_script, ..} = tx_in {
+		    if unlocking_script.to_bytes().len() > MAX_STANDARD_UNLOCKING_SCRIPT_SIZE {
+			return Err(PolicyError::UnlockingScriptSize {input_index});
+		    }
+		    if !unlocking_script.is_push_only() {
+			return Err(PolicyError::UnlockingScriptNotPushOnly {input_index});
+		    }
+		}
+	    }
+	}
+	for (output_index, tx_out) in transaction.tx_outs.iter().enumerate() {
+	    match tx_out.locking_script.classify() {
+		ScriptType::NullData(_) => {
+		    if tx_out.locking_script.to_bytes().len() > self.max_op_return_size {
+			return Err(PolicyError::OpReturnSize {output_index});
+		    }
+		    continue; // unspendable, so it can't be dust
+		},
+		ScriptType::NonStandard if self.standard_scripts_only => return Err(PolicyError::NonStandardOutput {output_index}),
+		ScriptType::Multisig {public_keys, ..} if self.standard_scripts_only && public_keys.len() > MAX_STANDARD_BARE_MULTISIG_KEYS => {
+		    return Err(PolicyError::NonStandardOutput {output_index});
+		},
+		_ => (),
+	    }
+	    if tx_out.value < self.dust_threshold(tx_out) {
+		return Err(PolicyError::Dust {output_index});
+	    }
+	}
+	Ok(())
+    }
+
+    /// The checks that need the tx_outs that the transaction spends (in the same order as its tx_ins), i.e. its sigops and its fee.
+    /// A transaction that spends more than it has, or whose values are out of range, is left for the consensus checks to turn away
+    pub fn check_tx_ins(&self, transaction: &Transaction, spent_tx_outs: &[TxOut]) -> Result<(), PolicyError> {
+	let sigops_cost = transaction.sigop_cost(spent_tx_outs);
+	if sigops_cost > self.max_tx_sigops_cost {
+	    return Err(PolicyError::SigopsCost(sigops_cost));
+	}
+	if let Ok(fee) = transaction.fee(spent_tx_outs) {
+	    let min_fee = self.min_fee(transaction.vsize());
+	    if fee < min_fee {
+		return Err(PolicyError::FeeTooLow {fee, min_fee});
+	    }
+	}
+	Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Hash;
+    use crate::script::{Script, StackOp, pay_to_public_key_hash, pay_to_witness_public_key_hash, pay_to_script_hash, pay_to_witness_script_hash};
+    use crate::transaction::SEQUENCE_FINAL;
+
+    fn transaction_with(unlocking_script: Script, tx_outs: Vec<TxOut>) -> Transaction {
+	let tx_in = TxIn::TxPrevious {tx_hash: Hash::from(1234), tx_out_index: 0, unlocking_script, sequence: SEQUENCE_FINAL, witness: vec![]};
+	Transaction {version: 2, lock_time: 0, tx_ins: vec![tx_in], tx_outs}
+    }
+
+    fn bare_multisig(key_count: usize) -> Script {
+	let mut ops = vec![StackOp::Val(1)];
+	ops.extend((0..key_count).map(|_| StackOp::Bytes(vec![2; 33].into_boxed_slice())));
+	ops.extend(vec![StackOp::Val(key_count as i32), StackOp::OpCheckMultiSig]);
+	Script {ops}
+    }
+
+    /// the well known dust limits of bitcoin core
+    #[test]
+    fn test_dust_threshold() {
+	let policy = MempoolPolicy::default();
+	let dust_threshold = |locking_script: Script| policy.dust_threshold(&TxOut {value: 0, locking_script});
+	assert_eq!(dust_threshold(pay_to_public_key_hash(&[2; 33])), 546);
+	assert_eq!(dust_threshold(pay_to_script_hash(&Script {ops: vec![]})), 540);
+	assert_eq!(dust_threshold(pay_to_witness_public_key_hash(&[2; 33])), 294);
+	assert_eq!(dust_threshold(pay_to_witness_script_hash(&Script {ops: vec![]})), 330);
+	assert_eq!(dust_threshold(Script::parse_asm("OP_RETURN 68656c6c6f").unwrap()), 0);
+	assert_eq!(MempoolPolicy {dust_relay_fee_rate: 0, ..MempoolPolicy::default()}.dust_threshold(&TxOut {value: 0, locking_script: pay_to_public_key_hash(&[2; 33])}), 0);
+    }
+
+    #[test]
+    fn test_check_transaction() {
+	let policy = MempoolPolicy::default();
+	let pay_to = |value: u64, locking_script: Script| TxOut {value, locking_script};
+	let standard = transaction_with(Script {ops: vec![StackOp::Bytes(vec![1; 72].into_boxed_slice())]}, vec![pay_to(546, pay_to_public_key_hash(&[2; 33]))]);
+	assert_eq!(policy.check_transaction(&standard), Ok(()));
+
+	let check_tx_outs = |tx_outs: Vec<TxOut>| policy.check_transaction(&transaction_with(Script {ops: vec![]}, tx_outs));
+	assert_eq!(check_tx_outs(vec![pay_to(545, pay_to_public_key_hash(&[2; 33]))]), Err(PolicyError::Dust {output_index: 0}));
+	assert_eq!(check_tx_outs(vec![pay_to(1000, pay_to_public_key_hash(&[2; 33])), pay_to(1000, Script {ops: vec![StackOp::OpDup]})]),
+		   Err(PolicyError::NonStandardOutput {output_index: 1}));
+	// bare multisig is only standard with up to 3 keys
+	assert_eq!(check_tx_outs(vec![pay_to(1000, bare_multisig(3))]), Ok(()));
+	assert_eq!(check_tx_outs(vec![pay_to(1000, bare_multisig(4))]), Err(PolicyError::NonStandardOutput {output_index: 0}));
+	// null data is never dust, but it can only be so big
+	let op_return = |data_len: usize| Script {ops: vec![StackOp::OpReturn, StackOp::Bytes(vec![7; data_len].into_boxed_slice())]};
+	assert_eq!(op_return(80).to_bytes().len(), MAX_OP_RETURN_RELAY);
+	assert_eq!(check_tx_outs(vec![pay_to(0, op_return(80))]), Ok(()));
+	assert_eq!(check_tx_outs(vec![pay_to(0, op_return(81))]), Err(PolicyError::OpReturnSize {output_index: 0}));
+	assert_eq!(MempoolPolicy {max_op_return_size: 100, ..MempoolPolicy::default()}.check_transaction(&transaction_with(Script {ops: vec![]}, vec![pay_to(0, op_return(81))])), Ok(()));
+	// with standard scripts switched off, anything goes that isn't dust
+	let anything = MempoolPolicy {standard_scripts_only: false, ..MempoolPolicy::default()};
+	assert_eq!(anything.check_transaction(&transaction_with(Script {ops: vec![]}, vec![pay_to(1000, Script {ops: vec![StackOp::OpDup]})])), Ok(()));
+
+	// unlocking scripts only push, and not too much
+	let not_push_only = transaction_with(Script {ops: vec![StackOp::Val(1), StackOp::OpDup]}, standard.tx_outs.clone());
+	assert_eq!(policy.check_transaction(&not_push_only), Err(PolicyError::UnlockingScriptNotPushOnly {input_index: 0}));
+	let big_push = |len: usize| transaction_with(Script {ops: vec![StackOp::Bytes(vec![1; len].into_boxed_slice())]}, standard.tx_outs.clone());
+	assert_eq!(policy.check_transaction(&big_push(MAX_STANDARD_UNLOCKING_SCRIPT_SIZE - 3)), Ok(())); // OP_PUSHDATA2 and the length
+	assert_eq!(policy.check_transaction(&big_push(MAX_STANDARD_UNLOCKING_SCRIPT_SIZE - 2)), Err(PolicyError::UnlockingScriptSize {input_index: 0}));
+	assert_eq!(MempoolPolicy {push_only_unlocking_scripts: false, ..MempoolPolicy::default()}.check_transaction(&not_push_only), Ok(()));
+
+	let heavy = MempoolPolicy {max_tx_weight: standard.weight() - 1, ..MempoolPolicy::default()};
+	assert_eq!(heavy.check_transaction(&standard), Err(PolicyError::TxWeight(standard.weight())));
+    }
+
+    #[test]
+    fn test_check_tx_ins() {
+	let policy = MempoolPolicy::default();
+	let transaction = transaction_with(Script {ops: vec![StackOp::Bytes(vec![1; 72].into_boxed_slice())]}, vec![TxOut {value: 10_000, locking_script: pay_to_public_key_hash(&[2; 33])}]);
+	let vsize = transaction.vsize();
+	assert_eq!(policy.min_fee(vsize), vsize as u64); // 1 satoshi per vbyte
+	let spending = |value: u64| vec![TxOut {value, locking_script: pay_to_public_key_hash(&[3; 33])}];
+	assert_eq!(policy.check_tx_ins(&transaction, &spending(10_000 + vsize as u64)), Ok(()));
+	assert_eq!(policy.check_tx_ins(&transaction, &spending(10_000 + vsize as u64 - 1)), Err(PolicyError::FeeTooLow {fee: vsize as u64 - 1, min_fee: vsize as u64}));
+	assert_eq!(MempoolPolicy {min_relay_fee_rate: 0, ..MempoolPolicy::default()}.check_tx_ins(&transaction, &spending(10_000)), Ok(()));
+	// spending more than there is isn't for the policy to decide
+	assert_eq!(policy.check_tx_ins(&transaction, &spending(5_000)), Ok(()));
+
+	// a bare multisig with 20 keys counts 20 sigops, 4 times over
+	let many_sigops = transaction_with(Script {ops: vec![]}, vec![TxOut {value: 10_000, locking_script: Script::parse_asm("OP_CHECKMULTISIG").unwrap()}; 200]);
+	assert_eq!(many_sigops.sigop_cost(&spending(10_000_000)), 16_000);
+	assert_eq!(policy.check_tx_ins(&many_sigops, &spending(10_000_000)), Ok(()));
+	let mut too_many_sigops = many_sigops.clone();
+	too_many_sigops.tx_outs.push(TxOut {value: 10_000, locking_script: Script::parse_asm("OP_CHECKSIG").unwrap()});
+	assert_eq!(policy.check_tx_ins(&too_many_sigops, &spending(10_000_000)), Err(PolicyError::SigopsCost(16_004)));
+    }
+}
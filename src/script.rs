@@ -2,34 +2,269 @@ use serde::{Serialize, Deserialize};
 use ecdsa::{VerifyingKey};
 use k256::{Secp256k1};
 use sha2::{Sha256, Digest};
-use ecdsa::signature::{
-    Verifier, Signature}; // trait in scope for signing a message
+use sha1::{Sha1};
+use ripemd::{Ripemd160};
 
 use elliptic_curve::sec1::{EncodedPoint};
-use bincode;
+use std::fmt;
+
+use crate::hash_to_bytes;
+use crate::encode::{Encodable, Decodable, DecodeError, CompactSize, encode_bytes, decode_bytes};
+use crate::transaction::{Transaction, TxOut, LOCKTIME_THRESHOLD, SEQUENCE_FINAL, SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_TYPE_FLAG, SEQUENCE_LOCKTIME_MASK};
+use crate::sighash::{signature_hash, segwit_signature_hash, taproot_signature_hash, verify_hash, signature_from_der, SIGHASH_ALL, SIGHASH_SINGLE, SIGHASH_ANYONECANPAY, SIGHASH_DEFAULT};
+use crate::taproot::{schnorr_verify, tweak_public_key, tap_leaf_hash, verify_taproot_commitment, ANNEX_TAG, TAPROOT_LEAF_MASK, TAPROOT_LEAF_TAPSCRIPT,
+		     TAPROOT_CONTROL_BASE_SIZE, TAPROOT_CONTROL_NODE_SIZE, TAPROOT_CONTROL_MAX_NODE_COUNT};
 
 /// enum to hold the various Script operations and their associated values
-/// we derive Serialize and Deserialize so that we can turn the StackOp into bytes during hashing
-/// (I couldn't find a more direct way to do that like everything else, but there might be)
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum StackOp {
     Bool(bool),
     Val(i32),
-    Bytes(Box<[u8]>), // the data stored here is the byte representation of an EncodedPoint<Secp256k1> or a hash of it
-    OpAdd, // pop the top two values, and put val1 + val2 on the top of the stack
-    OpSub, // pop the top two values, and put val1 (bottom) - val2 (top) on the top of the stack
+    Bytes(Box<[u8]>), // data to push onto the stack as it is, e.g. an SEC1 encoded public key or a hash of one
+    NonMinimalPush(u8, Box<[u8]>), // data pushed with a bigger push opcode than it needs (e.g. OP_PUSHDATA1 for 1 byte). Only decoding makes these, so the script encodes back to the same bytes
+    OpNop, // do nothing
+    OpAdd, // pop the top two numbers, and put val1 + val2 on the top of the stack
+    OpSub, // pop the top two numbers, and put val1 (bottom) - val2 (top) on the top of the stack
+    Op1Add, // add 1 to the top number
+    Op1Sub, // take 1 from the top number
+    OpNegate, // flip the sign of the top number
+    OpAbs, // make the top number positive
+    OpNot, // replace the top number with whether it is 0
+    Op0NotEqual, // replace the top number with whether it isn't 0
+    OpBoolAnd, // pop the top two numbers, and put whether neither is 0 on the stack
+    OpBoolOr, // pop the top two numbers, and put whether either isn't 0 on the stack
+    OpNumEqual, // pop the top two numbers, and put whether they are equal on the stack
+    OpNumEqualVerify, // combine OpNumEqual and OpVerify in one go
+    OpNumNotEqual, // pop the top two numbers, and put whether they are different on the stack
+    OpLessThan, // pop the top two numbers, and put val1 < val2 on the stack
+    OpGreaterThan, // pop the top two numbers, and put val1 > val2 on the stack
+    OpLessThanOrEqual, // pop the top two numbers, and put val1 <= val2 on the stack
+    OpGreaterThanOrEqual, // pop the top two numbers, and put val1 >= val2 on the stack
+    OpMin, // pop the top two numbers, and put the smaller one back
+    OpMax, // pop the top two numbers, and put the larger one back
+    OpWithin, // pop x, min and max, and put whether min <= x < max on the stack
+    OpToAltStack, // move the top item onto the alt stack
+    OpFromAltStack, // move the top item of the alt stack back onto the stack
+    OpDrop, // remove the top item
+    Op2Drop, // remove the top two items
     OpDup, // duplicate the top value of the stack
+    Op2Dup, // duplicate the top two items
+    Op3Dup, // duplicate the top three items
+    OpIfDup, // duplicate the top item if it is true
+    OpNip, // remove the second item from the top
+    OpOver, // copy the second item from the top to the top
+    Op2Over, // copy the third and fourth items from the top to the top
+    OpPick, // pop n, and copy the item n below the top to the top
+    OpRoll, // pop n, and move the item n below the top to the top
+    OpRot, // move the third item from the top to the top
+    Op2Rot, // move the fifth and sixth items from the top to the top
+    OpSwap, // swap the top two items
+    Op2Swap, // swap the top two pairs of items
+    OpTuck, // copy the top item to below the second item
+    OpDepth, // put the number of items on the stack on the stack
+    OpSize, // put the length in bytes of the top item on the stack (keeping the item)
     OpEqual, // pop the top two values, and put val1 == val2 on the top of the stack
-    OpHash160, // run the top element of the stack through hash 160    
-    OpCheckSig,
+    OpRipemd160, // replace the top item with its RIPEMD-160 hash
+    OpSha1, // replace the top item with its SHA-1 hash
+    OpSha256, // replace the top item with its SHA-256 hash
+    OpHash160, // replace the top item with its hash 160, i.e. SHA-256 then RIPEMD-160
+    OpHash256, // replace the top item with its double SHA-256 hash
+    OpCheckSig, // pop a public key and a signature, and put whether the signature signs the spending transaction on the stack
+    OpCheckSigVerify, // combine OpCheckSig and OpVerify in one go
+    OpCheckMultiSig, // pop n, n public keys, m and m signatures (plus one extra item), and put whether all m signatures check out
+    OpCheckMultiSigVerify, // combine OpCheckMultiSig and OpVerify in one go
+    OpCheckSigAdd, // tapscript only: pop a public key, n and a signature, and put n + 1 on the stack if the signature checks out (n if it is empty)
     OpVerify, // mark the transaction as invalid if the top value on the stack is not true
     OpEqVerify, // combine OpEq and OpVerify in one go.
+    OpIf, // pop the top value, and only execute what follows (up to the matching OpElse or OpEndIf) if it is true
+    OpNotIf, // same as OpIf, but execute what follows if the top value is false
+    OpElse, // switch from executing to skipping (or the other way around) within the current OpIf
+    OpEndIf, // the end of the current OpIf
+    OpReturn, // fail straight away, which makes a locking script starting with it provably unspendable
+    OpCheckLockTimeVerify, // fail unless the spending transaction's lock_time is at least the top value (which stays on the stack)
+    OpCheckSequenceVerify, // fail unless the sequence of the tx_in being unlocked is a relative lock time of at least the top value
+    OpCodeSeparator, // do nothing, but only the part of the script after the last one that was run gets signed
+    OpUpgradableNop(u8), // OP_NOP1 and OP_NOP4 to OP_NOP10, which do nothing, so that soft forks can give them a meaning (like CLTV and CSV did)
+    OpInvalid(u8), // an opcode that is disabled (e.g. OP_CAT), reserved or unassigned. Most of them only fail the script if they are run
+}
+
+// the real bitcoin opcodes for pushing data and numbers, and the ones we only look for in OpInvalid. The rest are in OPCODES
+const OP_0: u8 = 0x00;
+const OP_PUSHDATA1: u8 = 0x4c;
+const OP_PUSHDATA2: u8 = 0x4d;
+const OP_PUSHDATA4: u8 = 0x4e;
+const OP_1NEGATE: u8 = 0x4f;
+const OP_RESERVED: u8 = 0x50;
+const OP_1: u8 = 0x51;
+const OP_16: u8 = 0x60;
+const OP_VERIF: u8 = 0x65;
+const OP_VERNOTIF: u8 = 0x66;
+
+/// numbers that get pushed in a script are little endian, as short as possible, with the sign in the top bit of the last byte
+fn encode_script_num(val: i64) -> Vec<u8> {
+    let mut bytes = vec![];
+    let mut abs = val.unsigned_abs();
+    while abs > 0 {
+	bytes.push((abs & 0xff) as u8);
+	abs >>= 8;
+    }
+    if let Some(last) = bytes.last_mut() {
+	if *last & 0x80 != 0 {
+	    // the top bit is taken, so the sign needs a byte of its own
+	    bytes.push(if val < 0 {0x80} else {0x00});
+	} else if val < 0 {
+	    *last |= 0x80;
+	}
+    }
+    bytes
+}
+
+/// the reverse of encode_script_num()
+fn decode_script_num(bytes: &[u8]) -> i64 {
+    let mut val = bytes.iter().rev().fold(0_i64, |val, byte| (val << 8) | *byte as i64);
+    if let Some(last) = bytes.last() {
+	if last & 0x80 != 0 {
+	    // clear the sign bit and negate
+	    val &= !(0x80_i64 << (8 * (bytes.len() - 1)));
+	    val = -val;
+	}
+    }
+    val
+}
+
+/// the opcodes that don't push anything: their name in ASM, their byte in a script, and the StackOp that they are
+const OPCODES: [(&str, u8, StackOp); 70] = [
+    ("OP_NOP", 0x61, StackOp::OpNop),
+    ("OP_IF", 0x63, StackOp::OpIf),
+    ("OP_NOTIF", 0x64, StackOp::OpNotIf),
+    ("OP_ELSE", 0x67, StackOp::OpElse),
+    ("OP_ENDIF", 0x68, StackOp::OpEndIf),
+    ("OP_VERIFY", 0x69, StackOp::OpVerify),
+    ("OP_RETURN", 0x6a, StackOp::OpReturn),
+    ("OP_TOALTSTACK", 0x6b, StackOp::OpToAltStack),
+    ("OP_FROMALTSTACK", 0x6c, StackOp::OpFromAltStack),
+    ("OP_2DROP", 0x6d, StackOp::Op2Drop),
+    ("OP_2DUP", 0x6e, StackOp::Op2Dup),
+    ("OP_3DUP", 0x6f, StackOp::Op3Dup),
+    ("OP_2OVER", 0x70, StackOp::Op2Over),
+    ("OP_2ROT", 0x71, StackOp::Op2Rot),
+    ("OP_2SWAP", 0x72, StackOp::Op2Swap),
+    ("OP_IFDUP", 0x73, StackOp::OpIfDup),
+    ("OP_DEPTH", 0x74, StackOp::OpDepth),
+    ("OP_DROP", 0x75, StackOp::OpDrop),
+    ("OP_DUP", 0x76, StackOp::OpDup),
+    ("OP_NIP", 0x77, StackOp::OpNip),
+    ("OP_OVER", 0x78, StackOp::OpOver),
+    ("OP_PICK", 0x79, StackOp::OpPick),
+    ("OP_ROLL", 0x7a, StackOp::OpRoll),
+    ("OP_ROT", 0x7b, StackOp::OpRot),
+    ("OP_SWAP", 0x7c, StackOp::OpSwap),
+    ("OP_TUCK", 0x7d, StackOp::OpTuck),
+    ("OP_SIZE", 0x82, StackOp::OpSize),
+    ("OP_EQUAL", 0x87, StackOp::OpEqual),
+    ("OP_EQUALVERIFY", 0x88, StackOp::OpEqVerify),
+    ("OP_1ADD", 0x8b, StackOp::Op1Add),
+    ("OP_1SUB", 0x8c, StackOp::Op1Sub),
+    ("OP_NEGATE", 0x8f, StackOp::OpNegate),
+    ("OP_ABS", 0x90, StackOp::OpAbs),
+    ("OP_NOT", 0x91, StackOp::OpNot),
+    ("OP_0NOTEQUAL", 0x92, StackOp::Op0NotEqual),
+    ("OP_ADD", 0x93, StackOp::OpAdd),
+    ("OP_SUB", 0x94, StackOp::OpSub),
+    ("OP_BOOLAND", 0x9a, StackOp::OpBoolAnd),
+    ("OP_BOOLOR", 0x9b, StackOp::OpBoolOr),
+    ("OP_NUMEQUAL", 0x9c, StackOp::OpNumEqual),
+    ("OP_NUMEQUALVERIFY", 0x9d, StackOp::OpNumEqualVerify),
+    ("OP_NUMNOTEQUAL", 0x9e, StackOp::OpNumNotEqual),
+    ("OP_LESSTHAN", 0x9f, StackOp::OpLessThan),
+    ("OP_GREATERTHAN", 0xa0, StackOp::OpGreaterThan),
+    ("OP_LESSTHANOREQUAL", 0xa1, StackOp::OpLessThanOrEqual),
+    ("OP_GREATERTHANOREQUAL", 0xa2, StackOp::OpGreaterThanOrEqual),
+    ("OP_MIN", 0xa3, StackOp::OpMin),
+    ("OP_MAX", 0xa4, StackOp::OpMax),
+    ("OP_WITHIN", 0xa5, StackOp::OpWithin),
+    ("OP_RIPEMD160", 0xa6, StackOp::OpRipemd160),
+    ("OP_SHA1", 0xa7, StackOp::OpSha1),
+    ("OP_SHA256", 0xa8, StackOp::OpSha256),
+    ("OP_HASH160", 0xa9, StackOp::OpHash160),
+    ("OP_HASH256", 0xaa, StackOp::OpHash256),
+    ("OP_CODESEPARATOR", 0xab, StackOp::OpCodeSeparator),
+    ("OP_CHECKSIG", 0xac, StackOp::OpCheckSig),
+    ("OP_CHECKSIGVERIFY", 0xad, StackOp::OpCheckSigVerify),
+    ("OP_CHECKMULTISIG", 0xae, StackOp::OpCheckMultiSig),
+    ("OP_CHECKMULTISIGVERIFY", 0xaf, StackOp::OpCheckMultiSigVerify),
+    ("OP_NOP1", 0xb0, StackOp::OpUpgradableNop(1)),
+    ("OP_CHECKLOCKTIMEVERIFY", 0xb1, StackOp::OpCheckLockTimeVerify),
+    ("OP_CHECKSEQUENCEVERIFY", 0xb2, StackOp::OpCheckSequenceVerify),
+    ("OP_NOP4", 0xb3, StackOp::OpUpgradableNop(4)),
+    ("OP_NOP5", 0xb4, StackOp::OpUpgradableNop(5)),
+    ("OP_NOP6", 0xb5, StackOp::OpUpgradableNop(6)),
+    ("OP_NOP7", 0xb6, StackOp::OpUpgradableNop(7)),
+    ("OP_NOP8", 0xb7, StackOp::OpUpgradableNop(8)),
+    ("OP_NOP9", 0xb8, StackOp::OpUpgradableNop(9)),
+    ("OP_NOP10", 0xb9, StackOp::OpUpgradableNop(10)),
+    ("OP_CHECKSIGADD", 0xba, StackOp::OpCheckSigAdd),
+];
+
+/// push some data using the smallest push opcode that fits
+fn push_data(data: &[u8], bytes: &mut Vec<u8>) {
+    let len = data.len();
+    if len < OP_PUSHDATA1 as usize {
+	bytes.push(len as u8); // the opcodes 0x01 to 0x4b push that many bytes
+    } else if len <= 0xff {
+	bytes.push(OP_PUSHDATA1);
+	bytes.push(len as u8);
+    } else if len <= 0xffff {
+	bytes.push(OP_PUSHDATA2);
+	bytes.extend_from_slice(&(len as u16).to_le_bytes());
+    } else {
+	bytes.push(OP_PUSHDATA4);
+	bytes.extend_from_slice(&(len as u32).to_le_bytes());
+    }
+    bytes.extend_from_slice(data);
 }
 
 impl StackOp {
-    pub fn to_be_bytes(&self) -> Vec<u8> {
-	let encoded: Vec<u8> = bincode::serialize(self).unwrap();
-	encoded
+    /// write out the opcode (and any data it pushes) that this StackOp corresponds to in bitcoin script
+    fn encode(&self, bytes: &mut Vec<u8>) {
+	match self {
+	    StackOp::Bool(val) => bytes.push(if *val {OP_1} else {OP_0}),
+	    StackOp::Val(0) => bytes.push(OP_0),
+	    StackOp::Val(-1) => bytes.push(OP_1NEGATE),
+	    StackOp::Val(val @ 1..=16) => bytes.push(OP_1 + (*val as u8) - 1),
+	    StackOp::Val(val) => push_data(&encode_script_num(*val as i64), bytes),
+	    StackOp::Bytes(data) => push_data(data, bytes),
+	    StackOp::NonMinimalPush(opcode, data) => {
+		bytes.push(*opcode);
+		match *opcode {
+		    OP_PUSHDATA1 => bytes.push(data.len() as u8),
+		    OP_PUSHDATA2 => bytes.extend_from_slice(&(data.len() as u16).to_le_bytes()),
+		    OP_PUSHDATA4 => bytes.extend_from_slice(&(data.len() as u32).to_le_bytes()),
+		    _ => (),
+		}
+		bytes.extend_from_slice(data);
+	    },
+	    StackOp::OpInvalid(opcode) => bytes.push(*opcode),
+	    _ => {
+		let (_, opcode, _) = OPCODES.iter().find(|(_, _, named_op)| named_op == self).expect("every other op has an opcode");
+		bytes.push(*opcode)
+	    },
+	}
+    }
+
+    /// whether the op just pushes something onto the stack
+    fn is_push(&self) -> bool {
+	matches!(self, StackOp::Bool(_) | StackOp::Val(_) | StackOp::Bytes(_) | StackOp::NonMinimalPush(..))
+    }
+
+    /// whether a push uses the shortest way there is to push its data (bitcoin core's CheckMinimalPush). Bytes always get the smallest
+    /// push opcode for their length, but one byte from 1 to 16 (or 0x81) should have been OP_1 to OP_16 (or OP_1NEGATE)
+    fn is_minimal_push(&self) -> bool {
+	match self {
+	    StackOp::Bytes(bytes) => !matches!(**bytes, [1..=16] | [0x81]),
+	    StackOp::NonMinimalPush(..) => false,
+	    _ => true,
+	}
     }
 }
 
@@ -37,264 +272,1443 @@ impl StackOp {
 /// the requirment for ownership of the utxo
 /// the locking script formally describes the conditions needed to spend a given UTXO,
 /// Usually requiring a signature from a specific address
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Script {
     pub ops: Vec<StackOp>
 }
 
-/// TODO: add the second half of this hash (ripemd160)
+impl Script {
+    /// the raw bytes of the script, the way it appears inside a transaction
+    pub fn to_bytes(&self) -> Vec<u8> {
+	let mut bytes = vec![];
+	for op in &self.ops {
+	    op.encode(&mut bytes);
+	}
+	bytes
+    }
+
+    /// The reverse of to_bytes(). Small numbers come back as Vals and all other pushes come back as Bytes, unless they use a bigger
+    /// push opcode than they need, in which case they keep it in a NonMinimalPush. Opcodes that we can't run become OpInvalid,
+    /// so every script that doesn't run off the end of a push comes back to exactly the same bytes
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, ScriptDecodeError> {
+	let mut ops = vec![];
+	let script_len = bytes.len();
+	while let Some((&opcode, rest)) = bytes.split_first() {
+	    let position = script_len - bytes.len(); // where this opcode sits in the script
+	    bytes = rest;
+	    let push_len = match opcode {
+		0x01..=0x4b => Some(opcode as usize),
+		OP_PUSHDATA1 => Some(take_len(&mut bytes, 1).ok_or(ScriptDecodeError::TruncatedPush(position))?),
+		OP_PUSHDATA2 => Some(take_len(&mut bytes, 2).ok_or(ScriptDecodeError::TruncatedPush(position))?),
+		OP_PUSHDATA4 => Some(take_len(&mut bytes, 4).ok_or(ScriptDecodeError::TruncatedPush(position))?),
+		_ => None,
+	    };
+	    let op = match (opcode, push_len) {
+		(_, Some(len)) => {
+		    if bytes.len() < len {
+			return Err(ScriptDecodeError::TruncatedPush(position));
+		    }
+		    let (data, rest) = bytes.split_at(len);
+		    bytes = rest;
+		    let mut minimal = vec![];
+		    push_data(data, &mut minimal);
+		    if minimal[0] == opcode {
+			StackOp::Bytes(data.into())
+		    } else {
+			StackOp::NonMinimalPush(opcode, data.into())
+		    }
+		},
+		(OP_0, None) => StackOp::Val(0),
+		(OP_1NEGATE, None) => StackOp::Val(-1),
+		(OP_1..=OP_16, None) => StackOp::Val((opcode - OP_1) as i32 + 1),
+		(_, None) => match OPCODES.iter().find(|(_, byte, _)| *byte == opcode) {
+		    Some((_, _, op)) => op.clone(),
+		    None => StackOp::OpInvalid(opcode),
+		},
+	    };
+	    ops.push(op);
+	}
+	Ok(Script {ops})
+    }
+
+    /// a locking script that starts with OpReturn can never be unlocked, so its tx_out is just a place to put data
+    pub fn is_unspendable(&self) -> bool {
+	matches!(self.ops.first(), Some(StackOp::OpReturn))
+    }
+
+    /// whether this is a pay to script hash locking script (BIP16), i.e. OP_HASH160 <20 byte hash> OP_EQUAL
+    pub fn is_pay_to_script_hash(&self) -> bool {
+	matches!(self.ops.as_slice(), [StackOp::OpHash160, StackOp::Bytes(hash), StackOp::OpEqual] if hash.len() == 20)
+    }
+
+    /// whether the script only pushes data, which is all that an unlocking script for pay to script hash may do
+    pub fn is_push_only(&self) -> bool {
+	self.ops.iter().all(StackOp::is_push)
+    }
+
+    /// If this is a segwit locking script (BIP141), return its version and witness program. That is a script of just a version
+    /// (OP_0 to OP_16) and a direct push of 2 to 40 bytes. It's defined on the raw bytes, so we check what the ops encode to, the same way bitcoin core does
+    pub fn witness_program(&self) -> Option<(u8, Vec<u8>)> {
+	let bytes = self.to_bytes();
+	if bytes.len() < 4 || bytes.len() > 42 || bytes[1] as usize + 2 != bytes.len() {
+	    return None;
+	}
+	match bytes[0] {
+	    OP_0 => Some((0, bytes[2..].to_vec())),
+	    OP_1..=OP_16 => Some((bytes[0] - OP_1 + 1, bytes[2..].to_vec())),
+	    _ => None,
+	}
+    }
+
+    /// Which of the standard templates this locking script follows, if any, along with what it locks to.
+    /// These are the kinds of tx_out that wallets know how to spend, and the only ones that get relayed (see MempoolPolicy)
+    pub fn classify(&self) -> ScriptType {
+	if let Some((version, program)) = self.witness_program() {
+	    return match (version, program.len()) {
+		(0, 20) => ScriptType::PayToWitnessPublicKeyHash(program),
+		(0, 32) => ScriptType::PayToWitnessScriptHash(program),
+		(0, _) => ScriptType::NonStandard,
+		(1, 32) => ScriptType::PayToTaproot(program),
+		_ => ScriptType::WitnessUnknown {version, program},
+	    };
+	}
+	match self.ops.as_slice() {
+	    [StackOp::Bytes(public_key), StackOp::OpCheckSig] if is_public_key_size(public_key) => ScriptType::PayToPublicKey(public_key.to_vec()),
+	    [StackOp::OpDup, StackOp::OpHash160, StackOp::Bytes(hash), StackOp::OpEqVerify, StackOp::OpCheckSig] if hash.len() == 20 => {
+		ScriptType::PayToPublicKeyHash(hash.to_vec())
+	    },
+	    [StackOp::OpHash160, StackOp::Bytes(hash), StackOp::OpEqual] if hash.len() == 20 => ScriptType::PayToScriptHash(hash.to_vec()),
+	    [StackOp::OpReturn, data @ ..] if (Script {ops: data.to_vec()}).is_push_only() => {
+		ScriptType::NullData(data.iter().map(push_item).collect())
+	    },
+	    [required, keys @ .., count, StackOp::OpCheckMultiSig] => {
+		let public_keys: Vec<Vec<u8>> = keys.iter().filter_map(|key| match key {
+		    StackOp::Bytes(public_key) if is_public_key_size(public_key) => Some(public_key.to_vec()),
+		    _ => None,
+		}).collect();
+		match (small_int(required), small_int(count)) {
+		    (Some(required), Some(count)) if required >= 1 && required <= count && count == keys.len() && count == public_keys.len() => {
+			ScriptType::Multisig {required, public_keys}
+		    },
+		    _ => ScriptType::NonStandard,
+		}
+	    },
+	    _ => ScriptType::NonStandard,
+	}
+    }
+
+    /// The redeem script that a pay to script hash unlocking script pushes last, as long as the unlocking script only pushes,
+    /// and what it pushes decodes as a script
+    pub fn redeem_script(&self) -> Option<Script> {
+	if !self.is_push_only() {
+	    return None;
+	}
+	Script::from_bytes(&push_item(self.ops.last()?)).ok()
+    }
+
+    /// How many signatures the script checks, the way bitcoin core counts them without running it: one for OpCheckSig,
+    /// and MAX_PUBKEYS_PER_MULTISIG for OpCheckMultiSig. If accurate, an OpCheckMultiSig right after OP_1 to OP_16 counts that many instead,
+    /// which is how the signatures of redeem scripts and witness scripts get counted
+    pub fn sigop_count(&self, accurate: bool) -> usize {
+	let mut count = 0;
+	let mut last_op = None;
+	for op in &self.ops {
+	    match op {
+		StackOp::OpCheckSig | StackOp::OpCheckSigVerify => count += 1,
+		StackOp::OpCheckMultiSig | StackOp::OpCheckMultiSigVerify => {
+		    count += match last_op.and_then(small_int) {
+			Some(key_count @ 1..=16) if accurate => key_count,
+			_ => MAX_PUBKEYS_PER_MULTISIG,
+		    };
+		},
+		_ => (),
+	    }
+	    last_op = Some(op);
+	}
+	count
+    }
+}
+
+/// The standard kinds of locking script that Script::classify() knows about, with the hashes, keys or data that they lock to
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptType {
+    PayToPublicKey(Vec<u8>), // <public key> OP_CHECKSIG, the oldest kind, which the early coinbases paid to
+    PayToPublicKeyHash(Vec<u8>), // the 20 byte hash 160 of the public key, see pay_to_public_key_hash()
+    PayToScriptHash(Vec<u8>), // the 20 byte hash 160 of the redeem script, see pay_to_script_hash()
+    PayToWitnessPublicKeyHash(Vec<u8>), // the 20 byte segwit version 0 program, see pay_to_witness_public_key_hash()
+    PayToWitnessScriptHash(Vec<u8>), // the 32 byte segwit version 0 program, see pay_to_witness_script_hash()
+    PayToTaproot(Vec<u8>), // the 32 byte output key, see pay_to_taproot()
+    WitnessUnknown {version: u8, program: Vec<u8>}, // a segwit version that isn't defined yet. These are fine to send to, so later upgrades can be used right away
+    Multisig {required: usize, public_keys: Vec<Vec<u8>>}, // a bare m of n multisig, see pay_to_multisig()
+    NullData(Vec<Vec<u8>>), // OP_RETURN followed by nothing but pushes, which is how data gets put in the chain. It can never be spent
+    NonStandard, // anything else
+}
+
+/// whether the bytes are the right size for a SEC1 public key, going by their first byte: 33 bytes compressed, 65 bytes uncompressed (or hybrid)
+fn is_public_key_size(bytes: &[u8]) -> bool {
+    match bytes.first() {
+	Some(0x02) | Some(0x03) => bytes.len() == 33,
+	Some(0x04) | Some(0x06) | Some(0x07) => bytes.len() == 65,
+	_ => false,
+    }
+}
+
+/// the number that an OP_0 to OP_16 pushes, for the counts in a multisig script
+fn small_int(op: &StackOp) -> Option<usize> {
+    match op {
+	StackOp::Val(val @ 0..=16) => Some(*val as usize),
+	StackOp::Bool(val) => Some(*val as usize),
+	_ => None,
+    }
+}
+
+/// the item that a push op puts on the stack
+fn push_item(op: &StackOp) -> Vec<u8> {
+    match op {
+	StackOp::Bool(val) => bool_item(*val),
+	StackOp::Val(val) => encode_script_num(*val as i64),
+	StackOp::Bytes(data) | StackOp::NonMinimalPush(_, data) => data.to_vec(),
+	_ => vec![],
+    }
+}
+
+impl Script {
+    /// Parse the ASM format that bitcoin core and block explorers show, e.g. "OP_DUP OP_HASH160 <hex> OP_EQUALVERIFY OP_CHECKSIG".
+    /// Tokens are separated by whitespace. Each one is either an opcode name, a decimal number (pushed as a Val),
+    /// or hex data (pushed as Bytes). OP_0 to OP_16 and OP_1NEGATE (and OP_FALSE/OP_TRUE) are Vals as well
+    pub fn parse_asm(asm: &str) -> Result<Self, AsmError> {
+	let mut ops = vec![];
+	for (index, token) in asm.split_whitespace().enumerate() {
+	    ops.push(Self::parse_asm_token(token).ok_or_else(|| {
+		if token.starts_with("OP_") {
+		    AsmError::UnknownOpcode(index, token.to_string())
+		} else {
+		    AsmError::InvalidData(index, token.to_string())
+		}
+	    })?);
+	}
+	Ok(Script {ops})
+    }
+
+    fn parse_asm_token(token: &str) -> Option<StackOp> {
+	if let Some((_, _, op)) = OPCODES.iter().find(|(name, _, _)| *name == token) {
+	    return Some(op.clone());
+	}
+	match token {
+	    "OP_0" | "OP_FALSE" => return Some(StackOp::Val(0)),
+	    "OP_1NEGATE" => return Some(StackOp::Val(-1)),
+	    "OP_TRUE" => return Some(StackOp::Val(1)),
+	    _ => (),
+	}
+	if let Some(small) = token.strip_prefix("OP_") {
+	    return match small.parse::<i32>() {
+		Ok(val @ 1..=16) => Some(StackOp::Val(val)),
+		_ => None,
+	    };
+	}
+	// anything that looks like a number is one, like in the output of Display. Everything else has to be hex
+	let is_number = !token.is_empty() && token.trim_start_matches('-').chars().all(|c| c.is_ascii_digit());
+	if is_number {
+	    return token.parse::<i32>().ok().map(StackOp::Val);
+	}
+	hex::decode(token).ok().map(|data| StackOp::Bytes(data.into_boxed_slice()))
+    }
+}
+
+/// Prints the script the same way bitcoin core's ASM does: opcodes by name, small numbers and pushes of up to 4 bytes as decimal numbers,
+/// and any longer pushes as hex
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	for (i, op) in self.ops.iter().enumerate() {
+	    if i > 0 {
+		write!(f, " ")?;
+	    }
+	    match op {
+		StackOp::Bool(val) => write!(f, "{}", *val as i32)?,
+		StackOp::Val(val) => write!(f, "{}", val)?,
+		StackOp::Bytes(data) | StackOp::NonMinimalPush(_, data) if data.len() <= 4 => write!(f, "{}", decode_script_num(data))?,
+		StackOp::Bytes(data) | StackOp::NonMinimalPush(_, data) => write!(f, "{}", hex::encode(data))?,
+		StackOp::OpInvalid(_) => write!(f, "OP_UNKNOWN")?,
+		_ => {
+		    let (name, _, _) = OPCODES.iter().find(|(_, _, named_op)| named_op == op).expect("every other op has a name");
+		    write!(f, "{}", name)?
+		},
+	    }
+	}
+	Ok(())
+    }
+}
+
+/// The reasons that ASM can't be parsed into a Script. Each one says which token (counting from 0) was the problem, and what it was
+#[derive(Debug, PartialEq)]
+pub enum AsmError {
+    UnknownOpcode(usize, String), // it starts with OP_, but isn't an opcode that we have
+    InvalidData(usize, String), // it is neither a number that fits in an i32, nor an even length hex string
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match self {
+	    AsmError::UnknownOpcode(index, token) => write!(f, "unknown opcode {:?} at token {}", token, index),
+	    AsmError::InvalidData(index, token) => write!(f, "{:?} at token {} is neither a number nor hex", token, index),
+	}
+    }
+}
+
+/// The reasons that raw bytes can't be turned into a Script
+#[derive(Debug, PartialEq)]
+pub enum ScriptDecodeError {
+    TruncatedPush(usize), // the push at this position in the script wants more bytes than there are left
+}
+
+/// read the little endian length that follows one of the OP_PUSHDATA opcodes
+fn take_len(bytes: &mut &[u8], size: usize) -> Option<usize> {
+    if bytes.len() < size {
+	return None;
+    }
+    let (len_bytes, rest) = bytes.split_at(size);
+    *bytes = rest;
+    Some(len_bytes.iter().rev().fold(0, |len, byte| (len << 8) | *byte as usize))
+}
+
+/// on the wire a script is a byte string, i.e. prefixed with its length
+impl Encodable for Script {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+	encode_bytes(&self.to_bytes(), bytes);
+    }
+}
+
+impl Decodable for Script {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+	Ok(Script::from_bytes(&decode_bytes(bytes)?)?)
+    }
+}
+
+/// the classic pay to public key hash locking script: only the holder of the private key for this (SEC1 encoded) public key
+/// can unlock it, by providing the public key and a signature
+pub fn pay_to_public_key_hash(public_key_bytes: &[u8]) -> Script {
+    let pub_hash = hash_160_to_bytes(public_key_bytes);
+    Script {ops: vec![StackOp::OpDup, StackOp::OpHash160, StackOp::Bytes(pub_hash.into_boxed_slice()), StackOp::OpEqVerify, StackOp::OpCheckSig]}
+}
+
+/// A bare m of n multisig locking script: any required of the public_keys have to sign, with their signatures
+/// in the same order as the keys. Panics if more keys are required than given, or if there are more than MAX_PUBKEYS_PER_MULTISIG keys
+pub fn pay_to_multisig(required: usize, public_keys: &[VerifyingKey<Secp256k1>]) -> Script {
+    assert!(required <= public_keys.len() && public_keys.len() <= MAX_PUBKEYS_PER_MULTISIG, "can't require {} of {} keys", required, public_keys.len());
+    let mut ops = vec![StackOp::Val(required as i32)];
+    for public_key in public_keys {
+	ops.push(StackOp::Bytes(public_key.to_encoded_point(true).to_bytes()));
+    }
+    ops.push(StackOp::Val(public_keys.len() as i32));
+    ops.push(StackOp::OpCheckMultiSig);
+    Script {ops}
+}
+
+/// A pay to script hash locking script (BIP16): it is unlocked by pushing the serialized redeem_script (after whatever the redeem script
+/// needs to unlock it), so the tx_out only has to hold a hash, no matter how complicated the conditions in the redeem script are
+pub fn pay_to_script_hash(redeem_script: &Script) -> Script {
+    let script_hash = hash_160_to_bytes(&redeem_script.to_bytes());
+    Script {ops: vec![StackOp::OpHash160, StackOp::Bytes(script_hash.into_boxed_slice()), StackOp::OpEqual]}
+}
+
+/// The segwit version of pay to public key hash: version 0 and the 20 byte hash 160 of the public key. The signature and public key
+/// go in the witness, and get checked the same way as for pay_to_public_key_hash()
+pub fn pay_to_witness_public_key_hash(public_key_bytes: &[u8]) -> Script {
+    let pub_hash = hash_160_to_bytes(public_key_bytes);
+    Script {ops: vec![StackOp::Val(0), StackOp::Bytes(pub_hash.into_boxed_slice())]}
+}
+
+/// The segwit version of pay to script hash: version 0 and the 32 byte SHA-256 of the witness script, which goes on the end of the witness.
+/// The longer hash makes collisions harder to find for scripts with several parties
+pub fn pay_to_witness_script_hash(witness_script: &Script) -> Script {
+    let script_hash = Sha256::digest(witness_script.to_bytes()).to_vec();
+    Script {ops: vec![StackOp::Val(0), StackOp::Bytes(script_hash.into_boxed_slice())]}
+}
+
+/// A taproot locking script (BIP341): version 1 and the 32 byte output key, which is the x only internal key tweaked with the merkle root
+/// of the script tree, if there is one. It is unlocked either by a signature for the output key, or by any of the scripts in the tree.
+/// Panics if the internal key isn't on the curve
+pub fn pay_to_taproot(internal_key: &[u8; 32], merkle_root: Option<&[u8; 32]>) -> Script {
+    let (output_key, _) = tweak_public_key(internal_key, merkle_root).expect("the internal key is on the curve");
+    Script {ops: vec![StackOp::Val(1), StackOp::Bytes(output_key.to_vec().into_boxed_slice())]}
+}
+
+/// the hash that public keys (and scripts for P2SH) get locked to: RIPEMD-160 of the SHA-256, so it is 20 bytes long
 pub fn hash_160_to_bytes(bytes: &[u8]) -> Vec<u8> {
-    let mut hasher = Sha256::new();
-    hasher.update(bytes);
-    hasher.finalize().to_vec()
+    Ripemd160::digest(Sha256::digest(bytes)).to_vec()
+}
+
+/// The largest script that can be run, in bytes (a tapscript is only limited by the size of the block)
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+
+/// The most non-push opcodes that a single script may contain, same as bitcoin
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+
+/// The most items that the stack and the alt stack may hold between them
+pub const MAX_STACK_SIZE: usize = 1000;
+
+/// The largest item that can be pushed onto the stack, in bytes
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+
+/// The most public keys that an OpCheckMultiSig can check against
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+
+/// Rather than an op count, a tapscript gets a budget of validation weight: this much plus the size of the witness.
+/// Each signature that it checks uses up VALIDATION_WEIGHT_PER_SIGOP, so the work can't grow faster than the transaction does
+pub const VALIDATION_WEIGHT_OFFSET: i64 = 50;
+pub const VALIDATION_WEIGHT_PER_SIGOP: i64 = 50;
+
+/// Script verification flags, to switch on the stricter rules. These get or'ed together into ExecutionContext::flags
+pub const SCRIPT_VERIFY_NONE: u32 = 0;
+pub const SCRIPT_VERIFY_P2SH: u32 = 1 << 0; // pay to script hash locking scripts also run the redeem script that the unlocking script pushed
+pub const SCRIPT_VERIFY_STRICTENC: u32 = 1 << 1; // ECDSA signatures have to be strict DER, with a hash type that we know, and public keys compressed or uncompressed
+pub const SCRIPT_VERIFY_DERSIG: u32 = 1 << 2; // ECDSA signatures have to be strict DER (BIP66)
+pub const SCRIPT_VERIFY_LOW_S: u32 = 1 << 3; // ECDSA signatures have to be strict DER, with the lower of the two s values that work (BIP62)
+pub const SCRIPT_VERIFY_NULLDUMMY: u32 = 1 << 4; // the extra item that OpCheckMultiSig pops has to be empty (BIP147)
+pub const SCRIPT_VERIFY_MINIMALDATA: u32 = 1 << 6; // pushes and the numbers that ops read off the stack have to be as short as possible
+pub const SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_NOPS: u32 = 1 << 7; // running one of the NOPs that a soft fork could give a meaning to is an error
+pub const SCRIPT_VERIFY_CLEANSTACK: u32 = 1 << 8; // the scripts have to leave exactly one item on the stack. Only makes sense with P2SH and WITNESS
+pub const SCRIPT_VERIFY_CHECKLOCKTIMEVERIFY: u32 = 1 << 9; // OpCheckLockTimeVerify does its check, rather than nothing at all
+pub const SCRIPT_VERIFY_CHECKSEQUENCEVERIFY: u32 = 1 << 10; // OpCheckSequenceVerify does its check, rather than nothing at all
+pub const SCRIPT_VERIFY_WITNESS: u32 = 1 << 11; // segwit locking scripts are unlocked by the witness (BIP141)
+pub const SCRIPT_VERIFY_NULLFAIL: u32 = 1 << 14; // a signature that doesn't check out has to be empty, so failing one can't be swapped for another (BIP146)
+pub const SCRIPT_VERIFY_TAPROOT: u32 = 1 << 17; // segwit version 1 locking scripts are taproot outputs (BIP341 and BIP342)
+/// the rules that every transaction in a block has to follow
+pub const SCRIPT_VERIFY_CONSENSUS: u32 = SCRIPT_VERIFY_P2SH | SCRIPT_VERIFY_DERSIG | SCRIPT_VERIFY_NULLDUMMY | SCRIPT_VERIFY_CHECKLOCKTIMEVERIFY | SCRIPT_VERIFY_CHECKSEQUENCEVERIFY | SCRIPT_VERIFY_WITNESS | SCRIPT_VERIFY_TAPROOT;
+
+/// Which kind of script is running, since that decides how signatures get checked
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SigVersion {
+    Base, // unlocking, locking and redeem scripts, signed with the legacy signature hash
+    WitnessV0, // the scripts of segwit version 0, signed with the BIP143 signature hash
+    Taproot, // a taproot key path spend, which is just a Schnorr signature over the BIP341 signature hash
+    Tapscript, // a script from a taproot script tree (BIP342), whose signatures also commit to the leaf
+}
+
+/// Everything about the transaction being verified that a script might need to look at while it runs
+#[derive(Debug, Clone, Copy)]
+pub struct ExecutionContext<'a> {
+    pub transaction: &'a Transaction, // the spending transaction, which signatures sign
+    pub input_index: usize, // which of its tx_ins is being unlocked
+    pub amount: u64, // the value of the tx_out being spent
+    pub flags: u32, // which SCRIPT_VERIFY_ rules are in force
+    pub spent_tx_outs: &'a [TxOut], // the tx_outs that all of the tx_ins spend, which taproot signatures commit to
+}
+
+impl<'a> ExecutionContext<'a> {
+    pub fn new(transaction: &'a Transaction, input_index: usize, amount: u64, flags: u32) -> Self {
+	ExecutionContext {transaction, input_index, amount, flags, spent_tx_outs: &[]}
+    }
+
+    /// the tx_outs spent by each of the tx_ins (in the same order). Without them, no taproot signature can check out
+    pub fn with_spent_tx_outs(mut self, spent_tx_outs: &'a [TxOut]) -> Self {
+	self.spent_tx_outs = spent_tx_outs;
+	self
+    }
+}
+
+/// What a taproot spend knows about itself beyond the ExecutionContext, which its signatures commit to: the annex, if there is one,
+/// and for a tapscript the hash of its leaf. A tapscript also keeps track of how much validation weight it has left
+#[derive(Debug, Clone, Default)]
+struct ExecutionData {
+    annex: Option<Vec<u8>>,
+    tapleaf_hash: Option<[u8; 32]>,
+    codeseparator_pos: u32, // where the last OpCodeSeparator that ran in the tapscript is, which its signatures commit to
+    validation_weight_left: i64,
+}
+
+/// The reasons that a script can fail to unlock
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScriptError {
+    StackUnderflow, // an op wanted more items than there were on the stack
+    InvalidStackOperation, // OpPick or OpRoll got a negative number
+    InvalidAltStackOperation, // OpFromAltStack found the alt stack empty
+    NumberOverflow, // an op wanted a number, but the item is longer than a number can be (4 bytes, or 5 for lock times)
+    NonMinimalNumber, // with SCRIPT_VERIFY_MINIMALDATA, a number wasn't encoded as short as possible
+    MinimalData, // with SCRIPT_VERIFY_MINIMALDATA, data was pushed with a bigger opcode than it needs
+    DiscourageUpgradableNops, // with SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_NOPS, an upgradable NOP was run
+    VerifyFailed, // OpVerify found false
+    EqualVerifyFailed, // OpEqVerify found two different items
+    NumEqualVerifyFailed, // OpNumEqualVerify found two different numbers
+    PubKeyType, // with SCRIPT_VERIFY_STRICTENC, a public key that is neither compressed nor uncompressed (e.g. hybrid). Or an empty one in a tapscript
+    CheckSigVerifyFailed, // OpCheckSigVerify found a signature that doesn't check out
+    CheckMultiSigVerifyFailed, // OpCheckMultiSigVerify found signatures that don't check out
+    PubKeyCount, // OpCheckMultiSig was asked for a negative number of keys, or more than MAX_PUBKEYS_PER_MULTISIG
+    SigCount, // OpCheckMultiSig was asked for a negative number of signatures, or more than there are keys
+    SigNullDummy, // with SCRIPT_VERIFY_NULLDUMMY, the extra item that OpCheckMultiSig pops wasn't empty
+    SigDer, // with SCRIPT_VERIFY_DERSIG (or LOW_S or STRICTENC), an ECDSA signature isn't strict DER
+    SigHighS, // with SCRIPT_VERIFY_LOW_S, an ECDSA signature has the high s value
+    SigHashType, // with SCRIPT_VERIFY_STRICTENC, an ECDSA signature has a hash type that we don't know
+    SigNullFail, // with SCRIPT_VERIFY_NULLFAIL, a signature that didn't check out wasn't empty
+    PushSize, // a push of more than MAX_SCRIPT_ELEMENT_SIZE bytes
+    OpCountExceeded, // the script has more than MAX_OPS_PER_SCRIPT non-push ops
+    ScriptSize, // the script is bigger than MAX_SCRIPT_SIZE
+    StackSize, // the stack and the alt stack hold more than MAX_STACK_SIZE items between them
+    UnbalancedConditional, // an OpElse or OpEndIf without an OpIf, or an OpIf that is never ended
+    OpReturn, // OpReturn was executed
+    NegativeLockTime, // OpCheckLockTimeVerify or OpCheckSequenceVerify got a negative number
+    UnsatisfiedLockTime, // the lock time that OpCheckLockTimeVerify or OpCheckSequenceVerify wants hasn't been set in the transaction
+    SigPushOnly, // the unlocking script for a pay to script hash locking script does more than push data
+    InvalidRedeemScript, // the redeem script for a pay to script hash locking script (or the witness script) can't be decoded
+    WitnessProgramWrongLength, // a version 0 witness program is neither 20 nor 32 bytes
+    WitnessProgramWitnessEmpty, // a pay to witness script hash was spent without a witness script
+    WitnessProgramMismatch, // the witness script doesn't hash to the witness program, or a pay to witness public key hash witness isn't two items
+    WitnessMalleated, // a segwit locking script, but the unlocking script isn't empty
+    WitnessMalleatedP2SH, // a segwit redeem script, but the unlocking script does more than push it
+    WitnessUnexpected, // a tx_in has a witness, but what it spends isn't a segwit locking script
+    CleanStack, // a witness script (or with SCRIPT_VERIFY_CLEANSTACK, any script) has to leave exactly one item on the stack
+    BadOpcode, // OpInvalid was run (or OP_VERIF or OP_VERNOTIF was there at all), or OpCheckSigAdd outside of a tapscript
+    DisabledOpcode, // one of the disabled opcodes, like OP_CAT, anywhere in the script
+    SchnorrSigSize, // a taproot signature that isn't 64 bytes (or 65 with the hash type)
+    SchnorrSigHashType, // a taproot signature with an unknown hash type, or 0 spelled out, or SIGHASH_SINGLE without a matching tx_out
+    SchnorrSig, // a taproot signature that doesn't check out. In a tapscript only the empty signature is allowed to fail
+    TaprootWrongControlSize, // the control block of a taproot script path spend is the wrong size
+    TapscriptValidationWeight, // a tapscript checked too many signatures for the size of its witness
+    TapscriptCheckMultiSig, // OpCheckMultiSig in a tapscript, where OpCheckSigAdd takes its place
+    TapscriptMinimalIf, // in a tapscript, the item that OpIf pops has to be empty or exactly 1
+    EvalFalse, // everything ran, but the stack didn't end with true on top
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	let description = match self {
+	    ScriptError::StackUnderflow => "not enough items on the stack",
+	    ScriptError::InvalidStackOperation => "invalid stack operation",
+	    ScriptError::InvalidAltStackOperation => "not enough items on the alt stack",
+	    ScriptError::NumberOverflow => "script number overflow",
+	    ScriptError::NonMinimalNumber => "script number isn't minimally encoded",
+	    ScriptError::MinimalData => "data push larger than necessary",
+	    ScriptError::DiscourageUpgradableNops => "NOPx reserved for soft-fork upgrades",
+	    ScriptError::VerifyFailed => "OP_VERIFY failed",
+	    ScriptError::EqualVerifyFailed => "OP_EQUALVERIFY failed",
+	    ScriptError::NumEqualVerifyFailed => "OP_NUMEQUALVERIFY failed",
+	    ScriptError::PubKeyType => "public key is neither compressed or uncompressed",
+	    ScriptError::CheckSigVerifyFailed => "OP_CHECKSIGVERIFY failed",
+	    ScriptError::CheckMultiSigVerifyFailed => "OP_CHECKMULTISIGVERIFY failed",
+	    ScriptError::PubKeyCount => "public key count out of range",
+	    ScriptError::SigCount => "signature count out of range",
+	    ScriptError::SigNullDummy => "OP_CHECKMULTISIG dummy isn't empty",
+	    ScriptError::SigDer => "non-canonical DER signature",
+	    ScriptError::SigHighS => "non-canonical signature: S value is unnecessarily high",
+	    ScriptError::SigHashType => "signature hash type missing or not understood",
+	    ScriptError::SigNullFail => "signature must be empty if the check fails",
+	    ScriptError::PushSize => "push larger than 520 bytes",
+	    ScriptError::OpCountExceeded => "too many opcodes",
+	    ScriptError::ScriptSize => "script is too big",
+	    ScriptError::StackSize => "stack size limit exceeded",
+	    ScriptError::UnbalancedConditional => "unbalanced conditional",
+	    ScriptError::OpReturn => "OP_RETURN was executed",
+	    ScriptError::NegativeLockTime => "negative lock time",
+	    ScriptError::UnsatisfiedLockTime => "lock time requirement not satisfied",
+	    ScriptError::SigPushOnly => "only pushes are allowed in the unlocking script",
+	    ScriptError::InvalidRedeemScript => "the redeem script can't be decoded",
+	    ScriptError::WitnessProgramWrongLength => "witness program has incorrect length",
+	    ScriptError::WitnessProgramWitnessEmpty => "witness program was passed an empty witness",
+	    ScriptError::WitnessProgramMismatch => "witness program hash mismatch",
+	    ScriptError::WitnessMalleated => "witness requires an empty unlocking script",
+	    ScriptError::WitnessMalleatedP2SH => "witness requires an unlocking script of only the redeem script",
+	    ScriptError::WitnessUnexpected => "witness provided for a non-witness script",
+	    ScriptError::CleanStack => "stack size must be exactly one after execution",
+	    ScriptError::BadOpcode => "opcode missing or not understood",
+	    ScriptError::DisabledOpcode => "attempted to use a disabled opcode",
+	    ScriptError::SchnorrSigSize => "invalid Schnorr signature size",
+	    ScriptError::SchnorrSigHashType => "invalid Schnorr signature hash type",
+	    ScriptError::SchnorrSig => "invalid Schnorr signature",
+	    ScriptError::TaprootWrongControlSize => "invalid taproot control block size",
+	    ScriptError::TapscriptValidationWeight => "too much signature validation relative to witness weight",
+	    ScriptError::TapscriptCheckMultiSig => "OP_CHECKMULTISIG is not available in tapscript",
+	    ScriptError::TapscriptMinimalIf => "OP_IF/NOTIF argument must be minimal in tapscript",
+	    ScriptError::EvalFalse => "script evaluated to false",
+	};
+	write!(f, "{}", description)
+    }
+}
+
+/// CastToBool: whether a stack item counts as true, e.g. for OpIf. That's anything but bytes that are all zero
+/// (where the last one may also be 0x80, i.e. negative zero), so the empty item is false too
+fn is_true(item: &[u8]) -> bool {
+    match item.split_last() {
+	Some((last, rest)) => rest.iter().any(|byte| *byte != 0) || (*last & 0x7f) != 0,
+	None => false,
+    }
+}
+
+/// the stack item for true or false, the same as OP_1 and OP_0 push
+fn bool_item(val: bool) -> Vec<u8> {
+    if val {vec![1]} else {vec![]}
+}
+
+/// whether the bytes are the shortest encoding of their number, i.e. the last byte isn't just there for a sign bit that could have gone in the byte before it
+fn is_minimal_num(bytes: &[u8]) -> bool {
+    match bytes {
+	[] => true,
+	[.., last] if *last & 0x7f != 0 => true,
+	[_] => false, // zero or negative zero, which should be empty
+	[.., second_last, _] => *second_last & 0x80 != 0,
+    }
+}
+
+/// Read a stack item as a number, which can be up to max_len bytes long. That's 4 bytes for arithmetic, so
+/// numbers only go from -2^31 + 1 to 2^31 - 1, but the results of arithmetic can be larger (they just can't be used for more arithmetic).
+/// With SCRIPT_VERIFY_MINIMALDATA numbers have to be minimally encoded
+fn item_to_num(item: &[u8], max_len: usize, context: &ExecutionContext) -> Result<i64, ScriptError> {
+    if item.len() > max_len {
+	return Err(ScriptError::NumberOverflow);
+    }
+    if context.flags & SCRIPT_VERIFY_MINIMALDATA != 0 && !is_minimal_num(item) {
+	return Err(ScriptError::NonMinimalNumber);
+    }
+    Ok(decode_script_num(item))
+}
+
+/// pop the top item as a number, e.g. an operand for OpAdd or a count for OpCheckMultiSig
+fn pop_num(stack: &mut Vec<Vec<u8>>, context: &ExecutionContext) -> Result<i64, ScriptError> {
+    item_to_num(&pop(stack)?, 4, context)
+}
+
+/// The check behind OpCheckLockTimeVerify: the spending transaction's lock_time has to be of the same kind (height or time)
+/// as the required one, and at least as large. The tx_in can't opt out of the lock_time with SEQUENCE_FINAL either
+fn check_lock_time(lock_time: i64, context: &ExecutionContext) -> bool {
+    let tx_lock_time = context.transaction.lock_time as i64;
+    let threshold = LOCKTIME_THRESHOLD as i64;
+    if (lock_time < threshold) != (tx_lock_time < threshold) || lock_time > tx_lock_time {
+	return false;
+    }
+    matches!(context.transaction.tx_ins.get(context.input_index), Some(tx_in) if tx_in.sequence() != SEQUENCE_FINAL)
+}
+
+/// The check behind OpCheckSequenceVerify: the sequence of the tx_in being unlocked has to be a relative lock time
+/// (so the transaction needs version 2) of the same kind (blocks or time) as the required one, and at least as large
+fn check_sequence(sequence: i64, context: &ExecutionContext) -> bool {
+    let tx_sequence = match context.transaction.tx_ins.get(context.input_index) {
+	Some(tx_in) => tx_in.sequence(),
+	None => return false,
+    };
+    if context.transaction.version < 2 || tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+	return false;
+    }
+    let mask = (SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK) as i64;
+    let (sequence, tx_sequence) = (sequence & mask, tx_sequence as i64 & mask);
+    let type_flag = SEQUENCE_LOCKTIME_TYPE_FLAG as i64;
+    (sequence & type_flag) == (tx_sequence & type_flag) && sequence <= tx_sequence
+}
+
+/// BIP66 strict DER, on the signature with its hash type byte still on the end: 0x30 len 0x02 len r 0x02 len s hash_type,
+/// where the lengths all add up, and r and s are positive and don't have any leading zeros that they don't need
+fn is_strict_der(bytes_sig: &[u8]) -> bool {
+    // the shortest has one byte each for r and s, and the longest 33
+    if bytes_sig.len() < 9 || bytes_sig.len() > 73 || bytes_sig[0] != 0x30 || bytes_sig[1] as usize != bytes_sig.len() - 3 {
+	return false;
+    }
+    let r_len = bytes_sig[3] as usize;
+    if 5 + r_len >= bytes_sig.len() {
+	return false;
+    }
+    let s_len = bytes_sig[5 + r_len] as usize;
+    if r_len + s_len + 7 != bytes_sig.len() {
+	return false;
+    }
+    let is_strict_integer = |tag: u8, int: &[u8]| {
+	tag == 0x02 && !int.is_empty() && int[0] & 0x80 == 0 && !(int.len() > 1 && int[0] == 0 && int[1] & 0x80 == 0)
+    };
+    is_strict_integer(bytes_sig[2], &bytes_sig[4..4 + r_len]) && is_strict_integer(bytes_sig[4 + r_len], &bytes_sig[6 + r_len..6 + r_len + s_len])
+}
+
+/// The rules for how an ECDSA signature is encoded, for whichever of them are switched on. The empty signature always goes,
+/// since that's how to not sign. SCRIPT_VERIFY_LOW_S wants the lower of the two s values that work (see verify_hash()),
+/// so that the signature can't be changed into the other one, which would change the txid
+fn check_signature_encoding(bytes_sig: &[u8], flags: u32) -> Result<(), ScriptError> {
+    let (hash_type, der) = match bytes_sig.split_last() {
+	Some(split) => split,
+	None => return Ok(()),
+    };
+    if flags & (SCRIPT_VERIFY_DERSIG | SCRIPT_VERIFY_LOW_S | SCRIPT_VERIFY_STRICTENC) != 0 && !is_strict_der(bytes_sig) {
+	return Err(ScriptError::SigDer);
+    }
+    // one that can't be read is left for the verification to fail
+    if flags & SCRIPT_VERIFY_LOW_S != 0 && signature_from_der(der).is_some_and(|signature| signature.normalize_s().is_some()) {
+	return Err(ScriptError::SigHighS);
+    }
+    if flags & SCRIPT_VERIFY_STRICTENC != 0 && !(SIGHASH_ALL..=SIGHASH_SINGLE).contains(&(hash_type & !SIGHASH_ANYONECANPAY)) {
+	return Err(ScriptError::SigHashType);
+    }
+    Ok(())
+}
+
+/// With SCRIPT_VERIFY_STRICTENC a public key has to be compressed (33 bytes starting with 0x02 or 0x03) or uncompressed (65 bytes starting with 0x04),
+/// which rules out the hybrid encoding. Like bitcoin core, it only goes by the encoding: whether it's on the curve is left for the verification
+fn check_public_key_encoding(bytes_pub: &[u8], flags: u32) -> Result<(), ScriptError> {
+    let is_compressed_or_uncompressed = match bytes_pub.first() {
+	Some(0x02 | 0x03) => bytes_pub.len() == 33,
+	Some(0x04) => bytes_pub.len() == 65,
+	_ => false,
+    };
+    if flags & SCRIPT_VERIFY_STRICTENC != 0 && !is_compressed_or_uncompressed {
+	return Err(ScriptError::PubKeyType);
+    }
+    Ok(())
+}
+
+/// Does the ECDSA signature (DER encoded, with its hash type byte on the end) sign the spending transaction, with script as the script code?
+/// Which signature hash gets signed depends on the sig_version, which is Base or WitnessV0 (taproot has check_schnorr_sig()).
+/// For Base, the signature is taken out of the script code first, see find_and_delete().
+/// Fails if the signature or the public key breaks the encoding rules in context.flags. Otherwise a signature or a public key that can't be
+/// parsed just doesn't verify, like in bitcoin core, so e.g. a multisig can still be unlocked with the other keys
+fn check_sig(bytes_sig: &[u8], bytes_pub: &[u8], script: &Script, context: &ExecutionContext, sig_version: SigVersion) -> Result<bool, ScriptError> {
+    check_signature_encoding(bytes_sig, context.flags)?;
+    check_public_key_encoding(bytes_pub, context.flags)?;
+    let public_key = match parse_public_key(bytes_pub) {
+	Some(public_key) => public_key,
+	None => return Ok(false),
+    };
+    // the last byte of the signature says which parts of the transaction it signs
+    let verified = match bytes_sig.split_last() {
+	Some((hash_type, bytes_der)) => match signature_from_der(bytes_der) {
+	    Some(signature) => {
+		let sighash = match sig_version {
+		    SigVersion::Base => signature_hash(context.transaction, context.input_index, &find_and_delete(script, bytes_sig), *hash_type),
+		    _ => segwit_signature_hash(context.transaction, context.input_index, script, context.amount, *hash_type),
+		};
+		verify_hash(&public_key, &sighash, &signature)
+	    },
+	    None => false, // a malformed signature just doesn't verify
+	},
+	None => false,
+    };
+    Ok(verified)
+}
+
+/// Parse an SEC1 public key the way libsecp256k1 does, which also takes the hybrid encoding: 0x06 or 0x07 (for an even or odd y),
+/// then x and y in full like an uncompressed key
+fn parse_public_key(bytes_pub: &[u8]) -> Option<VerifyingKey<Secp256k1>> {
+    let mut bytes_pub = bytes_pub.to_vec();
+    if let [tag @ (0x06 | 0x07), .., last] = bytes_pub[..] {
+	if bytes_pub.len() != 65 || tag & 1 != last & 1 {
+	    return None;
+	}
+	bytes_pub[0] = 0x04;
+    }
+    let encoded_point = EncodedPoint::<Secp256k1>::from_bytes(&bytes_pub).ok()?;
+    VerifyingKey::<Secp256k1>::from_encoded_point(&encoded_point).ok()
+}
+
+/// Bitcoin core's FindAndDelete(): the script code for a legacy signature check, with every push of the signature taken out of it,
+/// since the signature can't sign itself. It goes by the exact bytes, so a push of the signature with a bigger push opcode stays in
+fn find_and_delete(script_code: &Script, signature: &[u8]) -> Script {
+    let mut push = vec![];
+    push_data(signature, &mut push);
+    let ops = script_code.ops.iter().filter(|op| {
+	let mut bytes = vec![];
+	op.encode(&mut bytes);
+	bytes != push
+    }).cloned().collect();
+    Script {ops}
+}
+
+/// Does the BIP340 signature sign the spending transaction for the x only public key, for a key path spend or a tapscript?
+/// The hash type byte is left off for SIGHASH_DEFAULT, so spelling it out as 0 isn't allowed.
+/// Unlike ECDSA signatures, any signature that doesn't check out is an error
+fn check_schnorr_sig(bytes_sig: &[u8], bytes_pub: &[u8], context: &ExecutionContext, execution_data: &ExecutionData) -> Result<(), ScriptError> {
+    let (signature, hash_type) = match bytes_sig.len() {
+	64 => (bytes_sig, SIGHASH_DEFAULT),
+	65 if bytes_sig[64] != SIGHASH_DEFAULT => (&bytes_sig[..64], bytes_sig[64]),
+	65 => return Err(ScriptError::SchnorrSigHashType),
+	_ => return Err(ScriptError::SchnorrSigSize),
+    };
+    let sighash = taproot_signature_hash(context.transaction, context.input_index, context.spent_tx_outs, hash_type,
+					 execution_data.annex.as_deref(), execution_data.tapleaf_hash.as_ref().map(|leaf_hash| (leaf_hash, execution_data.codeseparator_pos)))
+	.ok_or(ScriptError::SchnorrSigHashType)?;
+    if !schnorr_verify(bytes_pub, &hash_to_bytes(&sighash), signature) {
+	return Err(ScriptError::SchnorrSig);
+    }
+    Ok(())
+}
+
+/// The signature check for OpCheckSig and OpCheckSigAdd in a tapscript. The empty signature is the only way to not sign, and anything else
+/// has to check out. Each signature uses up some validation weight. An empty public key is an error, and public keys of sizes other
+/// than 32 bytes are left for future upgrades, so any signature goes for them
+fn check_sig_tapscript(bytes_sig: &[u8], bytes_pub: &[u8], context: &ExecutionContext, execution_data: &mut ExecutionData) -> Result<bool, ScriptError> {
+    let signed = !bytes_sig.is_empty();
+    if signed {
+	execution_data.validation_weight_left -= VALIDATION_WEIGHT_PER_SIGOP;
+	if execution_data.validation_weight_left < 0 {
+	    return Err(ScriptError::TapscriptValidationWeight);
+	}
+    }
+    if bytes_pub.is_empty() {
+	return Err(ScriptError::PubKeyType);
+    }
+    if signed && bytes_pub.len() == 32 {
+	check_schnorr_sig(bytes_sig, bytes_pub, context, execution_data)?;
+    }
+    Ok(signed)
+}
+
+/// The stack for OpCheckMultiSig looks like (from the top down): n, n public keys, m, m signatures, and one more item.
+/// The signatures have to be in the same order as their public keys, so each one is checked against the remaining keys in turn,
+/// until it matches or there aren't enough keys left for the rest of the signatures.
+/// The extra item is popped because of an off by one bug in the original bitcoin, which everyone now has to copy.
+/// With SCRIPT_VERIFY_NULLFAIL, all the signatures have to be empty if they don't all check out.
+/// For a legacy script, all of the signatures are taken out of the script code before any of them are checked.
+/// Returns whether all the signatures checked out, along with n, which counts towards the op count
+fn check_multisig(stack: &mut Vec<Vec<u8>>, script: &Script, context: &ExecutionContext, sig_version: SigVersion) -> Result<(bool, usize), ScriptError> {
+    let key_count = pop_num(stack, context)?;
+    if key_count < 0 || key_count > MAX_PUBKEYS_PER_MULTISIG as i64 {
+	return Err(ScriptError::PubKeyCount);
+    }
+    let mut public_keys = vec![];
+    for _ in 0..key_count {
+	public_keys.push(pop(stack)?);
+    }
+    let sig_count = pop_num(stack, context)?;
+    if sig_count < 0 || sig_count > key_count {
+	return Err(ScriptError::SigCount);
+    }
+    let mut signatures = vec![];
+    for _ in 0..sig_count {
+	signatures.push(pop(stack)?);
+    }
+    let dummy = pop(stack)?;
+    if context.flags & SCRIPT_VERIFY_NULLDUMMY != 0 && !dummy.is_empty() {
+	return Err(ScriptError::SigNullDummy);
+    }
+
+    // both were popped top first, so flip them around to have the first signature matched against the first public key
+    public_keys.reverse();
+    signatures.reverse();
+    let script = match sig_version {
+	SigVersion::Base => signatures.iter().fold(script.clone(), |script_code, signature| find_and_delete(&script_code, signature)),
+	_ => script.clone(),
+    };
+    let (mut key_index, mut sig_index) = (0, 0);
+    while sig_index < signatures.len() {
+	if signatures.len() - sig_index > public_keys.len() - key_index {
+	    // not enough keys left for the signatures that are left
+	    if context.flags & SCRIPT_VERIFY_NULLFAIL != 0 && signatures.iter().any(|signature| !signature.is_empty()) {
+		return Err(ScriptError::SigNullFail);
+	    }
+	    return Ok((false, key_count as usize));
+	}
+	// an empty signature never matches, but the public key still has to be encoded properly
+	let matched = check_sig(&signatures[sig_index], &public_keys[key_index], &script, context, sig_version)?;
+	if matched {
+	    sig_index += 1;
+	}
+	key_index += 1;
+    }
+    Ok((true, key_count as usize))
+}
+
+/// pop the top item, or fail if the stack is empty
+fn pop(stack: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, ScriptError> {
+    stack.pop().ok_or(ScriptError::StackUnderflow)
+}
+
+/// pop the top two items, returned in the order they were pushed (so the top one is second)
+fn pop_two(stack: &mut Vec<Vec<u8>>) -> Result<(Vec<u8>, Vec<u8>), ScriptError> {
+    let top = pop(stack)?;
+    let below = pop(stack)?;
+    Ok((below, top))
+}
 
+/// the length of the stack, or an error if it doesn't have at least depth items
+fn check_depth(stack: &[Vec<u8>], depth: usize) -> Result<usize, ScriptError> {
+    if stack.len() < depth {
+	return Err(ScriptError::StackUnderflow);
+    }
+    Ok(stack.len())
 }
 
 /// given an unlocking script and a locking script, this function executes them on a stack and
-/// returns a bool to indicate if the unlocking script is valid for the locking script, i.e. is the
-/// the associated transaction allowed
+/// returns whether the unlocking script is valid for the locking script, i.e. is the
+/// the associated transaction allowed, and if not, what went wrong
 /// "A transaction is valid if nothing in the combined script triggers failure and the top stack
 /// item is True when the script exits."
-/// The previous transaction hash (for the tx_prev that we are trying to unlock) is used for OpChecksig as
-/// the "message" to verify the signature on. If OpChecksig does not occur, then this argument is not used
-pub fn execute_scripts(unlocking_script: &Script, locking_script: &Script, tx_previous_hash: &[u8]) -> bool {
-    let mut stack: Vec<StackOp> = Vec::new();
-    for op in unlocking_script.ops.iter().chain(locking_script.ops.iter()) {
-	println!("stack = {:?}", stack);	
-	println!("op = {:?}", op);
+/// Like bitcoin, the unlocking script runs first, and the locking script then runs on the stack that it left behind.
+/// The context says which transaction and tx_in OpChecksig verifies the signature against: the signature hash of the
+/// transaction, with the locking script standing in for the unlocking script.
+/// The stack only holds bytes, the same as in bitcoin: numbers are pushed as their script number encoding, and true and false as 1 and empty.
+/// With SCRIPT_VERIFY_P2SH, a pay to script hash locking script only checks the hash of the redeem script, so then the redeem script
+/// has to succeed as well, run on the stack that the unlocking script left behind (without the redeem script itself).
+/// With SCRIPT_VERIFY_WITNESS, a segwit locking script (or redeem script) is unlocked by the witness of the tx_in instead, see verify_witness_program().
+/// The unlocking script then has to be empty (or only push the redeem script), and a tx_in that doesn't spend a segwit locking script
+/// mustn't have a witness, since nothing would check it
+pub fn execute_scripts(unlocking_script: &Script, locking_script: &Script, context: &ExecutionContext) -> Result<(), ScriptError> {
+    let witness = context.transaction.tx_ins.get(context.input_index).map(|tx_in| tx_in.witness()).unwrap_or(&[]);
+    let check_witness = context.flags & SCRIPT_VERIFY_WITNESS != 0;
+    let mut had_witness = false;
+    let mut stack: Vec<Vec<u8>> = Vec::new();
+    let mut execution_data = ExecutionData::default(); // only taproot uses it
+    eval_script(unlocking_script, &mut stack, context, SigVersion::Base, &mut execution_data)?;
+    let is_pay_to_script_hash = context.flags & SCRIPT_VERIFY_P2SH != 0 && locking_script.is_pay_to_script_hash();
+    let unlocked_stack = if is_pay_to_script_hash {stack.clone()} else {vec![]};
+    eval_script(locking_script, &mut stack, context, SigVersion::Base, &mut execution_data)?;
+    // nothing triggered an early exit, so check if the top value is True
+    check_top_is_true(&stack)?;
+
+    if let Some((version, program)) = locking_script.witness_program().filter(|_| check_witness) {
+	had_witness = true;
+	// anything in the unlocking script could be changed by anyone, which would change the txid
+	if !unlocking_script.ops.is_empty() {
+	    return Err(ScriptError::WitnessMalleated);
+	}
+	verify_witness_program(witness, version, &program, false, context)?;
+    }
+
+    if is_pay_to_script_hash {
+	// otherwise the unlocking script could do anything at all, as long as it left the redeem script on top
+	if !unlocking_script.is_push_only() {
+	    return Err(ScriptError::SigPushOnly);
+	}
+	stack = unlocked_stack;
+	let redeem_script_bytes = pop(&mut stack)?;
+	let redeem_script = Script::from_bytes(&redeem_script_bytes).map_err(|_| ScriptError::InvalidRedeemScript)?;
+	eval_script(&redeem_script, &mut stack, context, SigVersion::Base, &mut execution_data)?;
+	check_top_is_true(&stack)?;
+
+	if let Some((version, program)) = redeem_script.witness_program().filter(|_| check_witness) {
+	    had_witness = true;
+	    // segwit nested in pay to script hash: the redeem script has to be all there is to the unlocking script
+	    if unlocking_script.to_bytes() != (Script {ops: vec![StackOp::Bytes(redeem_script_bytes.into_boxed_slice())]}).to_bytes() {
+		return Err(ScriptError::WitnessMalleatedP2SH);
+	    }
+	    verify_witness_program(witness, version, &program, true, context)?;
+	}
+    }
+
+    if check_witness && !had_witness && !witness.is_empty() {
+	return Err(ScriptError::WitnessUnexpected);
+    }
+    // a witness program leaves its version and program behind, but the witness had to leave a clean stack already
+    if context.flags & SCRIPT_VERIFY_CLEANSTACK != 0 && !had_witness && stack.len() != 1 {
+	return Err(ScriptError::CleanStack);
+    }
+    Ok(())
+}
+
+/// Unlock a witness program of the given version with the witness. For version 0, a 20 byte program is a pay to witness public key hash,
+/// where the witness has to be a signature and a public key that the usual pay to public key hash script accepts.
+/// A 32 byte program is a pay to witness script hash, where the last item of the witness is the witness script, which has to hash to the program,
+/// and runs on the rest of the witness. Either way, the script has to leave exactly one item behind, which has to be true.
+/// With SCRIPT_VERIFY_TAPROOT, a version 1 program of 32 bytes is a taproot output, see verify_taproot(), unless it is nested in pay to script hash.
+/// Other versions aren't defined yet, so they always succeed. That way they can be given meaning by a soft fork later
+fn verify_witness_program(witness: &[Vec<u8>], version: u8, program: &[u8], is_pay_to_script_hash: bool, context: &ExecutionContext) -> Result<(), ScriptError> {
+    if version == 1 && program.len() == 32 && !is_pay_to_script_hash && context.flags & SCRIPT_VERIFY_TAPROOT != 0 {
+	return verify_taproot(witness, program, context);
+    }
+    if version != 0 {
+	return Ok(());
+    }
+    let (script, mut stack) = match program.len() {
+	32 => {
+	    let (witness_script_bytes, stack) = witness.split_last().ok_or(ScriptError::WitnessProgramWitnessEmpty)?;
+	    if Sha256::digest(witness_script_bytes).as_slice() != program {
+		return Err(ScriptError::WitnessProgramMismatch);
+	    }
+	    let witness_script = Script::from_bytes(witness_script_bytes).map_err(|_| ScriptError::InvalidRedeemScript)?;
+	    (witness_script, stack.to_vec())
+	},
+	20 => {
+	    if witness.len() != 2 {
+		return Err(ScriptError::WitnessProgramMismatch);
+	    }
+	    let script = Script {ops: vec![StackOp::OpDup, StackOp::OpHash160, StackOp::Bytes(program.into()), StackOp::OpEqVerify, StackOp::OpCheckSig]};
+	    (script, witness.to_vec())
+	},
+	_ => return Err(ScriptError::WitnessProgramWrongLength),
+    };
+    // the witness items didn't get pushed by a script, so their size wasn't checked yet
+    if stack.iter().any(|item| item.len() > MAX_SCRIPT_ELEMENT_SIZE) {
+	return Err(ScriptError::PushSize);
+    }
+    eval_script(&script, &mut stack, context, SigVersion::WitnessV0, &mut ExecutionData::default())?;
+    if stack.len() != 1 {
+	return Err(ScriptError::CleanStack);
+    }
+    check_top_is_true(&stack)
+}
+
+/// Unlock a taproot output (BIP341), whose witness program is the output key. If the last of at least two witness items starts with ANNEX_TAG,
+/// it is the annex, which is taken off and only gets signed. Then a single item left is a key path spend: a signature for the output key.
+/// Otherwise it is a script path spend, where the last item is the control block and the one before it is the script, which the output key
+/// has to commit to (see verify_taproot_commitment()). A tapscript then runs on the rest of the witness, while other leaf versions succeed,
+/// to be given meaning by a soft fork later
+fn verify_taproot(witness: &[Vec<u8>], program: &[u8], context: &ExecutionContext) -> Result<(), ScriptError> {
+    let mut stack = witness.to_vec();
+    let mut execution_data = ExecutionData::default();
+    if stack.len() >= 2 && stack.last().and_then(|item| item.first()) == Some(&ANNEX_TAG) {
+	execution_data.annex = stack.pop();
+    }
+    if stack.len() == 1 {
+	return check_schnorr_sig(&stack[0], program, context, &execution_data);
+    }
+    let control_block = stack.pop().ok_or(ScriptError::WitnessProgramWitnessEmpty)?;
+    let script_bytes = pop(&mut stack)?;
+    let path_len = control_block.len().saturating_sub(TAPROOT_CONTROL_BASE_SIZE);
+    if control_block.len() < TAPROOT_CONTROL_BASE_SIZE || path_len % TAPROOT_CONTROL_NODE_SIZE != 0
+	|| path_len / TAPROOT_CONTROL_NODE_SIZE > TAPROOT_CONTROL_MAX_NODE_COUNT {
+	return Err(ScriptError::TaprootWrongControlSize);
+    }
+    let leaf_version = control_block[0] & TAPROOT_LEAF_MASK;
+    let leaf_hash = tap_leaf_hash(leaf_version, &script_bytes);
+    if !verify_taproot_commitment(&control_block, program, &leaf_hash) {
+	return Err(ScriptError::WitnessProgramMismatch);
+    }
+    if leaf_version != TAPROOT_LEAF_TAPSCRIPT {
+	return Ok(());
+    }
+
+    // a tapscript gets to check one signature for every 50 bytes of witness
+    let mut witness_bytes = vec![];
+    CompactSize(witness.len() as u64).encode(&mut witness_bytes);
+    for item in witness {
+	encode_bytes(item, &mut witness_bytes);
+    }
+    execution_data.tapleaf_hash = Some(leaf_hash);
+    execution_data.codeseparator_pos = u32::MAX; // none yet
+    execution_data.validation_weight_left = witness_bytes.len() as i64 + VALIDATION_WEIGHT_OFFSET;
+    // OP_SUCCESSx makes the script succeed before anything is run, so new opcodes can be added by a soft fork
+    if has_op_success(&script_bytes)? {
+	return Ok(());
+    }
+    let script = Script::from_bytes(&script_bytes).map_err(|_| ScriptError::BadOpcode)?;
+    if stack.len() > MAX_STACK_SIZE {
+	return Err(ScriptError::StackSize);
+    }
+    if stack.iter().any(|item| item.len() > MAX_SCRIPT_ELEMENT_SIZE) {
+	return Err(ScriptError::PushSize);
+    }
+    eval_script(&script, &mut stack, context, SigVersion::Tapscript, &mut execution_data)?;
+    if stack.len() != 1 {
+	return Err(ScriptError::CleanStack);
+    }
+    check_top_is_true(&stack)
+}
+
+/// the opcodes that satoshi disabled, e.g. OP_CAT and OP_MUL. Outside of tapscript they fail the script just by being in it
+fn is_disabled_opcode(opcode: u8) -> bool {
+    matches!(opcode, 0x7e..=0x81 | 0x83..=0x86 | 0x8d..=0x8e | 0x95..=0x99)
+}
+
+/// the opcodes that make a tapscript succeed straight away (BIP342), i.e. the ones that were disabled or never had a meaning
+fn is_op_success(opcode: u8) -> bool {
+    matches!(opcode, 0x50 | 0x62 | 0x7e..=0x81 | 0x83..=0x86 | 0x89..=0x8a | 0x8d..=0x8e | 0x95..=0x99 | 0xbb..=0xfe)
+}
+
+/// Whether the raw tapscript has an OP_SUCCESSx opcode, skipping over the data that gets pushed. This has to happen before the script is decoded,
+/// since we don't have StackOps for them. A push that runs off the end of the script before any OP_SUCCESSx is an error
+fn has_op_success(mut bytes: &[u8]) -> Result<bool, ScriptError> {
+    while let Some((&opcode, rest)) = bytes.split_first() {
+	bytes = rest;
+	let push_len = match opcode {
+	    0x01..=0x4b => opcode as usize,
+	    OP_PUSHDATA1 => take_len(&mut bytes, 1).ok_or(ScriptError::BadOpcode)?,
+	    OP_PUSHDATA2 => take_len(&mut bytes, 2).ok_or(ScriptError::BadOpcode)?,
+	    OP_PUSHDATA4 => take_len(&mut bytes, 4).ok_or(ScriptError::BadOpcode)?,
+	    _ if is_op_success(opcode) => return Ok(true),
+	    _ => 0,
+	};
+	if bytes.len() < push_len {
+	    return Err(ScriptError::BadOpcode);
+	}
+	bytes = &bytes[push_len..];
+    }
+    Ok(false)
+}
+
+/// a script succeeds if it leaves something true on top of the stack
+fn check_top_is_true(stack: &[Vec<u8>]) -> Result<(), ScriptError> {
+    match stack.last() {
+	Some(top) if is_true(top) => Ok(()),
+	_ => Err(ScriptError::EvalFalse),
+    }
+}
+
+/// Run a single script on the given stack. The script is also what gets signed in place of the unlocking script by OpCheckSig,
+/// from just after the last OpCodeSeparator that was run.
+/// A tapscript has somewhat different rules: there's no op count (the validation weight in the execution_data stands in for it),
+/// OpCheckSigAdd replaces OpCheckMultiSig, signatures are Schnorr signatures, and OpIf only takes empty or 1
+fn eval_script(script: &Script, stack: &mut Vec<Vec<u8>>, context: &ExecutionContext, sig_version: SigVersion, execution_data: &mut ExecutionData) -> Result<(), ScriptError> {
+    let is_tapscript = sig_version == SigVersion::Tapscript;
+    if !is_tapscript && script.to_bytes().len() > MAX_SCRIPT_SIZE {
+	return Err(ScriptError::ScriptSize);
+    }
+    // OP_RESERVED sits among the push opcodes, so it doesn't count either
+    let mut op_count = script.ops.iter().filter(|op| !op.is_push() && **op != StackOp::OpInvalid(OP_RESERVED)).count();
+    if !is_tapscript && op_count > MAX_OPS_PER_SCRIPT {
+	return Err(ScriptError::OpCountExceeded);
+    }
+    // one entry for each OpIf that we are inside of, saying whether its current branch is being executed.
+    // Ops only run if all of them are, but the conditionals themselves are always looked at, to keep track of the nesting
+    let mut exec_stack: Vec<bool> = vec![];
+    // somewhere to put items out of the way for a while. It is only there for the one script
+    let mut alt_stack: Vec<Vec<u8>> = vec![];
+    let mut script_code_start = 0;
+    for (position, op) in script.ops.iter().enumerate() {
+	let executing = exec_stack.iter().all(|branch| *branch);
 	match op {
-	    StackOp::Bool(val) => stack.push(StackOp::Bool(*val)),	    
-	    StackOp::Val(val) => stack.push(StackOp::Val(*val)),
-	    StackOp::Bytes(bytes_box) => stack.push(StackOp::Bytes(bytes_box.clone())),
-	    StackOp::OpAdd => {
-		// attempt to pop two numbers off the stack, add them, then put the result
-		// back on the stack. If this is not possible, we return false as invalid
-		if let (Some(op1), Some(op2)) = (stack.pop(), stack.pop()) {
-		    if let (StackOp::Val(val1), StackOp::Val(val2)) = (op1, op2) {
-			stack.push(StackOp::Val(val1 + val2));
-		    } else {
-			return false;
+	    StackOp::OpIf | StackOp::OpNotIf => {
+		let mut branch = false; // a skipped OpIf skips both of its branches
+		if executing {
+		    let item = pop(stack)?;
+		    if is_tapscript && !(item.is_empty() || item == [1]) {
+			return Err(ScriptError::TapscriptMinimalIf);
 		    }
-		} else {
-		    return false;
-		}
-	    },
-	    StackOp::OpSub => {
-		// attempt to pop two numbers off the stack, sub them, then put the result
-		// back on the stack. If this is not possible, we return false as invalid
-		if let (Some(op1), Some(op2)) = (stack.pop(), stack.pop()) {
-		    if let (StackOp::Val(val2), StackOp::Val(val1)) = (op1, op2) {
-			// we want to sub the bottom by the top
-			stack.push(StackOp::Val(val1 - val2));
-		    } else {
-			return false;
+		    branch = is_true(&item);
+		    if *op == StackOp::OpNotIf {
+			branch = !branch;
 		    }
-		} else {
-		    return false;
 		}
+		exec_stack.push(branch);
+	    }
+	    StackOp::OpElse => {
+		let branch = exec_stack.last_mut().ok_or(ScriptError::UnbalancedConditional)?;
+		*branch = !*branch;
+	    }
+	    StackOp::OpEndIf => {
+		exec_stack.pop().ok_or(ScriptError::UnbalancedConditional)?;
+	    }
+	    // these fail even in a branch that isn't taken
+	    StackOp::OpInvalid(opcode) if is_disabled_opcode(*opcode) => return Err(ScriptError::DisabledOpcode),
+	    StackOp::OpInvalid(OP_VERIF | OP_VERNOTIF) => return Err(ScriptError::BadOpcode),
+	    StackOp::Bytes(bytes) | StackOp::NonMinimalPush(_, bytes) if bytes.len() > MAX_SCRIPT_ELEMENT_SIZE => return Err(ScriptError::PushSize),
+	    _ if !executing => {}, // in a branch that isn't taken
+	    _ if context.flags & SCRIPT_VERIFY_MINIMALDATA != 0 && !op.is_minimal_push() => return Err(ScriptError::MinimalData),
+	    StackOp::OpReturn => return Err(ScriptError::OpReturn),
+	    StackOp::Bool(val) => stack.push(bool_item(*val)),
+	    StackOp::Val(val) => stack.push(encode_script_num(*val as i64)),
+	    StackOp::Bytes(bytes) | StackOp::NonMinimalPush(_, bytes) => stack.push(bytes.to_vec()),
+	    StackOp::OpNop => {},
+	    StackOp::OpUpgradableNop(_) => {
+		if context.flags & SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_NOPS != 0 {
+		    return Err(ScriptError::DiscourageUpgradableNops);
+		}
+	    }
+	    StackOp::OpCodeSeparator => {
+		script_code_start = position + 1;
+		execution_data.codeseparator_pos = position as u32;
+	    }
+	    StackOp::OpInvalid(_) => return Err(ScriptError::BadOpcode),
+	    StackOp::Op1Add | StackOp::Op1Sub | StackOp::OpNegate | StackOp::OpAbs | StackOp::OpNot | StackOp::Op0NotEqual => {
+		// the result is calculated with 64 bits, so it can't overflow, even though it might not fit in 4 bytes
+		let val = pop_num(stack, context)?;
+		stack.push(match op {
+		    StackOp::Op1Add => encode_script_num(val + 1),
+		    StackOp::Op1Sub => encode_script_num(val - 1),
+		    StackOp::OpNegate => encode_script_num(-val),
+		    StackOp::OpAbs => encode_script_num(val.abs()),
+		    StackOp::OpNot => bool_item(val == 0),
+		    _ => bool_item(val != 0),
+		});
+	    }
+	    StackOp::OpAdd | StackOp::OpSub | StackOp::OpBoolAnd | StackOp::OpBoolOr | StackOp::OpNumEqual | StackOp::OpNumNotEqual
+		| StackOp::OpLessThan | StackOp::OpGreaterThan | StackOp::OpLessThanOrEqual | StackOp::OpGreaterThanOrEqual
+		| StackOp::OpMin | StackOp::OpMax => {
+		// pop two numbers off the stack, and put the result back on the stack. val1 was the bottom one
+		let val2 = pop_num(stack, context)?;
+		let val1 = pop_num(stack, context)?;
+		stack.push(match op {
+		    StackOp::OpAdd => encode_script_num(val1 + val2),
+		    StackOp::OpSub => encode_script_num(val1 - val2),
+		    StackOp::OpBoolAnd => bool_item(val1 != 0 && val2 != 0),
+		    StackOp::OpBoolOr => bool_item(val1 != 0 || val2 != 0),
+		    StackOp::OpNumEqual => bool_item(val1 == val2),
+		    StackOp::OpNumNotEqual => bool_item(val1 != val2),
+		    StackOp::OpLessThan => bool_item(val1 < val2),
+		    StackOp::OpGreaterThan => bool_item(val1 > val2),
+		    StackOp::OpLessThanOrEqual => bool_item(val1 <= val2),
+		    StackOp::OpGreaterThanOrEqual => bool_item(val1 >= val2),
+		    StackOp::OpMin => encode_script_num(val1.min(val2)),
+		    _ => encode_script_num(val1.max(val2)),
+		});
+	    }
+	    StackOp::OpNumEqualVerify => {
+		let val2 = pop_num(stack, context)?;
+		let val1 = pop_num(stack, context)?;
+		if val1 != val2 {
+		    return Err(ScriptError::NumEqualVerifyFailed);
+		}
+	    }
+	    StackOp::OpWithin => {
+		let max = pop_num(stack, context)?;
+		let min = pop_num(stack, context)?;
+		let val = pop_num(stack, context)?;
+		stack.push(bool_item(min <= val && val < max));
+	    }
+	    StackOp::OpToAltStack => alt_stack.push(pop(stack)?),
+	    StackOp::OpFromAltStack => stack.push(alt_stack.pop().ok_or(ScriptError::InvalidAltStackOperation)?),
+	    StackOp::OpDrop => {
+		pop(stack)?;
+	    }
+	    StackOp::Op2Drop => {
+		pop_two(stack)?;
 	    }
 	    StackOp::OpDup => {
-		// attempt to pop a numbers off the stack, then put it back onto the stack twice
-		// If this is not possible, we return false as invalid
-		if let Some(op1) = stack.pop() {
-		    if let StackOp::Val(val1) = op1 {
-			stack.push(StackOp::Val(val1));
-			stack.push(StackOp::Val(val1));			
-		    } else if let StackOp::Bytes(bytes_box) = op1 {
-			stack.push(StackOp::Bytes(bytes_box.clone()));
-			stack.push(StackOp::Bytes(bytes_box.clone()));			
-		    } else {
-			return false;
-		    }
-		} else {
-		    return false;
+		// copy whatever is on top of the stack, and put it on the stack again
+		let top = stack.last().ok_or(ScriptError::StackUnderflow)?.clone();
+		stack.push(top);
+	    }
+	    StackOp::Op2Dup => {
+		let len = check_depth(stack, 2)?;
+		stack.extend_from_within(len - 2..);
+	    }
+	    StackOp::Op3Dup => {
+		let len = check_depth(stack, 3)?;
+		stack.extend_from_within(len - 3..);
+	    }
+	    StackOp::OpIfDup => {
+		let top = stack.last().ok_or(ScriptError::StackUnderflow)?.clone();
+		if is_true(&top) {
+		    stack.push(top);
 		}
 	    }
-	    StackOp::OpHash160 => {
-		if let Some(op1) = stack.pop() {
-		    match op1 {
-			/*
-			StackOp::Val(val1) => {
-			    let hash = hash_160_to_bytes(&val1.to_be_bytes());
-			    stack.push(StackOp::Bytes(hash.into_boxed_slice()));
-			},
-			 */
-			StackOp::Bytes(bytes) => {
-			    let hash = hash_160_to_bytes(&bytes);
-			    stack.push(StackOp::Bytes(hash.into_boxed_slice()));
-			}
-			_ => {
-			    ()
-			},
-		    }
-		} else {
-		    return false;
+	    StackOp::OpNip => {
+		let len = check_depth(stack, 2)?;
+		stack.remove(len - 2);
+	    }
+	    StackOp::OpOver => {
+		let len = check_depth(stack, 2)?;
+		stack.extend_from_within(len - 2..len - 1);
+	    }
+	    StackOp::Op2Over => {
+		let len = check_depth(stack, 4)?;
+		stack.extend_from_within(len - 4..len - 2);
+	    }
+	    StackOp::OpPick | StackOp::OpRoll => {
+		// n counts down from the top item, which is 0 (once n itself is popped)
+		let n = pop_num(stack, context)?;
+		if n < 0 {
+		    return Err(ScriptError::InvalidStackOperation);
+		}
+		if n >= stack.len() as i64 {
+		    return Err(ScriptError::StackUnderflow);
 		}
+		let index = stack.len() - 1 - n as usize;
+		let item = if *op == StackOp::OpPick {stack[index].clone()} else {stack.remove(index)};
+		stack.push(item);
+	    }
+	    StackOp::OpRot => {
+		let len = check_depth(stack, 3)?;
+		let item = stack.remove(len - 3);
+		stack.push(item);
+	    }
+	    StackOp::Op2Rot => {
+		let len = check_depth(stack, 6)?;
+		let items: Vec<Vec<u8>> = stack.drain(len - 6..len - 4).collect();
+		stack.extend(items);
+	    }
+	    StackOp::OpSwap => {
+		let len = check_depth(stack, 2)?;
+		stack.swap(len - 2, len - 1);
+	    }
+	    StackOp::Op2Swap => {
+		let len = check_depth(stack, 4)?;
+		stack.swap(len - 4, len - 2);
+		stack.swap(len - 3, len - 1);
+	    }
+	    StackOp::OpTuck => {
+		let len = check_depth(stack, 2)?;
+		let top = stack[len - 1].clone();
+		stack.insert(len - 2, top);
+	    }
+	    StackOp::OpDepth => stack.push(encode_script_num(stack.len() as i64)),
+	    StackOp::OpSize => {
+		let size = stack.last().ok_or(ScriptError::StackUnderflow)?.len();
+		stack.push(encode_script_num(size as i64));
+	    }
+	    StackOp::OpRipemd160 | StackOp::OpSha1 | StackOp::OpSha256 | StackOp::OpHash160 | StackOp::OpHash256 => {
+		let item = pop(stack)?;
+		stack.push(match op {
+		    StackOp::OpRipemd160 => Ripemd160::digest(&item).to_vec(),
+		    StackOp::OpSha1 => Sha1::digest(&item).to_vec(),
+		    StackOp::OpSha256 => Sha256::digest(&item).to_vec(),
+		    StackOp::OpHash160 => hash_160_to_bytes(&item),
+		    _ => Sha256::digest(Sha256::digest(&item)).to_vec(),
+		});
 	    }
-		
 	    StackOp::OpEqual => {
-		// attempt to pop two numbers off the stack, and see if they are equal
-		// if so, we put true on the stack
-		if let (Some(op1), Some(op2)) = (stack.pop(), stack.pop()) {
-		    if let (StackOp::Val(val1), StackOp::Val(val2)) = (&op1, &op2) {
-			stack.push(StackOp::Bool(val1 == val2));
-		    } else if let (StackOp::Bytes(bytes1), StackOp::Bytes(bytes2)) = (&op1, &op2) {
-			println!("bytes1 = {:?}", bytes1);
-			println!("bytes2 = {:?}", bytes2);			
-			println!("bytes1 == bytes2 = {:?}", bytes1 == bytes2);						
-			stack.push(StackOp::Bool(bytes1 == bytes2));
-		    } else {
-			return false;
-		    }
+		// pop two items off the stack, and put whether they have the same bytes on the stack
+		let (item1, item2) = pop_two(stack)?;
+		stack.push(bool_item(item1 == item2));
+	    }
+	    StackOp::OpCheckSig | StackOp::OpCheckSigVerify => {
+		let (bytes_sig, bytes_pub) = pop_two(stack)?;
+		let verified = if is_tapscript {
+		    check_sig_tapscript(&bytes_sig, &bytes_pub, context, execution_data)?
 		} else {
-		    return false;
+		    let script_code = Script {ops: script.ops[script_code_start..].to_vec()};
+		    let verified = check_sig(&bytes_sig, &bytes_pub, &script_code, context, sig_version)?;
+		    if !verified && !bytes_sig.is_empty() && context.flags & SCRIPT_VERIFY_NULLFAIL != 0 {
+			return Err(ScriptError::SigNullFail);
+		    }
+		    verified
+		};
+		if *op == StackOp::OpCheckSig {
+		    stack.push(bool_item(verified));
+		} else if !verified {
+		    return Err(ScriptError::CheckSigVerifyFailed);
+		}
+	    }
+	    StackOp::OpCheckSigAdd => {
+		if !is_tapscript {
+		    return Err(ScriptError::BadOpcode);
+		}
+		let bytes_pub = pop(stack)?;
+		let n = pop_num(stack, context)?;
+		let bytes_sig = pop(stack)?;
+		let verified = check_sig_tapscript(&bytes_sig, &bytes_pub, context, execution_data)?;
+		stack.push(encode_script_num(n + verified as i64));
+	    }
+	    StackOp::OpCheckMultiSig | StackOp::OpCheckMultiSigVerify => {
+		if is_tapscript {
+		    return Err(ScriptError::TapscriptCheckMultiSig);
+		}
+		let script_code = Script {ops: script.ops[script_code_start..].to_vec()};
+		let (verified, key_count) = check_multisig(stack, &script_code, context, sig_version)?;
+		// every public key counts as an op of its own
+		op_count += key_count;
+		if op_count > MAX_OPS_PER_SCRIPT {
+		    return Err(ScriptError::OpCountExceeded);
+		}
+		if *op == StackOp::OpCheckMultiSig {
+		    stack.push(bool_item(verified));
+		} else if !verified {
+		    return Err(ScriptError::CheckMultiSigVerifyFailed);
+		}
+	    }
+	    StackOp::OpCheckLockTimeVerify => {
+		if context.flags & SCRIPT_VERIFY_CHECKLOCKTIMEVERIFY != 0 {
+		    // lock times go up to 2^32 - 1, so they can take 5 bytes
+		    let lock_time = item_to_num(stack.last().ok_or(ScriptError::StackUnderflow)?, 5, context)?;
+		    if lock_time < 0 {
+			return Err(ScriptError::NegativeLockTime);
+		    }
+		    if !check_lock_time(lock_time, context) {
+			return Err(ScriptError::UnsatisfiedLockTime);
+		    }
+		} else if context.flags & SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_NOPS != 0 {
+		    // without its own flag, it is still NOP2
+		    return Err(ScriptError::DiscourageUpgradableNops);
 		}
 	    }
-	    StackOp::OpCheckSig => {
-		if let (Some(op1), Some(op2)) = (stack.pop(), stack.pop()) {
-		    if let (StackOp::Bytes(bytes_pub), StackOp::Bytes(bytes_sig)) = (&op1, &op2) {
-			let encoded_point_opt = EncodedPoint::<Secp256k1>::from_bytes(bytes_pub);
-			if let Ok(encoded_point) = encoded_point_opt {
-			    let public_key_opt = VerifyingKey::<Secp256k1>::from_encoded_point(&encoded_point);
-			    if let Ok(public_key) = public_key_opt {
-				let signature: ecdsa::Signature<Secp256k1> = Signature::from_bytes(bytes_sig).expect("problem deserializing signature");
-				// the "message" that was signed was the transaction of the previous hash that
-				// led to the locking script that we are currently trying to unlock.
-				let verified_res = public_key.verify(tx_previous_hash, &signature);
-				if let Ok(_) = verified_res {
-				    stack.push(StackOp::Bool(true));
-				} else {
-				    stack.push(StackOp::Bool(false));				    
-				}
-			} else {
-			    return false;
-			}
-			} else {
-			    // if we couldn't get a public key from the bytes
-			    return false;
-			}
-		    } else {
-			return false;
+	    StackOp::OpCheckSequenceVerify => {
+		if context.flags & SCRIPT_VERIFY_CHECKSEQUENCEVERIFY != 0 {
+		    let sequence = item_to_num(stack.last().ok_or(ScriptError::StackUnderflow)?, 5, context)?;
+		    if sequence < 0 {
+			return Err(ScriptError::NegativeLockTime);
 		    }
-		} else {
-		    return false;
+		    // with the disable flag set, there is nothing to check, so it can be used for future upgrades
+		    if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG as i64 == 0 && !check_sequence(sequence, context) {
+			return Err(ScriptError::UnsatisfiedLockTime);
+		    }
+		} else if context.flags & SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_NOPS != 0 {
+		    // and this one NOP3
+		    return Err(ScriptError::DiscourageUpgradableNops);
 		}
-		
 	    }
 	    StackOp::OpVerify => {
-		if let Some(op1) = stack.pop() {
-		    if let StackOp::Bool(val1) = op1 {
-			if !val1 {
-			    return false;
-			}
-		    } else {
-			return false;
-		    }
-		} else {
-		    return false;
+		if !is_true(&pop(stack)?) {
+		    return Err(ScriptError::VerifyFailed);
 		}
 	    }
 	    StackOp::OpEqVerify => {
-		if let (Some(op1), Some(op2)) = (stack.pop(), stack.pop()) {
-		    if let (StackOp::Val(val1), StackOp::Val(val2)) = (&op1, &op2) {
-			if val1 != val2 {
-			    return false
-			}
-		    } else if let (StackOp::Bytes(bytes1), StackOp::Bytes(bytes2)) = (&op1, &op2) {
-			if bytes1 != bytes2 {
-			    return false
-			}
-		    } else {
-			return false;
-		    }
-		} else {
-		    return false;
+		let (item1, item2) = pop_two(stack)?;
+		if item1 != item2 {
+		    return Err(ScriptError::EqualVerifyFailed);
 		}
 	    }
 	}
-    }
-    println!("stack at end = {:?}", stack);
-    // nothing triggered an early exit, so check if the top value is True
-    if let Some(op) = stack.pop() {
-	if let StackOp::Bool(val) = op {	
-	    return val
+	if stack.len() + alt_stack.len() > MAX_STACK_SIZE {
+	    return Err(ScriptError::StackSize);
 	}
     }
-    false
+    if !exec_stack.is_empty() {
+	return Err(ScriptError::UnbalancedConditional);
+    }
+    Ok(())
 }
 
-    
+	
 
 #[cfg(test)]
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
     use super::*;
-    use crate::transaction::{Transaction, TxIn, TxOut};
+    use crate::Hash;
+    use crate::transaction::{TxIn, TxOut};
+    use crate::sighash::{sign_tx_in, sign_segwit_tx_in, sign_taproot_tx_in, signature_to_der, SIGHASH_NONE};
+    use crate::taproot::{x_only_public_key, schnorr_sign, tweak_private_key, tap_branch_hash, control_block};
     use ecdsa::{SigningKey};
-    use ecdsa::signature::{Signer}; // trait in scope for signing a message
-    
+    use ecdsa::signature::{Signature, Signer, Verifier}; // traits in scope for signing a message, and reading a signature from bytes
+
+    /// run scripts that don't check any signatures, so the transaction doesn't matter
+    fn run_scripts(unlocking_script: &Script, locking_script: &Script) -> Result<(), ScriptError> {
+	let transaction = Transaction {version: 1, lock_time: 0, tx_ins: vec![], tx_outs: vec![]};
+	execute_scripts(unlocking_script, locking_script, &ExecutionContext::new(&transaction, 0, 0, SCRIPT_VERIFY_NONE))
+    }
+	
     #[test]    
     fn test_valid_simple_equal() {
 	let locking_script = Script {ops: vec![StackOp::Val(5), StackOp::OpEqual]};
 	let unlocking_script = Script {ops: vec![StackOp::Val(5)]};
-	let is_valid = execute_scripts(&unlocking_script, &locking_script, &[0]);
-	assert_eq!(is_valid, true);
+	let result = run_scripts(&unlocking_script, &locking_script);
+	assert_eq!(result, Ok(()));
     }
 
     #[test]    
     fn test_valid_equal_with_extra_on_stack() {
 	let locking_script = Script {ops: vec![StackOp::Val(5), StackOp::OpEqual]};
 	let unlocking_script = Script {ops: vec![StackOp::Val(1), StackOp::Val(5)]};
-	let is_valid = execute_scripts(&unlocking_script, &locking_script, &[0]);	
-	assert_eq!(is_valid, true);
+	let result = run_scripts(&unlocking_script, &locking_script);
+	assert_eq!(result, Ok(()));
     }
-    
+	
     #[test]    
     fn test_invalid_simple_equal() {
 	let locking_script = Script {ops: vec![StackOp::Val(5), StackOp::OpEqual]};
 	let unlocking_script = Script {ops: vec![StackOp::Val(6)]};
-	let is_valid = execute_scripts(&unlocking_script, &locking_script, &[0]);
-	assert_eq!(is_valid, false);
+	let result = run_scripts(&unlocking_script, &locking_script);
+	assert_eq!(result, Err(ScriptError::EvalFalse));
     }
 
     #[test]    
     fn test_valid_add() {
 	let locking_script = Script {ops: vec![StackOp::Val(5), StackOp::OpEqual]};
 	let unlocking_script = Script {ops: vec![StackOp::Val(3), StackOp::Val(2), StackOp::OpAdd]};
-	let is_valid = execute_scripts(&unlocking_script, &locking_script, &[0]);
-	assert_eq!(is_valid, true);
+	let result = run_scripts(&unlocking_script, &locking_script);
+	assert_eq!(result, Ok(()));
     }
 
     #[test]    
     fn test_valid_add_more_in_locking() {
 	let locking_script = Script {ops: vec![StackOp::Val(2), StackOp::OpAdd, StackOp::Val(5), StackOp::OpEqual]};
 	let unlocking_script = Script {ops: vec![StackOp::Val(3)]};
-	let is_valid = execute_scripts(&unlocking_script, &locking_script, &[0]);
-	assert_eq!(is_valid, true);
+	let result = run_scripts(&unlocking_script, &locking_script);
+	assert_eq!(result, Ok(()));
     }
-    
+	
     #[test]    
     fn test_valid_add_and_dup() {
 	let locking_script = Script {ops: vec![StackOp::OpDup, StackOp::OpAdd, StackOp::Val(8), StackOp::OpEqual]};
 	let unlocking_script = Script {ops: vec![StackOp::Val(4)]};
-	let is_valid = execute_scripts(&unlocking_script, &locking_script, &[0]);
-	assert_eq!(is_valid, true);
+	let result = run_scripts(&unlocking_script, &locking_script);
+	assert_eq!(result, Ok(()));
     }
 
     #[test]    
     fn test_valid_sub() {
 	let locking_script = Script {ops: vec![StackOp::Val(5), StackOp::OpEqual]};
 	let unlocking_script = Script {ops: vec![StackOp::Val(20), StackOp::Val(15), StackOp::OpSub]};
-	let is_valid = execute_scripts(&unlocking_script, &locking_script, &[0]);	
-	assert_eq!(is_valid, true);
+	let result = run_scripts(&unlocking_script, &locking_script);
+	assert_eq!(result, Ok(()));
     }
 
     #[test]    
     fn test_invalid_sub() {
 	let locking_script = Script {ops: vec![StackOp::Val(5), StackOp::OpEqual]};
 	let unlocking_script = Script {ops: vec![StackOp::Val(20), StackOp::Val(20), StackOp::OpSub]};
-	let is_valid = execute_scripts(&unlocking_script, &locking_script, &[0]);	
-	assert_eq!(is_valid, false);
+	let result = run_scripts(&unlocking_script, &locking_script);
+	assert_eq!(result, Err(ScriptError::EvalFalse));
     }
 
     #[test]
@@ -302,8 +1716,8 @@ mod tests {
 	// verify should simply not return false at that moment, but the scipt ends invalid with false on top
 	let locking_script = Script {ops: vec![StackOp::Bool(true), StackOp::OpVerify]};
 	let unlocking_script = Script {ops: vec![StackOp::Bool(false)]};
-	let is_valid = execute_scripts(&unlocking_script, &locking_script, &[0]);
-	assert_eq!(is_valid, false);
+	let result = run_scripts(&unlocking_script, &locking_script);
+	assert_eq!(result, Err(ScriptError::EvalFalse));
     }
 
     #[test]    
@@ -311,44 +1725,341 @@ mod tests {
 	// verify will reuturn false, even though the stack would end with true on top
 	let locking_script = Script {ops: vec![StackOp::Bool(false), StackOp::OpVerify]};
 	let unlocking_script = Script {ops: vec![StackOp::Bool(true)]};
-	let is_valid = execute_scripts(&unlocking_script, &locking_script, &[0]);
-	assert_eq!(is_valid, false);
+	let result = run_scripts(&unlocking_script, &locking_script);
+	assert_eq!(result, Err(ScriptError::VerifyFailed));
     }
-    
+	
     #[test]
     fn test_op_eq_verify() {
 	let locking_script = Script {ops: vec![StackOp::Val(5), StackOp::Val(5), StackOp::OpEqVerify]};
 	let unlocking_script = Script {ops: vec![StackOp::Bool(false)]};
-	let is_valid = execute_scripts(&unlocking_script, &locking_script, &[0]);
-	assert_eq!(is_valid, false);
+	let result = run_scripts(&unlocking_script, &locking_script);
+	assert_eq!(result, Err(ScriptError::EvalFalse));
     }
 
     #[test]
     fn test_op_eq_verify2() {
 	let locking_script = Script {ops: vec![StackOp::Val(5), StackOp::Val(4), StackOp::OpEqVerify]};
 	let unlocking_script = Script {ops: vec![StackOp::Bool(true)]};
-	let is_valid = execute_scripts(&unlocking_script, &locking_script, &[0]);
-	assert_eq!(is_valid, false);
+	let result = run_scripts(&unlocking_script, &locking_script);
+	assert_eq!(result, Err(ScriptError::EqualVerifyFailed));
     }
 
     #[test]    
     fn test_valid_multiple_dup() {
 	let locking_script = Script {ops: vec![StackOp::OpDup, StackOp::OpDup, StackOp::OpDup, StackOp::Val(8), StackOp::OpEqual]};
 	let unlocking_script = Script {ops: vec![StackOp::Val(8)]};
-	let is_valid = execute_scripts(&unlocking_script, &locking_script, &[0]);
-	assert_eq!(is_valid, true);
+	let result = run_scripts(&unlocking_script, &locking_script);
+	assert_eq!(result, Ok(()));
+    }
+
+
+
+    #[test]
+    fn test_script_errors() {
+	let empty = Script {ops: vec![]};
+	let add = Script {ops: vec![StackOp::Val(1), StackOp::OpAdd]};
+	assert_eq!(run_scripts(&empty, &add), Err(ScriptError::StackUnderflow));
+	assert_eq!(run_scripts(&empty, &empty), Err(ScriptError::EvalFalse));
+	let bytes = Script {ops: vec![StackOp::Bytes(vec![1, 2, 3, 4, 5].into_boxed_slice())]};
+	assert_eq!(run_scripts(&bytes, &add), Err(ScriptError::NumberOverflow));
+	let verify_zero = Script {ops: vec![StackOp::Val(0), StackOp::OpVerify]};
+	assert_eq!(run_scripts(&empty, &verify_zero), Err(ScriptError::VerifyFailed));
+
+	// the op count limit is per script, and pushes don't count towards it
+	let mut ops = vec![StackOp::Val(0)];
+	ops.extend(vec![StackOp::OpDup; MAX_OPS_PER_SCRIPT]);
+	let at_limit = Script {ops};
+	assert_eq!(run_scripts(&empty, &at_limit), Err(ScriptError::EvalFalse)); // zero isn't true
+	let mut over_limit = at_limit.clone();
+	over_limit.ops.push(StackOp::OpDup);
+	assert_eq!(run_scripts(&empty, &over_limit), Err(ScriptError::OpCountExceeded));
+	assert_eq!(run_scripts(&at_limit, &Script {ops: vec![StackOp::Val(0), StackOp::OpEqual]}), Ok(()));
+    }
+
+    #[test]
+    fn test_byte_stack() {
+	let bytes = |bytes: &[u8]| StackOp::Bytes(bytes.to_vec().into_boxed_slice());
+	// numbers and booleans are just bytes once they are on the stack
+	let locking_script = Script {ops: vec![StackOp::OpEqual]};
+	assert_eq!(run_scripts(&Script {ops: vec![StackOp::Val(1), bytes(&[1])]}, &locking_script), Ok(()));
+	assert_eq!(run_scripts(&Script {ops: vec![StackOp::Bool(true), StackOp::Val(1)]}, &locking_script), Ok(()));
+	assert_eq!(run_scripts(&Script {ops: vec![StackOp::Bool(false), bytes(&[])]}, &locking_script), Ok(()));
+	assert_eq!(run_scripts(&Script {ops: vec![StackOp::Val(-1), bytes(&[0x81])]}, &locking_script), Ok(()));
+	// but the same number with different bytes isn't equal, even though it is numerically
+	assert_eq!(run_scripts(&Script {ops: vec![StackOp::Val(1), bytes(&[1, 0])]}, &locking_script), Err(ScriptError::EvalFalse));
+	assert_eq!(run_scripts(&Script {ops: vec![StackOp::Val(1), bytes(&[1, 0])]}, &Script {ops: vec![StackOp::OpNumEqual]}), Ok(()));
+
+	// anything can be hashed
+	let hash_one = hash_160_to_bytes(&[1]);
+	let locking_script = Script {ops: vec![StackOp::OpHash160, bytes(&hash_one), StackOp::OpEqual]};
+	assert_eq!(run_scripts(&Script {ops: vec![StackOp::Val(1)]}, &locking_script), Ok(()));
+
+	// CastToBool decides whether the script succeeded, and what OpVerify and OpIf do
+	for (item, truth) in [(vec![], false), (vec![0], false), (vec![0, 0, 0x80], false), (vec![0x80], false), (vec![1], true), (vec![0, 1], true), (vec![0x81], true), (vec![0x80, 0], true)] {
+	    assert_eq!(is_true(&item), truth, "{:?}", item);
+	    let expected = if truth {Ok(())} else {Err(ScriptError::EvalFalse)};
+	    assert_eq!(run_scripts(&Script {ops: vec![bytes(&item)]}, &Script {ops: vec![]}), expected);
+	    let expected = if truth {Ok(())} else {Err(ScriptError::VerifyFailed)};
+	    assert_eq!(run_scripts(&Script {ops: vec![bytes(&item)]}, &Script {ops: vec![StackOp::OpVerify, StackOp::Val(1)]}), expected);
+	}
+	assert_eq!(run_asm("1 1 OP_ADD", "OP_IF 1 OP_ELSE 0 OP_ENDIF"), Ok(()));
+
+	// pushes can only be so big
+	let big = Script {ops: vec![bytes(&[1; MAX_SCRIPT_ELEMENT_SIZE]), StackOp::OpSize]};
+	assert_eq!(run_scripts(&big, &Script {ops: vec![StackOp::Bytes(encode_script_num(520).into_boxed_slice()), StackOp::OpEqual]}), Ok(()));
+	let too_big = Script {ops: vec![bytes(&[1; MAX_SCRIPT_ELEMENT_SIZE + 1])]};
+	assert_eq!(run_scripts(&too_big, &Script {ops: vec![]}), Err(ScriptError::PushSize));
+	// even in a branch that isn't taken
+	let skipped = Script {ops: vec![StackOp::Val(0), StackOp::OpIf, bytes(&[1; MAX_SCRIPT_ELEMENT_SIZE + 1]), StackOp::OpEndIf, StackOp::Val(1)]};
+	assert_eq!(run_scripts(&Script {ops: vec![]}, &skipped), Err(ScriptError::PushSize));
+	let skipped = Script {ops: vec![StackOp::Val(0), StackOp::OpIf, bytes(&[1; MAX_SCRIPT_ELEMENT_SIZE]), StackOp::OpEndIf, StackOp::Val(1)]};
+	assert_eq!(run_scripts(&Script {ops: vec![]}, &skipped), Ok(()));
+    }
+
+    /// run the scripts, both written in ASM
+    fn run_asm(unlocking_asm: &str, locking_asm: &str) -> Result<(), ScriptError> {
+	run_scripts(&Script::parse_asm(unlocking_asm).unwrap(), &Script::parse_asm(locking_asm).unwrap())
+    }
+
+    /// run the script on its own, and show what it leaves on the stack (as ASM, so true is 1)
+    fn stack_after_script(script: &Script, flags: u32) -> Result<String, ScriptError> {
+	let transaction = Transaction {version: 1, lock_time: 0, tx_ins: vec![], tx_outs: vec![]};
+	let mut stack = vec![];
+	eval_script(script, &mut stack, &ExecutionContext::new(&transaction, 0, 0, flags), SigVersion::Base, &mut ExecutionData::default())?;
+	Ok(Script {ops: stack.into_iter().map(|item| StackOp::Bytes(item.into_boxed_slice())).collect()}.to_string())
+    }
+
+    fn stack_after(asm: &str) -> Result<String, ScriptError> {
+	stack_after_script(&Script::parse_asm(asm).unwrap(), SCRIPT_VERIFY_NONE)
+    }
+
+    #[test]
+    fn test_opcode_table() {
+	// every op round trips through its byte and its name
+	for (name, opcode, op) in OPCODES.iter() {
+	    let script = Script {ops: vec![op.clone()]};
+	    assert_eq!(script.to_bytes(), vec![*opcode]);
+	    assert_eq!(Script::from_bytes(&[*opcode]), Ok(script.clone()));
+	    assert_eq!(script.to_string(), *name);
+	    assert_eq!(Script::parse_asm(name), Ok(script));
+	}
+    }
+
+    #[test]
+    fn test_stack_ops() {
+	let cases = [
+	    ("1 2 3 OP_DROP", "1 2"),
+	    ("1 2 3 OP_2DROP", "1"),
+	    ("1 2 OP_DUP", "1 2 2"),
+	    ("1 2 OP_2DUP", "1 2 1 2"),
+	    ("1 2 3 OP_3DUP", "1 2 3 1 2 3"),
+	    ("0 OP_IFDUP 1 OP_IFDUP", "0 1 1"),
+	    ("1 2 OP_NIP", "2"),
+	    ("1 2 OP_OVER", "1 2 1"),
+	    ("1 2 3 4 OP_2OVER", "1 2 3 4 1 2"),
+	    ("1 2 3 2 OP_PICK", "1 2 3 1"),
+	    ("1 2 3 0 OP_PICK", "1 2 3 3"),
+	    ("1 2 3 2 OP_ROLL", "2 3 1"),
+	    ("1 2 3 0 OP_ROLL", "1 2 3"),
+	    ("1 2 3 OP_ROT", "2 3 1"),
+	    ("1 2 3 4 5 6 OP_2ROT", "3 4 5 6 1 2"),
+	    ("1 2 OP_SWAP", "2 1"),
+	    ("1 2 3 4 OP_2SWAP", "3 4 1 2"),
+	    ("1 2 OP_TUCK", "2 1 2"),
+	    ("OP_DEPTH 7 OP_DEPTH", "0 7 2"),
+	    ("0 OP_SIZE -1 OP_SIZE ffffff7f OP_SIZE", "0 0 -1 1 2147483647 4"),
+	    ("1 OP_TOALTSTACK 2 OP_TOALTSTACK 3 OP_FROMALTSTACK OP_FROMALTSTACK", "3 2 1"),
+	];
+	for (asm, expected) in cases {
+	    assert_eq!(stack_after(asm), Ok(expected.to_string()), "{}", asm);
+	}
+
+	assert_eq!(stack_after("1 2 OP_ROT"), Err(ScriptError::StackUnderflow));
+	assert_eq!(stack_after("1 2 3 OP_2SWAP"), Err(ScriptError::StackUnderflow));
+	assert_eq!(stack_after("1 1 OP_PICK"), Err(ScriptError::StackUnderflow));
+	assert_eq!(stack_after("1 -1 OP_ROLL"), Err(ScriptError::InvalidStackOperation));
+	assert_eq!(stack_after("OP_SIZE"), Err(ScriptError::StackUnderflow));
+	assert_eq!(stack_after("OP_FROMALTSTACK"), Err(ScriptError::InvalidAltStackOperation));
+	// each script gets an alt stack of its own
+	assert_eq!(run_asm("1 OP_TOALTSTACK", "OP_FROMALTSTACK"), Err(ScriptError::InvalidAltStackOperation));
+    }
+
+    #[test]
+    fn test_arithmetic() {
+	let cases = [
+	    ("5 OP_1ADD 5 OP_1SUB", "6 4"),
+	    ("5 OP_NEGATE -5 OP_NEGATE", "-5 5"),
+	    ("-5 OP_ABS 5 OP_ABS", "5 5"),
+	    ("0 OP_NOT 3 OP_NOT", "1 0"),
+	    ("0 OP_0NOTEQUAL -3 OP_0NOTEQUAL", "0 1"),
+	    ("7 3 OP_ADD 7 3 OP_SUB", "10 4"),
+	    ("1 0 OP_BOOLAND 2 3 OP_BOOLAND", "0 1"),
+	    ("1 0 OP_BOOLOR 0 0 OP_BOOLOR", "1 0"),
+	    ("3 3 OP_NUMEQUAL 3 4 OP_NUMEQUAL", "1 0"),
+	    ("3 3 OP_NUMNOTEQUAL 3 4 OP_NUMNOTEQUAL", "0 1"),
+	    ("3 4 OP_LESSTHAN 4 3 OP_LESSTHAN", "1 0"),
+	    ("3 4 OP_GREATERTHAN 4 3 OP_GREATERTHAN", "0 1"),
+	    ("4 4 OP_LESSTHANOREQUAL 4 4 OP_GREATERTHANOREQUAL 5 4 OP_LESSTHANOREQUAL", "1 1 0"),
+	    ("3 -4 OP_MIN 3 -4 OP_MAX", "-4 3"),
+	    ("2 2 4 OP_WITHIN 4 2 4 OP_WITHIN 1 2 4 OP_WITHIN", "1 0 0"),
+	    ("3 3 OP_NUMEQUALVERIFY", ""),
+	    // a number pushed as bytes is still a number
+	    ("ffffff7f OP_1SUB ffffffff OP_1ADD", "2147483646 -2147483646"),
+	];
+	for (asm, expected) in cases {
+	    assert_eq!(stack_after(asm), Ok(expected.to_string()), "{}", asm);
+	}
+	assert_eq!(stack_after("3 4 OP_NUMEQUALVERIFY"), Err(ScriptError::NumEqualVerifyFailed));
+	assert_eq!(run_asm("2 3", "OP_ADD 5 OP_NUMEQUAL"), Ok(()));
+	assert_eq!(run_asm("2 3", "OP_ADD 6 OP_NUMEQUAL"), Err(ScriptError::EvalFalse));
+    }
+
+    #[test]
+    fn test_script_num_limits() {
+	// results can go past 4 bytes (without overflowing), but can't be used for any more arithmetic
+	assert_eq!(stack_after("ffffff7f ffffff7f OP_ADD"), Ok("feffffff00".to_string()));
+	assert_eq!(stack_after("ffffff7f OP_1ADD"), Ok("0000008000".to_string()));
+	assert_eq!(stack_after("ffffffff OP_1SUB"), Ok("0000008080".to_string()));
+	assert_eq!(stack_after("ffffffff OP_1SUB OP_1ADD"), Err(ScriptError::NumberOverflow));
+	assert_eq!(stack_after("ffffff7f OP_1ADD OP_1SUB"), Err(ScriptError::NumberOverflow));
+	assert_eq!(stack_after("ffffff7f ffffff7f OP_ADD 0 OP_NUMEQUAL"), Err(ScriptError::NumberOverflow));
+	assert_eq!(stack_after("ffffffff00 OP_ABS"), Err(ScriptError::NumberOverflow));
+	// -2^31 fits in a Val, but not in 4 bytes
+	let min = Script {ops: vec![StackOp::Val(i32::MIN), StackOp::OpNegate]};
+	assert_eq!(stack_after_script(&min, SCRIPT_VERIFY_NONE), Err(ScriptError::NumberOverflow));
+	assert_eq!(stack_after_script(&Script {ops: vec![StackOp::Val(i32::MAX), StackOp::Val(i32::MAX), StackOp::OpSub]}, SCRIPT_VERIFY_NONE), Ok("0".to_string()));
+
+	// padded numbers are fine, unless they have to be minimal
+	for (bytes, minimal) in [(vec![], true), (vec![5], true), (vec![0x80], false), (vec![0], false), (vec![5, 0], false), (vec![0xff, 0], true), (vec![5, 0x80], false), (vec![0xff, 0x80], true)] {
+	    assert_eq!(is_minimal_num(&bytes), minimal, "{:?}", bytes);
+	}
+	let padded = Script {ops: vec![StackOp::Bytes(vec![5, 0].into_boxed_slice()), StackOp::Op1Add]};
+	assert_eq!(stack_after_script(&padded, SCRIPT_VERIFY_NONE), Ok("6".to_string()));
+	assert_eq!(stack_after_script(&padded, SCRIPT_VERIFY_MINIMALDATA), Err(ScriptError::NonMinimalNumber));
+	let negative_zero = Script {ops: vec![StackOp::Bytes(vec![0x80].into_boxed_slice()), StackOp::OpNot]};
+	assert_eq!(stack_after_script(&negative_zero, SCRIPT_VERIFY_NONE), Ok("1".to_string()));
+	assert_eq!(stack_after_script(&negative_zero, SCRIPT_VERIFY_MINIMALDATA), Err(ScriptError::NonMinimalNumber));
+    }
+
+    #[test]
+    fn test_minimal_push() {
+	let pushes = [
+	    (StackOp::Bytes(vec![5].into_boxed_slice()), false), // should have been OP_5
+	    (StackOp::Bytes(vec![0x81].into_boxed_slice()), false), // should have been OP_1NEGATE
+	    (StackOp::NonMinimalPush(OP_PUSHDATA1, vec![1, 2].into_boxed_slice()), false),
+	    (StackOp::Bytes(vec![17].into_boxed_slice()), true),
+	    (StackOp::Bytes(vec![].into_boxed_slice()), true),
+	    (StackOp::Bytes(vec![0x81, 0].into_boxed_slice()), true), // it's only about the push, not what the data means
+	    (StackOp::Val(5), true),
+	];
+	for (push, minimal) in pushes {
+	    let script = Script {ops: vec![push.clone()]};
+	    assert!(stack_after_script(&script, SCRIPT_VERIFY_NONE).is_ok());
+	    let expected = if minimal {stack_after_script(&script, SCRIPT_VERIFY_NONE)} else {Err(ScriptError::MinimalData)};
+	    assert_eq!(stack_after_script(&script, SCRIPT_VERIFY_MINIMALDATA), expected, "{:?}", push);
+	}
+	// only pushes that run count
+	let skipped = Script {ops: vec![StackOp::Val(0), StackOp::OpIf, StackOp::Bytes(vec![5].into_boxed_slice()), StackOp::OpEndIf]};
+	assert_eq!(stack_after_script(&skipped, SCRIPT_VERIFY_MINIMALDATA), Ok("".to_string()));
+    }
+
+    #[test]
+    fn test_discourage_upgradable_nops() {
+	for op in [StackOp::OpUpgradableNop(1), StackOp::OpUpgradableNop(10), StackOp::OpCheckLockTimeVerify, StackOp::OpCheckSequenceVerify] {
+	    let script = Script {ops: vec![StackOp::Val(1), op.clone()]};
+	    assert_eq!(stack_after_script(&script, SCRIPT_VERIFY_NONE), Ok("1".to_string()));
+	    assert_eq!(stack_after_script(&script, SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_NOPS), Err(ScriptError::DiscourageUpgradableNops), "{:?}", op);
+	    let skipped = Script {ops: vec![StackOp::Val(0), StackOp::OpIf, op, StackOp::OpEndIf]};
+	    assert_eq!(stack_after_script(&skipped, SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_NOPS), Ok("".to_string()));
+	}
+	// OP_NOP itself was never meant for anything else
+	assert_eq!(stack_after_script(&Script {ops: vec![StackOp::OpNop]}, SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_NOPS), Ok("".to_string()));
     }
 
+    #[test]
+    fn test_if_else() {
+	let locking_asm = "OP_IF 2 OP_ELSE 3 OP_ENDIF 3 OP_EQUAL";
+	assert_eq!(run_asm("0", locking_asm), Ok(()));
+	assert_eq!(run_asm("1", locking_asm), Err(ScriptError::EvalFalse));
+	assert_eq!(run_asm("", locking_asm), Err(ScriptError::StackUnderflow));
+	// anything but zero is true, and negative zero is still zero
+	assert_eq!(run_asm("7", locking_asm), Err(ScriptError::EvalFalse));
+	assert_eq!(run_scripts(&Script {ops: vec![StackOp::Bytes(vec![0, 0x80].into_boxed_slice())]}, &Script::parse_asm(locking_asm).unwrap()), Ok(()));
+
+	let locking_asm = "OP_NOTIF 2 OP_ELSE 3 OP_ENDIF 3 OP_EQUAL";
+	assert_eq!(run_asm("1", locking_asm), Ok(()));
+	assert_eq!(run_asm("0", locking_asm), Err(ScriptError::EvalFalse));
+
+	// a branch that isn't taken doesn't run at all, even if it would fail
+	let locking_asm = "OP_IF OP_RETURN OP_ADD OP_ENDIF 5 OP_EQUAL";
+	assert_eq!(run_asm("5 0", locking_asm), Ok(()));
+	assert_eq!(run_asm("5 1", locking_asm), Err(ScriptError::OpReturn));
+	// and an OpElse can flip back and forth
+	assert_eq!(run_asm("0", "OP_IF 1 OP_ELSE 2 OP_ELSE 3 OP_ENDIF 2 OP_EQUAL"), Ok(()));
+    }
+
+    #[test]
+    fn test_nested_if() {
+	// the top of the stack picks the outer branch, and the one below it the inner branch
+	let locking_asm = "OP_IF OP_IF 1 OP_ELSE 2 OP_ENDIF OP_ELSE OP_IF 3 OP_ELSE 4 OP_ENDIF OP_ENDIF";
+	for (unlocking_asm, expected) in [("1 1", 1), ("0 1", 2), ("1 0", 3), ("0 0", 4)] {
+	    assert_eq!(run_asm(unlocking_asm, &format!("{} {} OP_EQUAL", locking_asm, expected)), Ok(()));
+	    assert_eq!(run_asm(unlocking_asm, &format!("{} {} OP_EQUAL", locking_asm, expected + 1)), Err(ScriptError::EvalFalse));
+	}
+	// an OpIf inside of a skipped branch doesn't pop anything
+	assert_eq!(run_asm("0", "OP_IF OP_IF OP_RETURN OP_ENDIF OP_ENDIF 1 1 OP_EQUAL"), Ok(()));
+    }
 
+    #[test]
+    fn test_unbalanced_conditional() {
+	for locking_asm in ["OP_ENDIF", "OP_ELSE", "1 OP_IF 1", "0 OP_IF OP_IF OP_ENDIF", "1 OP_IF OP_ENDIF OP_ENDIF", "0 OP_NOTIF 1 OP_ELSE"] {
+	    assert_eq!(run_asm("", locking_asm), Err(ScriptError::UnbalancedConditional), "{}", locking_asm);
+	}
+	// an OpIf can't be ended by the other script
+	assert_eq!(run_asm("1 OP_IF", "OP_ENDIF 1 1 OP_EQUAL"), Err(ScriptError::UnbalancedConditional));
+    }
+
+    #[test]
+    fn test_op_return() {
+	let locking_script = Script::parse_asm("OP_RETURN 68656c6c6f").unwrap();
+	assert!(locking_script.is_unspendable());
+	assert!(!Script::parse_asm("1 OP_RETURN").unwrap().is_unspendable());
+	assert_eq!(run_asm("1", "OP_RETURN 68656c6c6f"), Err(ScriptError::OpReturn));
+
+	let conditionals = Script::parse_asm("OP_IF OP_NOTIF OP_ELSE OP_ENDIF OP_RETURN").unwrap();
+	assert_eq!(conditionals.to_bytes(), vec![0x63, 0x64, 0x67, 0x68, 0x6a]);
+	assert_eq!(Script::from_bytes(&conditionals.to_bytes()), Ok(conditionals.clone()));
+	assert_eq!(conditionals.to_string(), "OP_IF OP_NOTIF OP_ELSE OP_ENDIF OP_RETURN");
+    }
 
     #[test]
     fn test_op_hash_160_valid() {
 	let b = "adamadamadamadamadamadamadamadam".as_bytes(); // arbitrary for testing. 32 long
-	let answer = hash_160_to_bytes(&b);
+	let answer = hash_160_to_bytes(b);
 	let locking_script = Script {ops: vec![StackOp::OpHash160, StackOp::Bytes(answer.into_boxed_slice()), StackOp::OpEqual]};
 	let unlocking_script = Script {ops: vec![StackOp::Bytes(b.into())]};
-	let is_valid = execute_scripts(&unlocking_script, &locking_script, &[0]);
-	assert_eq!(is_valid, true);
+	let result = run_scripts(&unlocking_script, &locking_script);
+	assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn test_hash_ops() {
+	// the hashes of nothing, and of "abc"
+	let cases = [
+	    ("OP_RIPEMD160", "9c1185a5c5e9fc54612808977ee8f548b2258d31", "8eb208f7e05d987a9b044a8e98c6b087f15a0bfc"),
+	    ("OP_SHA1", "da39a3ee5e6b4b0d3255bfef95601890afd80709", "a9993e364706816aba3e25717850c26c9cd0d89d"),
+	    ("OP_SHA256", "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+	    ("OP_HASH160", "b472a266d0bd89c13706a4132ccfb16f7c3b9fcb", "bb1be98c142444d7a56aa3981c3942a978e4dc33"),
+	    ("OP_HASH256", "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456", "4f8b42c22dd3729b519ba6f68d2da7cc5b2d606d05daed5ad5128cc03e6c6358"),
+	];
+	for (name, empty_hash, abc_hash) in cases {
+	    assert_eq!(stack_after(&format!("0 {}", name)), Ok(empty_hash.to_string()));
+	    let abc = Script {ops: vec![StackOp::Bytes(b"abc".to_vec().into_boxed_slice()), Script::parse_asm(name).unwrap().ops[0].clone()]};
+	    assert_eq!(stack_after_script(&abc, SCRIPT_VERIFY_NONE), Ok(abc_hash.to_string()));
+	}
+	assert_eq!(hash_160_to_bytes(b"").len(), 20);
+	assert_eq!(stack_after("OP_SHA256"), Err(ScriptError::StackUnderflow));
     }
 
     /*
@@ -359,15 +2070,149 @@ mod tests {
 	let locking_script = Script {ops: vec![StackOp::OpHash160, StackOp::Val(answer), StackOp::OpEqVerify]};
 	let unlocking_script = Script {ops: vec![StackOp::Bytes(b)]};
 	let is_valid = execute_scripts(unlocking_script, locking_script, 0);
-	assert_eq!(is_valid, false);
+	assert!(!is_valid);
     }
      */
-    
+	
+    #[test]
+    fn test_script_to_bytes() {
+	// the classic pay to public key hash locking script
+	let pub_hash = vec![0xab; 20];
+	let locking_script = Script {ops: vec![StackOp::OpDup, StackOp::OpHash160, StackOp::Bytes(pub_hash.clone().into_boxed_slice()), StackOp::OpEqVerify, StackOp::OpCheckSig]};
+	let mut expected = vec![0x76, 0xa9, 0x14];
+	expected.extend_from_slice(&pub_hash);
+	expected.extend_from_slice(&[0x88, 0xac]);
+	assert_eq!(locking_script.to_bytes(), expected);
+	assert_eq!(Script::from_bytes(&expected), Ok(locking_script));
+
+	// small numbers get their own opcodes, larger ones are pushed as little endian bytes with a sign bit
+	let numbers = Script {ops: vec![StackOp::Val(0), StackOp::Val(-1), StackOp::Val(1), StackOp::Val(16), StackOp::Val(17),
+					StackOp::Val(-17), StackOp::Val(128), StackOp::Val(-256), StackOp::OpAdd, StackOp::OpSub, StackOp::OpEqual, StackOp::OpVerify]};
+	let bytes = numbers.to_bytes();
+	assert_eq!(hex::encode(&bytes), "004f51600111019102800002008193948769");
+	// numbers that don't fit an opcode come back as the bytes that were pushed
+	let decoded = Script::from_bytes(&bytes).unwrap();
+	assert_eq!(decoded.ops[..4], numbers.ops[..4]);
+	assert_eq!(decoded.ops[4], StackOp::Bytes(vec![0x11].into_boxed_slice()));
+	assert_eq!(decoded.to_bytes(), bytes);
+	assert_eq!(Script {ops: vec![StackOp::Bool(true), StackOp::Bool(false)]}.to_bytes(), vec![0x51, 0x00]);
+    }
+
+    #[test]
+    fn test_script_pushdata() {
+	for (len, prefix) in [(75, vec![0x4b]), (76, vec![0x4c, 76]), (255, vec![0x4c, 0xff]), (256, vec![0x4d, 0x00, 0x01]), (0x10000, vec![0x4e, 0x00, 0x00, 0x01, 0x00])] {
+	    let data = vec![7; len];
+	    let script = Script {ops: vec![StackOp::Bytes(data.clone().into_boxed_slice())]};
+	    let bytes = script.to_bytes();
+	    assert_eq!(bytes[..prefix.len()], prefix[..]);
+	    assert_eq!(bytes.len(), prefix.len() + len);
+	    assert_eq!(Script::from_bytes(&bytes), Ok(script));
+	}
+    }
+
+    #[test]
+    fn test_script_decode_errors() {
+	// a direct push of 3 bytes with only 2 left
+	assert_eq!(Script::from_bytes(&[0x76, 0x03, 0x01, 0x02]), Err(ScriptDecodeError::TruncatedPush(1)));
+	// the length of the OP_PUSHDATA2 itself is cut off
+	assert_eq!(Script::from_bytes(&[0x4d, 0x01]), Err(ScriptDecodeError::TruncatedPush(0)));
+	assert_eq!(Script::from_bytes(&[0x4c, 0x02, 0x01]), Err(ScriptDecodeError::TruncatedPush(0)));
+	// OP_INVALIDOPCODE only fails when it's run, so it decodes fine
+	assert_eq!(Script::from_bytes(&[0x76, 0xff]), Ok(Script {ops: vec![StackOp::OpDup, StackOp::OpInvalid(0xff)]}));
+	assert_eq!(Script::from_bytes(&[]), Ok(Script {ops: vec![]}));
+    }
+
+    #[test]
+    fn test_script_non_minimal_push() {
+	// OP_PUSHDATA1 for a single byte, OP_PUSHDATA2 for 5 bytes, OP_PUSHDATA4 for nothing
+	for bytes in [vec![0x4c, 0x01, 0x07], vec![0x4d, 0x05, 0x00, 1, 2, 3, 4, 5], vec![0x4e, 0x00, 0x00, 0x00, 0x00]] {
+	    let script = Script::from_bytes(&bytes).unwrap();
+	    assert!(matches!(script.ops[..], [StackOp::NonMinimalPush(opcode, _)] if opcode == bytes[0]));
+	    assert_eq!(script.to_bytes(), bytes);
+	    assert!(script.is_push_only());
+	}
+	// it still pushes the same item
+	let push = Script::from_bytes(&[0x4c, 0x01, 0x07]).unwrap();
+	assert_eq!(run_scripts(&push, &Script {ops: vec![StackOp::Val(7), StackOp::OpEqual]}), Ok(()));
+	// the templates go by the exact bytes, like bitcoin core, so a non-minimal push doesn't make a witness program or a P2SH script
+	let hash = [0x11; 20];
+	let mut p2wpkh = vec![0x00, 0x4c, 0x14];
+	p2wpkh.extend_from_slice(&hash);
+	let p2wpkh = Script::from_bytes(&p2wpkh).unwrap();
+	assert_eq!(p2wpkh.witness_program(), None);
+	assert_eq!(p2wpkh.classify(), ScriptType::NonStandard);
+	let mut p2sh = vec![0xa9, 0x4c, 0x14];
+	p2sh.extend_from_slice(&hash);
+	p2sh.push(0x87);
+	let p2sh = Script::from_bytes(&p2sh).unwrap();
+	assert!(!p2sh.is_pay_to_script_hash());
+	assert_eq!(p2sh.classify(), ScriptType::NonStandard);
+    }
+
+    #[test]
+    fn test_script_unknown_opcodes() {
+	// OP_CODESEPARATOR, OP_NOP1, OP_NOP4, OP_NOP10, OP_RESERVED, OP_VER, OP_CAT and 0xbb, all between OP_1 and OP_DROP
+	let bytes = vec![0xab, 0xb0, 0xb3, 0xb9, 0x50, 0x62, 0x7e, 0xbb];
+	for opcode in bytes.iter().copied() {
+	    let script = Script::from_bytes(&[0x51, opcode, 0x75]).unwrap();
+	    assert_eq!(script.to_bytes(), vec![0x51, opcode, 0x75]);
+	}
+	let script = Script::from_bytes(&bytes).unwrap();
+	assert_eq!(script.ops[..4], [StackOp::OpCodeSeparator, StackOp::OpUpgradableNop(1), StackOp::OpUpgradableNop(4), StackOp::OpUpgradableNop(10)]);
+	assert_eq!(script.ops[4..], [StackOp::OpInvalid(0x50), StackOp::OpInvalid(0x62), StackOp::OpInvalid(0x7e), StackOp::OpInvalid(0xbb)]);
+	assert_eq!(script.to_string(), "OP_CODESEPARATOR OP_NOP1 OP_NOP4 OP_NOP10 OP_UNKNOWN OP_UNKNOWN OP_UNKNOWN OP_UNKNOWN");
+
+	let run = |bytes: &[u8]| run_scripts(&Script {ops: vec![]}, &Script::from_bytes(bytes).unwrap());
+	// the NOPs and OP_CODESEPARATOR do nothing
+	assert_eq!(run(&[0xab, 0xb0, 0xb3, 0xb9, 0x51]), Ok(()));
+	// the rest fail when they're run, but are fine in a branch that isn't taken
+	for opcode in [0x50, 0x62, 0x89, 0xbb, 0xff] {
+	    assert_eq!(run(&[0x51, opcode]), Err(ScriptError::BadOpcode));
+	    assert_eq!(run(&[0x00, 0x63, opcode, 0x68, 0x51]), Ok(()));
+	}
+	// except for the disabled ones, and OP_VERIF and OP_VERNOTIF, which fail anywhere
+	assert_eq!(run(&[0x00, 0x63, 0x7e, 0x68, 0x51]), Err(ScriptError::DisabledOpcode));
+	assert_eq!(run(&[0x00, 0x63, 0x95, 0x68, 0x51]), Err(ScriptError::DisabledOpcode));
+	assert_eq!(run(&[0x00, 0x63, 0x65, 0x68, 0x51]), Err(ScriptError::BadOpcode));
+	assert_eq!(run(&[0x00, 0x63, 0x66, 0x68, 0x51]), Err(ScriptError::BadOpcode));
+    }
+
+    #[test]
+    fn test_asm() {
+	// a pay to public key hash locking script, as shown by a block explorer
+	let asm = "OP_DUP OP_HASH160 0389035a9225b3839e2bbf32d826a1e222031fd8 OP_EQUALVERIFY OP_CHECKSIG";
+	let script = Script::parse_asm(asm).unwrap();
+	assert_eq!(hex::encode(script.to_bytes()), "76a9140389035a9225b3839e2bbf32d826a1e222031fd888ac");
+	assert_eq!(script.to_string(), asm);
+
+	let script = Script::parse_asm("OP_0 OP_1NEGATE OP_TRUE OP_16 17 -1000 OP_ADD\tOP_SUB  OP_EQUAL OP_VERIFY").unwrap();
+	assert_eq!(script.ops, vec![StackOp::Val(0), StackOp::Val(-1), StackOp::Val(1), StackOp::Val(16), StackOp::Val(17), StackOp::Val(-1000),
+				    StackOp::OpAdd, StackOp::OpSub, StackOp::OpEqual, StackOp::OpVerify]);
+	assert_eq!(script.to_string(), "0 -1 1 16 17 -1000 OP_ADD OP_SUB OP_EQUAL OP_VERIFY");
+
+	// like bitcoin core, pushes of up to 4 bytes are shown as numbers
+	let script = Script::from_bytes(&hex::decode("02e88304ffffff7f").unwrap()).unwrap();
+	assert_eq!(script.to_string(), "-1000 2147483647");
+	assert_eq!(Script {ops: vec![StackOp::Bool(true), StackOp::Bool(false)]}.to_string(), "1 0");
+	assert_eq!(Script::parse_asm(""), Ok(Script {ops: vec![]}));
+    }
+
+    #[test]
+    fn test_asm_errors() {
+	assert_eq!(Script::parse_asm("OP_DUP OP_HASH160 OP_FOO OP_EQUAL"), Err(AsmError::UnknownOpcode(2, "OP_FOO".to_string())));
+	assert_eq!(Script::parse_asm("OP_17"), Err(AsmError::UnknownOpcode(0, "OP_17".to_string())));
+	// odd length hex, not hex at all, and a number that doesn't fit
+	assert_eq!(Script::parse_asm("OP_DUP abc"), Err(AsmError::InvalidData(1, "abc".to_string())));
+	assert_eq!(Script::parse_asm("xyz1"), Err(AsmError::InvalidData(0, "xyz1".to_string())));
+	assert_eq!(Script::parse_asm("1 99999999999"), Err(AsmError::InvalidData(1, "99999999999".to_string())));
+	assert_eq!(AsmError::UnknownOpcode(2, "OP_FOO".to_string()).to_string(), "unknown opcode \"OP_FOO\" at token 2");
+    }
+
     #[test]
     fn test_signature() {
 	let b = "adamadamadamadamadamadamadamadam".as_bytes(); // arbitrary for testing. 32 long
 	println!("priv before as bytes: {:?}", b);
-	let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(&b).unwrap();
+	let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(b).unwrap();
 	let b_2 = private_key.to_bytes();
 	println!("priv after as bytes: {:?}", b_2);
 
@@ -382,7 +2227,7 @@ mod tests {
 	println!("sig as bytes: {:?}", sig_as_bytes);
 
 	// lol there is a Signature trait and a Signature struct?
-	let sig2:  ecdsa::Signature<Secp256k1> = Signature::from_bytes(sig_as_bytes).expect("problem deserializing");
+	let _sig2:  ecdsa::Signature<Secp256k1> = Signature::from_bytes(sig_as_bytes).expect("problem deserializing");
 	
 	let public_key: VerifyingKey<Secp256k1> = private_key.verifying_key();
 	let c = public_key.to_encoded_point(true).to_bytes();
@@ -413,43 +2258,866 @@ mod tests {
 	"OP_DUP OP_HASH160 7f9b1a7fb68d60c536c2fd8aeaa53a8f3cc025a8 OP_EQUALVERIFY OP_CHECKSIG"
 	 */
 	let priv_bytes = "adamadamadamadamadamadamadamadam".as_bytes(); // arbitrary for testing. 32 long
-	let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(&priv_bytes).unwrap();
+	let private_key: SigningKey<Secp256k1> = SigningKey::<Secp256k1>::from_bytes(priv_bytes).unwrap();
 	let public_key: VerifyingKey<Secp256k1> = private_key.verifying_key();
 	let public_key_bytes = public_key.to_encoded_point(true).to_bytes();
 	let pub_hash = hash_160_to_bytes(&public_key_bytes);
 	
 	let locking_script = Script {ops: vec![StackOp::OpDup, StackOp::OpHash160, StackOp::Bytes(pub_hash.into_boxed_slice()), StackOp::OpEqVerify, StackOp::OpCheckSig]};
 
-	// Note: we could probably just pass in an arbitrary hash, but lets use a "real" transaction hash
-	// To keep the testing more integrated.
-	
-	let tx_in = TxIn::Coinbase {
-	    coinbase: 33,
-	    sequence: 5580,
+	// the transaction that spends the tx_out locked by the script above, sending most of it elsewhere
+	let tx_in = TxIn::TxPrevious {
+	    tx_hash: Hash::from(1234),
+	    tx_out_index: 0,
+	    unlocking_script: Script {ops: vec![]},
+	    sequence: 0xffffffff,
+	    witness: vec![],
 	};
-	let tx_out1 = TxOut {
-	    // This is the TxOut that we would be unlocking
-	    value: 222,
-	    locking_script: locking_script.clone()
+	let tx_out = TxOut {
+	    value: 200,
+	    locking_script: Script {ops: vec![StackOp::Val(7)]},
 	};
-	let transaction = Transaction {
+	let mut transaction = Transaction {
 	    version: 1,
 	    lock_time: 5,
 	    tx_ins: vec![tx_in],
-	    tx_outs: vec![tx_out1],		
+	    tx_outs: vec![tx_out],
 	};
-	let tx_hash_bytes = transaction.hash_to_bytes();
 
-
-	let sig = private_key.try_sign(&tx_hash_bytes).expect("should be able to sign the transaction hash here");
-	println!("sig: {:?}", sig);	
-	let sig_as_bytes = sig.as_bytes().to_vec(); //TODO: is there a way to go right from &[u8] --> Box instead of through vec?
+	let sig_as_bytes = sign_tx_in(&private_key, &transaction, 0, &locking_script, SIGHASH_ALL);
 	let unlocking_script = Script {ops: vec![StackOp::Bytes(sig_as_bytes.into_boxed_slice()), StackOp::Bytes(public_key_bytes)]};
-	
-	let is_valid = execute_scripts(&unlocking_script, &locking_script, &tx_hash_bytes);
-	assert_eq!(is_valid, true);
-	
+	if let TxIn::TxPrevious {unlocking_script: script, ..} = &mut transaction.tx_ins[0] {
+	    *script = unlocking_script.clone();
+	}
+	let context = ExecutionContext::new(&transaction, 0, 222, SCRIPT_VERIFY_NONE);
+	assert_eq!(execute_scripts(&unlocking_script, &locking_script, &context), Ok(()));
+
+	// the same signature on a copy that pays someone else doesn't unlock anything
+	let mut modified = transaction.clone();
+	modified.tx_outs[0].locking_script = Script {ops: vec![StackOp::Val(8)]};
+	let context = ExecutionContext::new(&modified, 0, 222, SCRIPT_VERIFY_NONE);
+	assert_eq!(execute_scripts(&unlocking_script, &locking_script, &context), Err(ScriptError::EvalFalse));
+	// and neither does a garbled signature
+	let garbled = Script {ops: vec![StackOp::Bytes(vec![1, 2, 3].into_boxed_slice()), unlocking_script.ops[1].clone()]};
+	let context = ExecutionContext::new(&transaction, 0, 222, SCRIPT_VERIFY_NONE);
+	assert_eq!(execute_scripts(&garbled, &locking_script, &context), Err(ScriptError::EvalFalse));
+	// and neither does something that isn't a public key, which isn't an error either
+	let not_a_key = Script {ops: vec![unlocking_script.ops[0].clone(), StackOp::Bytes(vec![2; 5].into_boxed_slice())]};
+	let locking_script = Script {ops: vec![StackOp::OpCheckSig, StackOp::OpNot]};
+	assert_eq!(execute_scripts(&not_a_key, &locking_script, &context), Ok(()));
+    }
+
+    #[test]
+    fn test_hybrid_public_key() {
+	// the uncompressed key with 0x06 or 0x07 for the parity of y in front
+	let private_key = SigningKey::<Secp256k1>::from_bytes(b"adamadamadamadamadamadamadamadam").unwrap();
+	let mut hybrid = private_key.verifying_key().to_encoded_point(false).as_bytes().to_vec();
+	hybrid[0] = 0x06 | (hybrid[64] & 1);
+	assert_eq!(parse_public_key(&hybrid), Some(private_key.verifying_key()));
+	let locking_script = Script {ops: vec![StackOp::Bytes(hybrid.clone().into_boxed_slice()), StackOp::OpCheckSig]};
+	let transaction = spending_tx();
+	let signature = sign_tx_in(&private_key, &transaction, 0, &locking_script, SIGHASH_ALL);
+	let spend = with_unlocking(&transaction, vec![StackOp::Bytes(signature.into_boxed_slice())], vec![]);
+	assert_eq!(run_tx_in(&spend, &locking_script, SCRIPT_VERIFY_NONE), Ok(()));
+	// with the wrong parity it isn't a key
+	hybrid[0] ^= 1;
+	assert_eq!(parse_public_key(&hybrid), None);
+	assert_eq!(parse_public_key(&hybrid[..33]), None);
+    }
+
+    /// a transaction that spends a single tx_out, for the signature tests
+    fn spending_tx() -> Transaction {
+	let tx_in = TxIn::TxPrevious {
+	    tx_hash: Hash::from(1234),
+	    tx_out_index: 0,
+	    unlocking_script: Script {ops: vec![]},
+	    sequence: 0xffffffff,
+	    witness: vec![],
+	};
+	let tx_out = TxOut {
+	    value: 200,
+	    locking_script: Script {ops: vec![StackOp::Val(7)]},
+	};
+	Transaction {version: 1, lock_time: 0, tx_ins: vec![tx_in], tx_outs: vec![tx_out]}
+    }
 
+    #[test]
+    fn test_op_check_sig_verify() {
+	let private_key = SigningKey::<Secp256k1>::from_bytes("adamadamadamadamadamadamadamadam".as_bytes()).unwrap();
+	let public_key_bytes = private_key.verifying_key().to_encoded_point(true).to_bytes();
+	let locking_script = Script {ops: vec![StackOp::Bytes(public_key_bytes), StackOp::OpCheckSigVerify, StackOp::Bool(true)]};
+	let transaction = spending_tx();
+	let context = ExecutionContext::new(&transaction, 0, 222, SCRIPT_VERIFY_NONE);
+	let signature = sign_tx_in(&private_key, &transaction, 0, &locking_script, SIGHASH_ALL);
+	let unlocking_script = Script {ops: vec![StackOp::Bytes(signature.clone().into_boxed_slice())]};
+	assert_eq!(execute_scripts(&unlocking_script, &locking_script, &context), Ok(()));
+
+	let mut other_transaction = spending_tx();
+	other_transaction.lock_time = 1;
+	let context = ExecutionContext::new(&other_transaction, 0, 222, SCRIPT_VERIFY_NONE);
+	assert_eq!(execute_scripts(&unlocking_script, &locking_script, &context), Err(ScriptError::CheckSigVerifyFailed));
+    }
+
+    #[test]
+    fn test_find_and_delete() {
+	let private_key = SigningKey::<Secp256k1>::from_bytes(b"adamadamadamadamadamadamadamadam").unwrap();
+	let public_key = StackOp::Bytes(private_key.verifying_key().to_encoded_point(true).to_bytes());
+	let transaction = spending_tx();
+	// the locking script has the signature in it as well, but it gets taken out of the script code, so it signs the rest
+	let script_code = Script {ops: vec![StackOp::OpDrop, public_key.clone(), StackOp::OpCheckSig]};
+	let signature = sign_tx_in(&private_key, &transaction, 0, &script_code, SIGHASH_ALL).into_boxed_slice();
+	let locking_script = Script {ops: vec![StackOp::Bytes(signature.clone()), StackOp::OpDrop, public_key.clone(), StackOp::OpCheckSig]};
+	let spend = with_unlocking(&transaction, vec![StackOp::Bytes(signature.clone())], vec![]);
+	assert_eq!(run_tx_in(&spend, &locking_script, SCRIPT_VERIFY_NONE), Ok(()));
+	// pushed with OP_PUSHDATA1 it isn't the same bytes, so it stays in the script code and the signature is for the wrong one
+	let mut non_minimal = locking_script.clone();
+	non_minimal.ops[0] = StackOp::NonMinimalPush(OP_PUSHDATA1, signature.clone());
+	assert_eq!(Script::from_bytes(&non_minimal.to_bytes()), Ok(non_minimal.clone()));
+	assert_eq!(run_tx_in(&spend, &non_minimal, SCRIPT_VERIFY_NONE), Err(ScriptError::EvalFalse));
+
+	// OpCheckMultiSig takes out all of its signatures before checking any of them
+	let script_code = Script {ops: vec![StackOp::OpDrop, StackOp::Val(1), public_key.clone(), StackOp::Val(1), StackOp::OpCheckMultiSig]};
+	let signature = sign_tx_in(&private_key, &transaction, 0, &script_code, SIGHASH_ALL).into_boxed_slice();
+	let mut locking_script = script_code.clone();
+	locking_script.ops.insert(0, StackOp::Bytes(signature.clone()));
+	let spend = with_unlocking(&transaction, vec![StackOp::Val(0), StackOp::Bytes(signature)], vec![]);
+	assert_eq!(run_tx_in(&spend, &locking_script, SCRIPT_VERIFY_NONE), Ok(()));
+    }
+
+    #[test]
+    fn test_op_code_separator() {
+	let private_key = SigningKey::<Secp256k1>::from_bytes(b"adamadamadamadamadamadamadamadam").unwrap();
+	let public_key = StackOp::Bytes(private_key.verifying_key().to_encoded_point(true).to_bytes());
+	let transaction = spending_tx();
+	let sign = |script_code: &Script| StackOp::Bytes(sign_tx_in(&private_key, &transaction, 0, script_code, SIGHASH_ALL).into_boxed_slice());
+	// the first signature is for the whole script, and the second one only for what comes after the OpCodeSeparator
+	let locking_script = Script {ops: vec![public_key.clone(), StackOp::OpCheckSigVerify, StackOp::OpCodeSeparator, public_key.clone(), StackOp::OpCheckSig]};
+	let after_separator = Script {ops: locking_script.ops[3..].to_vec()};
+	let spend = with_unlocking(&transaction, vec![sign(&after_separator), sign(&locking_script)], vec![]);
+	assert_eq!(run_tx_in(&spend, &locking_script, SCRIPT_VERIFY_NONE), Ok(()));
+	let spend = with_unlocking(&transaction, vec![sign(&locking_script), sign(&locking_script)], vec![]);
+	assert_eq!(run_tx_in(&spend, &locking_script, SCRIPT_VERIFY_NONE), Err(ScriptError::EvalFalse));
+	// the OpCodeSeparators that are left in the script code don't get signed
+	let without_separator = Script {ops: vec![public_key.clone(), StackOp::OpCheckSigVerify, public_key.clone(), StackOp::OpCheckSig]};
+	assert_eq!(signature_hash(&transaction, 0, &locking_script, SIGHASH_ALL), signature_hash(&transaction, 0, &without_separator, SIGHASH_ALL));
+
+	// one in a branch that isn't taken doesn't count
+	let locking_script = Script {ops: vec![public_key.clone(), StackOp::OpCheckSigVerify, StackOp::Val(0), StackOp::OpIf, StackOp::OpCodeSeparator, StackOp::OpEndIf,
+					       public_key.clone(), StackOp::OpCheckSig]};
+	let spend = with_unlocking(&transaction, vec![sign(&locking_script), sign(&locking_script)], vec![]);
+	assert_eq!(run_tx_in(&spend, &locking_script, SCRIPT_VERIFY_NONE), Ok(()));
+
+	// for segwit version 0 the script code starts after it as well, but nothing is taken out
+	let witness_script = Script {ops: vec![StackOp::OpCodeSeparator, public_key.clone(), StackOp::OpCheckSig]};
+	let signature = |script_code: &Script| sign_segwit_tx_in(&private_key, &transaction, 0, script_code, 222, SIGHASH_ALL);
+	let after_separator = Script {ops: witness_script.ops[1..].to_vec()};
+	let locking_script = pay_to_witness_script_hash(&witness_script);
+	let spend = with_unlocking(&transaction, vec![], vec![signature(&after_separator), witness_script.to_bytes()]);
+	assert_eq!(run_tx_in(&spend, &locking_script, SCRIPT_VERIFY_CONSENSUS), Ok(()));
+	let spend = with_unlocking(&transaction, vec![], vec![signature(&witness_script), witness_script.to_bytes()]);
+	assert_eq!(run_tx_in(&spend, &locking_script, SCRIPT_VERIFY_CONSENSUS), Err(ScriptError::EvalFalse));
+    }
+
+    #[test]
+    fn test_op_check_multisig() {
+	let private_keys: Vec<SigningKey<Secp256k1>> = ["adamadamadamadamadamadamadamadam", "evevevevevevevevevevevevevevevev", "caincaincaincaincaincaincaincain"]
+	    .iter().map(|seed| SigningKey::<Secp256k1>::from_bytes(seed.as_bytes()).unwrap()).collect();
+	let public_keys: Vec<VerifyingKey<Secp256k1>> = private_keys.iter().map(|key| key.verifying_key()).collect();
+	let locking_script = pay_to_multisig(2, &public_keys);
+	assert_eq!(locking_script.ops.len(), 6);
+	assert_eq!(locking_script.to_bytes()[0], 0x52); // OP_2
+	assert_eq!(locking_script.to_bytes()[locking_script.to_bytes().len() - 2..], [0x53, 0xae]); // OP_3 OP_CHECKMULTISIG
+
+	let transaction = spending_tx();
+	let signatures: Vec<StackOp> = private_keys.iter()
+	    .map(|key| StackOp::Bytes(sign_tx_in(key, &transaction, 0, &locking_script, SIGHASH_ALL).into_boxed_slice()))
+	    .collect();
+	// the extra item that gets popped goes first, then the signatures in the order of their keys
+	let unlock_with = |dummy: StackOp, signers: &[usize]| {
+	    let mut ops = vec![dummy];
+	    ops.extend(signers.iter().map(|i| signatures[*i].clone()));
+	    Script {ops}
+	};
+	let run = |unlocking_script: &Script, locking_script: &Script, flags: u32| {
+	    execute_scripts(unlocking_script, locking_script, &ExecutionContext::new(&transaction, 0, 222, flags))
+	};
+
+	// any two of the three, as long as they are in order
+	for signers in [[0, 1], [0, 2], [1, 2]] {
+	    assert_eq!(run(&unlock_with(StackOp::Val(0), &signers), &locking_script, SCRIPT_VERIFY_NONE), Ok(()));
+	}
+	assert_eq!(run(&unlock_with(StackOp::Val(0), &[1, 0]), &locking_script, SCRIPT_VERIFY_NONE), Err(ScriptError::EvalFalse));
+	assert_eq!(run(&unlock_with(StackOp::Val(0), &[0, 0]), &locking_script, SCRIPT_VERIFY_NONE), Err(ScriptError::EvalFalse));
+	// one signature short means there is nothing left for the dummy
+	assert_eq!(run(&unlock_with(StackOp::Val(0), &[0]), &locking_script, SCRIPT_VERIFY_NONE), Err(ScriptError::StackUnderflow));
+
+	// the dummy can be anything, unless NULLDUMMY is in force
+	let non_null_dummy = unlock_with(StackOp::Val(5), &[0, 1]);
+	assert_eq!(run(&non_null_dummy, &locking_script, SCRIPT_VERIFY_NONE), Ok(()));
+	assert_eq!(run(&non_null_dummy, &locking_script, SCRIPT_VERIFY_NULLDUMMY), Err(ScriptError::SigNullDummy));
+	assert_eq!(run(&unlock_with(StackOp::Bytes(vec![].into_boxed_slice()), &[0, 1]), &locking_script, SCRIPT_VERIFY_NULLDUMMY), Ok(()));
+
+	// the verify version fails straight away instead
+	let mut verify_script = locking_script.clone();
+	verify_script.ops.pop();
+	verify_script.ops.extend(vec![StackOp::OpCheckMultiSigVerify, StackOp::Bool(true)]);
+	assert_eq!(run(&unlock_with(StackOp::Val(0), &[1, 2]), &verify_script, SCRIPT_VERIFY_NONE), Err(ScriptError::CheckMultiSigVerifyFailed)); // signed a different script
+	let signatures: Vec<StackOp> = private_keys.iter()
+	    .map(|key| StackOp::Bytes(sign_tx_in(key, &transaction, 0, &verify_script, SIGHASH_ALL).into_boxed_slice()))
+	    .collect();
+	let unlocking_script = Script {ops: vec![StackOp::Val(0), signatures[1].clone(), signatures[2].clone()]};
+	assert_eq!(run(&unlocking_script, &verify_script, SCRIPT_VERIFY_NONE), Ok(()));
+	let unlocking_script = Script {ops: vec![StackOp::Val(0), signatures[2].clone(), signatures[1].clone()]};
+	assert_eq!(run(&unlocking_script, &verify_script, SCRIPT_VERIFY_NONE), Err(ScriptError::CheckMultiSigVerifyFailed));
+
+	// the counts have to make sense
+	assert_eq!(run(&Script {ops: vec![StackOp::Val(0)]}, &Script::parse_asm("0 21 OP_CHECKMULTISIG").unwrap(), SCRIPT_VERIFY_NONE), Err(ScriptError::PubKeyCount));
+	assert_eq!(run(&Script {ops: vec![StackOp::Val(0)]}, &Script::parse_asm("-1 OP_CHECKMULTISIG").unwrap(), SCRIPT_VERIFY_NONE), Err(ScriptError::PubKeyCount));
+	assert_eq!(run(&Script {ops: vec![StackOp::Val(0)]}, &Script::parse_asm("1 0 OP_CHECKMULTISIG").unwrap(), SCRIPT_VERIFY_NONE), Err(ScriptError::SigCount));
+	// zero of zero is always fine
+	assert_eq!(run(&Script {ops: vec![StackOp::Val(0)]}, &Script::parse_asm("0 0 OP_CHECKMULTISIG").unwrap(), SCRIPT_VERIFY_NONE), Ok(()));
+
+	// a key that doesn't parse just never matches, so the other two can still unlock it
+	let mut garbage_key = locking_script.clone();
+	garbage_key.ops[1] = StackOp::Bytes(vec![2; 5].into_boxed_slice());
+	let sign = |key: &SigningKey<Secp256k1>, script_code: &Script| StackOp::Bytes(sign_tx_in(key, &transaction, 0, script_code, SIGHASH_ALL).into_boxed_slice());
+	let unlocking_script = Script {ops: vec![StackOp::Val(0), sign(&private_keys[1], &garbage_key), sign(&private_keys[2], &garbage_key)]};
+	assert_eq!(run(&unlocking_script, &garbage_key, SCRIPT_VERIFY_NONE), Ok(()));
+	let unlocking_script = Script {ops: vec![StackOp::Val(0), sign(&private_keys[0], &garbage_key), sign(&private_keys[2], &garbage_key)]};
+	assert_eq!(run(&unlocking_script, &garbage_key, SCRIPT_VERIFY_NONE), Err(ScriptError::EvalFalse));
+    }
+
+    /// a locking script that checks a lock time (or a sequence) with the given op. The number stays on the stack
+    /// (there is no OP_DROP yet), so something true goes on top of it
+    fn lock_time_script(lock_time: i64, op: StackOp) -> Script {
+	let lock_time = StackOp::Bytes(encode_script_num(lock_time).into_boxed_slice());
+	Script {ops: vec![lock_time, op, StackOp::Val(1), StackOp::Val(1), StackOp::OpEqual]}
+    }
+
+    /// run the locking script against a spending transaction with the given version, lock_time and sequence
+    fn run_with_lock_time(version: u32, lock_time: u32, sequence: u32, locking_script: &Script, flags: u32) -> Result<(), ScriptError> {
+	let mut transaction = spending_tx();
+	transaction.version = version;
+	transaction.lock_time = lock_time;
+	if let TxIn::TxPrevious {sequence: tx_sequence, ..} = &mut transaction.tx_ins[0] {
+	    *tx_sequence = sequence;
+	}
+	execute_scripts(&Script {ops: vec![]}, locking_script, &ExecutionContext::new(&transaction, 0, 222, flags))
+    }
+
+    #[test]
+    fn test_pay_to_script_hash() {
+	let private_keys: Vec<SigningKey<Secp256k1>> = ["adamadamadamadamadamadamadamadam", "evevevevevevevevevevevevevevevev", "caincaincaincaincaincaincaincain"]
+	    .iter().map(|seed| SigningKey::<Secp256k1>::from_bytes(seed.as_bytes()).unwrap()).collect();
+	let public_keys: Vec<VerifyingKey<Secp256k1>> = private_keys.iter().map(|key| key.verifying_key()).collect();
+	let redeem_script = pay_to_multisig(2, &public_keys);
+	let locking_script = pay_to_script_hash(&redeem_script);
+	assert!(locking_script.is_pay_to_script_hash());
+	assert!(!redeem_script.is_pay_to_script_hash());
+	let locking_bytes = locking_script.to_bytes();
+	assert_eq!(locking_bytes.len(), 23);
+	assert_eq!((locking_bytes[0], locking_bytes[1], locking_bytes[22]), (0xa9, 0x14, 0x87)); // OP_HASH160 <20 bytes> OP_EQUAL
+	assert_eq!(Script::from_bytes(&locking_bytes), Ok(locking_script.clone()));
+
+	// the signatures sign the redeem script, since that is the script that checks them
+	let transaction = spending_tx();
+	let sign = |key: &SigningKey<Secp256k1>, script_code: &Script| StackOp::Bytes(sign_tx_in(key, &transaction, 0, script_code, SIGHASH_ALL).into_boxed_slice());
+	let redeem_push = StackOp::Bytes(redeem_script.to_bytes().into_boxed_slice());
+	let unlocking_script = Script {ops: vec![StackOp::Val(0), sign(&private_keys[0], &redeem_script), sign(&private_keys[2], &redeem_script), redeem_push.clone()]};
+	assert!(unlocking_script.is_push_only());
+	let run = |unlocking_script: &Script, flags: u32| {
+	    execute_scripts(unlocking_script, &locking_script, &ExecutionContext::new(&transaction, 0, 222, flags))
+	};
+	assert_eq!(run(&unlocking_script, SCRIPT_VERIFY_P2SH), Ok(()));
+
+	// without P2SH, only the hash gets checked, so the signatures don't matter
+	let wrong_order = Script {ops: vec![StackOp::Val(0), sign(&private_keys[2], &redeem_script), sign(&private_keys[0], &redeem_script), redeem_push.clone()]};
+	assert_eq!(run(&wrong_order, SCRIPT_VERIFY_NONE), Ok(()));
+	assert_eq!(run(&wrong_order, SCRIPT_VERIFY_P2SH), Err(ScriptError::EvalFalse));
+	let signed_locking_script = Script {ops: vec![StackOp::Val(0), sign(&private_keys[0], &locking_script), sign(&private_keys[1], &locking_script), redeem_push.clone()]};
+	assert_eq!(run(&signed_locking_script, SCRIPT_VERIFY_P2SH), Err(ScriptError::EvalFalse));
+	let one_signature = Script {ops: vec![StackOp::Val(0), sign(&private_keys[0], &redeem_script), redeem_push.clone()]};
+	assert_eq!(run(&one_signature, SCRIPT_VERIFY_P2SH), Err(ScriptError::StackUnderflow));
+
+	// a different redeem script doesn't have the right hash
+	let other_redeem_script = pay_to_multisig(1, &public_keys);
+	let other_push = StackOp::Bytes(other_redeem_script.to_bytes().into_boxed_slice());
+	let other = Script {ops: vec![StackOp::Val(0), sign(&private_keys[0], &other_redeem_script), other_push]};
+	assert_eq!(run(&other, SCRIPT_VERIFY_P2SH), Err(ScriptError::EvalFalse));
+
+	// the unlocking script can only push
+	let mut not_push_only = unlocking_script.clone();
+	not_push_only.ops.insert(0, StackOp::OpNop);
+	assert_eq!(run(&not_push_only, SCRIPT_VERIFY_NONE), Ok(()));
+	assert_eq!(run(&not_push_only, SCRIPT_VERIFY_P2SH), Err(ScriptError::SigPushOnly));
+
+	// a redeem script with the right hash still has to decode, and a push off the end of it doesn't
+	let garbage = [0x02, 0x01];
+	let garbage_locking_script = Script {ops: vec![StackOp::OpHash160, StackOp::Bytes(hash_160_to_bytes(&garbage).into_boxed_slice()), StackOp::OpEqual]};
+	let context = ExecutionContext::new(&transaction, 0, 222, SCRIPT_VERIFY_P2SH);
+	let garbage_unlocking_script = Script {ops: vec![StackOp::Bytes(garbage.to_vec().into_boxed_slice())]};
+	assert_eq!(execute_scripts(&garbage_unlocking_script, &garbage_locking_script, &context), Err(ScriptError::InvalidRedeemScript));
+    }
+
+    #[test]
+    fn test_witness_program() {
+	let public_key_bytes = [2; 33];
+	let locking_script = pay_to_witness_public_key_hash(&public_key_bytes);
+	assert_eq!(locking_script.witness_program(), Some((0, hash_160_to_bytes(&public_key_bytes))));
+	assert_eq!(hex::encode(&locking_script.to_bytes()[..2]), "0014"); // OP_0 <20 bytes>
+	let witness_script = Script {ops: vec![StackOp::Val(1)]};
+	assert_eq!(pay_to_witness_script_hash(&witness_script).witness_program(), Some((0, Sha256::digest([0x51]).to_vec())));
+	// any version up to 16, with 2 to 40 bytes
+	let program = |version: i32, len: usize| Script {ops: vec![StackOp::Val(version), StackOp::Bytes(vec![7; len].into_boxed_slice())]}.witness_program();
+	assert_eq!(program(1, 32), Some((1, vec![7; 32])));
+	assert_eq!(program(16, 2), Some((16, vec![7; 2])));
+	assert_eq!(program(16, 40), Some((16, vec![7; 40])));
+	assert_eq!(program(1, 1), None);
+	assert_eq!(program(1, 41), None);
+	assert_eq!(program(17, 20), None);
+	assert_eq!(program(-1, 20), None);
+	assert_eq!(pay_to_public_key_hash(&public_key_bytes).witness_program(), None);
+    }
+
+    #[test]
+    fn test_classify() {
+	// the genesis block paid to a bare uncompressed public key, and a regular mainnet tx_out to a public key hash
+	let genesis = Script::from_bytes(&hex::decode("4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac").unwrap()).unwrap();
+	assert!(matches!(genesis.classify(), ScriptType::PayToPublicKey(public_key) if public_key.len() == 65));
+	let mainnet_p2pkh = Script::from_bytes(&hex::decode("76a9140389035a9225b3839e2bbf32d826a1e222031fd888ac").unwrap()).unwrap();
+	assert_eq!(mainnet_p2pkh.classify(), ScriptType::PayToPublicKeyHash(hex::decode("0389035a9225b3839e2bbf32d826a1e222031fd8").unwrap()));
+
+	let private_keys: Vec<SigningKey<Secp256k1>> = ["adamadamadamadamadamadamadamadam", "evevevevevevevevevevevevevevevev", "caincaincaincaincaincaincaincain"]
+	    .iter().map(|seed| SigningKey::<Secp256k1>::from_bytes(seed.as_bytes()).unwrap()).collect();
+	let public_keys: Vec<VerifyingKey<Secp256k1>> = private_keys.iter().map(|key| key.verifying_key()).collect();
+	let public_key_bytes: Vec<Vec<u8>> = public_keys.iter().map(|key| key.to_encoded_point(true).to_bytes().to_vec()).collect();
+	let multisig = pay_to_multisig(2, &public_keys);
+	assert_eq!(multisig.classify(), ScriptType::Multisig {required: 2, public_keys: public_key_bytes.clone()});
+	let compressed = Script {ops: vec![StackOp::Bytes(public_key_bytes[0].clone().into_boxed_slice()), StackOp::OpCheckSig]};
+	assert_eq!(compressed.classify(), ScriptType::PayToPublicKey(public_key_bytes[0].clone()));
+	assert_eq!(pay_to_public_key_hash(&public_key_bytes[0]).classify(), ScriptType::PayToPublicKeyHash(hash_160_to_bytes(&public_key_bytes[0])));
+	assert_eq!(pay_to_script_hash(&multisig).classify(), ScriptType::PayToScriptHash(hash_160_to_bytes(&multisig.to_bytes())));
+	assert_eq!(pay_to_witness_public_key_hash(&public_key_bytes[0]).classify(), ScriptType::PayToWitnessPublicKeyHash(hash_160_to_bytes(&public_key_bytes[0])));
+	assert_eq!(pay_to_witness_script_hash(&multisig).classify(), ScriptType::PayToWitnessScriptHash(Sha256::digest(multisig.to_bytes()).to_vec()));
+	let taproot = pay_to_taproot(&x_only_public_key(&private_keys[0]), None);
+	assert_eq!(taproot.classify(), ScriptType::PayToTaproot(taproot.witness_program().unwrap().1));
+	let future_version = Script {ops: vec![StackOp::Val(2), StackOp::Bytes(vec![7; 20].into_boxed_slice())]};
+	assert_eq!(future_version.classify(), ScriptType::WitnessUnknown {version: 2, program: vec![7; 20]});
+	// version 0 only has the two sizes
+	assert_eq!(Script {ops: vec![StackOp::Val(0), StackOp::Bytes(vec![7; 21].into_boxed_slice())]}.classify(), ScriptType::NonStandard);
+
+	// data after OP_RETURN, which has to be nothing but pushes
+	assert_eq!(Script::parse_asm("OP_RETURN 68656c6c6f 5").unwrap().classify(), ScriptType::NullData(vec![b"hello".to_vec(), vec![5]]));
+	assert_eq!(Script::parse_asm("OP_RETURN").unwrap().classify(), ScriptType::NullData(vec![]));
+	assert_eq!(Script::parse_asm("OP_RETURN OP_DUP").unwrap().classify(), ScriptType::NonStandard);
+
+	// multisig counts have to match the keys, and the keys have to look like keys
+	let key_push = |i: usize| StackOp::Bytes(public_key_bytes[i].clone().into_boxed_slice());
+	let multisig_ops = |required: StackOp, keys: Vec<StackOp>, count: StackOp| {
+	    let mut ops = vec![required];
+	    ops.extend(keys);
+	    ops.extend(vec![count, StackOp::OpCheckMultiSig]);
+	    Script {ops}.classify()
+	};
+	assert_eq!(multisig_ops(StackOp::Bool(true), vec![key_push(0)], StackOp::Val(1)), ScriptType::Multisig {required: 1, public_keys: vec![public_key_bytes[0].clone()]});
+	assert_eq!(multisig_ops(StackOp::Val(2), vec![key_push(0), key_push(1)], StackOp::Val(3)), ScriptType::NonStandard);
+	assert_eq!(multisig_ops(StackOp::Val(3), vec![key_push(0), key_push(1)], StackOp::Val(2)), ScriptType::NonStandard);
+	assert_eq!(multisig_ops(StackOp::Val(0), vec![key_push(0)], StackOp::Val(1)), ScriptType::NonStandard);
+	assert_eq!(multisig_ops(StackOp::Val(1), vec![StackOp::Bytes(vec![2; 32].into_boxed_slice())], StackOp::Val(1)), ScriptType::NonStandard);
+	assert_eq!(multisig_ops(StackOp::Val(1), vec![key_push(0)], StackOp::Bytes(vec![1].into_boxed_slice())), ScriptType::NonStandard);
+
+	// a public key has to have the right size for its first byte
+	let mut wrong_prefix = public_key_bytes[0].clone();
+	wrong_prefix[0] = 0x04;
+	assert_eq!(Script {ops: vec![StackOp::Bytes(wrong_prefix.into_boxed_slice()), StackOp::OpCheckSig]}.classify(), ScriptType::NonStandard);
+	assert_eq!(Script {ops: vec![StackOp::OpDup]}.classify(), ScriptType::NonStandard);
+	assert_eq!(Script {ops: vec![]}.classify(), ScriptType::NonStandard);
+    }
+
+    /// run the scripts of tx_in 0 of the transaction, spending a tx_out of 222
+    fn run_tx_in(transaction: &Transaction, locking_script: &Script, flags: u32) -> Result<(), ScriptError> {
+	let unlocking_script = match &transaction.tx_ins[0] {
+	    TxIn::TxPrevious {unlocking_script, ..} => unlocking_script.clone(),
+	    TxIn::Coinbase {..} => panic!("expected a regular tx_in"),
+	};
+	execute_scripts(&unlocking_script, locking_script, &ExecutionContext::new(transaction, 0, 222, flags))
+    }
+
+    fn with_unlocking(transaction: &Transaction, ops: Vec<StackOp>, witness: Vec<Vec<u8>>) -> Transaction {
+	let mut transaction = transaction.clone();
+	if let TxIn::TxPrevious {unlocking_script, ..} = &mut transaction.tx_ins[0] {
+	    *unlocking_script = Script {ops};
+	}
+	*transaction.tx_ins[0].witness_mut() = witness;
+	transaction
+    }
+
+    #[test]
+    fn test_clean_stack() {
+	let flags = SCRIPT_VERIFY_P2SH | SCRIPT_VERIFY_WITNESS | SCRIPT_VERIFY_CLEANSTACK;
+	let two_items = with_unlocking(&spending_tx(), vec![StackOp::Val(1), StackOp::Val(1)], vec![]);
+	let empty = Script {ops: vec![]};
+	assert_eq!(run_tx_in(&two_items, &empty, SCRIPT_VERIFY_CONSENSUS), Ok(()));
+	assert_eq!(run_tx_in(&two_items, &empty, flags), Err(ScriptError::CleanStack));
+	let one_item = with_unlocking(&spending_tx(), vec![StackOp::Val(1)], vec![]);
+	assert_eq!(run_tx_in(&one_item, &empty, flags), Ok(()));
+
+	// for pay to script hash, it's the stack that the redeem script leaves behind that has to be clean
+	let redeem_script = Script {ops: vec![StackOp::Val(1), StackOp::Val(1)]};
+	let locking_script = pay_to_script_hash(&redeem_script);
+	let spend = with_unlocking(&spending_tx(), vec![StackOp::Bytes(redeem_script.to_bytes().into_boxed_slice())], vec![]);
+	assert_eq!(run_tx_in(&spend, &locking_script, SCRIPT_VERIFY_CONSENSUS), Ok(()));
+	assert_eq!(run_tx_in(&spend, &locking_script, flags), Err(ScriptError::CleanStack));
+	let redeem_script = Script {ops: vec![StackOp::Val(1)]};
+	let spend = with_unlocking(&spending_tx(), vec![StackOp::Bytes(redeem_script.to_bytes().into_boxed_slice())], vec![]);
+	assert_eq!(run_tx_in(&spend, &pay_to_script_hash(&redeem_script), flags), Ok(()));
+    }
+
+    #[test]
+    fn test_pay_to_witness_public_key_hash() {
+	let private_key = SigningKey::<Secp256k1>::from_bytes(b"adamadamadamadamadamadamadamadam").unwrap();
+	let public_key_bytes = private_key.verifying_key().to_encoded_point(true).to_bytes().to_vec();
+	let locking_script = pay_to_witness_public_key_hash(&public_key_bytes);
+	// the signature commits to the amount, with the pay to public key hash script as the script code
+	let transaction = spending_tx();
+	let script_code = pay_to_public_key_hash(&public_key_bytes);
+	let signature = sign_segwit_tx_in(&private_key, &transaction, 0, &script_code, 222, SIGHASH_ALL);
+	let spend = with_unlocking(&transaction, vec![], vec![signature.clone(), public_key_bytes.clone()]);
+	assert_eq!(run_tx_in(&spend, &locking_script, SCRIPT_VERIFY_CONSENSUS), Ok(()));
+	let context = ExecutionContext::new(&spend, 0, 223, SCRIPT_VERIFY_CONSENSUS);
+	assert_eq!(execute_scripts(&Script {ops: vec![]}, &locking_script, &context), Err(ScriptError::EvalFalse));
+	// a legacy signature doesn't do
+	let legacy_signature = sign_tx_in(&private_key, &transaction, 0, &script_code, SIGHASH_ALL);
+	let spend_legacy = with_unlocking(&transaction, vec![], vec![legacy_signature, public_key_bytes.clone()]);
+	assert_eq!(run_tx_in(&spend_legacy, &locking_script, SCRIPT_VERIFY_CONSENSUS), Err(ScriptError::EvalFalse));
+
+	// the witness has to be exactly the signature and the public key
+	let too_many = with_unlocking(&transaction, vec![], vec![vec![], signature.clone(), public_key_bytes.clone()]);
+	assert_eq!(run_tx_in(&too_many, &locking_script, SCRIPT_VERIFY_CONSENSUS), Err(ScriptError::WitnessProgramMismatch));
+	// and the unlocking script has to be empty
+	let malleated = with_unlocking(&transaction, vec![StackOp::Val(1)], vec![signature.clone(), public_key_bytes.clone()]);
+	assert_eq!(run_tx_in(&malleated, &locking_script, SCRIPT_VERIFY_CONSENSUS), Err(ScriptError::WitnessMalleated));
+	// old nodes only see a script that leaves the hash on the stack, so to them anyone can spend it
+	let empty = with_unlocking(&transaction, vec![], vec![]);
+	assert_eq!(run_tx_in(&empty, &locking_script, SCRIPT_VERIFY_NONE), Ok(()));
+	assert_eq!(run_tx_in(&empty, &locking_script, SCRIPT_VERIFY_CONSENSUS), Err(ScriptError::WitnessProgramMismatch));
+
+	// a witness for something that isn't a witness program
+	let legacy_spend = with_unlocking(&transaction, vec![StackOp::Val(1)], vec![vec![1]]);
+	let not_segwit = Script {ops: vec![StackOp::OpVerify, StackOp::Val(1)]};
+	assert_eq!(run_tx_in(&legacy_spend, &not_segwit, SCRIPT_VERIFY_CONSENSUS), Err(ScriptError::WitnessUnexpected));
+	assert_eq!(run_tx_in(&legacy_spend, &not_segwit, SCRIPT_VERIFY_CONSENSUS & !SCRIPT_VERIFY_WITNESS), Ok(()));
+	// versions that aren't defined yet can be spent by anyone
+	let future_version = Script {ops: vec![StackOp::Val(2), StackOp::Bytes(vec![7; 20].into_boxed_slice())]};
+	assert_eq!(run_tx_in(&legacy_spend, &future_version, SCRIPT_VERIFY_CONSENSUS), Err(ScriptError::WitnessMalleated));
+	assert_eq!(run_tx_in(&with_unlocking(&transaction, vec![], vec![vec![1]]), &future_version, SCRIPT_VERIFY_CONSENSUS), Ok(()));
+	let wrong_length = Script {ops: vec![StackOp::Val(0), StackOp::Bytes(vec![7; 21].into_boxed_slice())]};
+	assert_eq!(run_tx_in(&empty, &wrong_length, SCRIPT_VERIFY_CONSENSUS), Err(ScriptError::WitnessProgramWrongLength));
+    }
+
+    #[test]
+    fn test_pay_to_witness_script_hash() {
+	let private_keys: Vec<SigningKey<Secp256k1>> = ["adamadamadamadamadamadamadamadam", "evevevevevevevevevevevevevevevev", "caincaincaincaincaincaincaincain"]
+	    .iter().map(|seed| SigningKey::<Secp256k1>::from_bytes(seed.as_bytes()).unwrap()).collect();
+	let public_keys: Vec<VerifyingKey<Secp256k1>> = private_keys.iter().map(|key| key.verifying_key()).collect();
+	let witness_script = pay_to_multisig(2, &public_keys);
+	let witness_script_bytes = witness_script.to_bytes();
+	let locking_script = pay_to_witness_script_hash(&witness_script);
+	let transaction = spending_tx();
+	let sign = |key: &SigningKey<Secp256k1>| sign_segwit_tx_in(key, &transaction, 0, &witness_script, 222, SIGHASH_ALL);
+	let witness = vec![vec![], sign(&private_keys[0]), sign(&private_keys[2]), witness_script_bytes.clone()];
+	assert_eq!(run_tx_in(&with_unlocking(&transaction, vec![], witness.clone()), &locking_script, SCRIPT_VERIFY_CONSENSUS), Ok(()));
+
+	let wrong_order = vec![vec![], sign(&private_keys[2]), sign(&private_keys[0]), witness_script_bytes.clone()];
+	assert_eq!(run_tx_in(&with_unlocking(&transaction, vec![], wrong_order), &locking_script, SCRIPT_VERIFY_CONSENSUS), Err(ScriptError::EvalFalse));
+	// the witness script has to hash to the program
+	let other_script = pay_to_multisig(1, &public_keys).to_bytes();
+	let other = vec![vec![], sign(&private_keys[0]), other_script];
+	assert_eq!(run_tx_in(&with_unlocking(&transaction, vec![], other), &locking_script, SCRIPT_VERIFY_CONSENSUS), Err(ScriptError::WitnessProgramMismatch));
+	assert_eq!(run_tx_in(&with_unlocking(&transaction, vec![], vec![]), &locking_script, SCRIPT_VERIFY_CONSENSUS), Err(ScriptError::WitnessProgramWitnessEmpty));
+	// anything left over on the stack is an error, rather than just ignored
+	let mut extra = witness.clone();
+	extra.insert(0, vec![1]);
+	assert_eq!(run_tx_in(&with_unlocking(&transaction, vec![], extra), &locking_script, SCRIPT_VERIFY_CONSENSUS), Err(ScriptError::CleanStack));
+	// witness items have the same size limit as pushes
+	let big_item_script = Script {ops: vec![StackOp::OpDrop, StackOp::Val(1)]};
+	let big_item = vec![vec![0; MAX_SCRIPT_ELEMENT_SIZE + 1], big_item_script.to_bytes()];
+	assert_eq!(run_tx_in(&with_unlocking(&transaction, vec![], big_item), &pay_to_witness_script_hash(&big_item_script), SCRIPT_VERIFY_CONSENSUS), Err(ScriptError::PushSize));
+
+	// nested in pay to script hash: the unlocking script only pushes the witness program, and the witness does the rest
+	let redeem_script = pay_to_witness_script_hash(&witness_script);
+	let nested_locking_script = pay_to_script_hash(&redeem_script);
+	let redeem_push = StackOp::Bytes(redeem_script.to_bytes().into_boxed_slice());
+	let nested = with_unlocking(&transaction, vec![redeem_push.clone()], witness.clone());
+	assert_eq!(run_tx_in(&nested, &nested_locking_script, SCRIPT_VERIFY_CONSENSUS), Ok(()));
+	let malleated = with_unlocking(&transaction, vec![StackOp::Val(0), redeem_push], witness.clone());
+	assert_eq!(run_tx_in(&malleated, &nested_locking_script, SCRIPT_VERIFY_CONSENSUS), Err(ScriptError::WitnessMalleatedP2SH));
+    }
+
+    #[test]
+    fn test_check_lock_time_verify() {
+	let run = |lock_time: u32, sequence: u32, locking_script: &Script| {
+	    run_with_lock_time(1, lock_time, sequence, locking_script, SCRIPT_VERIFY_CHECKLOCKTIMEVERIFY)
+	};
+	let at_height_100 = lock_time_script(100, StackOp::OpCheckLockTimeVerify);
+	assert_eq!(run(100, 0, &at_height_100), Ok(()));
+	assert_eq!(run(150, 0, &at_height_100), Ok(()));
+	assert_eq!(run(99, 0, &at_height_100), Err(ScriptError::UnsatisfiedLockTime));
+	// the transaction's lock_time has to be a height as well, and it can't be switched off with the sequence
+	assert_eq!(run(LOCKTIME_THRESHOLD + 100, 0, &at_height_100), Err(ScriptError::UnsatisfiedLockTime));
+	assert_eq!(run(100, SEQUENCE_FINAL, &at_height_100), Err(ScriptError::UnsatisfiedLockTime));
+	// a time past 2^31 takes 5 bytes
+	let time = 0x80000000_u32;
+	let at_time = lock_time_script(time as i64, StackOp::OpCheckLockTimeVerify);
+	assert_eq!(run(time, 0, &at_time), Ok(()));
+	assert_eq!(run(time - 1, 0, &at_time), Err(ScriptError::UnsatisfiedLockTime));
+
+	assert_eq!(run(0, 0, &lock_time_script(-1, StackOp::OpCheckLockTimeVerify)), Err(ScriptError::NegativeLockTime));
+	assert_eq!(run(0, 0, &Script {ops: vec![StackOp::OpCheckLockTimeVerify]}), Err(ScriptError::StackUnderflow));
+	// without the flag, it does nothing
+	assert_eq!(run_with_lock_time(1, 99, 0, &at_height_100, SCRIPT_VERIFY_NONE), Ok(()));
+    }
+
+    #[test]
+    fn test_check_sequence_verify() {
+	let run = |version: u32, sequence: u32, locking_script: &Script| {
+	    run_with_lock_time(version, 0, sequence, locking_script, SCRIPT_VERIFY_CHECKSEQUENCEVERIFY)
+	};
+	let ten_blocks = lock_time_script(10, StackOp::OpCheckSequenceVerify);
+	assert_eq!(run(2, 10, &ten_blocks), Ok(()));
+	assert_eq!(run(2, 11, &ten_blocks), Ok(()));
+	assert_eq!(run(2, 9, &ten_blocks), Err(ScriptError::UnsatisfiedLockTime));
+	// relative lock times need version 2, and the tx_in mustn't disable them
+	assert_eq!(run(1, 10, &ten_blocks), Err(ScriptError::UnsatisfiedLockTime));
+	assert_eq!(run(2, 10 | SEQUENCE_LOCKTIME_DISABLE_FLAG, &ten_blocks), Err(ScriptError::UnsatisfiedLockTime));
+	// blocks and time don't mix
+	assert_eq!(run(2, 10 | SEQUENCE_LOCKTIME_TYPE_FLAG, &ten_blocks), Err(ScriptError::UnsatisfiedLockTime));
+	let ten_units = lock_time_script((10 | SEQUENCE_LOCKTIME_TYPE_FLAG) as i64, StackOp::OpCheckSequenceVerify);
+	assert_eq!(run(2, 10 | SEQUENCE_LOCKTIME_TYPE_FLAG, &ten_units), Ok(()));
+	// bits outside of the type flag and the lock time itself are ignored
+	assert_eq!(run(2, 10 | 1 << 16, &ten_blocks), Ok(()));
+	// a required sequence with the disable flag always passes
+	let disabled = lock_time_script(SEQUENCE_LOCKTIME_DISABLE_FLAG as i64, StackOp::OpCheckSequenceVerify);
+	assert_eq!(run(1, 0, &disabled), Ok(()));
+
+	assert_eq!(run(2, 10, &lock_time_script(-1, StackOp::OpCheckSequenceVerify)), Err(ScriptError::NegativeLockTime));
+	assert_eq!(run_with_lock_time(1, 0, 0, &ten_blocks, SCRIPT_VERIFY_NONE), Ok(()));
+    }
+
+    /// run the scripts of tx_in 0 of the transaction, spending a tx_out of 222 locked by the locking script, which taproot signatures commit to
+    fn run_taproot(transaction: &Transaction, locking_script: &Script, flags: u32) -> Result<(), ScriptError> {
+	let unlocking_script = match &transaction.tx_ins[0] {
+	    TxIn::TxPrevious {unlocking_script, ..} => unlocking_script.clone(),
+	    TxIn::Coinbase {..} => panic!("expected a regular tx_in"),
+	};
+	let spent_tx_outs = [TxOut {value: 222, locking_script: locking_script.clone()}];
+	let context = ExecutionContext::new(transaction, 0, 222, flags).with_spent_tx_outs(&spent_tx_outs);
+	execute_scripts(&unlocking_script, locking_script, &context)
+    }
+
+    #[test]
+    fn test_taproot_key_path() {
+	let private_key = SigningKey::<Secp256k1>::from_bytes(b"adamadamadamadamadamadamadamadam").unwrap();
+	let internal_key = x_only_public_key(&private_key);
+	let locking_script = pay_to_taproot(&internal_key, None);
+	let (version, output_key) = locking_script.witness_program().unwrap();
+	assert_eq!((version, output_key.len()), (1, 32));
+	assert_eq!(hex::encode(&locking_script.to_bytes()[..2]), "5120"); // OP_1 <32 bytes>
+
+	// the signature is for the tweaked key, and leaves off the hash type for SIGHASH_DEFAULT
+	let tweaked_key = tweak_private_key(&private_key, None).unwrap();
+	let transaction = spending_tx();
+	let spent_tx_outs = [TxOut {value: 222, locking_script: locking_script.clone()}];
+	let sign = |key: &SigningKey<Secp256k1>, hash_type: u8| sign_taproot_tx_in(key, &transaction, 0, &spent_tx_outs, hash_type, None);
+	let signature = sign(&tweaked_key, SIGHASH_DEFAULT);
+	assert_eq!(signature.len(), 64);
+	let run = |witness: Vec<Vec<u8>>| run_taproot(&with_unlocking(&transaction, vec![], witness), &locking_script, SCRIPT_VERIFY_CONSENSUS);
+	assert_eq!(run(vec![signature.clone()]), Ok(()));
+	assert_eq!(sign(&tweaked_key, SIGHASH_ALL).len(), 65);
+	assert_eq!(run(vec![sign(&tweaked_key, SIGHASH_ALL)]), Ok(()));
+	assert_eq!(run(vec![sign(&tweaked_key, SIGHASH_ALL | SIGHASH_ANYONECANPAY)]), Ok(()));
+
+	// any signature that doesn't check out is an error, even the untweaked key's
+	assert_eq!(run(vec![sign(&private_key, SIGHASH_DEFAULT)]), Err(ScriptError::SchnorrSig));
+	let mut wrong_hash_type = sign(&tweaked_key, SIGHASH_ALL);
+	wrong_hash_type[64] = SIGHASH_NONE;
+	assert_eq!(run(vec![wrong_hash_type]), Err(ScriptError::SchnorrSig));
+	// SIGHASH_DEFAULT can't be spelled out, and the size has to be right
+	let mut explicit_default = signature.clone();
+	explicit_default.push(SIGHASH_DEFAULT);
+	assert_eq!(run(vec![explicit_default]), Err(ScriptError::SchnorrSigHashType));
+	let mut unknown_hash_type = signature.clone();
+	unknown_hash_type.push(0x04);
+	assert_eq!(run(vec![unknown_hash_type]), Err(ScriptError::SchnorrSigHashType));
+	assert_eq!(run(vec![signature[..63].to_vec()]), Err(ScriptError::SchnorrSigSize));
+	assert_eq!(run(vec![vec![]]), Err(ScriptError::SchnorrSigSize));
+	assert_eq!(run(vec![]), Err(ScriptError::WitnessProgramWitnessEmpty));
+
+	// the signature commits to the amounts and locking scripts of what is being spent, so those have to be known
+	let spend = with_unlocking(&transaction, vec![], vec![signature.clone()]);
+	let context = ExecutionContext::new(&spend, 0, 222, SCRIPT_VERIFY_CONSENSUS);
+	assert_eq!(execute_scripts(&Script {ops: vec![]}, &locking_script, &context), Err(ScriptError::SchnorrSigHashType));
+	let other_amount = [TxOut {value: 223, locking_script: locking_script.clone()}];
+	assert_eq!(execute_scripts(&Script {ops: vec![]}, &locking_script, &context.with_spent_tx_outs(&other_amount)), Err(ScriptError::SchnorrSig));
+
+	// an annex gets signed as well
+	let annex = vec![ANNEX_TAG, 1, 2, 3];
+	assert_eq!(run(vec![signature.clone(), annex.clone()]), Err(ScriptError::SchnorrSig));
+	let sighash = taproot_signature_hash(&transaction, 0, &spent_tx_outs, SIGHASH_DEFAULT, Some(&annex), None).unwrap();
+	let annex_signature = schnorr_sign(&tweaked_key, &hash_to_bytes(&sighash), &[0; 32]).to_vec();
+	assert_eq!(run(vec![annex_signature.clone(), annex.clone()]), Ok(()));
+	assert_eq!(run(vec![annex_signature, vec![0x51, 1, 2, 3]]), Err(ScriptError::TaprootWrongControlSize)); // not an annex, so a script path spend
+
+	// without taproot, version 1 isn't defined, and nested in pay to script hash it isn't taproot either
+	assert_eq!(run_taproot(&with_unlocking(&transaction, vec![], vec![vec![1]]), &locking_script, SCRIPT_VERIFY_CONSENSUS & !SCRIPT_VERIFY_TAPROOT), Ok(()));
+	let nested_locking_script = pay_to_script_hash(&locking_script);
+	let nested = with_unlocking(&transaction, vec![StackOp::Bytes(locking_script.to_bytes().into_boxed_slice())], vec![vec![1]]);
+	assert_eq!(run_taproot(&nested, &nested_locking_script, SCRIPT_VERIFY_CONSENSUS), Ok(()));
+    }
+
+    #[test]
+    fn test_taproot_script_path() {
+	let private_keys: Vec<SigningKey<Secp256k1>> = ["adamadamadamadamadamadamadamadam", "evevevevevevevevevevevevevevevev", "caincaincaincaincaincaincaincain"]
+	    .iter().map(|seed| SigningKey::<Secp256k1>::from_bytes(seed.as_bytes()).unwrap()).collect();
+	let public_keys: Vec<StackOp> = private_keys.iter().map(|key| StackOp::Bytes(x_only_public_key(key).to_vec().into_boxed_slice())).collect();
+	// a 2 of 3 multisig the tapscript way, and a script that always succeeds
+	let multisig_script = Script {ops: vec![public_keys[0].clone(), StackOp::OpCheckSig, public_keys[1].clone(), StackOp::OpCheckSigAdd,
+						public_keys[2].clone(), StackOp::OpCheckSigAdd, StackOp::Val(2), StackOp::OpNumEqual]};
+	let multisig_bytes = multisig_script.to_bytes();
+	let success_bytes = vec![0x50]; // OP_SUCCESS80
+	let multisig_leaf = tap_leaf_hash(TAPROOT_LEAF_TAPSCRIPT, &multisig_bytes);
+	let success_leaf = tap_leaf_hash(TAPROOT_LEAF_TAPSCRIPT, &success_bytes);
+	let merkle_root = tap_branch_hash(&multisig_leaf, &success_leaf);
+
+	// nobody knows the private key for this internal key, so the key path is out
+	let internal_key = x_only_public_key(&SigningKey::<Secp256k1>::from_bytes(b"abelabelabelabelabelabelabelabel").unwrap());
+	let locking_script = pay_to_taproot(&internal_key, Some(&merkle_root));
+	let (_, parity) = tweak_public_key(&internal_key, Some(&merkle_root)).unwrap();
+	let multisig_control = control_block(TAPROOT_LEAF_TAPSCRIPT, &internal_key, parity, &[success_leaf]);
+	let success_control = control_block(TAPROOT_LEAF_TAPSCRIPT, &internal_key, parity, &[multisig_leaf]);
+
+	let transaction = spending_tx();
+	let spent_tx_outs = [TxOut {value: 222, locking_script: locking_script.clone()}];
+	let sign = |i: usize, leaf_hash: &[u8; 32]| sign_taproot_tx_in(&private_keys[i], &transaction, 0, &spent_tx_outs, SIGHASH_DEFAULT, Some(leaf_hash));
+	let run = |witness: Vec<Vec<u8>>| run_taproot(&with_unlocking(&transaction, vec![], witness), &locking_script, SCRIPT_VERIFY_CONSENSUS);
+	// the first key's signature goes on top, and an empty signature doesn't sign
+	let multisig_witness = |signatures: [Vec<u8>; 3]| {
+	    let [signature0, signature1, signature2] = signatures;
+	    vec![signature2, signature1, signature0, multisig_bytes.clone(), multisig_control.clone()]
+	};
+	assert_eq!(run(multisig_witness([sign(0, &multisig_leaf), vec![], sign(2, &multisig_leaf)])), Ok(()));
+	assert_eq!(run(multisig_witness([vec![], sign(1, &multisig_leaf), sign(2, &multisig_leaf)])), Ok(()));
+	assert_eq!(run(multisig_witness([sign(0, &multisig_leaf), vec![], vec![]])), Err(ScriptError::EvalFalse));
+	// but a signature that doesn't check out is an error
+	assert_eq!(run(multisig_witness([sign(0, &multisig_leaf), vec![], sign(2, &success_leaf)])), Err(ScriptError::SchnorrSig));
+	assert_eq!(run(multisig_witness([sign(0, &multisig_leaf), sign(2, &multisig_leaf), vec![]])), Err(ScriptError::SchnorrSig));
+
+	// the other leaf succeeds whatever else is in the witness, and so does a leaf version that isn't defined yet
+	assert_eq!(run(vec![success_bytes.clone(), success_control.clone()]), Ok(()));
+	assert_eq!(run(vec![vec![0], vec![1; 600], success_bytes.clone(), success_control.clone(), vec![ANNEX_TAG]]), Ok(()));
+	let future_leaf = tap_leaf_hash(0xc2, &[0x6a]);
+	let future_locking_script = pay_to_taproot(&internal_key, Some(&future_leaf));
+	let (_, future_parity) = tweak_public_key(&internal_key, Some(&future_leaf)).unwrap();
+	let future_spend = with_unlocking(&transaction, vec![], vec![vec![0x6a], control_block(0xc2, &internal_key, future_parity, &[])]);
+	assert_eq!(run_taproot(&future_spend, &future_locking_script, SCRIPT_VERIFY_CONSENSUS), Ok(()));
+
+	// the control block has to commit the output key to the script
+	assert_eq!(run(vec![multisig_bytes.clone(), success_control.clone()]), Err(ScriptError::WitnessProgramMismatch));
+	let mut wrong_parity = success_control.clone();
+	wrong_parity[0] ^= 1;
+	assert_eq!(run(vec![success_bytes.clone(), wrong_parity]), Err(ScriptError::WitnessProgramMismatch));
+	assert_eq!(run(vec![success_bytes.clone(), success_control[..64].to_vec()]), Err(ScriptError::TaprootWrongControlSize));
+	assert_eq!(run(vec![success_bytes.clone(), success_control[..32].to_vec()]), Err(ScriptError::TaprootWrongControlSize));
+	assert_eq!(run(vec![success_bytes.clone(), vec![0; TAPROOT_CONTROL_BASE_SIZE + (TAPROOT_CONTROL_MAX_NODE_COUNT + 1) * TAPROOT_CONTROL_NODE_SIZE]]),
+		   Err(ScriptError::TaprootWrongControlSize));
+    }
+
+    /// spend a taproot output whose only leaf is the given tapscript, with the given witness items under the script and its control block
+    fn run_tapscript(script_bytes: &[u8], mut witness: Vec<Vec<u8>>) -> Result<(), ScriptError> {
+	let internal_key = x_only_public_key(&SigningKey::<Secp256k1>::from_bytes(b"abelabelabelabelabelabelabelabel").unwrap());
+	let leaf_hash = tap_leaf_hash(TAPROOT_LEAF_TAPSCRIPT, script_bytes);
+	let (_, parity) = tweak_public_key(&internal_key, Some(&leaf_hash)).unwrap();
+	witness.push(script_bytes.to_vec());
+	witness.push(control_block(TAPROOT_LEAF_TAPSCRIPT, &internal_key, parity, &[]));
+	run_taproot(&with_unlocking(&spending_tx(), vec![], witness), &pay_to_taproot(&internal_key, Some(&leaf_hash)), SCRIPT_VERIFY_CONSENSUS)
+    }
+
+    #[test]
+    fn test_tapscript_rules() {
+	// OpCheckMultiSig is out, and OpCheckSigAdd is only in
+	let multisig = Script::parse_asm("0 0 OP_CHECKMULTISIG").unwrap();
+	assert_eq!(run_scripts(&Script {ops: vec![StackOp::Val(0)]}, &multisig), Ok(()));
+	assert_eq!(run_tapscript(&multisig.to_bytes(), vec![vec![]]), Err(ScriptError::TapscriptCheckMultiSig));
+	let check_sig_add = Script {ops: vec![StackOp::Val(0), StackOp::Val(0), StackOp::Bytes(vec![2; 33].into_boxed_slice()), StackOp::OpCheckSigAdd]};
+	assert_eq!(run_scripts(&Script {ops: vec![]}, &check_sig_add), Err(ScriptError::BadOpcode));
+	assert_eq!(run_tapscript(&check_sig_add.to_bytes(), vec![]), Err(ScriptError::EvalFalse));
+	assert_eq!(Script::from_bytes(&check_sig_add.to_bytes()), Ok(check_sig_add.clone()));
+
+	// OpIf only takes empty or 1
+	let if_script = Script {ops: vec![StackOp::OpIf, StackOp::Val(1), StackOp::OpElse, StackOp::Val(1), StackOp::OpEndIf]};
+	assert_eq!(run_tapscript(&if_script.to_bytes(), vec![vec![1]]), Ok(()));
+	assert_eq!(run_tapscript(&if_script.to_bytes(), vec![vec![]]), Ok(()));
+	assert_eq!(run_tapscript(&if_script.to_bytes(), vec![vec![2]]), Err(ScriptError::TapscriptMinimalIf));
+	assert_eq!(run_tapscript(&if_script.to_bytes(), vec![vec![1, 0]]), Err(ScriptError::TapscriptMinimalIf));
+	assert_eq!(run_scripts(&Script {ops: vec![StackOp::Val(2)]}, &if_script), Ok(()));
+
+	// public keys that aren't 32 bytes are for later, but the empty one is an error
+	let unknown_key = Script {ops: vec![StackOp::Bytes(vec![2; 33].into_boxed_slice()), StackOp::OpCheckSig]};
+	assert_eq!(run_tapscript(&unknown_key.to_bytes(), vec![vec![1; 64]]), Ok(()));
+	assert_eq!(run_tapscript(&unknown_key.to_bytes(), vec![vec![]]), Err(ScriptError::EvalFalse));
+	let empty_key = Script {ops: vec![StackOp::Val(0), StackOp::OpCheckSig]};
+	assert_eq!(run_tapscript(&empty_key.to_bytes(), vec![vec![1; 64]]), Err(ScriptError::PubKeyType));
+
+	// each signature uses up validation weight, which only the size of the witness pays for
+	let check_sigs = |count: usize| {
+	    let mut ops = vec![];
+	    for _ in 0..count {
+		ops.extend(vec![StackOp::OpDup, StackOp::Bytes(vec![2; 33].into_boxed_slice()), StackOp::OpCheckSig, StackOp::OpVerify]);
+	    }
+	    Script {ops}.to_bytes()
+	};
+	assert_eq!(run_tapscript(&check_sigs(5), vec![vec![1]]), Ok(()));
+	assert_eq!(run_tapscript(&check_sigs(10), vec![vec![1]]), Err(ScriptError::TapscriptValidationWeight));
+	assert_eq!(run_tapscript(&check_sigs(10), vec![vec![1; 500]]), Ok(()));
+	// and there is no op count limit
+	let nops = Script {ops: vec![StackOp::OpNop; MAX_OPS_PER_SCRIPT + 1]};
+	assert_eq!(run_scripts(&Script {ops: vec![StackOp::Val(1)]}, &nops), Err(ScriptError::OpCountExceeded));
+	assert_eq!(run_tapscript(&nops.to_bytes(), vec![vec![1]]), Ok(()));
+
+	// OP_SUCCESSx anywhere means success, but not inside a push
+	assert_eq!(run_tapscript(&[0x00, 0x89, 0x6a], vec![]), Ok(()));
+	assert_eq!(run_tapscript(&[0x01, 0x50], vec![]), Ok(()));
+	assert_eq!(run_tapscript(&[0x01, 0xbb, 0x00, 0x87], vec![]), Err(ScriptError::EvalFalse));
+	assert_eq!(run_tapscript(&[0x02, 0x50], vec![]), Err(ScriptError::BadOpcode));
+	assert!((0..=0xff).filter(|opcode| is_op_success(*opcode)).all(|opcode| !OPCODES.iter().any(|(_, byte, _)| *byte == opcode)));
+    }
+
+    #[test]
+    fn test_tapscript_code_separator() {
+	// a tapscript signature commits to the position of the last OpCodeSeparator that was run
+	let private_key = SigningKey::<Secp256k1>::from_bytes(b"adamadamadamadamadamadamadamadam").unwrap();
+	let script = Script {ops: vec![StackOp::OpCodeSeparator, StackOp::Bytes(x_only_public_key(&private_key).to_vec().into_boxed_slice()), StackOp::OpCheckSig]};
+	let script_bytes = script.to_bytes();
+	let internal_key = x_only_public_key(&SigningKey::<Secp256k1>::from_bytes(b"abelabelabelabelabelabelabelabel").unwrap());
+	let leaf_hash = tap_leaf_hash(TAPROOT_LEAF_TAPSCRIPT, &script_bytes);
+	let spent_tx_outs = [TxOut {value: 222, locking_script: pay_to_taproot(&internal_key, Some(&leaf_hash))}];
+	let sign = |codeseparator_pos: u32| {
+	    let sighash = taproot_signature_hash(&spending_tx(), 0, &spent_tx_outs, SIGHASH_DEFAULT, None, Some((&leaf_hash, codeseparator_pos))).unwrap();
+	    schnorr_sign(&private_key, &hash_to_bytes(&sighash), &[0; 32]).to_vec()
+	};
+	assert_eq!(run_tapscript(&script_bytes, vec![sign(0)]), Ok(()));
+	assert_eq!(run_tapscript(&script_bytes, vec![sign(u32::MAX)]), Err(ScriptError::SchnorrSig));
     }
 
+    #[test]
+    fn test_resource_limits() {
+	// 19 pushes of 500 bytes come to 9557 bytes of script, 20 go over MAX_SCRIPT_SIZE
+	let pushes = |count: usize| Script {ops: vec![StackOp::Bytes(vec![1; 500].into_boxed_slice()); count]};
+	assert_eq!(pushes(19).to_bytes().len(), 19 * 503);
+	assert_eq!(run_scripts(&Script {ops: vec![]}, &pushes(19)), Ok(()));
+	assert_eq!(run_scripts(&Script {ops: vec![]}, &pushes(20)), Err(ScriptError::ScriptSize));
+	assert_eq!(run_scripts(&pushes(20), &Script {ops: vec![StackOp::Val(1)]}), Err(ScriptError::ScriptSize));
+	// tapscripts are exempt
+	let mut big_tapscript = pushes(20);
+	big_tapscript.ops.extend(vec![StackOp::Op2Drop; 10]);
+	big_tapscript.ops.push(StackOp::Val(1));
+	assert_eq!(run_tapscript(&big_tapscript.to_bytes(), vec![]), Ok(()));
+
+	// the stack and the alt stack share MAX_STACK_SIZE, wherever the items came from
+	let ones = |count: usize| Script {ops: vec![StackOp::Val(1); count]};
+	assert_eq!(run_scripts(&ones(500), &ones(500)), Ok(()));
+	assert_eq!(run_scripts(&ones(500), &ones(501)), Err(ScriptError::StackSize));
+	let mut alt = ones(999);
+	alt.ops.push(StackOp::OpToAltStack);
+	assert_eq!(run_scripts(&Script {ops: vec![]}, &alt), Ok(()));
+	alt.ops.extend(ones(2).ops);
+	assert_eq!(run_scripts(&Script {ops: vec![]}, &alt), Err(ScriptError::StackSize));
+	// which goes for the witness of a tapscript as well
+	assert_eq!(run_tapscript(&[0x6d], vec![vec![1]; 3]), Ok(()));
+	assert_eq!(run_tapscript(&[0x6d], vec![vec![1]; MAX_STACK_SIZE + 1]), Err(ScriptError::StackSize));
+
+	// signatures that don't even parse just fail to check out
+	let public_key_bytes = SigningKey::<Secp256k1>::from_bytes(b"abelabelabelabelabelabelabelabel").unwrap().verifying_key().to_encoded_point(true).to_bytes();
+	let check_sig = Script {ops: vec![StackOp::Bytes(public_key_bytes), StackOp::OpCheckSig]};
+	let transaction = spending_tx();
+	let context = ExecutionContext::new(&transaction, 0, 0, SCRIPT_VERIFY_NONE);
+	for signature in [vec![], vec![SIGHASH_ALL], vec![0xff; 65], vec![0x30; 73]] {
+	    let unlocking_script = Script {ops: vec![StackOp::Bytes(signature.into_boxed_slice())]};
+	    assert_eq!(execute_scripts(&unlocking_script, &check_sig, &context), Err(ScriptError::EvalFalse));
+	}
+    }
+
+    /// A real mainnet spend (the same as in the transaction tests) has a signature with a high s value. It was fine back then and still is,
+    /// but anyone could have swapped in the low s version, so it doesn't get relayed anymore
+    #[test]
+    fn test_signature_encoding() {
+	use crate::encode::deserialize;
+	let transaction: Transaction = deserialize(&hex::decode("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap()).unwrap();
+	let (signature_bytes, public_key_bytes) = match &transaction.tx_ins[0] {
+	    TxIn::TxPrevious {unlocking_script, ..} => (push_item(&unlocking_script.ops[0]), push_item(&unlocking_script.ops[1])),
+	    TxIn::Coinbase {..} => panic!("expected a regular tx_in"),
+	};
+	let locking_script = pay_to_public_key_hash(&public_key_bytes);
+	assert_eq!(run_tx_in(&transaction, &locking_script, SCRIPT_VERIFY_CONSENSUS), Ok(()));
+	assert_eq!(run_tx_in(&transaction, &locking_script, SCRIPT_VERIFY_CONSENSUS | SCRIPT_VERIFY_LOW_S), Err(ScriptError::SigHighS));
+	let (hash_type, der) = signature_bytes.split_last().unwrap();
+	let low_s = signature_from_der(der).unwrap().normalize_s().unwrap();
+	let mut low_s_bytes = signature_to_der(&low_s);
+	low_s_bytes.push(*hash_type);
+	let malleated = with_unlocking(&transaction, vec![StackOp::Bytes(low_s_bytes.into_boxed_slice()), StackOp::Bytes(public_key_bytes.into_boxed_slice())], vec![]);
+	assert_ne!(malleated.txid(), transaction.txid());
+	assert_eq!(run_tx_in(&malleated, &locking_script, SCRIPT_VERIFY_CONSENSUS | SCRIPT_VERIFY_LOW_S), Ok(()));
+
+	// a signature that is only lax DER, here with a long form length and a leading zero too many, goes until BIP66
+	let private_key = SigningKey::<Secp256k1>::from_bytes(b"abelabelabelabelabelabelabelabel").unwrap();
+	let public_key_bytes = private_key.verifying_key().to_encoded_point(true).to_bytes();
+	let locking_script = pay_to_public_key_hash(&public_key_bytes);
+	let signed_with = |signature_bytes: Vec<u8>| {
+	    with_unlocking(&spending_tx(), vec![StackOp::Bytes(signature_bytes.into_boxed_slice()), StackOp::Bytes(public_key_bytes.clone())], vec![])
+	};
+	let signature_bytes = sign_tx_in(&private_key, &spending_tx(), 0, &locking_script, SIGHASH_ALL);
+	assert_eq!(run_tx_in(&signed_with(signature_bytes.clone()), &locking_script, SCRIPT_VERIFY_CONSENSUS | SCRIPT_VERIFY_LOW_S | SCRIPT_VERIFY_STRICTENC), Ok(()));
+	let r_len = signature_bytes[3] as usize;
+	let lax = [&[0x30, 0x81, signature_bytes[1] + 1, 0x02, r_len as u8 + 1, 0x00], &signature_bytes[4..]].concat();
+	assert_eq!(run_tx_in(&signed_with(lax.clone()), &locking_script, SCRIPT_VERIFY_NONE), Ok(()));
+	assert_eq!(run_tx_in(&signed_with(lax), &locking_script, SCRIPT_VERIFY_CONSENSUS), Err(ScriptError::SigDer));
+
+	// a hash type that doesn't exist signs everything, like SIGHASH_ALL, but it isn't standard
+	let unknown_hash_type = sign_tx_in(&private_key, &spending_tx(), 0, &locking_script, 0x04);
+	assert_eq!(run_tx_in(&signed_with(unknown_hash_type.clone()), &locking_script, SCRIPT_VERIFY_CONSENSUS), Ok(()));
+	assert_eq!(run_tx_in(&signed_with(unknown_hash_type), &locking_script, SCRIPT_VERIFY_STRICTENC), Err(ScriptError::SigHashType));
+
+	// STRICTENC also wants public keys compressed or uncompressed, so a hybrid one is out
+	let mut hybrid = private_key.verifying_key().to_encoded_point(false).as_bytes().to_vec();
+	hybrid[0] = 0x06 | (hybrid[64] & 1);
+	let hybrid_script = Script {ops: vec![StackOp::Bytes(hybrid.into_boxed_slice()), StackOp::OpCheckSig]};
+	let signature = sign_tx_in(&private_key, &spending_tx(), 0, &hybrid_script, SIGHASH_ALL);
+	let spend = with_unlocking(&spending_tx(), vec![StackOp::Bytes(signature.into_boxed_slice())], vec![]);
+	assert_eq!(run_tx_in(&spend, &hybrid_script, SCRIPT_VERIFY_CONSENSUS), Ok(()));
+	assert_eq!(run_tx_in(&spend, &hybrid_script, SCRIPT_VERIFY_CONSENSUS | SCRIPT_VERIFY_STRICTENC), Err(ScriptError::PubKeyType));
+	// and anything else that isn't a key is too, even with the empty signature, for OpCheckSig and OpCheckMultiSig alike
+	let not_a_key = StackOp::Bytes(vec![2; 5].into_boxed_slice());
+	let check_sig_not = Script {ops: vec![not_a_key.clone(), StackOp::OpCheckSig, StackOp::OpNot]};
+	let multisig_not = Script {ops: vec![StackOp::Val(1), not_a_key, StackOp::Val(1), StackOp::OpCheckMultiSig, StackOp::OpNot]};
+	for (unlocking_ops, locking_script) in [(vec![StackOp::Val(0)], check_sig_not), (vec![StackOp::Val(0), StackOp::Val(0)], multisig_not)] {
+	    let spend = with_unlocking(&spending_tx(), unlocking_ops, vec![]);
+	    assert_eq!(run_tx_in(&spend, &locking_script, SCRIPT_VERIFY_CONSENSUS), Ok(()));
+	    assert_eq!(run_tx_in(&spend, &locking_script, SCRIPT_VERIFY_STRICTENC), Err(ScriptError::PubKeyType));
+	}
+
+	// with NULLFAIL, only the empty signature may fail, for OpCheckSig and OpCheckMultiSig alike
+	let other_key = SigningKey::<Secp256k1>::from_bytes(b"evevevevevevevevevevevevevevevev").unwrap();
+	let wrong_signature = StackOp::Bytes(sign_tx_in(&other_key, &spending_tx(), 0, &locking_script, SIGHASH_ALL).into_boxed_slice());
+	let check_sig_not = Script {ops: vec![StackOp::Bytes(public_key_bytes.clone()), StackOp::OpCheckSig, StackOp::OpNot]};
+	let multisig_not = Script {ops: vec![StackOp::Val(1), StackOp::Bytes(public_key_bytes.clone()), StackOp::Val(1), StackOp::OpCheckMultiSig, StackOp::OpNot]};
+	for (unlocking_ops, locking_script) in [(vec![], check_sig_not), (vec![StackOp::Val(0)], multisig_not)] {
+	    let mut wrong = unlocking_ops.clone();
+	    wrong.push(wrong_signature.clone());
+	    let mut empty = unlocking_ops;
+	    empty.push(StackOp::Val(0));
+	    assert_eq!(run_tx_in(&with_unlocking(&spending_tx(), wrong.clone(), vec![]), &locking_script, SCRIPT_VERIFY_CONSENSUS), Ok(()));
+	    assert_eq!(run_tx_in(&with_unlocking(&spending_tx(), wrong, vec![]), &locking_script, SCRIPT_VERIFY_NULLFAIL), Err(ScriptError::SigNullFail));
+	    assert_eq!(run_tx_in(&with_unlocking(&spending_tx(), empty, vec![]), &locking_script, SCRIPT_VERIFY_NULLFAIL), Ok(()));
+	}
+    }
 }
@@ -0,0 +1,613 @@
+use ecdsa::{SigningKey, VerifyingKey, Signature};
+use ecdsa::hazmat::{SignPrimitive, VerifyPrimitive, DigestPrimitive, rfc6979_generate_k};
+use elliptic_curve::ops::Reduce;
+use k256::{Secp256k1, Scalar, SecretKey, PublicKey};
+
+use sha2::{Sha256, Digest};
+
+use crate::{Hash, sha256d, hash_to_bytes};
+use crate::script::{Script, StackOp};
+use crate::transaction::{Transaction, TxIn, TxOut, OutPoint};
+use crate::encode::{Encodable, serialize, encode_bytes};
+use crate::taproot::{tagged_hash, schnorr_sign};
+
+// The hash type is the last byte of a signature, and says which parts of the spending transaction the signature commits to
+pub const SIGHASH_ALL: u8 = 0x01; // all of the tx_ins and all of the tx_outs
+pub const SIGHASH_NONE: u8 = 0x02; // all of the tx_ins, but none of the tx_outs, so anyone can decide where the funds go
+pub const SIGHASH_SINGLE: u8 = 0x03; // all of the tx_ins, but only the tx_out with the same index as the tx_in being signed
+pub const SIGHASH_ANYONECANPAY: u8 = 0x80; // combined with one of the above: only the tx_in being signed, so others can add more tx_ins
+pub const SIGHASH_DEFAULT: u8 = 0x00; // taproot only: the same as SIGHASH_ALL, but the signature can leave off the hash type byte
+
+/// The legacy (pre-segwit) signature hash, i.e. the message that a signature in the given tx_in actually signs.
+/// It is the double SHA256 of a copy of the spending transaction where the unlocking script of the tx_in being signed is
+/// replaced by the script_code (the locking script of the tx_out it spends), all other unlocking scripts are emptied,
+/// and the tx_ins and tx_outs are trimmed down according to the hash type. The hash type itself goes on the end as 4 bytes.
+/// Any OP_CODESEPARATORs left in the script_code are taken out too (the script code starts after the last one that was run).
+/// Since the signature covers where the funds go, it can't be reused to redirect them somewhere else.
+pub fn signature_hash(transaction: &Transaction, input_index: usize, script_code: &Script, hash_type: u8) -> Hash {
+    let base_type = hash_type & 0x1f;
+    let anyone_can_pay = hash_type & SIGHASH_ANYONECANPAY != 0;
+    if input_index >= transaction.tx_ins.len() || (base_type == SIGHASH_SINGLE && input_index >= transaction.tx_outs.len()) {
+	// bitcoin core famously signs the number one here rather than failing, so we have to do the same
+	return Hash::one();
+    }
+
+    let mut tx_ins: Vec<TxIn> = transaction.tx_ins.iter().enumerate().map(|(i, tx_in)| {
+	let mut tx_in = tx_in.clone();
+	if let TxIn::TxPrevious {unlocking_script, sequence, ..} = &mut tx_in {
+	    if i == input_index {
+		*unlocking_script = Script {ops: script_code.ops.iter().filter(|op| **op != StackOp::OpCodeSeparator).cloned().collect()};
+	    } else {
+		*unlocking_script = Script {ops: vec![]};
+		if base_type == SIGHASH_NONE || base_type == SIGHASH_SINGLE {
+		    // the other tx_ins are free to be updated
+		    *sequence = 0;
+		}
+	    }
+	}
+	tx_in
+    }).collect();
+    if anyone_can_pay {
+	tx_ins = vec![tx_ins.swap_remove(input_index)];
+    }
+
+    let tx_outs = match base_type {
+	SIGHASH_NONE => vec![],
+	SIGHASH_SINGLE => {
+	    // the tx_outs before ours are blanked out (but still there, so that ours keeps its index)
+	    let mut tx_outs = vec![TxOut {value: u64::MAX, locking_script: Script {ops: vec![]}}; input_index];
+	    tx_outs.push(transaction.tx_outs[input_index].clone());
+	    tx_outs
+	},
+	_ => transaction.tx_outs.clone(),
+    };
+
+    let tx_copy = Transaction {
+	version: transaction.version,
+	lock_time: transaction.lock_time,
+	tx_ins,
+	tx_outs,
+    };
+    // the witnesses were copied along with the tx_ins, but they never get signed
+    let mut bytes = vec![];
+    tx_copy.encode_without_witness(&mut bytes);
+    (hash_type as u32).encode(&mut bytes);
+    sha256d(&bytes)
+}
+
+/// The signature hash for segwit version 0 (BIP143), which fixes two problems with the legacy one: it commits to the amount
+/// of the tx_out being spent (so an offline signer knows the fee it is signing), and the parts that are the same for every tx_in
+/// (the outpoints, the sequences and the tx_outs) get hashed once up front, rather than the whole transaction getting hashed
+/// again for each tx_in. Those hashes are zero where the hash type leaves them out.
+/// There is no SIGHASH_SINGLE bug here, a missing tx_out just signs a zero hash of the tx_outs
+pub fn segwit_signature_hash(transaction: &Transaction, input_index: usize, script_code: &Script, amount: u64, hash_type: u8) -> Hash {
+    let base_type = hash_type & 0x1f;
+    let anyone_can_pay = hash_type & SIGHASH_ANYONECANPAY != 0;
+    let tx_in = match transaction.tx_ins.get(input_index) {
+	Some(tx_in) => tx_in,
+	None => return Hash::one(), // same as the legacy hash, rather than panicking
+    };
+    let outpoint = |tx_in: &TxIn| tx_in.outpoint().unwrap_or_else(OutPoint::null);
+
+    let mut hash_prevouts = Hash::zero();
+    if !anyone_can_pay {
+	let mut bytes = vec![];
+	for tx_in in &transaction.tx_ins {
+	    outpoint(tx_in).encode(&mut bytes);
+	}
+	hash_prevouts = sha256d(&bytes);
+    }
+    let mut hash_sequence = Hash::zero();
+    if !anyone_can_pay && base_type != SIGHASH_SINGLE && base_type != SIGHASH_NONE {
+	let mut bytes = vec![];
+	for tx_in in &transaction.tx_ins {
+	    tx_in.sequence().encode(&mut bytes);
+	}
+	hash_sequence = sha256d(&bytes);
+    }
+    let hash_outputs = if base_type != SIGHASH_SINGLE && base_type != SIGHASH_NONE {
+	let mut bytes = vec![];
+	for tx_out in &transaction.tx_outs {
+	    tx_out.encode(&mut bytes);
+	}
+	sha256d(&bytes)
+    } else if base_type == SIGHASH_SINGLE && input_index < transaction.tx_outs.len() {
+	sha256d(&serialize(&transaction.tx_outs[input_index]))
+    } else {
+	Hash::zero()
+    };
+
+    let mut bytes = vec![];
+    transaction.version.encode(&mut bytes);
+    hash_prevouts.encode(&mut bytes);
+    hash_sequence.encode(&mut bytes);
+    outpoint(tx_in).encode(&mut bytes);
+    script_code.encode(&mut bytes);
+    amount.encode(&mut bytes);
+    tx_in.sequence().encode(&mut bytes);
+    hash_outputs.encode(&mut bytes);
+    transaction.lock_time.encode(&mut bytes);
+    (hash_type as u32).encode(&mut bytes);
+    sha256d(&bytes)
+}
+
+/// The signature hash for taproot (BIP341), for both key path spends and tapscripts (BIP342). On top of what BIP143 commits to,
+/// it commits to the amounts and locking scripts of all the tx_outs being spent (spent_tx_outs, in the order of the tx_ins),
+/// so a signer can tell exactly what it is spending, even for the tx_ins that aren't its own. The parts that are the same
+/// for every tx_in are hashed once with a single SHA-256, and the whole message is a tagged hash.
+/// For a tapscript, there's the hash of the leaf being run and the position of the last OP_CODESEPARATOR that was run in it (u32::MAX if none was).
+/// The annex is the last witness item if it was tagged as one.
+/// Unlike the other signature hashes, this one can fail: for an unknown hash type, SIGHASH_SINGLE without a matching tx_out,
+/// or when the spent tx_outs don't line up with the tx_ins
+pub fn taproot_signature_hash(transaction: &Transaction, input_index: usize, spent_tx_outs: &[TxOut], hash_type: u8, annex: Option<&[u8]>, tapscript: Option<(&[u8; 32], u32)>) -> Option<Hash> {
+    if !matches!(hash_type, 0x00..=0x03 | 0x81..=0x83) || input_index >= transaction.tx_ins.len() || spent_tx_outs.len() != transaction.tx_ins.len() {
+	return None;
+    }
+    let output_type = if hash_type == SIGHASH_DEFAULT {SIGHASH_ALL} else {hash_type & 0x03};
+    let anyone_can_pay = hash_type & SIGHASH_ANYONECANPAY != 0;
+    let outpoint = |tx_in: &TxIn| tx_in.outpoint().unwrap_or_else(OutPoint::null);
+
+    let mut message = vec![0x00]; // the epoch, so that the whole thing can be changed some day
+    message.push(hash_type);
+    transaction.version.encode(&mut message);
+    transaction.lock_time.encode(&mut message);
+    if !anyone_can_pay {
+	let (mut prevouts, mut amounts, mut locking_scripts, mut sequences) = (vec![], vec![], vec![], vec![]);
+	for (tx_in, spent_tx_out) in transaction.tx_ins.iter().zip(spent_tx_outs) {
+	    outpoint(tx_in).encode(&mut prevouts);
+	    spent_tx_out.value.encode(&mut amounts);
+	    spent_tx_out.locking_script.encode(&mut locking_scripts);
+	    tx_in.sequence().encode(&mut sequences);
+	}
+	for bytes in [prevouts, amounts, locking_scripts, sequences] {
+	    message.extend_from_slice(&Sha256::digest(bytes));
+	}
+    }
+    if output_type == SIGHASH_ALL {
+	let mut bytes = vec![];
+	for tx_out in &transaction.tx_outs {
+	    tx_out.encode(&mut bytes);
+	}
+	message.extend_from_slice(&Sha256::digest(bytes));
+    }
+    // whether this is a tapscript, and whether there is an annex
+    message.push(2 * tapscript.is_some() as u8 + annex.is_some() as u8);
+    if anyone_can_pay {
+	let tx_in = &transaction.tx_ins[input_index];
+	outpoint(tx_in).encode(&mut message);
+	spent_tx_outs[input_index].encode(&mut message);
+	tx_in.sequence().encode(&mut message);
+    } else {
+	(input_index as u32).encode(&mut message);
+    }
+    if let Some(annex) = annex {
+	let mut bytes = vec![];
+	encode_bytes(annex, &mut bytes);
+	message.extend_from_slice(&Sha256::digest(bytes));
+    }
+    if output_type == SIGHASH_SINGLE {
+	let tx_out = transaction.tx_outs.get(input_index)?;
+	message.extend_from_slice(&Sha256::digest(serialize(tx_out)));
+    }
+    if let Some((leaf_hash, codeseparator_pos)) = tapscript {
+	message.extend_from_slice(leaf_hash);
+	message.push(0x00); // the key version
+	codeseparator_pos.encode(&mut message);
+    }
+    Some(Hash::from_little_endian(&tagged_hash("TapSighash", &message)))
+}
+
+/// the signature hash as a scalar, the way ECDSA wants it: the raw digest read as a big endian number
+fn sighash_scalar(sighash: &Hash) -> Scalar {
+    <Scalar as Reduce<k256::U256>>::from_be_bytes_reduced(hash_to_bytes(sighash).into())
+}
+
+/// sign an already computed signature hash (rather than letting ecdsa hash a message for us),
+/// with a deterministic nonce from RFC 6979 and a low s value
+pub fn sign_hash(private_key: &SigningKey<Secp256k1>, sighash: &Hash) -> Signature<Secp256k1> {
+    let secret = SecretKey::from_be_bytes(&private_key.to_bytes()).expect("a signing key is always a valid secret key").to_nonzero_scalar();
+    let z = sighash_scalar(sighash);
+    let k = rfc6979_generate_k::<Secp256k1, <Secp256k1 as DigestPrimitive>::Digest>(&secret, &z, &[]);
+    let (signature, _) = secret.try_sign_prehashed(**k, z).expect("k comes from RFC 6979, so it isn't zero");
+    signature
+}
+
+/// the reverse of sign_hash(): does the signature sign this signature hash with the given public key?
+/// Like bitcoin core, a high s value gets normalized first, since the network has always allowed them (the ecdsa crate doesn't)
+pub fn verify_hash(public_key: &VerifyingKey<Secp256k1>, sighash: &Hash, signature: &Signature<Secp256k1>) -> bool {
+    let signature = signature.normalize_s().unwrap_or(*signature);
+    PublicKey::from(public_key).as_affine().verify_prehashed(sighash_scalar(sighash), &signature).is_ok()
+}
+
+/// The signature the way it goes in a script: DER encoded, i.e. 0x30 len 0x02 len r 0x02 len s, with r and s as big endian numbers
+/// without leading zeros, except for one where the top bit would otherwise make them negative
+pub fn signature_to_der(signature: &Signature<Secp256k1>) -> Vec<u8> {
+    let der_integer = |int: &[u8]| {
+	let int = &int[int.iter().take_while(|byte| **byte == 0).count()..];
+	let padding: &[u8] = if int[0] & 0x80 != 0 { &[0] } else { &[] }; // r and s are never zero, so there is a first byte
+	[&[0x02, (padding.len() + int.len()) as u8], padding, int].concat()
+    };
+    let (r, s) = signature.as_ref().split_at(32);
+    let body = [der_integer(r), der_integer(s)].concat();
+    [vec![0x30, body.len() as u8], body].concat()
+}
+
+/// The reverse of signature_to_der(), but as forgiving as bitcoin core is when it reads a signature, since before BIP66
+/// anything that OpenSSL would take was fine: lengths can be in the long form, r and s can have extra leading zeros,
+/// and whatever comes after s is ignored. None if there still aren't an r and an s in there, or they aren't valid for the curve
+pub fn signature_from_der(der: &[u8]) -> Option<Signature<Secp256k1>> {
+    // read a length, which is either the byte itself, or 0x80 plus how many bytes of length come next
+    fn read_len(der: &mut &[u8]) -> Option<usize> {
+	let (first, rest) = der.split_first()?;
+	*der = rest;
+	if first & 0x80 == 0 {
+	    return Some(*first as usize);
+	}
+	let len_size = (first & 0x7f) as usize;
+	if len_size > 4 || der.len() < len_size {
+	    return None;
+	}
+	let (len_bytes, rest) = der.split_at(len_size);
+	*der = rest;
+	Some(len_bytes.iter().fold(0, |len, byte| (len << 8) | *byte as usize))
+    }
+    // read an integer into the 32 bytes of a field element
+    fn read_integer(der: &mut &[u8]) -> Option<[u8; 32]> {
+	let (tag, rest) = der.split_first()?;
+	*der = rest;
+	let len = read_len(der)?;
+	if *tag != 0x02 || der.len() < len {
+	    return None;
+	}
+	let (int, rest) = der.split_at(len);
+	*der = rest;
+	let int = &int[int.iter().take_while(|byte| **byte == 0).count()..];
+	if int.len() > 32 {
+	    return None;
+	}
+	let mut bytes = [0; 32];
+	bytes[32 - int.len()..].copy_from_slice(int);
+	Some(bytes)
+    }
+    let mut der = der;
+    let (tag, rest) = der.split_first()?;
+    der = rest;
+    if *tag != 0x30 {
+	return None;
+    }
+    read_len(&mut der)?; // bitcoin core doesn't hold the signature to this length either
+    let r = read_integer(&mut der)?;
+    let s = read_integer(&mut der)?;
+    Signature::from_scalars(r, s).ok()
+}
+
+/// Sign the given tx_in of the transaction, which spends a tx_out locked by script_code.
+/// Returns the signature as it goes in an unlocking script, i.e. DER encoded with the hash type byte on the end
+pub fn sign_tx_in(private_key: &SigningKey<Secp256k1>, transaction: &Transaction, input_index: usize, script_code: &Script, hash_type: u8) -> Vec<u8> {
+    let sighash = signature_hash(transaction, input_index, script_code, hash_type);
+    let mut signature_bytes = signature_to_der(&sign_hash(private_key, &sighash));
+    signature_bytes.push(hash_type);
+    signature_bytes
+}
+
+/// Sign the given tx_in of the transaction, which spends a segwit version 0 tx_out of the given amount.
+/// For pay to witness public key hash the script_code is the pay to public key hash of the same key, and for pay to witness script hash
+/// it is the witness script. The result goes in the witness, DER encoded with the hash type byte on the end
+pub fn sign_segwit_tx_in(private_key: &SigningKey<Secp256k1>, transaction: &Transaction, input_index: usize, script_code: &Script, amount: u64, hash_type: u8) -> Vec<u8> {
+    let sighash = segwit_signature_hash(transaction, input_index, script_code, amount, hash_type);
+    let mut signature_bytes = signature_to_der(&sign_hash(private_key, &sighash));
+    signature_bytes.push(hash_type);
+    signature_bytes
+}
+
+/// Sign the given tx_in of the transaction, which spends a taproot tx_out, with a BIP340 Schnorr signature. For a key path spend the private key
+/// is the tweaked one, and for a tapscript leaf_hash is the hash of the leaf that the signature is checked in (which mustn't have run an OP_CODESEPARATOR).
+/// Like sign_hash(), the nonce is deterministic. The hash type byte only goes on the end if it isn't SIGHASH_DEFAULT.
+/// Panics if the transaction can't be signed with this hash type, see taproot_signature_hash()
+pub fn sign_taproot_tx_in(private_key: &SigningKey<Secp256k1>, transaction: &Transaction, input_index: usize, spent_tx_outs: &[TxOut], hash_type: u8, leaf_hash: Option<&[u8; 32]>) -> Vec<u8> {
+    let sighash = taproot_signature_hash(transaction, input_index, spent_tx_outs, hash_type, None, leaf_hash.map(|leaf_hash| (leaf_hash, u32::MAX))).expect("the transaction can be signed with this hash type");
+    let mut signature_bytes = schnorr_sign(private_key, &hash_to_bytes(&sighash), &[0; 32]).to_vec();
+    if hash_type != SIGHASH_DEFAULT {
+	signature_bytes.push(hash_type);
+    }
+    signature_bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::{StackOp, pay_to_public_key_hash};
+
+    fn key_from_seed(seed: &str) -> SigningKey<Secp256k1> {
+	SigningKey::<Secp256k1>::from_bytes(seed.as_bytes()).unwrap()
+    }
+
+    /// a transaction with three tx_ins and three tx_outs
+    fn three_by_three() -> Transaction {
+	let tx_ins = (0..3).map(|i| TxIn::TxPrevious {
+	    tx_hash: Hash::from(100 + i),
+	    tx_out_index: i as u32,
+	    unlocking_script: Script {ops: vec![StackOp::Val(i)]},
+	    sequence: 0xffffffff,
+	    witness: vec![],
+	}).collect();
+	let tx_outs = (0..3).map(|i| TxOut {
+	    value: 1000 * (i + 1),
+	    locking_script: Script {ops: vec![StackOp::OpDup]},
+	}).collect();
+	Transaction {version: 1, lock_time: 0, tx_ins, tx_outs}
+    }
+
+    #[test]
+    fn test_sign_and_verify_hash() {
+	let private_key = key_from_seed("adamadamadamadamadamadamadamadam");
+	let sighash = sha256d(b"something to sign");
+	let signature = sign_hash(&private_key, &sighash);
+	assert!(verify_hash(&private_key.verifying_key(), &sighash, &signature));
+	assert!(!verify_hash(&private_key.verifying_key(), &sha256d(b"something else"), &signature));
+	let other_key = key_from_seed("evevevevevevevevevevevevevevevev");
+	assert!(!verify_hash(&other_key.verifying_key(), &sighash, &signature));
+    }
+
+    #[test]
+    fn test_der_encoding() {
+	let private_key = key_from_seed("adamadamadamadamadamadamadamadam");
+	let signature = sign_hash(&private_key, &sha256d(b"something to sign"));
+	let der = signature_to_der(&signature);
+	assert_eq!(der[0], 0x30);
+	assert_eq!(der[1] as usize, der.len() - 2);
+	assert_eq!(signature_from_der(&der), Some(signature));
+	// small numbers lose their leading zeros, and big ones get one to stay positive
+	let mut one = [0; 32];
+	one[31] = 1;
+	let small = Signature::<Secp256k1>::from_scalars(one, [0x80; 32]).unwrap();
+	let mut expected = vec![0x30, 38, 0x02, 0x01, 0x01, 0x02, 0x21, 0x00];
+	expected.extend_from_slice(&[0x80; 32]);
+	assert_eq!(signature_to_der(&small), expected);
+	assert_eq!(signature_from_der(&expected), Some(small));
+
+	// long form lengths and extra leading zeros still read as the same signature
+	let (r, s) = signature.as_ref().split_at(32);
+	let lax = [&[0x30, 0x81, 0x47, 0x02, 0x21, 0x00][..], r, &[0x02, 0x81, 0x21, 0x00], s].concat();
+	assert_eq!(signature_from_der(&lax), Some(signature));
+	// but not when something is missing, or r or s is zero
+	assert_eq!(signature_from_der(&[]), None);
+	assert_eq!(signature_from_der(&der[..der.len() - 1]), None);
+	assert_eq!(signature_from_der(&der[1..]), None);
+	assert_eq!(signature_from_der(&[0x30, 0x06, 0x02, 0x01, 0x00, 0x02, 0x01, 0x01]), None);
+    }
+
+    /// which changes to the spending transaction each hash type notices
+    #[test]
+    fn test_hash_types_commit_to() {
+	let script_code = Script {ops: vec![StackOp::OpCheckSig]};
+	let transaction = three_by_three();
+	let sighash = |transaction: &Transaction, hash_type: u8| signature_hash(transaction, 1, &script_code, hash_type);
+
+	let mut other_tx_out_changed = transaction.clone();
+	other_tx_out_changed.tx_outs[2].value += 1;
+	let mut own_tx_out_changed = transaction.clone();
+	own_tx_out_changed.tx_outs[1].value += 1;
+	let mut other_tx_in_removed = transaction.clone();
+	other_tx_in_removed.tx_ins.remove(0);
+	other_tx_in_removed.tx_ins.insert(0, transaction.tx_ins[2].clone());
+	let mut other_sequence_changed = transaction.clone();
+	if let TxIn::TxPrevious {sequence, ..} = &mut other_sequence_changed.tx_ins[0] {
+	    *sequence = 5;
+	}
+	// the unlocking scripts never matter, since the signatures live in them
+	let mut unlocking_script_changed = transaction.clone();
+	if let TxIn::TxPrevious {unlocking_script, ..} = &mut unlocking_script_changed.tx_ins[0] {
+	    unlocking_script.ops.push(StackOp::OpDup);
+	}
+
+	for hash_type in [SIGHASH_ALL, SIGHASH_NONE, SIGHASH_SINGLE] {
+	    assert_eq!(sighash(&transaction, hash_type), sighash(&unlocking_script_changed, hash_type));
+	    assert_ne!(sighash(&transaction, hash_type), sighash(&transaction, hash_type | SIGHASH_ANYONECANPAY));
+	}
+	assert_ne!(sighash(&transaction, SIGHASH_ALL), sighash(&other_tx_out_changed, SIGHASH_ALL));
+	assert_ne!(sighash(&transaction, SIGHASH_ALL), sighash(&other_sequence_changed, SIGHASH_ALL));
+	assert_eq!(sighash(&transaction, SIGHASH_NONE), sighash(&own_tx_out_changed, SIGHASH_NONE));
+	assert_eq!(sighash(&transaction, SIGHASH_NONE), sighash(&other_sequence_changed, SIGHASH_NONE));
+	assert_eq!(sighash(&transaction, SIGHASH_SINGLE), sighash(&other_tx_out_changed, SIGHASH_SINGLE));
+	assert_ne!(sighash(&transaction, SIGHASH_SINGLE), sighash(&own_tx_out_changed, SIGHASH_SINGLE));
+	assert_ne!(sighash(&transaction, SIGHASH_ALL), sighash(&other_tx_in_removed, SIGHASH_ALL));
+	assert_eq!(sighash(&transaction, SIGHASH_ALL | SIGHASH_ANYONECANPAY), sighash(&other_tx_in_removed, SIGHASH_ALL | SIGHASH_ANYONECANPAY));
+
+	// SIGHASH_SINGLE without a matching tx_out signs the number one
+	let mut fewer_tx_outs = transaction.clone();
+	fewer_tx_outs.tx_outs.truncate(1);
+	assert_eq!(sighash(&fewer_tx_outs, SIGHASH_SINGLE), Hash::one());
+    }
+
+    /// A real mainnet spend: the signature in its unlocking script has to verify against our signature hash.
+    /// As a bonus, its s value is high, which the network allowed back then
+    #[test]
+    fn test_mainnet_signature_hash() {
+	use crate::encode::deserialize;
+	// the same transaction as in the transaction tests, spending a pay to public key hash output
+	let tx_bytes = hex::decode("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap();
+	let transaction: Transaction = deserialize(&tx_bytes).unwrap();
+	let (signature_bytes, public_key_bytes) = match &transaction.tx_ins[0] {
+	    TxIn::TxPrevious {unlocking_script, ..} => match unlocking_script.ops.as_slice() {
+		[StackOp::Bytes(signature_bytes), StackOp::Bytes(public_key_bytes)] => (signature_bytes.clone(), public_key_bytes.clone()),
+		_ => panic!("expected a signature and a public key"),
+	    },
+	    _ => panic!("expected a regular tx_in"),
+	};
+	// the spent tx_out is locked to the hash 160 of this public key
+	let script_code = Script::parse_asm("OP_DUP OP_HASH160 95aef692617d767d1edc5c8a594d30f6eecd14c2 OP_EQUALVERIFY OP_CHECKSIG").unwrap();
+	assert_eq!(pay_to_public_key_hash(&public_key_bytes), script_code);
+	let (hash_type, der_signature) = signature_bytes.split_last().unwrap();
+	assert_eq!(*hash_type, SIGHASH_ALL);
+	let signature = signature_from_der(der_signature).unwrap();
+	assert_eq!(signature_to_der(&signature), der_signature);
+	let public_key = VerifyingKey::<Secp256k1>::from_sec1_bytes(&public_key_bytes).unwrap();
+	assert!(verify_hash(&public_key, &signature_hash(&transaction, 0, &script_code, SIGHASH_ALL), &signature));
+
+	// sending the funds anywhere else breaks the signature
+	let mut redirected = transaction.clone();
+	redirected.tx_outs[0].locking_script = Script::parse_asm("OP_DUP OP_HASH160 0000000000000000000000000000000000000000 OP_EQUALVERIFY OP_CHECKSIG").unwrap();
+	assert!(!verify_hash(&public_key, &signature_hash(&redirected, 0, &script_code, SIGHASH_ALL), &signature));
+    }
+    /// check segwit_signature_hash() against the examples in BIP143 itself
+    #[test]
+    fn test_bip143_vectors() {
+	use crate::encode::deserialize;
+	use crate::hash_to_hex;
+	let sighash = |tx_hex: &str, input_index: usize, script_code: &Script, amount: u64, hash_type: u8| {
+	    let transaction: Transaction = deserialize(&hex::decode(tx_hex).unwrap()).unwrap();
+	    segwit_signature_hash(&transaction, input_index, script_code, amount, hash_type)
+	};
+	// the BIP shows the raw digests
+	let check = |tx_hex: &str, input_index: usize, script_code: &Script, amount: u64, hash_type: u8, expected: &str| {
+	    assert_eq!(hex::encode(hash_to_bytes(&sighash(tx_hex, input_index, script_code, amount, hash_type))), expected);
+	};
+	// native pay to witness public key hash: the script code is the pay to public key hash of the same key
+	let public_key_bytes = hex::decode("025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357").unwrap();
+	check("0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000",
+	      1, &pay_to_public_key_hash(&public_key_bytes), 600_000_000, SIGHASH_ALL, "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670");
+	// pay to witness public key hash nested in pay to script hash
+	let public_key_bytes = hex::decode("03ad1d8e89212f0b92c74d23bb710c00662ad1470198ac48c43f7d6f93a2a26873").unwrap();
+	check("0100000001db6b1b20aa0fd7b23880be2ecbd4a98130974cf4748fb66092ac4d3ceb1a54770100000000feffffff02b8b4eb0b000000001976a914a457b684d7f0d539a46a45bbc043f35b59d0d96388ac0008af2f000000001976a914fd270b1ee6abcaea97fea7ad0402e8bd8ad6d77c88ac92040000",
+	      0, &pay_to_public_key_hash(&public_key_bytes), 1_000_000_000, SIGHASH_ALL, "64f3b0f4dd2bb3aa1ce8566d220cc74dda9df97d8490cc81d89d735c92e59fb6");
+	// a 6 of 6 multisig witness script, nested in pay to script hash
+	let witness_script = Script::from_bytes(&hex::decode("56210307b8ae49ac90a048e9b53357a2354b3334e9c8bee813ecb98e99a7e07e8c3ba32103b28f0c28bfab54554ae8c658ac5c3e0ce6e79ad336331f78c428dd43eea8449b21034b8113d703413d57761b8b9781957b8c0ac1dfe69f492580ca4195f50376ba4a21033400f6afecb833092a9a21cfdf1ed1376e58c5d1f47de74683123987e967a8f42103a6d48b1131e94ba04d9737d61acdaa1322008af9602b3b14862c07a1789aac162102d8b661b0b3302ee2f162b09e07a55ad5dfbe673a9f01d9f0c19617681024306b56ae").unwrap()).unwrap();
+	check("010000000136641869ca081e70f394c6948e8af409e18b619df2ed74aa106c1ca29787b96e0100000000ffffffff0200e9a435000000001976a914389ffce9cd9ae88dcc0631e88a821ffdbe9bfe2688acc0832f05000000001976a9147480a33f950689af511e6e84c138dbbd3c3ee41588ac00000000",
+	      0, &witness_script, 987654321, SIGHASH_ALL, "185c0be5263dce5b4bb50a047973c1b6272bfbd0103a89444597dc40b248ee7c");
+
+	// every hash type, from bitcoin core. These ones are shown byte reversed, like txids
+	let tx_hex = "0200000001cf309ee0839b8aaa3fbc84f8bd32e9c6357e99b49bf6a3af90308c68e762f1d70100000000feffffff0288528c61000000001600146e8d9e07c543a309dcdeba8b50a14a991a658c5be0aebb0000000000160014698d8419804a5d5994704d47947889ff7620c004db000000";
+	let script_code = Script::from_bytes(&hex::decode("76a91462744660c6b5133ddeaacbc57d2dc2d7b14d0b0688ac").unwrap()).unwrap();
+	let vectors = [
+	    (SIGHASH_ALL, "0a1bc2758dbb5b3a56646f8cafbf63f410cc62b77a482f8b87552683300a7711"),
+	    (SIGHASH_NONE, "3e275ac8b084f79f756dcd535bffb615cc94a685eefa244d9031eaf22e4cec12"),
+	    (SIGHASH_SINGLE, "191a08165ffacc3ea55753b225f323c35fd00d9cc0268081a4a501921fc6ec14"),
+	    (SIGHASH_ALL | SIGHASH_ANYONECANPAY, "4b6b612530f94470bbbdef18f57f2990d56b239f41b8728b9a49dc8121de4559"),
+	    (SIGHASH_NONE | SIGHASH_ANYONECANPAY, "a7e916d3acd4bb97a21e6793828279aeab02162adf8099ea4f309af81f3d5adb"),
+	    (SIGHASH_SINGLE | SIGHASH_ANYONECANPAY, "d9276e2a48648ddb53a4aaa58314fc2b8067c13013e1913ffb67e0988ce82c78"),
+	];
+	for (hash_type, expected) in vectors {
+	    assert_eq!(hash_to_hex(&sighash(tx_hex, 0, &script_code, 1648888940, hash_type)), expected);
+	}
+    }
+
+    /// A real mainnet transaction with a pay to public key hash tx_in and two pay to witness public key hash tx_ins nested in pay to script hash.
+    /// The signatures in the witnesses have to verify against the segwit signature hash, which needs the amounts from the spent transactions
+    #[test]
+    fn test_mainnet_segwit_signature_hash() {
+	use crate::encode::deserialize;
+	use crate::script::{pay_to_script_hash, pay_to_witness_public_key_hash};
+	let transaction: Transaction = deserialize(&hex::decode("020000000001031cfbc8f54fbfa4a33a30068841371f80dbfe166211242213188428f437445c91000000006a47304402206fbcec8d2d2e740d824d3d36cc345b37d9f65d665a99f5bd5c9e8d42270a03a8022013959632492332200c2908459547bf8dbf97c65ab1a28dec377d6f1d41d3d63e012103d7279dfb90ce17fe139ba60a7c41ddf605b25e1c07a4ddcb9dfef4e7d6710f48feffffff476222484f5e35b3f0e43f65fc76e21d8be7818dd6a989c160b1e5039b7835fc00000000171600140914414d3c94af70ac7e25407b0689e0baa10c77feffffffa83d954a62568bbc99cc644c62eb7383d7c2a2563041a0aeb891a6a4055895570000000017160014795d04cc2d4f31480d9a3710993fbd80d04301dffeffffff06fef72f000000000017a91476fd7035cd26f1a32a5ab979e056713aac25796887a5000f00000000001976a914b8332d502a529571c6af4be66399cd33379071c588ac3fda0500000000001976a914fc1d692f8de10ae33295f090bea5fe49527d975c88ac522e1b00000000001976a914808406b54d1044c429ac54c0e189b0d8061667e088ac6eb68501000000001976a914dfab6085f3a8fb3e6710206a5a959313c5618f4d88acbba20000000000001976a914eb3026552d7e3f3073457d0bee5d4757de48160d88ac0002483045022100bee24b63212939d33d513e767bc79300051f7a0d433c3fcf1e0e3bf03b9eb1d70220588dc45a9ce3a939103b4459ce47500b64e23ab118dfc03c9caa7d6bfc32b9c601210354fd80328da0f9ae6eef2b3a81f74f9a6f66761fadf96f1d1d22b1fd6845876402483045022100e29c7e3a5efc10da6269e5fc20b6a1cb8beb92130cc52c67e46ef40aaa5cac5f0220644dd1b049727d991aece98a105563416e10a5ac4221abac7d16931842d5c322012103960b87412d6e169f30e12106bdf70122aabb9eb61f455518322a18b920a4dfa887d30700").unwrap()).unwrap();
+	let spent_transactions: Vec<Transaction> = ["020000000001040aacd2c49f5f3c0968cfa8caf9d5761436d95385252e3abb4de8f5dcf8a582f20000000017160014bcadb2baea98af0d9a902e53a7e9adff43b191e9feffffff96cd3c93cac3db114aafe753122bd7d1afa5aa4155ae04b3256344ecca69d72001000000171600141d9984579ceb5c67ebfbfb47124f056662fe7adbfeffffffc878dd74d3a44072eae6178bb94b9253177db1a5aaa6d068eb0e4db7631762e20000000017160014df2a48cdc53dae1aba7aa71cb1f9de089d75aac3feffffffe49f99275bc8363f5f593f4eec371c51f62c34ff11cc6d8d778787d340d6896c0100000017160014229b3b297a0587e03375ab4174ef56eeb0968735feffffff03360d0f00000000001976a9149f44b06f6ee92ddbc4686f71afe528c09727a5c788ac24281b00000000001976a9140277b4f68ff20307a2a9f9b4487a38b501eb955888ac227c0000000000001976a9148020cd422f55eef8747a9d418f5441030f7c9c7788ac0247304402204aa3bd9682f9a8e101505f6358aacd1749ecf53a62b8370b97d59243b3d6984f02200384ad449870b0e6e89c92505880411285ecd41cf11e7439b973f13bad97e53901210205b392ffcb83124b1c7ce6dd594688198ef600d34500a7f3552d67947bbe392802473044022033dfd8d190a4ae36b9f60999b217c775b96eb10dee3a1ff50fb6a75325719106022005872e4e36d194e49ced2ebcf8bb9d843d842e7b7e0eb042f4028396088d292f012103c9d7cbf369410b090480de2aa15c6c73d91b9ffa7d88b90724614b70be41e98e0247304402207d952de9e59e4684efed069797e3e2d993e9f98ec8a9ccd599de43005fe3f713022076d190cc93d9513fc061b1ba565afac574e02027c9efbfa1d7b71ab8dbb21e0501210313ad44bc030cc6cb111798c2bf3d2139418d751c1e79ec4e837ce360cc03b97a024730440220029e75edb5e9413eb98d684d62a077b17fa5b7cc19349c1e8cc6c4733b7b7452022048d4b9cae594f03741029ff841e35996ef233701c1ea9aa55c301362ea2e2f68012103590657108a72feb8dc1dec022cf6a230bb23dc7aaa52f4032384853b9f8388baf9d20700", "0200000000010166c3d39490dc827a2594c7b17b7d37445e1f4b372179649cd2ce4475e3641bbb0100000017160014e69aa750e9bff1aca1e32e57328b641b611fc817fdffffff01e87c5d010000000017a914f3890da1b99e44cd3d52f7bcea6a1351658ea7be87024830450221009eb97597953dc288de30060ba02d4e91b2bde1af2ecf679c7f5ab5989549aa8002202a98f8c3bd1a5a31c0d72950dd6e2e3870c6c5819a6c3db740e91ebbbc5ef4800121023f3d3b8e74b807e32217dea2c75c8d0bd46b8665b3a2d9b3cb310959de52a09bc9d20700", "01000000027a1120a30cef95422638e8dab9dedf720ec614b1b21e451a4957a5969afb869d000000006a47304402200ecc318a829a6cad4aa9db152adbf09b0cd2de36f47b53f5dade3bc7ef086ca702205722cda7404edd6012eedd79b2d6f24c0a0c657df1a442d0a2166614fb164a4701210372f4b97b34e9c408741cd1fc97bcc7ffdda6941213ccfde1cb4075c0f17aab06ffffffffc23b43e5a18e5a66087c0d5e64d58e8e21fcf83ce3f5e4f7ecb902b0e80a7fb6010000006b483045022100f10076a0ea4b4cf8816ed27a1065883efca230933bf2ff81d5db6258691ff75202206b001ef87624e76244377f57f0c84bc5127d0dd3f6e0ef28b276f176badb223a01210309a3a61776afd39de4ed29b622cd399d99ecd942909c36a8696cfd22fc5b5a1affffffff0200127a000000000017a914f895e1dd9b29cb228e9b06a15204e3b57feaf7cc8769311d09000000001976a9144d00da12aaa51849d2583ae64525d4a06cd70fde88ac00000000"].iter()
+	    .map(|tx_hex| deserialize(&hex::decode(tx_hex).unwrap()).unwrap())
+	    .collect();
+	assert!(transaction.has_witness());
+	assert!(transaction.tx_ins[0].witness().is_empty());
+	for input_index in 1..3 {
+	    let outpoint = transaction.tx_ins[input_index].outpoint().unwrap();
+	    let spent_tx_out = &spent_transactions.iter().find(|spent| spent.txid() == outpoint.tx_hash).unwrap().tx_outs[outpoint.tx_out_index as usize];
+	    let (signature_bytes, public_key_bytes) = match transaction.tx_ins[input_index].witness() {
+		[signature_bytes, public_key_bytes] => (signature_bytes, public_key_bytes),
+		_ => panic!("expected a signature and a public key"),
+	    };
+	    // the unlocking script only pushes the redeem script, which is the witness program
+	    let redeem_script = pay_to_witness_public_key_hash(public_key_bytes);
+	    assert_eq!(spent_tx_out.locking_script, pay_to_script_hash(&redeem_script));
+	    if let TxIn::TxPrevious {unlocking_script, ..} = &transaction.tx_ins[input_index] {
+		assert_eq!(unlocking_script.ops, vec![StackOp::Bytes(redeem_script.to_bytes().into_boxed_slice())]);
+	    }
+
+	    let (hash_type, der_signature) = signature_bytes.split_last().unwrap();
+	    assert_eq!(*hash_type, SIGHASH_ALL);
+	    let signature = signature_from_der(der_signature).unwrap();
+	    assert_eq!(signature_to_der(&signature), der_signature);
+	    let public_key = VerifyingKey::<Secp256k1>::from_sec1_bytes(public_key_bytes).unwrap();
+	    let script_code = pay_to_public_key_hash(public_key_bytes);
+	    assert!(verify_hash(&public_key, &segwit_signature_hash(&transaction, input_index, &script_code, spent_tx_out.value, SIGHASH_ALL), &signature));
+	    // the amount is signed, so a signer can't be lied to about the fee
+	    assert!(!verify_hash(&public_key, &segwit_signature_hash(&transaction, input_index, &script_code, spent_tx_out.value + 1, SIGHASH_ALL), &signature));
+	}
+	// the witnesses aren't part of the legacy signature hash or the txid
+	let mut witness_stripped = transaction.clone();
+	witness_stripped.tx_ins[1].witness_mut().clear();
+	let script_code = Script {ops: vec![StackOp::OpCheckSig]};
+	assert_eq!(signature_hash(&transaction, 0, &script_code, SIGHASH_ALL), signature_hash(&witness_stripped, 0, &script_code, SIGHASH_ALL));
+	assert_eq!(transaction.txid(), witness_stripped.txid());
+	assert_ne!(transaction.wtxid(), witness_stripped.wtxid());
+    }
+    /// Taproot signature hashes from bitcoin core's test framework: the transaction, the tx_outs it spends, the tx_in, the hash type,
+    /// and then the annex and the leaf script for some of them. They are shown as the raw digests
+    #[test]
+    fn test_taproot_signature_hash() {
+	use crate::encode::deserialize;
+	use crate::taproot::{tap_leaf_hash, TAPROOT_LEAF_TAPSCRIPT};
+	let check = |tx_hex: &str, spent_hex: &str, input_index: usize, hash_type: u8, annex_hex: Option<&str>, script_hex: Option<&str>, expected: &str| {
+	    let transaction: Transaction = deserialize(&hex::decode(tx_hex).unwrap()).unwrap();
+	    let spent_tx_outs: Vec<TxOut> = deserialize(&hex::decode(spent_hex).unwrap()).unwrap();
+	    let annex = annex_hex.map(|annex_hex| hex::decode(annex_hex).unwrap());
+	    let leaf_hash = script_hex.map(|script_hex| tap_leaf_hash(TAPROOT_LEAF_TAPSCRIPT, &hex::decode(script_hex).unwrap()));
+	    let sighash = taproot_signature_hash(&transaction, input_index, &spent_tx_outs, hash_type, annex.as_deref(), leaf_hash.as_ref().map(|leaf_hash| (leaf_hash, u32::MAX))).unwrap();
+	    assert_eq!(hex::encode(hash_to_bytes(&sighash)), expected);
+	};
+	// key path spends, with every hash type
+	check("020000000164eb050a5e3da0c2a65e4786f26d753b7bc69691fabccafb11f7acef36641f1846010000003101b2b404392a22000000000017a9147f2bde86fe78bf68a0544a4f290e12f0b7e0a08c87580200000000000017a91425d11723074ecfb96a0a83c3956bfaf362ae0c908758020000000000001600147e20f938993641de67bb0cdd71682aa34c4d29ad5802000000000000160014c64984dc8761acfa99418bd6bedc79b9287d652d72000000",
+	      "01365724000000000023542156b39dab4f8f3508e0432cfb41fab110170acaa2d4c42539cb90a4dc7c093bc500",
+	      0, SIGHASH_DEFAULT, None, None, "33ca0ebfb4a945eeee9569fc0f5040221275f88690b7f8592ada88ce3bdf6703");
+	check("0200000002fff49be59befe7566050737910f6ccdc5e749c7f8860ddc140386463d88c5ad0f3000000002cf68eb4a3d67f9d4c079249f7e4f27b8854815cb1ed13842d4fbf395f9e217fd605ee24090100000065235d9203f458520000000000160014b6d48333bb13b4c644e57c43a9a26df3a44b785e58020000000000001976a914eea9461a9e1e3f765d3af3e726162e0229fe3eb688ac58020000000000001976a9143a8869c9f2b5ea1d4ff3aeeb6a8fb2fffb1ad5fe88ac0ad7125c",
+	      "02591f220000000000225120f25ad35583ea31998d968871d7de1abd2a52f6fe4178b54ea158274806ff4ece48fb310000000000225120f25ad35583ea31998d968871d7de1abd2a52f6fe4178b54ea158274806ff4ece",
+	      1, SIGHASH_ALL, None, None, "626ab955d58c9a8a600a0c580549d06dc7da4e802eb2a531f62a588e430967a8");
+	check("0200000001350005f65aa830ced2079df348e2d8c2bdb4f10e2dde6a161d8a07b40d1ad87dae000000001611d0d603d9dc0e000000000017a914459b6d7d6bbb4d8837b4bf7e9a4556f952da2f5c8758020000000000001976a9141dd70e1299ffc2d5b51f6f87de9dfe9398c33cbb88ac58020000000000001976a9141dd70e1299ffc2d5b51f6f87de9dfe9398c33cbb88aca71c1f4f",
+	      "01c4811000000000002251201bf9297d0a2968ae6693aadd0fa514717afefd218087a239afb7418e2d22e65c",
+	      0, SIGHASH_ALL | SIGHASH_ANYONECANPAY, None, None, "dfa9437f9c9a1d1f9af271f79f2f5482f287cdb0d2e03fa92c8a9b216cc6061c");
+	check("020000000185bed1a6da2bffbd60ec681a1bfb71c5111d6395b99b3f8b2bf90167111bcb18f5010000007c83ace802ded24a00000000001600142c4698f9f7a773866879755aa78c516fb332af8e5802000000000000160014d38639dfbac4259323b98a472405db0c461b31fa61073747",
+	      "0144c84d0000000000225120e3f2107989c88e67296ab2faca930efa2e3a5bd3ff0904835a11c9e807458621",
+	      0, SIGHASH_NONE, None, None, "3129de36a5d05fff97ffca31eb75fcccbbbc27b3147a7a36a9e4b45d8b625067");
+	check("eb93dbb901028c8515589dac980b6e7f8e4088b77ed866ca0d6d210a7218b6fd0f6b22dd6d7300000000eb4740a9047efc0e0000000000160014913da2128d8fcf292b3691db0e187414aa1783825802000000000000160014913da2128d8fcf292b3691db0e187414aa178382580200000000000017a9143dd27f01c6f7ef9bb9159937b17f17065ed01a0c875802000000000000160014d7630e19df70ada9905ede1722b800c0005f246641000000",
+	      "013fed110000000000225120eb536ae8c33580290630fc495046e998086a64f8f33b93b07967d9029b265c55",
+	      0, SIGHASH_NONE | SIGHASH_ANYONECANPAY, None, None, "2441e8b0e063a2083ee790f14f2045022f07258ddde5ee01de543c9e789d80ae");
+	check("02000000017836b409a5fed32211407e44b971591f2032053f14701fb5b3a30c0ff382f2cc9c0100000061ac55f60288fb5600000000001976a9144ea02f6f182b082fb6ce47e36bbde390b6a41b5088ac58020000000000001976a9144ea02f6f182b082fb6ce47e36bbde390b6a41b5088ace4000000",
+	      "01efa558000000000022512007071ea3dc7e331b0687d0193d1e6d6ed10e645ef36f10ef8831d5e522ac9e80",
+	      0, SIGHASH_SINGLE, None, None, "30239345177cadd0e3ea413d49803580abb6cb27971b481b7788a78d35117a88");
+	check("0100000001aa6deae89d5e0aaca58714fc76ef6f3c8284224888089232d4e663843ed3ab3eae010000008b6657a60450cb4c0000000000160014a3d42b5413ef0c0701c4702f3cd7d4df222c147058020000000000001976a91430b4ed8723a4ee8992aa2c8814cfe5c3ad0ab9d988ac5802000000000000160014365b1166a6ed0a5e8e9dff17a6d00bbb43454bc758020000000000001976a914bc98c51a84fe7fad5dc380eb8b39586eff47241688ac4f313247",
+	      "0107af4e00000000002251202c36d243dfc06cb56a248e62df27ecba7417307511a81ae61aa41c597a929c69",
+	      0, SIGHASH_SINGLE | SIGHASH_ANYONECANPAY, None, None, "bf9c83f26c6dd16449e4921f813f551c4218e86f2ec906ca8611175b41b566df");
+	// with an annex
+	check("0200000001df8123752e8f37d132c4e9f1ff7e4f9b986ade9211267e9ebd5fd22a5e718dec6d01000000ce4023b903cb7b23000000000017a914a18b36ea7a094db2f4940fc09edf154e86de7bd787580200000000000017a914afd0d512a2c5c2b40e25669e9cc460303c325b8b87580200000000000017a914a18b36ea7a094db2f4940fc09edf154e86de7bd787f6020000",
+	      "01ea49260000000000225120ab5e9800806bf18cb246edcf5fe63441208fe955a4b5a35bbff65f5db622a010",
+	      0, SIGHASH_SINGLE | SIGHASH_ANYONECANPAY, Some("507b979802e62d397acb29f56743a791894b99372872fc5af06a4f6e8d242d0615cda53062bb20e6ec79756fe39183f0c128adfe85559a8fa042b042c018aa8010143799e44f0893c40e1e"), None,
+	      "3b003000add359a364a156e73e02846782a59d0d95ca8c4638aaad99f2ef915c");
+	// a tapscript
+	check("020000000189fc651483f9296b906455dd939813bf086b1bbe7c77635e157c8e14ae29062195010000004445b5c7044561320000000000160014331414dbdada7fb578f700f38fb69995fc9b5ab958020000000000001976a914268db0a8104cc6d8afd91233cc8b3d1ace8ac3ef88ac580200000000000017a914ec00dcb368d6a693e11986d265f659d2f59e8be2875802000000000000160014c715799a49a0bae3956df9c17cb4440a673ac0df6f010000",
+	      "011bec34000000000022512028055142ea437db73382e991861446040b61dd2185c4891d7daf6893d79f7182",
+	      0, SIGHASH_ALL, None, Some("20cc4e1107aea1d170c5ff5b6817e1303010049724fb3caa7941792ea9d29b3e2bacab"),
+	      "d66de5274a60400c7b08c86ba6b7f198f40660079edf53aca89d2a9501317f2e");
+	// a tapscript with an annex
+	check("020000000132fb72cb8fba496755f027a9743e2d698c831fdb8304e4d1a346ac92cbf51acba50100000026bdc7df044aad34000000000017a9144fa2554ed6174586854fa3bc01de58dcf33567d0875802000000000000160014950367e1e62cdf240b35b883fc2f5e39f0eb9ab95802000000000000160014950367e1e62cdf240b35b883fc2f5e39f0eb9ab958020000000000001600141b31217d48ccc8760dcc0710fade5866d628e733a02d5122",
+	      "011458360000000000225120a7baec3fb9f84614e3899fcc010c638f80f13539344120e1f4d8b68a9a011a13",
+	      0, SIGHASH_ALL, Some("50a6272b470e1460e3332ade7bb14b81671c564fb6245761bd5bd531394b28860e0b3808ab229fb51791fb6ae6fa82d915b2efb8f6df83ae1f5ab3db13e30928875e2a22b749d89358de481f19286cd4caa792ce27f9559082d227a731c5486882cc707f83da361c51b7aadd9a0cf68fe7480c410fa137b454482d9a1ebf0f96d760b4d61426fc109c6e8e99a508372c45caa7b000a41f8251305da3f206c1849985ba03f3d9592832b4053afbd23ab25d0465df0bc25a36c223aacf8e04ec736a418c72dc319e4da3e972e349713ca600965e7c665f2090d5a70e241ac164115a1f5639f28b1773327715ca307ace64a2de7f0e3df70a2ffee3857689f909c0dad46d8a20fa373a4cc6eed6d4c9806bf146f0d76baae1"),
+	      Some("7520ab9160dd8299dc1367659be3e8f66781fe440d52940c7f8d314a89b9f2698d406ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6ead6eadac"),
+	      "a0042aa434f9a75904b64043f2a283f8b4c143c7f4f7f49a6cbe5b9f745f4c15");
+    }
+
+    /// the cases where there isn't a taproot signature hash, so nothing can be signed
+    #[test]
+    fn test_taproot_signature_hash_failures() {
+	let transaction = three_by_three();
+	let spent_tx_outs = transaction.tx_outs.clone();
+	assert!(taproot_signature_hash(&transaction, 0, &spent_tx_outs, SIGHASH_DEFAULT, None, None).is_some());
+	// unknown hash types
+	for hash_type in [0x04, 0x80, 0x84, 0xff] {
+	    assert_eq!(taproot_signature_hash(&transaction, 0, &spent_tx_outs, hash_type, None, None), None);
+	}
+	// the spent tx_outs have to match up with the tx_ins, since they all get signed
+	assert_eq!(taproot_signature_hash(&transaction, 0, &spent_tx_outs[1..], SIGHASH_DEFAULT, None, None), None);
+	assert_eq!(taproot_signature_hash(&transaction, 3, &spent_tx_outs, SIGHASH_DEFAULT, None, None), None);
+	// no tx_out for SIGHASH_SINGLE, rather than signing the number one
+	let mut transaction = transaction;
+	transaction.tx_outs.truncate(2);
+	assert!(taproot_signature_hash(&transaction, 1, &spent_tx_outs, SIGHASH_SINGLE, None, None).is_some());
+	assert_eq!(taproot_signature_hash(&transaction, 2, &spent_tx_outs, SIGHASH_SINGLE, None, None), None);
+	assert!(taproot_signature_hash(&transaction, 2, &spent_tx_outs, SIGHASH_NONE, None, None).is_some());
+    }
+}
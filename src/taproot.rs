@@ -0,0 +1,355 @@
+use sha2::{Sha256, Digest};
+use ecdsa::{SigningKey};
+use elliptic_curve::ops::Reduce;
+use elliptic_curve::ff::PrimeField; // trait in scope for reading a scalar from bytes
+use elliptic_curve::sec1::{FromEncodedPoint, ToEncodedPoint};
+use elliptic_curve::group::Group; // trait in scope for the generator and the identity point
+use k256::{Secp256k1, Scalar, ProjectivePoint, AffinePoint, EncodedPoint, FieldBytes};
+use std::convert::TryInto;
+
+use crate::encode::encode_bytes;
+
+/// the leaf version of tapscript (BIP342), the only kind of script in a taproot tree that means anything so far
+pub const TAPROOT_LEAF_TAPSCRIPT: u8 = 0xc0;
+/// the leaf version is the first byte of the control block, without the bit for the parity of the output key
+pub const TAPROOT_LEAF_MASK: u8 = 0xfe;
+/// a control block is the first byte and the 32 byte internal key, then 32 bytes for each step up the tree
+pub const TAPROOT_CONTROL_BASE_SIZE: usize = 33;
+pub const TAPROOT_CONTROL_NODE_SIZE: usize = 32;
+pub const TAPROOT_CONTROL_MAX_NODE_COUNT: usize = 128;
+/// the last witness item is the annex if it starts with this byte (and there are at least two items)
+pub const ANNEX_TAG: u8 = 0x50;
+
+/// BIP340 hashes are tagged, so that a hash made for one purpose can't be passed off as one for another:
+/// SHA-256 of the SHA-256 of the tag (twice) and then the data
+pub fn tagged_hash(tag: &str, data: &[u8]) -> [u8; 32] {
+    let tag_hash = Sha256::digest(tag.as_bytes());
+    let mut hasher = Sha256::new();
+    hasher.update(tag_hash);
+    hasher.update(tag_hash);
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+/// a hash read as a big endian number, modulo the curve order
+fn scalar_from_hash(hash: &[u8; 32]) -> Scalar {
+    <Scalar as Reduce<k256::U256>>::from_be_bytes_reduced((*hash).into())
+}
+
+/// the private key as a scalar
+fn secret_scalar(private_key: &SigningKey<Secp256k1>) -> Scalar {
+    Option::from(Scalar::from_repr(private_key.to_bytes())).expect("a signing key is always a valid scalar")
+}
+
+/// The point with the given x coordinate and an even y coordinate, since an x only public key stands for that one.
+/// None if the bytes aren't an x coordinate on the curve
+fn lift_x(x: &[u8]) -> Option<ProjectivePoint> {
+    if x.len() != 32 {
+	return None;
+    }
+    let mut compressed = vec![0x02]; // the SEC1 prefix for an even y
+    compressed.extend_from_slice(x);
+    let encoded_point = EncodedPoint::from_bytes(&compressed).ok()?;
+    Option::<AffinePoint>::from(AffinePoint::from_encoded_point(&encoded_point)).map(ProjectivePoint::from)
+}
+
+/// the x coordinate of a point (which mustn't be the point at infinity), and whether its y coordinate is odd
+fn x_only(point: &ProjectivePoint) -> ([u8; 32], bool) {
+    let encoded_point = point.to_encoded_point(true);
+    let bytes = encoded_point.as_bytes();
+    (bytes[1..].try_into().expect("a compressed point is the prefix and 32 bytes"), bytes[0] == 0x03)
+}
+
+/// e in BIP340: the hash of R, the public key and the message, as a scalar
+fn challenge(r: &[u8], public_key: &[u8], message: &[u8]) -> Scalar {
+    scalar_from_hash(&tagged_hash("BIP0340/challenge", &[r, public_key, message].concat()))
+}
+
+/// The x only public key (BIP340) for a private key, which is just the x coordinate of the usual public key.
+/// If the y coordinate is odd, the key really stands for the negated private key, so signing takes care of that
+pub fn x_only_public_key(private_key: &SigningKey<Secp256k1>) -> [u8; 32] {
+    x_only(&(<ProjectivePoint as Group>::generator() * secret_scalar(private_key))).0
+}
+
+/// Sign the message with a BIP340 Schnorr signature: the 32 byte x coordinate of R and then the 32 byte s.
+/// The nonce is a hash of the private key (masked by aux_rand), the public key and the message, so it is deterministic for the same aux_rand
+pub fn schnorr_sign(private_key: &SigningKey<Secp256k1>, message: &[u8], aux_rand: &[u8; 32]) -> [u8; 64] {
+    let secret = secret_scalar(private_key);
+    let (public_key, odd) = x_only(&(<ProjectivePoint as Group>::generator() * secret));
+    let d = if odd {-secret} else {secret};
+    let mut t = d.to_bytes();
+    for (byte, aux_byte) in t.iter_mut().zip(tagged_hash("BIP0340/aux", aux_rand)) {
+	*byte ^= aux_byte;
+    }
+    let k = scalar_from_hash(&tagged_hash("BIP0340/nonce", &[&t[..], &public_key, message].concat()));
+    assert!(!bool::from(k.is_zero()), "the nonce hashed to zero");
+    let (r, odd) = x_only(&(<ProjectivePoint as Group>::generator() * k));
+    let k = if odd {-k} else {k};
+    let s = k + challenge(&r, &public_key, message) * d;
+    let mut signature = [0; 64];
+    signature[..32].copy_from_slice(&r);
+    signature[32..].copy_from_slice(&s.to_bytes());
+    signature
+}
+
+/// Does the BIP340 signature sign the message for the x only public key? Anything of the wrong size, a public key that isn't on the curve,
+/// or an s that is at least the curve order just doesn't verify
+pub fn schnorr_verify(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    if signature.len() != 64 {
+	return false;
+    }
+    let point = match lift_x(public_key) {
+	Some(point) => point,
+	None => return false,
+    };
+    let (r, s) = signature.split_at(32);
+    let s = match Option::<Scalar>::from(Scalar::from_repr(FieldBytes::clone_from_slice(s))) {
+	Some(s) => s,
+	None => return false,
+    };
+    let e = challenge(r, public_key, message);
+    let expected_r = <ProjectivePoint as Group>::generator() * s - point * e;
+    if expected_r == <ProjectivePoint as Group>::identity() {
+	return false;
+    }
+    // an r of p or more can't match, since x coordinates are always less than p
+    let (x, odd) = x_only(&expected_r);
+    !odd && x[..] == *r
+}
+
+/// the tweak (BIP341) for an internal key: the hash of the key and the merkle root of the script tree, if there is one
+fn tap_tweak(internal_key: &[u8], merkle_root: Option<&[u8; 32]>) -> [u8; 32] {
+    let mut data = internal_key.to_vec();
+    if let Some(merkle_root) = merkle_root {
+	data.extend_from_slice(merkle_root);
+    }
+    tagged_hash("TapTweak", &data)
+}
+
+/// The output key (BIP341) that commits to the internal key and the script tree: the internal key plus the tweak times the generator.
+/// Returns its x only bytes, which go in the locking script, and whether its y is odd, which goes in the control block for a script path spend.
+/// None if the internal key isn't on the curve (or, with vanishing odds, if the tweak is too large)
+pub fn tweak_public_key(internal_key: &[u8], merkle_root: Option<&[u8; 32]>) -> Option<([u8; 32], bool)> {
+    let point = lift_x(internal_key)?;
+    let tweak = Option::<Scalar>::from(Scalar::from_repr(tap_tweak(internal_key, merkle_root).into()))?;
+    let output_point = point + <ProjectivePoint as Group>::generator() * tweak;
+    if output_point == <ProjectivePoint as Group>::identity() {
+	return None;
+    }
+    Some(x_only(&output_point))
+}
+
+/// the private key for the output key from tweak_public_key(), which is what a key path spend is signed with
+pub fn tweak_private_key(private_key: &SigningKey<Secp256k1>, merkle_root: Option<&[u8; 32]>) -> Option<SigningKey<Secp256k1>> {
+    let secret = secret_scalar(private_key);
+    let (internal_key, odd) = x_only(&(<ProjectivePoint as Group>::generator() * secret));
+    let secret = if odd {-secret} else {secret}; // the internal key stands for the point with the even y
+    let tweak = Option::<Scalar>::from(Scalar::from_repr(tap_tweak(&internal_key, merkle_root).into()))?;
+    SigningKey::from_bytes(&(secret + tweak).to_bytes()).ok()
+}
+
+/// the hash of a leaf of the script tree: its leaf version and the script (with its length in front)
+pub fn tap_leaf_hash(leaf_version: u8, script_bytes: &[u8]) -> [u8; 32] {
+    let mut data = vec![leaf_version];
+    encode_bytes(script_bytes, &mut data);
+    tagged_hash("TapLeaf", &data)
+}
+
+/// the hash of a branch of the script tree. Its two children are sorted, so a merkle path doesn't have to say which side it goes on
+pub fn tap_branch_hash(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let (left, right) = if a <= b {(a, b)} else {(b, a)};
+    tagged_hash("TapBranch", &[&left[..], &right[..]].concat())
+}
+
+/// the merkle root of the script tree, from a leaf and the hashes next to it on the way up
+pub fn tap_merkle_root(leaf_hash: &[u8; 32], merkle_path: &[[u8; 32]]) -> [u8; 32] {
+    merkle_path.iter().fold(*leaf_hash, |hash, node| tap_branch_hash(&hash, node))
+}
+
+/// The control block that goes on the end of the witness for a script path spend: the leaf version with the parity of the output key,
+/// the internal key, and the merkle path from the leaf to the root
+pub fn control_block(leaf_version: u8, internal_key: &[u8; 32], output_key_parity: bool, merkle_path: &[[u8; 32]]) -> Vec<u8> {
+    let mut bytes = vec![leaf_version | output_key_parity as u8];
+    bytes.extend_from_slice(internal_key);
+    for node in merkle_path {
+	bytes.extend_from_slice(node);
+    }
+    bytes
+}
+
+/// Does the output key (the witness program) commit to the leaf with this hash, according to the control block?
+/// The internal key tweaked with the merkle root that the path leads to has to come out as the output key, with the parity that the control block says.
+/// The control block has to be of a valid size already
+pub fn verify_taproot_commitment(control_block: &[u8], program: &[u8], leaf_hash: &[u8; 32]) -> bool {
+    let internal_key = &control_block[1..TAPROOT_CONTROL_BASE_SIZE];
+    let merkle_path: Vec<[u8; 32]> = control_block[TAPROOT_CONTROL_BASE_SIZE..].chunks(TAPROOT_CONTROL_NODE_SIZE)
+	.map(|node| node.try_into().expect("the control block size was checked"))
+	.collect();
+    let merkle_root = tap_merkle_root(leaf_hash, &merkle_path);
+    match tweak_public_key(internal_key, Some(&merkle_root)) {
+	Some((output_key, parity)) => output_key[..] == *program && parity == (control_block[0] & 1 == 1),
+	None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex_32(hex_str: &str) -> [u8; 32] {
+	hex::decode(hex_str).unwrap().try_into().unwrap()
+    }
+
+    /// the first test vectors from BIP340's test-vectors.csv, where the signature is what signing gives
+    #[test]
+    fn test_bip340_signing() {
+	let vectors = [
+	    ("0000000000000000000000000000000000000000000000000000000000000003",
+	     "F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+	     "0000000000000000000000000000000000000000000000000000000000000000",
+	     "0000000000000000000000000000000000000000000000000000000000000000",
+	     "E907831F80848D1069A5371B402410364BDF1C5F8307B0084C55F1CE2DCA821525F66A4A85EA8B71E482A74F382D2CE5EBEEE8FDB2172F477DF4900D310536C0"),
+	    ("B7E151628AED2A6ABF7158809CF4F3C762E7160F38B4DA56A784D9045190CFEF",
+	     "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+	     "0000000000000000000000000000000000000000000000000000000000000001",
+	     "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+	     "6896BD60EEAE296DB48A229FF71DFE071BDE413E6D43F917DC8DCF8C78DE33418906D11AC976ABCCB20B091292BFF4EA897EFCB639EA871CFA95F6DE339E4B0A"),
+	    ("C90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B14E5C9",
+	     "DD308AFEC5777E13121FA72B9CC1B7CC0139715309B086C960E18FD969774EB8",
+	     "C87AA53824B4D7AE2EB035A2B5BBBCCC080E76CDC6D1692C4B0B62D798E6D906",
+	     "7E2D58D8B3BCDF1ABADEC7829054F90DDA9805AAB56C77333024B9D0A508B75C",
+	     "5831AAEED7B44BB74E5EAB94BA9D4294C49BCF2A60728D8B4C200F50DD313C1BAB745879A5AD954A72C45A91C3A51D3C7ADEA98D82F8481E0E1E03674A6F3FB7"),
+	    ("0B432B2677937381AEF05BB02A66ECD012773062CF3FA2549E44F58ED2401710",
+	     "25D1DFF95105F5253C4022F628A996AD3A0D95FBF21D468A1B33F8C160D8F517",
+	     "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+	     "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+	     "7EB0509757E246F19449885651611CB965ECC1A187DD51B64FDA1EDC9637D5EC97582B9CB13DB3933705B32BA982AF5AF25FD78881EBB32771FC5922EFC66EA3"),
+	];
+	for (secret_key, public_key, aux_rand, message, signature) in vectors {
+	    let private_key = SigningKey::<Secp256k1>::from_bytes(&hex::decode(secret_key).unwrap()).unwrap();
+	    let public_key = hex::decode(public_key).unwrap();
+	    let message = hex::decode(message).unwrap();
+	    let signature = hex::decode(signature).unwrap();
+	    assert_eq!(x_only_public_key(&private_key).to_vec(), public_key);
+	    assert_eq!(schnorr_sign(&private_key, &message, &hex_32(aux_rand)).to_vec(), signature);
+	    assert!(schnorr_verify(&public_key, &message, &signature));
+	    // any change to the message or the signature breaks it
+	    let mut other_message = message.clone();
+	    other_message[0] ^= 1;
+	    assert!(!schnorr_verify(&public_key, &other_message, &signature));
+	    let mut other_signature = signature.clone();
+	    other_signature[63] ^= 1;
+	    assert!(!schnorr_verify(&public_key, &message, &other_signature));
+	}
+    }
+
+    /// the verification only test vectors from BIP340, with the reason from the comment in the csv
+    #[test]
+    fn test_bip340_verification() {
+	let message = "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89";
+	let vectors = [
+	    // sG - eP is R with an x coordinate that starts with a lot of zeros, which some implementations get wrong
+	    ("D69C3509BB99E412E68B0FE8544E72837DFA30746D8BE2AA65975F29D22DC7B9",
+	     "4DF3C3F68FCC83B27E9D42C90431A72499F17875C81A599B566C9889B9696703",
+	     "00000000000000000000003B78CE563F89A0ED9414F5AA28AD0D96D6795F9C6376AFB1548AF603B3EB45C9F8207DEE1060CB71C04E80F593060B07D28308D7F4",
+	     true),
+	    // public key not on the curve
+	    ("EEFDEA4CDB677750A420FEE807EACF21EB9898AE79B9768766E4FAA04A2D4A34",
+	     message,
+	     "6CFF5C3BA86C69EA4B7376F31A9BCB4F74C1976089B2D9963DA2E5543E17776969E89B4C5564D00349106B8497785DD7D1D713A8AE82B32FA79D5F7FC407D39B",
+	     false),
+	    // r is equal to the field size
+	    ("DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+	     message,
+	     "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEFFFFFC2F69E89B4C5564D00349106B8497785DD7D1D713A8AE82B32FA79D5F7FC407D39B",
+	     false),
+	    // s is equal to the curve order
+	    ("DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+	     message,
+	     "6CFF5C3BA86C69EA4B7376F31A9BCB4F74C1976089B2D9963DA2E5543E177769FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141",
+	     false),
+	    // public key is not a valid x coordinate, because it exceeds the field size
+	    ("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEFFFFFC30",
+	     message,
+	     "6CFF5C3BA86C69EA4B7376F31A9BCB4F74C1976089B2D9963DA2E5543E17776969E89B4C5564D00349106B8497785DD7D1D713A8AE82B32FA79D5F7FC407D39B",
+	     false),
+	];
+	for (public_key, message, signature, valid) in vectors {
+	    let (public_key, message, signature) = (hex::decode(public_key).unwrap(), hex::decode(message).unwrap(), hex::decode(signature).unwrap());
+	    assert_eq!(schnorr_verify(&public_key, &message, &signature), valid);
+	}
+	// and a signature or public key of the wrong size doesn't verify either
+	let private_key = SigningKey::<Secp256k1>::from_bytes(&[7; 32]).unwrap();
+	let signature = schnorr_sign(&private_key, b"message", &[0; 32]);
+	let public_key = x_only_public_key(&private_key);
+	assert!(schnorr_verify(&public_key, b"message", &signature));
+	assert!(!schnorr_verify(&public_key, b"message", &signature[..63]));
+	assert!(!schnorr_verify(&public_key[..31], b"message", &signature));
+    }
+
+    /// the tagged hashes of nothing and of a zero byte, from bitcoin core
+    #[test]
+    fn test_tagged_hashes() {
+	assert_eq!(hex::encode(tagged_hash("TapLeaf", &[])), "5212c288a377d1f8164962a5a13429f9ba6a7b84e59776a52c6637df2106facb");
+	assert_eq!(hex::encode(tagged_hash("TapBranch", &[])), "53c373ec4d6f3c53c1f5fb2ff506dcefe1a0ed74874f93fa93c8214cbe9ffddf");
+	assert_eq!(hex::encode(tagged_hash("TapTweak", &[])), "8aa4229474ab0100b2d6f0687f031d1fc9d8eef92a042ad97d279bff456b15e4");
+	assert_eq!(hex::encode(tagged_hash("TapSighash", &[])), "dabc11914abcd8072900042a2681e52f8dba99ce82e224f97b5fdb7cd4b9c803");
+	assert_eq!(hex::encode(tagged_hash("TapLeaf", &[0])), "ed1382037800c9dd938dd8854f1a8863bcdeb6705069b4b56a66ec22519d5829");
+	assert_eq!(hex::encode(tagged_hash("TapBranch", &[0])), "92534b1960c7e6245af7d5fda2588db04aa6d646abc2b588dab2b69e5645eb1d");
+	assert_eq!(hex::encode(tagged_hash("TapTweak", &[0])), "cd8737b5e6047fc3f16f03e8b9959e3440e1bdf6dd02f7bb899c352ad490ea1e");
+	assert_eq!(hex::encode(tagged_hash("TapSighash", &[0])), "c2fd0de003889a09c4afcf676656a0d8a1fb706313ff7d509afb00c323c010cd");
+    }
+
+    /// Script path spends from bitcoin core's feature_taproot.py: the locking script, the leaf script and the control block.
+    /// Not all of them have the tapscript leaf version, but the commitment works the same for any leaf version
+    #[test]
+    fn test_control_block_commitments() {
+	let vectors = [
+	    ("51205dc8e62b15e0ebdf44751676be35ba32eed2e84608b290d4061bbff136cd7ba9", "6a", "c1a9d6f66cd4b25004f526bfa873e56942f98e8e492bd79ed6532b966104817c2bda584e7d32612381cf88edc1c02e28a296e807c16ad22f591ee113946e48a71e0641e660d1e5392fb79d64838c2b84faf04b7f5f283c9d8bf83e39e177b64372a0cd22eeab7e093873e851e247714eff762d8a30be699ba4456cfe6491b282e193a071350ae099005a5950d74f73ba13077a57bc478007fb0e4d1099ce9cf3d4"),
+	    ("5120e208c869c40d8827101c5ad3238018de0f3f5183d77a0c53d18ac28ddcbcd8ad", "f4", "c0a0eb12e60a52614986c623cbb6621dcdba3a47e3be6b37e032b7a11c7b98f40090ab1f4890d51115998242ebce636efb9ede1b516d9eb8952dc1068e0335306199aaf103cceb41d9bc37ec231aca89b984b5fd3c65977ce764d51033ac65adb4da14e029b1e154a85bfd9139e7aa2720b6070a4ceba8264ca61d5d3ac27aceb9ef4b54cd43c2d1fd5e11b5c2e93cf29b91ea3dc5b832201f02f7473a28c63246"),
+	    ("5120567666e7df90e0450bb608e17c01ed3fbcfa5355a5f8273e34e583bfaa70ce09", "203455139bf238a3067bd72ed77e0ab8db590330f55ed58dba7366b53bf4734279ac", "c1a0eb12e60a52614986c623cbb6621dcdba3a47e3be6b37e032b7a11c7b98f400"),
+	    ("5120580a19e47269414a55eb86d5d0c6c9b371455d9fd2154412a57dec840df99fe1", "6a", "bca0eb12e60a52614986c623cbb6621dcdba3a47e3be6b37e032b7a11c7b98f40042ba1bd1c63c03ccff60d4c4d53a653f87909eb3358e7fa45c9d805231fb08c933e1f4e0f9d17f591df1419df7d5b7eb5f744f404c5ef9ecdb1b89b18cafa3a816d8b5dba3205f9a9c05f866d91f40d2793a7586d502cb42f46c7a11f66ad4aa"),
+	    ("5120228b94a4806254a38d6efa8a134c28ebc89546209559dfe40b2b0493bafacc5b", "6a50", "c0a0eb12e60a52614986c623cbb6621dcdba3a47e3be6b37e032b7a11c7b98f4009c9aed3dfd11ab0e78bf87ef3bf296269dc4b0f7712140386d6980992bab4b45"),
+	    ("5120b0a79103c31fe51eea61d2873bad8a25a310da319d7e7a85f825fa7a00ea3f85", "203455139bf238a3067bd72ed77e0ab8db590330f55ed58dba7366b53bf4734279ad51", "c1a0eb12e60a52614986c623cbb6621dcdba3a47e3be6b37e032b7a11c7b98f400"),
+	    ("5120017316303aed02bcdec424c851c9eacbe192b013139bd9634c4e19b3475b06e1", "61", "02a0eb12e60a52614986c623cbb6621dcdba3a47e3be6b37e032b7a11c7b98f40050462265ca552b23cbb4fe021b474313c8cb87d4a18b3f7bdbeb2b418279ba31fc6509d829cd42336f563363cb3538d78758e0876c71e13012eb2b656eb0edb051a2420a840d5c8c6c762abc7410af2c311f606b20ca2ace56a8139f84b1379a"),
+	    ("512093c7378d96518a75448821c4f7c8f4bae7ce60f804d03d1f0628dd5dd0f5de51", "04ffffffff203455139bf238a3067bd72ed77e0ab8db590330f55ed58dba7366b53bf4734279ba04feffffff87ab", "c1a0eb12e60a52614986c623cbb6621dcdba3a47e3be6b37e032b7a11c7b98f400c9a5cd1f6c8a81f5648e39f9810591df1c9a8f1fe97c92e03ecd7c0c016c951983e05473c6e8238cb4c780ea2ce62552b2a3eee068ceffc00517cd7b97e10dad"),
+	    ("5120ee9aecb28f5f35ce1f8b5ec80275ac0f81bca4a21b29b4632fb4bcbef8823e6a", "2021a5981b13be29c9d4ea179ea44a8b773ea8c02d68f6f6eefd98de20d4bd055fac", "c13359c284c196b6e80f0cf1d93b6a397cf7ee722f0427b705bd954b88ada8838bd2622fd0e104fc50aa763b43c6a792d7d117029983abd687223b4344a9402c618bba7f5fc3fa8a57491f6842acde88c1e675ca35caea3b1a69ee2c2d9b10f615"),
+	];
+	for (locking_script, script, control_block) in vectors {
+	    let program = &hex::decode(locking_script).unwrap()[2..];
+	    let control_block = hex::decode(control_block).unwrap();
+	    let leaf_hash = tap_leaf_hash(control_block[0] & TAPROOT_LEAF_MASK, &hex::decode(script).unwrap());
+	    assert!(verify_taproot_commitment(&control_block, program, &leaf_hash));
+	    // the wrong parity, or a different script, doesn't match
+	    let mut wrong_parity = control_block.clone();
+	    wrong_parity[0] ^= 1;
+	    assert!(!verify_taproot_commitment(&wrong_parity, program, &leaf_hash));
+	    let other_leaf_hash = tap_leaf_hash(control_block[0] & TAPROOT_LEAF_MASK, &[0x51]);
+	    assert!(!verify_taproot_commitment(&control_block, program, &other_leaf_hash));
+	}
+    }
+
+    /// the tweaked private key signs for the tweaked public key, and a control block we build ourselves checks out
+    #[test]
+    fn test_tweaked_keys() {
+	let private_key = SigningKey::<Secp256k1>::from_bytes(b"adamadamadamadamadamadamadamadam").unwrap();
+	let internal_key = x_only_public_key(&private_key);
+	let leaves = [tap_leaf_hash(TAPROOT_LEAF_TAPSCRIPT, &[0x51]), tap_leaf_hash(TAPROOT_LEAF_TAPSCRIPT, &[0x52])];
+	let merkle_root = tap_branch_hash(&leaves[0], &leaves[1]);
+	assert_eq!(tap_branch_hash(&leaves[1], &leaves[0]), merkle_root);
+	for merkle_root in [None, Some(&merkle_root)] {
+	    let (output_key, parity) = tweak_public_key(&internal_key, merkle_root).unwrap();
+	    let tweaked_key = tweak_private_key(&private_key, merkle_root).unwrap();
+	    assert_eq!(x_only_public_key(&tweaked_key), output_key);
+	    let signature = schnorr_sign(&tweaked_key, b"message", &[0; 32]);
+	    assert!(schnorr_verify(&output_key, b"message", &signature));
+	    assert!(!schnorr_verify(&internal_key, b"message", &signature));
+	    if merkle_root.is_some() {
+		let control_block = control_block(TAPROOT_LEAF_TAPSCRIPT, &internal_key, parity, &leaves[1..]);
+		assert_eq!(control_block.len(), TAPROOT_CONTROL_BASE_SIZE + TAPROOT_CONTROL_NODE_SIZE);
+		assert!(verify_taproot_commitment(&control_block, &output_key, &leaves[0]));
+		assert!(!verify_taproot_commitment(&control_block, &output_key, &leaves[1]));
+	    }
+	}
+    }
+}
@@ -1,35 +1,179 @@
 use serde::{Serialize, Deserialize};
 //use ecdsa::{SigningKey, VerifyingKey};
-use sha2::{Sha256, Digest};
 //use k256::{Secp256k1};
 
-use crate::script::{Script};
-use crate::{Hash};
+use crate::script::{Script, ScriptError, ExecutionContext, execute_scripts};
+use crate::{Hash, sha256d};
 use crate::DoubleSHA;
+use crate::encode::{Encodable, Decodable, DecodeError, CompactSize, serialize, encode_bytes, decode_bytes, take_bytes};
+use crate::policy::PolicyError;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// the tx_out_index of the (null) outpoint that a coinbase tx_in refers to
+const COINBASE_TX_OUT_INDEX: u32 = 0xffffffff;
+
+/// a lock_time below this is a block height, and anything from here up is a unix timestamp
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+/// if every tx_in has this sequence, the lock_time is ignored
+pub const SEQUENCE_FINAL: u32 = 0xffffffff;
+// the parts of a sequence that make up a relative lock time (BIP 68)
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31; // the sequence isn't a relative lock time at all
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22; // the lock time is in units of 512 seconds rather than blocks
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff; // the lock time itself
+const SEQUENCE_LOCKTIME_GRANULARITY: u32 = 9; // 512 seconds is 2^9
+
+/// a byte of witness data only weighs a quarter as much as the rest of a transaction (BIP141)
+pub const WITNESS_SCALE_FACTOR: usize = 4;
+/// all the bitcoin there will ever be, in satoshis. No tx_out can be worth more, and neither can the tx_outs or tx_ins of a transaction added up
+pub const MAX_MONEY: u64 = 21_000_000 * 100_000_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum TxIn {
     // A transaction input can either come from a previous transaction output,
     // or if it is part of a block reward, then can be a coinbase
     TxPrevious {
 	tx_hash: Hash, // Hash of the transaction that we are getting this input from
-	tx_out_index: usize,// The index of the tx_out within the transaction
+	tx_out_index: u32,// The index of the tx_out within the transaction
 	unlocking_script: Script, // AKA: ScriptSig, but lets follow Mastering Bitcoin's convention
-	sequence: u32, // TODO: what is this haha
+	sequence: u32, // a relative lock time (see sequence_locks_satisfied()), or SEQUENCE_FINAL to opt out of the lock_time
+	witness: Vec<Vec<u8>>, // the items that unlock a segwit locking script (BIP141). They aren't part of the txid, so they can't be malleated
     },
     Coinbase {
-	coinbase: u32,
+	coinbase: Vec<u8>, // arbitrary bytes that the miner can fill however they like, sitting where the unlocking script would be
 	sequence: u32,
+	witness: Vec<Vec<u8>>, // in a block with segwit transactions, the reserved value that goes into the witness commitment
+    }
+}
+
+impl TxIn {
+    /// if this tx_in spends a previous tx_out, return the OutPoint that refers to it.
+    /// A coinbase does not spend anything, so it has no OutPoint
+    pub fn outpoint(&self) -> Option<OutPoint> {
+	match self {
+	    TxIn::TxPrevious {tx_hash, tx_out_index, ..} => Some(OutPoint::new(*tx_hash, *tx_out_index)),
+	    TxIn::Coinbase {..} => None,
+	}
+    }
+
+    pub fn sequence(&self) -> u32 {
+	match self {
+	    TxIn::TxPrevious {sequence, ..} | TxIn::Coinbase {sequence, ..} => *sequence,
+	}
+    }
+
+    pub fn witness(&self) -> &[Vec<u8>] {
+	match self {
+	    TxIn::TxPrevious {witness, ..} | TxIn::Coinbase {witness, ..} => witness,
+	}
+    }
+
+    /// the witness gets filled in once the transaction is otherwise complete, since segwit signatures sign everything else
+    pub fn witness_mut(&mut self) -> &mut Vec<Vec<u8>> {
+	match self {
+	    TxIn::TxPrevious {witness, ..} | TxIn::Coinbase {witness, ..} => witness,
+	}
+    }
+}
+
+/// on the wire, a coinbase looks like any other tx_in, except that it refers to a null outpoint (a zero hash and index 0xffffffff).
+/// The witness isn't here, it goes after all the tx_outs (see the Encodable for Transaction)
+impl Encodable for TxIn {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+	match self {
+	    TxIn::TxPrevious {tx_hash, tx_out_index, unlocking_script, sequence, ..} => {
+		OutPoint::new(*tx_hash, *tx_out_index).encode(bytes);
+		unlocking_script.encode(bytes);
+		sequence.encode(bytes);
+	    },
+	    TxIn::Coinbase {coinbase, sequence, ..} => {
+		OutPoint::null().encode(bytes);
+		encode_bytes(coinbase, bytes);
+		sequence.encode(bytes);
+	    },
+	}
+    }
+}
+
+impl Decodable for TxIn {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+	let tx_hash = Hash::decode(bytes)?;
+	let tx_out_index = u32::decode(bytes)?;
+	if tx_hash.is_zero() && tx_out_index == COINBASE_TX_OUT_INDEX {
+	    let coinbase = decode_bytes(bytes)?;
+	    let sequence = u32::decode(bytes)?;
+	    return Ok(TxIn::Coinbase {coinbase, sequence, witness: vec![]});
+	}
+	let unlocking_script = Script::decode(bytes)?;
+	let sequence = u32::decode(bytes)?;
+	Ok(TxIn::TxPrevious {tx_hash, tx_out_index, unlocking_script, sequence, witness: vec![]})
+    }
+}
+
+/// a witness is the number of items, followed by each item as a byte string
+fn encode_witness(witness: &[Vec<u8>], bytes: &mut Vec<u8>) {
+    CompactSize(witness.len() as u64).encode(bytes);
+    for item in witness {
+	encode_bytes(item, bytes);
+    }
+}
+
+fn decode_witness(bytes: &mut &[u8]) -> Result<Vec<Vec<u8>>, DecodeError> {
+    let CompactSize(len) = CompactSize::decode(bytes)?;
+    let mut witness = vec![];
+    for _ in 0..len {
+	witness.push(decode_bytes(bytes)?);
+    }
+    Ok(witness)
+}
+
+/// An OutPoint uniquely identifies a single tx_out in the chain,
+/// via the hash of the transaction it lives in and its index within that transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct OutPoint {
+    pub tx_hash: Hash,
+    pub tx_out_index: u32,
+}
+
+impl OutPoint {
+    pub fn new(tx_hash: Hash, tx_out_index: u32) -> Self {
+	Self {tx_hash, tx_out_index}
+    }
+
+    /// what a coinbase tx_in refers to, since it doesn't spend anything
+    pub fn null() -> Self {
+	Self::new(Hash::zero(), COINBASE_TX_OUT_INDEX)
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Encodable for OutPoint {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+	self.tx_hash.encode(bytes);
+	self.tx_out_index.encode(bytes);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TxOut {
-    pub value: u32, // number of satoshis 
+    pub value: u64, // number of satoshis 
     pub locking_script: Script, // AKA: ScriptPubKey, but following Master Bitcoin's convention
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Encodable for TxOut {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+	self.value.encode(bytes);
+	self.locking_script.encode(bytes);
+    }
+}
+
+impl Decodable for TxOut {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+	Ok(TxOut {
+	    value: u64::decode(bytes)?,
+	    locking_script: Script::decode(bytes)?,
+	})
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Transaction {
     pub version: u32,
     pub lock_time: u32,
@@ -38,89 +182,303 @@ pub struct Transaction {
 }
 
 impl Transaction {
-    pub fn hash_to_bytes(&self) -> Vec<u8> {
-        let mut hasher = Sha256::new();
-        hasher.update(self.version.to_be_bytes());
-        hasher.update(self.lock_time.to_be_bytes());
-	
+    /// the txid is the double SHA256 of the serialized transaction, without the witnesses.
+    /// That way a third party can't change the txid by messing with the signatures in the witnesses
+    pub fn txid(&self) -> Hash {
+	let mut bytes = vec![];
+	self.encode_without_witness(&mut bytes);
+	sha256d(&bytes)
+    }
+
+    /// the double SHA256 of the whole serialized transaction, witnesses and all. This is the same as the txid if there are no witnesses
+    pub fn wtxid(&self) -> Hash {
+	sha256d(&serialize(self))
+    }
+
+    /// whether any of the tx_ins has a witness, i.e. whether the transaction gets the segwit serialization
+    pub fn has_witness(&self) -> bool {
+	self.tx_ins.iter().any(|tx_in| !tx_in.witness().is_empty())
+    }
+
+    /// the serialization from before segwit, which is what the txid and legacy signatures are made from
+    pub fn encode_without_witness(&self, bytes: &mut Vec<u8>) {
+	self.version.encode(bytes);
+	self.tx_ins.encode(bytes);
+	self.tx_outs.encode(bytes);
+	self.lock_time.encode(bytes);
+    }
+
+    /// the size in bytes without the witnesses, which is all that nodes from before segwit get to see
+    pub fn base_size(&self) -> usize {
+	let mut bytes = vec![];
+	self.encode_without_witness(&mut bytes);
+	bytes.len()
+    }
+
+    /// the size in bytes of the whole serialized transaction
+    pub fn total_size(&self) -> usize {
+	serialize(self).len()
+    }
+
+    /// The weight (BIP141) is what block space is measured in: every byte counts 4 times, except for the witness bytes (and the marker and flag)
+    /// which only count once
+    pub fn weight(&self) -> usize {
+	self.base_size() * (WITNESS_SCALE_FACTOR - 1) + self.total_size()
+    }
+
+    /// the virtual size is the weight in units of non-witness bytes, rounded up. Fee rates are per vbyte
+    pub fn vsize(&self) -> usize {
+	self.weight().div_ceil(WITNESS_SCALE_FACTOR)
+    }
+
+    /// Every transaction needs at least one tx_in and one tx_out (bad-txns-vin-empty and bad-txns-vout-empty).
+    /// One without tx_ins couldn't even be told apart from the segwit marker when it gets decoded
+    pub fn check_not_empty(&self) -> Result<(), TransactionError> {
+	if self.tx_ins.is_empty() {
+	    return Err(TransactionError::NoTxIns);
+	}
+	if self.tx_outs.is_empty() {
+	    return Err(TransactionError::NoTxOuts);
+	}
+	Ok(())
+    }
+
+    /// The total value of the tx_outs. Like bitcoin core, each of them and the total have to be at most MAX_MONEY
+    /// (bad-txns-vout-toolarge and bad-txns-txouttotal-toolarge)
+    pub fn tx_out_value_sum(&self) -> Result<u64, TransactionError> {
+	let mut tx_out_value_sum: u64 = 0;
+	for tx_out in &self.tx_outs {
+	    if tx_out.value > MAX_MONEY {
+		return Err(TransactionError::TxOutValueTooLarge);
+	    }
+	    tx_out_value_sum = tx_out_value_sum.checked_add(tx_out.value).filter(|sum| *sum <= MAX_MONEY).ok_or(TransactionError::TxOutTotalTooLarge)?;
+	}
+	Ok(tx_out_value_sum)
+    }
+
+    /// The fee (or the tip for the miner), given the tx_outs that this transaction spends: what they are worth, less what the tx_outs are worth.
+    /// What is coming in has to be at most MAX_MONEY as well (bad-txns-inputvalues-outofrange), and the tx_outs can't add up to more than it
+    pub fn fee(&self, spent_tx_outs: &[TxOut]) -> Result<u64, TransactionError> {
+	let mut tx_in_value_sum: u64 = 0;
+	for tx_out in spent_tx_outs {
+	    tx_in_value_sum = tx_in_value_sum.checked_add(tx_out.value).filter(|sum| *sum <= MAX_MONEY).ok_or(TransactionError::TxInValueOutOfRange)?;
+	}
+	tx_in_value_sum.checked_sub(self.tx_out_value_sum()?).ok_or(TransactionError::OverSpend)
+    }
+
+    /// The sigop cost (BIP141) of the transaction, given the tx_outs that it spends (in the same order as its tx_ins, none for a coinbase).
+    /// Signature checks in unlocking, locking and redeem scripts cost WITNESS_SCALE_FACTOR each, while those in the witness cost 1,
+    /// the same as with weight. Tapscripts don't count here, they have their own validation weight
+    pub fn sigop_cost(&self, spent_tx_outs: &[TxOut]) -> usize {
+	let mut legacy_count: usize = self.tx_outs.iter().map(|tx_out| tx_out.locking_script.sigop_count(false)).sum();
 	for tx_in in &self.tx_ins {
-	    match tx_in {
-		TxIn::TxPrevious{tx_hash, tx_out_index, unlocking_script, sequence} => {
-                    for i in 0..4 {
-		        hasher.update(tx_hash.0[i].to_be_bytes());
-                    }
-		    hasher.update(tx_out_index.to_be_bytes());
-		    for op in &unlocking_script.ops {
-			hasher.update(op.to_be_bytes());
+	    legacy_count += match tx_in {
+		TxIn::TxPrevious {unlocking_script, ..} => unlocking_script.sigop_count(false),
+		TxIn::Coinbase {coinbase, ..} => Script::from_bytes(coinbase).map_or(0, |script| script.sigop_count(false)),
+	    };
+	}
+	let mut witness_count = 0;
+	for (tx_in, spent_tx_out) in self.tx_ins.iter().zip(spent_tx_outs) {
+	    if let TxIn::TxPrevious {unlocking_script, witness, ..} = tx_in {
+		let mut witness_program = spent_tx_out.locking_script.witness_program();
+		if spent_tx_out.locking_script.is_pay_to_script_hash() {
+		    if let Some(redeem_script) = unlocking_script.redeem_script() {
+			legacy_count += redeem_script.sigop_count(true);
+			witness_program = redeem_script.witness_program();
 		    }
-		    hasher.update(sequence.to_be_bytes());		    		    
-		},
-		TxIn::Coinbase{coinbase, sequence} => {
-		    hasher.update(coinbase.to_be_bytes());
-		    hasher.update(sequence.to_be_bytes());
 		}
+		witness_count += match witness_program {
+		    Some((0, program)) if program.len() == 20 => 1,
+		    Some((0, program)) if program.len() == 32 => {
+			witness.last().and_then(|script_bytes| Script::from_bytes(script_bytes).ok()).map_or(0, |script| script.sigop_count(true))
+		    },
+		    _ => 0,
+		};
 	    }
 	}
-	for tx_out in &self.tx_outs {
-	    hasher.update(tx_out.value.to_be_bytes());
-	    for op in &tx_out.locking_script.ops {
-		hasher.update(op.to_be_bytes());
-	    } 
+	legacy_count * WITNESS_SCALE_FACTOR + witness_count
+    }
+
+    /// a coinbase transaction has a single tx_in, which is a coinbase
+    pub fn is_coinbase(&self) -> bool {
+	matches!(self.tx_ins.as_slice(), [TxIn::Coinbase {..}])
+    }
+
+    /// Can this transaction go into a block at the given height, whose lock times are measured against the given time
+    /// (the median time past of the block before it)? Either the lock_time (a height or a time, depending on LOCKTIME_THRESHOLD)
+    /// has passed, or every tx_in has opted out of it with SEQUENCE_FINAL
+    pub fn is_final(&self, height: u32, time: u32) -> bool {
+	if self.lock_time == 0 {
+	    return true;
 	}
-	hasher.finalize().to_vec()
+	let passed = if self.lock_time < LOCKTIME_THRESHOLD {height} else {time};
+	self.lock_time < passed || self.tx_ins.iter().all(|tx_in| tx_in.sequence() == SEQUENCE_FINAL)
     }
-	
-    /// hash all the bytes of the transaction
-    /// TODO: is there a "nicer" way to do this rather than like depth first iterating through the whole data structure?
-    /// TODO: could we use serde to turn into bytes then simply hash that? is serde deterministic?x	
-    pub fn hash(&self) -> Hash {
-	//let hash_vecs: Vec<u8> = hasher.finalize().to_vec();
-	let hash_vec: Vec<u8> = self.hash_to_bytes();
-        Hash::from(&hash_vec[..])
+
+    /// The relative lock times of BIP 68, for transactions of version 2 and up: each tx_in whose sequence doesn't have the
+    /// disable flag set can only be spent once its sequence (a number of blocks, or of 512 second units with the type flag)
+    /// has passed since the tx_out it spends was mined.
+    /// coin_heights are the heights of the blocks that the spent tx_outs are in (in the order of the tx_ins), and
+    /// median_time_past_at gives the median time past of the block at some height. Would the transaction be allowed
+    /// in a block at the given height, with the given median time past of the block before it?
+    pub fn sequence_locks_satisfied(&self, coin_heights: &[u32], height: u32, median_time_past: u32, median_time_past_at: impl Fn(u32) -> u32) -> bool {
+	if self.version < 2 || self.is_coinbase() {
+	    return true;
+	}
+	// the last height and time at which the transaction is still locked. -1 means it isn't locked at all
+	let mut min_height: i64 = -1;
+	let mut min_time: i64 = -1;
+	for (tx_in, coin_height) in self.tx_ins.iter().zip(coin_heights) {
+	    let sequence = tx_in.sequence();
+	    if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+		continue;
+	    }
+	    let lock = (sequence & SEQUENCE_LOCKTIME_MASK) as i64;
+	    if sequence & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+		// the time counts from the median time past of the block before the one that the tx_out is in
+		let coin_time = median_time_past_at(coin_height.saturating_sub(1)) as i64;
+		min_time = min_time.max(coin_time + (lock << SEQUENCE_LOCKTIME_GRANULARITY) - 1);
+	    } else {
+		min_height = min_height.max(*coin_height as i64 + lock - 1);
+	    }
+	}
+	min_height < height as i64 && min_time < median_time_past as i64
     }
 
+    /// given the tx_outs that this transaction spends (in the same order as its tx_ins), check that each unlocking script
+    /// unlocks the corresponding locking script (under the given SCRIPT_VERIFY_ flags), and that the tx_outs don't sum to more than what is coming in.
+    /// Returns the tip for the miner, i.e. the difference between the two
+    pub fn verify_tx_ins(&self, spent_tx_outs: &[TxOut], flags: u32) -> Result<u64, TransactionError> {
+	if spent_tx_outs.len() != self.tx_ins.len() {
+	    // zip would quietly leave the extra tx_ins unchecked
+	    return Err(TransactionError::SpentTxOutCountMismatch);
+	}
+	for (input_index, (tx_in, tx_out_to_unlock)) in self.tx_ins.iter().zip(spent_tx_outs).enumerate() {
+	    // each tx_in must be unlocked, with signatures over this transaction
+	    if let TxIn::TxPrevious {unlocking_script, ..} = tx_in {
+		let locking_script = &tx_out_to_unlock.locking_script;
+		let context = ExecutionContext::new(self, input_index, tx_out_to_unlock.value, flags)
+		    .with_spent_tx_outs(spent_tx_outs);
+		execute_scripts(unlocking_script, locking_script, &context)
+		    .map_err(|error| TransactionError::InvalidScript {input_index, error})?;
+	    } else {
+		// we can only take as inputs previous outputs. Only a miner may receive a coinbase reward.
+		return Err(TransactionError::CoinbaseSpend);
+	    }
+	}
+
+	// mext check that the tx_out values don't sum to more than the tx_in values
+	self.fee(spent_tx_outs)
+    }
+}
+
+/// The version, the tx_ins, the tx_outs and then the lock time.
+/// If there are witnesses, the segwit serialization (BIP144) puts a marker byte of 0 and a flag byte of 1 after the version,
+/// and the witness of each tx_in after the tx_outs. Old nodes would read the marker as a transaction without tx_ins
+impl Encodable for Transaction {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+	if !self.has_witness() {
+	    self.encode_without_witness(bytes);
+	    return;
+	}
+	self.version.encode(bytes);
+	bytes.extend_from_slice(&[0x00, 0x01]);
+	self.tx_ins.encode(bytes);
+	self.tx_outs.encode(bytes);
+	for tx_in in &self.tx_ins {
+	    encode_witness(tx_in.witness(), bytes);
+	}
+	self.lock_time.encode(bytes);
+    }
+}
+
+/// Like bitcoin core, an empty list of tx_ins means that we are looking at the segwit marker, and the next byte is the flag.
+/// A flag of 0 can only be the (empty) list of tx_outs of a transaction that really has no tx_ins
+impl Decodable for Transaction {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+	let version = u32::decode(bytes)?;
+	let mut tx_ins = Vec::<TxIn>::decode(bytes)?;
+	let mut flag = 0;
+	let mut tx_outs = vec![];
+	if tx_ins.is_empty() {
+	    flag = take_bytes(bytes, 1)?[0];
+	    if flag != 0 {
+		tx_ins = Vec::<TxIn>::decode(bytes)?;
+		tx_outs = Vec::<TxOut>::decode(bytes)?;
+	    }
+	} else {
+	    tx_outs = Vec::<TxOut>::decode(bytes)?;
+	}
+	if flag & 1 != 0 {
+	    flag ^= 1;
+	    for tx_in in tx_ins.iter_mut() {
+		*tx_in.witness_mut() = decode_witness(bytes)?;
+	    }
+	    if !tx_ins.iter().any(|tx_in| !tx_in.witness().is_empty()) {
+		// then it should have been serialized the old way, and this one would have a different wtxid
+		return Err(DecodeError::SuperfluousWitness);
+	    }
+	}
+	if flag != 0 {
+	    return Err(DecodeError::UnknownTransactionFlag(flag));
+	}
+	let lock_time = u32::decode(bytes)?;
+	Ok(Transaction {version, lock_time, tx_ins, tx_outs})
+    }
 }
 
 impl DoubleSHA for Transaction {
 
     fn sha256d(&self) -> Hash {
-	let first_hash_vec: Vec<u8> = self.hash_to_bytes();
-        let mut hasher_2 = Sha256::new();
-        hasher_2.update(first_hash_vec);
-        let second_hash_vec = hasher_2.finalize().to_vec();
-        Hash::from(&second_hash_vec[..])
+	self.txid()
     }
 }
 
 #[derive(Debug, PartialEq)]
 pub enum TransactionError {
-    InvalidScript,    
+    InvalidScript {input_index: usize, error: ScriptError}, // the unlocking script of this tx_in didn't unlock what it spends
+    NoTxIns, // the transaction has no tx_ins at all
+    NoTxOuts, // the transaction has no tx_outs at all
     OverSpend,
     CoinbaseSpend,
-    TxInNotFound,
+    TxInNotFound, // the tx_out referenced by a tx_in never existed, or was spent by a block in the chain already
+    ImmatureCoinbaseSpend, // a tx_in spends a coinbase that isn't NetworkParams::coinbase_maturity blocks deep yet
+    DoubleSpend, // the tx_out referenced by a tx_in is spent twice (in the mempool, or the same transaction/block)
+    DuplicateTransaction, // a transaction with the same txid is already in the chain and still has unspent tx_outs (BIP30)
+    TxOutValueTooLarge, // a tx_out is worth more than MAX_MONEY
+    TxOutTotalTooLarge, // the tx_outs add up to more than MAX_MONEY
+    TxInValueOutOfRange, // the tx_outs that the tx_ins spend add up to more than MAX_MONEY
+    SpentTxOutCountMismatch, // verify_tx_ins was given a different number of spent tx_outs than there are tx_ins
+    NonFinal, // the lock_time hasn't passed yet
+    SequenceLocked, // the relative lock time of a tx_in hasn't passed yet
+    NonStandard(PolicyError), // not invalid, but the mempool doesn't take it (see MempoolPolicy)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::script::StackOp;
+    use crate::script::{StackOp, pay_to_public_key_hash, pay_to_script_hash, pay_to_witness_public_key_hash, pay_to_witness_script_hash};
+    use crate::encode::deserialize;
+    use crate::hash_to_hex;
     
     #[test]
     fn test_coin_base() {
 	let tx_in = TxIn::Coinbase {
-	    coinbase: 33,
+	    coinbase: vec![33],
 	    sequence: 5580,
+	    witness: vec![],
 	};
-	if let TxIn::Coinbase {coinbase, sequence: _} = tx_in {
-            assert_eq!(33, coinbase);
+	if let TxIn::Coinbase {coinbase, ..} = tx_in {
+	    assert_eq!(vec![33], coinbase);
 	}
     }
 
     #[test]
     fn test_hash_transaction() {
 	let tx_in = TxIn::Coinbase {
-	    coinbase: 33,
+	    coinbase: vec![33],
 	    sequence: 5580,
+	    witness: vec![],
 	};
 	let tx_out1 = TxOut {
 	    value: 222,
@@ -139,13 +497,229 @@ mod tests {
 
 	// note: this is simply the hash that comes out when i presently run it.
 	// This will at least show if something changes unexpectedly in the future
-	// 34955240534511464281001754460162170822162357866488741313605570467957795050853 decimal
-	// 4D47F70BE4C85658925EC886B1C362EBDBC96D9E41F1DC55520169815A11D565 hex
+	// 81734416380137952366328633771545610322064233256778366642938209312580089360422 decimal
+	// B4B40A4F99BD432E01FAE6D14024CDF2E22B707CB124476BD9F4CD599EE73026 hex
         // Note: this has changed multiple times as i impliment, so is it even a good test..?
-	let hash = transaction.hash();
+	let hash = transaction.txid();
         println!("hash = {:?}", hash);
-        let answer = Hash::from([0x4D, 0x47, 0xF7, 0x0B, 0xE4, 0xC8, 0x56, 0x58, 0x92, 0x5E, 0xC8, 0x86, 0xB1, 0xC3, 0x62, 0xEB, 0xDB,
-                                 0xC9, 0x6D, 0x9E, 0x41, 0xF1, 0xDC, 0x55, 0x52, 0x01, 0x69, 0x81, 0x5A, 0x11, 0xD5, 0x65]);
+	let answer = Hash::from([0xB4, 0xB4, 0x0A, 0x4F, 0x99, 0xBD, 0x43, 0x2E, 0x01, 0xFA, 0xE6, 0xD1, 0x40, 0x24, 0xCD, 0xF2, 0xE2,
+				 0x2B, 0x70, 0x7C, 0xB1, 0x24, 0x47, 0x6B, 0xD9, 0xF4, 0xCD, 0x59, 0x9E, 0xE7, 0x30, 0x26]);
 	assert_eq!(hash, answer);
     }
+
+    /// a regular mainnet transaction, spending a pay to public key hash output with one signature
+    #[test]
+    fn test_mainnet_transaction() {
+	let tx_bytes = hex::decode("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap();
+	let transaction: Transaction = deserialize(&tx_bytes).unwrap();
+	assert_eq!(transaction.version, 1);
+	assert_eq!(transaction.lock_time, 0);
+	assert!(!transaction.is_coinbase());
+	let outpoint = transaction.tx_ins[0].outpoint().unwrap();
+	assert_eq!(hash_to_hex(&outpoint.tx_hash), "ce9ea9f6f5e422c6a9dbcddb3b9a14d1c78fab9ab520cb281aa2a74a09575da1");
+	assert_eq!(outpoint.tx_out_index, 1);
+	assert_eq!(transaction.tx_outs[0].value, 100_000_000);
+	assert_eq!(transaction.tx_outs[0].locking_script.ops[0], StackOp::OpDup);
+
+	assert_eq!(serialize(&transaction), tx_bytes);
+	assert_eq!(hash_to_hex(&transaction.txid()), "a6eab3c14ab5272a58a5ba91505ba1a4b6d7a3a9fcbd187b6cd99a7b6d548cb7");
+    }
+
+    /// the coinbase of the genesis block, with the famous newspaper headline in its coinbase field
+    #[test]
+    fn test_mainnet_genesis_coinbase() {
+	let coinbase = "04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73";
+	let locking_script = "4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac";
+	let tx_hex = format!("01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d{}ffffffff0100f2052a0100000043{}00000000", coinbase, locking_script);
+	let tx_bytes = hex::decode(tx_hex).unwrap();
+	let transaction: Transaction = deserialize(&tx_bytes).unwrap();
+	assert!(transaction.is_coinbase());
+	if let TxIn::Coinbase {coinbase: coinbase_bytes, sequence, ..} = &transaction.tx_ins[0] {
+	    assert_eq!(hex::encode(coinbase_bytes), coinbase);
+	    assert_eq!(*sequence, 0xffffffff);
+	}
+	assert_eq!(transaction.tx_outs[0].value, 50 * 100_000_000);
+	assert_eq!(transaction.tx_outs[0].locking_script.to_bytes(), hex::decode(locking_script).unwrap());
+
+	assert_eq!(serialize(&transaction), tx_bytes);
+	assert_eq!(hash_to_hex(&transaction.txid()), "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b");
+    }
+
+    #[test]
+    fn test_decode_truncated_transaction() {
+	let tx_bytes = serialize(&Transaction {version: 1, lock_time: 0, tx_ins: vec![], tx_outs: vec![]});
+	assert_eq!(deserialize::<Transaction>(&tx_bytes[..tx_bytes.len() - 1]), Err(DecodeError::UnexpectedEnd));
+    }
+
+    /// a mainnet transaction in the segwit serialization, spending a pay to witness public key hash output
+    #[test]
+    fn test_segwit_transaction() {
+	let tx_bytes = hex::decode("02000000000101595895ea20179de87052b4046dfe6fd515860505d6511a9004cf12a1f93cac7c0100000000ffffffff01deb807000000000017a9140f3444e271620c736808aa7b33e370bd87cb5a078702483045022100fb60dad8df4af2841adc0346638c16d0b8035f5e3f3753b88db122e70c79f9370220756e6633b17fd2710e626347d28d60b0a2d6cbb41de51740644b9fb3ba7751040121028fa937ca8cba2197a37c007176ed8941055d3bcb8627d085e94553e62f057dcc00000000").unwrap();
+	let transaction: Transaction = deserialize(&tx_bytes).unwrap();
+	assert_eq!(transaction.version, 2);
+	assert!(transaction.has_witness());
+	let outpoint = transaction.tx_ins[0].outpoint().unwrap();
+	assert_eq!(hash_to_hex(&outpoint.tx_hash), "7cac3cf9a112cf04901a51d605058615d56ffe6d04b45270e89d1720ea955859");
+	assert_eq!(outpoint.tx_out_index, 1);
+	assert_eq!(transaction.tx_ins[0].witness().len(), 2);
+	assert_eq!(serialize(&transaction), tx_bytes);
+	assert_eq!(hash_to_hex(&transaction.txid()), "f5864806e3565c34d1b41e716f72609d00b55ea5eac5b924c9719a842ef42206");
+	assert_eq!(hash_to_hex(&transaction.wtxid()), "80b7d8a82d5d5bf92905b06f2014dd699e03837ca172e3a59d51426ebbe3e7f5");
+	assert_eq!(transaction.total_size(), tx_bytes.len());
+	assert_eq!(transaction.weight(), 442);
+	assert_eq!(transaction.vsize(), 111);
+
+	// without the witness it's an old style transaction, which weighs 4 per byte
+	let mut stripped = transaction.clone();
+	stripped.tx_ins[0].witness_mut().clear();
+	assert_eq!(stripped.txid(), transaction.txid());
+	assert_eq!(stripped.wtxid(), stripped.txid());
+	assert_eq!(stripped.total_size(), transaction.base_size());
+	assert_eq!(stripped.weight(), 4 * transaction.base_size());
+	assert_eq!(stripped.vsize(), transaction.base_size());
+    }
+
+    /// a testnet coinbase with a witness commitment, whose witness is the reserved value
+    #[test]
+    fn test_segwit_coinbase() {
+	let tx_bytes = hex::decode("010000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff3603da1b0e00045503bd5704c7dd8a0d0ced13bb5785010800000000000a636b706f6f6c122f4e696e6a61506f6f6c2f5345475749542fffffffff02b4e5a212000000001976a914876fbb82ec05caa6af7a3b5e5a983aae6c6cc6d688ac0000000000000000266a24aa21a9edf91c46b49eb8a29089980f02ee6b57e7d63d33b18b4fddac2bcd7db2a39837040120000000000000000000000000000000000000000000000000000000000000000000000000").unwrap();
+	let transaction: Transaction = deserialize(&tx_bytes).unwrap();
+	assert!(transaction.is_coinbase());
+	assert_eq!(transaction.tx_ins[0].witness(), &[vec![0; 32]]);
+	assert_eq!(transaction.weight(), 780);
+	assert_eq!(serialize(&transaction), tx_bytes);
+    }
+
+    #[test]
+    fn test_decode_witness_errors() {
+	let transaction = with_sequences(1, 0, &[0]);
+	let old_bytes = serialize(&transaction);
+	// the marker and flag, but then every witness is empty
+	let with_flag = |flag: u8, witnesses: &[u8]| {
+	    let mut tx_bytes = old_bytes[..4].to_vec();
+	    tx_bytes.extend_from_slice(&[0x00, flag]);
+	    tx_bytes.extend_from_slice(&old_bytes[4..old_bytes.len() - 4]);
+	    tx_bytes.extend_from_slice(witnesses);
+	    tx_bytes.extend_from_slice(&old_bytes[old_bytes.len() - 4..]);
+	    tx_bytes
+	};
+	assert_eq!(deserialize::<Transaction>(&with_flag(0x01, &[0x00])), Err(DecodeError::SuperfluousWitness));
+	// a flag that doesn't mean anything yet
+	assert_eq!(deserialize::<Transaction>(&with_flag(0x02, &[])), Err(DecodeError::UnknownTransactionFlag(0x02)));
+	let mut transaction = transaction;
+	*transaction.tx_ins[0].witness_mut() = vec![vec![1, 2, 3]];
+	assert_eq!(serialize(&transaction), with_flag(0x01, &[0x01, 0x03, 1, 2, 3]));
+	// a transaction without any tx_ins or tx_outs still decodes, since its flag would be the 0 tx_outs
+	let empty = Transaction {version: 1, lock_time: 0, tx_ins: vec![], tx_outs: vec![]};
+	assert_eq!(deserialize::<Transaction>(&serialize(&empty)), Ok(empty));
+    }
+
+    /// a transaction spending one tx_out for each of the given sequences
+    fn with_sequences(version: u32, lock_time: u32, sequences: &[u32]) -> Transaction {
+	let tx_ins = sequences.iter().enumerate().map(|(i, sequence)| TxIn::TxPrevious {
+	    tx_hash: Hash::from(i + 1),
+	    tx_out_index: 0,
+	    unlocking_script: Script {ops: vec![]},
+	    sequence: *sequence,
+	    witness: vec![],
+	}).collect();
+	Transaction {version, lock_time, tx_ins, tx_outs: vec![]}
+    }
+
+    #[test]
+    fn test_is_final() {
+	// no lock_time at all
+	assert!(with_sequences(1, 0, &[0]).is_final(0, 0));
+	// a height: final once a block is past it
+	let transaction = with_sequences(1, 100, &[0, SEQUENCE_FINAL]);
+	assert!(!transaction.is_final(100, 2_000_000_000));
+	assert!(transaction.is_final(101, 0));
+	// a time: the height doesn't matter
+	let transaction = with_sequences(1, LOCKTIME_THRESHOLD + 1000, &[0]);
+	assert!(!transaction.is_final(1_000_000, LOCKTIME_THRESHOLD + 1000));
+	assert!(transaction.is_final(0, LOCKTIME_THRESHOLD + 1001));
+	// unless every tx_in opts out
+	assert!(with_sequences(1, 100, &[SEQUENCE_FINAL, SEQUENCE_FINAL]).is_final(0, 0));
+    }
+
+    #[test]
+    fn test_sequence_locks() {
+	let no_time = |_| 0;
+	// 10 blocks after the tx_out at height 5, i.e. from height 15 on
+	let transaction = with_sequences(2, 0, &[10]);
+	assert!(!transaction.sequence_locks_satisfied(&[5], 14, 0, no_time));
+	assert!(transaction.sequence_locks_satisfied(&[5], 15, 0, no_time));
+	// the latest of several tx_ins counts
+	let transaction = with_sequences(2, 0, &[10, 3]);
+	assert!(!transaction.sequence_locks_satisfied(&[5, 13], 15, 0, no_time));
+	assert!(transaction.sequence_locks_satisfied(&[5, 13], 16, 0, no_time));
+	// version 1, or the disable flag, turns them off
+	assert!(with_sequences(1, 0, &[10]).sequence_locks_satisfied(&[5], 6, 0, no_time));
+	assert!(with_sequences(2, 0, &[10 | SEQUENCE_LOCKTIME_DISABLE_FLAG]).sequence_locks_satisfied(&[5], 6, 0, no_time));
+	// 2 * 512 seconds after the median time past of the block before the tx_out's
+	let transaction = with_sequences(2, 0, &[2 | SEQUENCE_LOCKTIME_TYPE_FLAG]);
+	let median_time_past_at = |height| if height == 4 {1_000_000} else {0};
+	assert!(!transaction.sequence_locks_satisfied(&[5], 100, 1_000_000 + 1024 - 1, median_time_past_at));
+	assert!(transaction.sequence_locks_satisfied(&[5], 100, 1_000_000 + 1024, median_time_past_at));
+    }
+
+    /// signature checks in redeem scripts and witness scripts are counted with the number of keys, and the witness ones count a quarter
+    #[test]
+    fn test_sigop_cost() {
+	let key = |prefix: u8| StackOp::Bytes(vec![prefix; 33].into_boxed_slice());
+	let multisig = Script {ops: vec![StackOp::Val(2), key(2), key(3), StackOp::Val(2), StackOp::OpCheckMultiSig]};
+	let spend = |unlocking_script: Script, witness: Vec<Vec<u8>>| TxIn::TxPrevious {tx_hash: Hash::from(1), tx_out_index: 0, unlocking_script, sequence: 0, witness};
+	let redeem_push = |script: &Script| Script {ops: vec![StackOp::Val(0), StackOp::Bytes(script.to_bytes().into_boxed_slice())]};
+	let wrapped_witness_program = pay_to_witness_public_key_hash(&[2; 33]);
+	let transaction = Transaction {
+	    version: 2,
+	    lock_time: 0,
+	    tx_ins: vec![
+		spend(redeem_push(&multisig), vec![]),
+		spend(Script {ops: vec![]}, vec![vec![], multisig.to_bytes()]),
+		spend(Script {ops: vec![]}, vec![vec![1; 72], vec![2; 33]]),
+		spend(Script {ops: vec![StackOp::Bytes(wrapped_witness_program.to_bytes().into_boxed_slice())]}, vec![vec![1; 72], vec![2; 33]]),
+	    ],
+	    tx_outs: vec![TxOut {value: 1, locking_script: multisig.clone()}, TxOut {value: 1, locking_script: pay_to_public_key_hash(&[2; 33])}],
+	};
+	let spent_tx_outs: Vec<TxOut> = [pay_to_script_hash(&multisig), pay_to_witness_script_hash(&multisig), wrapped_witness_program.clone(), pay_to_script_hash(&wrapped_witness_program)]
+	    .iter().map(|locking_script| TxOut {value: 1, locking_script: locking_script.clone()}).collect();
+	// the bare multisig tx_out counts as 20 keys, the pay to public key hash as 1, and the redeem script as 2
+	let legacy_cost = (20 + 1 + 2) * WITNESS_SCALE_FACTOR;
+	assert_eq!(transaction.sigop_cost(&spent_tx_outs), legacy_cost + 2 + 1 + 1);
+	// without the spent tx_outs, only the legacy sigops that don't depend on them are there
+	assert_eq!(transaction.sigop_cost(&[]), (20 + 1) * WITNESS_SCALE_FACTOR);
+	assert_eq!(multisig.sigop_count(false), 20);
+	assert_eq!(Script::parse_asm("OP_CHECKSIG OP_CHECKSIGVERIFY 0 OP_CHECKMULTISIGVERIFY").unwrap().sigop_count(true), 22);
+    }
+
+    #[test]
+    fn test_value_ranges() {
+	let pay = |value: u64| TxOut {value, locking_script: pay_to_public_key_hash(&[2; 33])};
+	let paying = |values: &[u64]| Transaction {version: 1, lock_time: 0, tx_ins: vec![], tx_outs: values.iter().copied().map(pay).collect()};
+	assert_eq!(paying(&[MAX_MONEY]).tx_out_value_sum(), Ok(MAX_MONEY));
+	assert_eq!(paying(&[1, MAX_MONEY - 1]).tx_out_value_sum(), Ok(MAX_MONEY));
+	assert_eq!(paying(&[1, MAX_MONEY + 1]).tx_out_value_sum(), Err(TransactionError::TxOutValueTooLarge));
+	assert_eq!(paying(&[MAX_MONEY, 1]).tx_out_value_sum(), Err(TransactionError::TxOutTotalTooLarge));
+	// which also keeps the sum from overflowing
+	assert_eq!(paying(&[u64::MAX, u64::MAX]).tx_out_value_sum(), Err(TransactionError::TxOutValueTooLarge));
+
+	let transaction = paying(&[MAX_MONEY - 10]);
+	assert_eq!(transaction.fee(&[pay(MAX_MONEY)]), Ok(10));
+	assert_eq!(transaction.fee(&[pay(MAX_MONEY - 11)]), Err(TransactionError::OverSpend));
+	assert_eq!(transaction.fee(&[pay(MAX_MONEY), pay(1)]), Err(TransactionError::TxInValueOutOfRange));
+	assert_eq!(transaction.fee(&[pay(u64::MAX), pay(u64::MAX)]), Err(TransactionError::TxInValueOutOfRange));
+	assert_eq!(paying(&[MAX_MONEY + 1]).fee(&[pay(MAX_MONEY)]), Err(TransactionError::TxOutValueTooLarge));
+    }
+
+    #[test]
+    fn test_verify_tx_ins_needs_every_spent_tx_out() {
+	let tx_in = TxIn::TxPrevious {tx_hash: Hash::zero(), tx_out_index: 0, unlocking_script: Script {ops: vec![]}, sequence: SEQUENCE_FINAL, witness: vec![]};
+	let anyone_can_spend = TxOut {value: 10, locking_script: Script {ops: vec![StackOp::Bool(true)]}};
+	let transaction = Transaction {version: 1, lock_time: 0, tx_ins: vec![tx_in.clone(), tx_in], tx_outs: vec![]};
+	assert_eq!(transaction.verify_tx_ins(&[anyone_can_spend.clone(), anyone_can_spend.clone()], 0), Ok(20));
+	// the second tx_in must not get away with spending nothing
+	assert_eq!(transaction.verify_tx_ins(std::slice::from_ref(&anyone_can_spend), 0), Err(TransactionError::SpentTxOutCountMismatch));
+	assert_eq!(transaction.verify_tx_ins(&[anyone_can_spend.clone(), anyone_can_spend.clone(), anyone_can_spend], 0), Err(TransactionError::SpentTxOutCountMismatch));
+    }
 }